@@ -0,0 +1,199 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+/// The surroundings of a cursor: the char right before it and the char right after it.
+pub type CursorContext = (Option<char>, Option<char>);
+
+/// What the `EditView` should do when the user types a char
+#[derive(Debug, PartialEq)]
+pub enum InsertAction {
+    /// Insert the char as it is
+    Insert,
+    /// Insert the char and the closing char passed along, then move the cursor between them
+    Close(char),
+    /// Don't insert anything, but move the cursor over the closing char that's already there
+    Overtype,
+}
+
+/// Brackets and quotes which are closed automatically while typing
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoPairs {
+    pairs: Vec<(char, char)>,
+}
+
+impl Default for AutoPairs {
+    fn default() -> Self {
+        Self::new(vec![
+            ('(', ')'),
+            ('[', ']'),
+            ('{', '}'),
+            ('"', '"'),
+            ('\'', '\''),
+        ])
+    }
+}
+
+impl AutoPairs {
+    pub fn new(pairs: Vec<(char, char)>) -> Self {
+        Self { pairs }
+    }
+
+    /// Create `AutoPairs` from the `surrounding_pairs` of a syntax config. Pairs which don't
+    /// consist of exactly one char on each side are ignored.
+    pub fn from_strings(pairs: &[(String, String)]) -> Self {
+        Self::new(
+            pairs
+                .iter()
+                .filter_map(|(open, close)| {
+                    let mut open_chars = open.chars();
+                    let mut close_chars = close.chars();
+                    match (
+                        open_chars.next(),
+                        open_chars.next(),
+                        close_chars.next(),
+                        close_chars.next(),
+                    ) {
+                        (Some(o), None, Some(c), None) => Some((o, c)),
+                        _ => None,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// The pairs as strings, e.g. for xi's `surrounding_pairs` config
+    pub fn to_strings(&self) -> Vec<(String, String)> {
+        self.pairs
+            .iter()
+            .map(|(o, c)| (o.to_string(), c.to_string()))
+            .collect()
+    }
+
+    fn closing_for(&self, open: char) -> Option<char> {
+        self.pairs.iter().find(|(o, _)| *o == open).map(|(_, c)| *c)
+    }
+
+    fn is_closing(&self, c: char) -> bool {
+        self.pairs.iter().any(|(_, close)| *close == c)
+    }
+
+    /// Decide what to do when `c` is typed. All cursors have to agree on an action, otherwise
+    /// the char is just inserted.
+    pub fn on_insert(&self, c: char, contexts: &[CursorContext]) -> InsertAction {
+        if contexts.is_empty() {
+            return InsertAction::Insert;
+        }
+
+        if self.is_closing(c) && contexts.iter().all(|(_, next)| *next == Some(c)) {
+            return InsertAction::Overtype;
+        }
+
+        if let Some(close) = self.closing_for(c) {
+            let can_close = contexts.iter().all(|(prev, next)| {
+                let next_ok = match next {
+                    None => true,
+                    Some(n) => n.is_whitespace() || self.is_closing(*n),
+                };
+                // Don't close quotes right after a word, e.g. in "don't"
+                let prev_ok = c != close
+                    || match prev {
+                        None => true,
+                        Some(p) => !p.is_alphanumeric() && *p != c,
+                    };
+                next_ok && prev_ok
+            });
+
+            if can_close {
+                return InsertAction::Close(close);
+            }
+        }
+
+        InsertAction::Insert
+    }
+
+    /// Returns true if backspace should delete the closing char after the cursors, too, because
+    /// every cursor sits inside an empty pair.
+    pub fn on_backspace(&self, contexts: &[CursorContext]) -> bool {
+        !contexts.is_empty()
+            && contexts.iter().all(|(prev, next)| match (prev, next) {
+                (Some(p), Some(n)) => self.closing_for(*p) == Some(*n),
+                _ => false,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn close_bracket() {
+        let pairs = AutoPairs::default();
+        assert_eq!(
+            pairs.on_insert('(', &[(None, None)]),
+            InsertAction::Close(')')
+        );
+        assert_eq!(
+            pairs.on_insert('[', &[(Some('a'), Some(' ')), (None, Some(')'))]),
+            InsertAction::Close(']')
+        );
+        assert_eq!(
+            pairs.on_insert('{', &[(None, Some('a'))]),
+            InsertAction::Insert
+        );
+        assert_eq!(pairs.on_insert('a', &[(None, None)]), InsertAction::Insert);
+    }
+
+    #[test]
+    fn close_quote() {
+        let pairs = AutoPairs::default();
+        assert_eq!(
+            pairs.on_insert('"', &[(Some(' '), None)]),
+            InsertAction::Close('"')
+        );
+        assert_eq!(
+            pairs.on_insert('\'', &[(Some('n'), Some('t'))]),
+            InsertAction::Insert
+        );
+        assert_eq!(
+            pairs.on_insert('\'', &[(Some('n'), None)]),
+            InsertAction::Insert
+        );
+    }
+
+    #[test]
+    fn overtype() {
+        let pairs = AutoPairs::default();
+        assert_eq!(
+            pairs.on_insert(')', &[(Some('('), Some(')'))]),
+            InsertAction::Overtype
+        );
+        assert_eq!(
+            pairs.on_insert('"', &[(Some('a'), Some('"'))]),
+            InsertAction::Overtype
+        );
+        assert_eq!(
+            pairs.on_insert(')', &[(Some('('), Some(')')), (None, None)]),
+            InsertAction::Insert
+        );
+    }
+
+    #[test]
+    fn backspace() {
+        let pairs = AutoPairs::default();
+        assert!(pairs.on_backspace(&[(Some('('), Some(')')), (Some('"'), Some('"'))]));
+        assert!(!pairs.on_backspace(&[(Some('('), Some(']'))]));
+        assert!(!pairs.on_backspace(&[(Some('('), Some(')')), (None, Some(')'))]));
+        assert!(!pairs.on_backspace(&[]));
+    }
+
+    #[test]
+    fn from_strings() {
+        let pairs = AutoPairs::from_strings(&[
+            ("<".to_string(), ">".to_string()),
+            ("/*".to_string(), "*/".to_string()),
+        ]);
+        assert_eq!(pairs, AutoPairs::new(vec![('<', '>')]));
+        assert_eq!(pairs.to_strings(), vec![("<".to_string(), ">".to_string())]);
+    }
+}
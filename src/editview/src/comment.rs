@@ -0,0 +1,207 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::syntaxes::SYNTAX_SET;
+use syntect::parsing::Scope;
+
+/// The comment markers of a language, as specified by syntect's metadata
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommentTokens {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
+impl CommentTokens {
+    /// Look up the comment markers of the syntax called `name`, e.g. "Rust"
+    pub fn for_syntax(name: &str) -> Self {
+        let scope: Scope = match SYNTAX_SET.find_syntax_by_name(name) {
+            Some(syntax) => syntax.scope,
+            None => return Self::default(),
+        };
+        let metadata = SYNTAX_SET.metadata().metadata_for_scope(&[scope]);
+
+        Self {
+            line: metadata
+                .line_comment()
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            block: metadata
+                .block_comment()
+                .map(|(start, end)| (start.trim().to_string(), end.trim().to_string()))
+                .filter(|(start, end)| !start.is_empty() && !end.is_empty()),
+        }
+    }
+}
+
+/// An edit which (un)comments lines, positions are (line, col) with cols in bytes
+#[derive(Debug, PartialEq)]
+pub enum CommentEdit {
+    /// Insert `text` at every position at once
+    Insert {
+        positions: Vec<(u64, u64)>,
+        text: String,
+    },
+    /// Delete every (line, start col, end col) range at once
+    Delete { ranges: Vec<(u64, u64, u64)> },
+    /// Replace everything between `start` and `end` with `text`
+    Replace {
+        start: (u64, u64),
+        end: (u64, u64),
+        text: String,
+    },
+}
+
+fn strip_line_ending(text: &str) -> &str {
+    text.trim_end_matches(|c| c == '\n' || c == '\r')
+}
+
+fn indentation(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+/// Comment out the `lines` (line number and text) with `token`, or uncomment them if all of them
+/// are commented already. Blank lines are left alone.
+pub fn toggle_line_comment(lines: &[(u64, &str)], token: &str) -> Option<CommentEdit> {
+    let lines: Vec<(u64, &str)> = lines
+        .iter()
+        .map(|(n, text)| (*n, strip_line_ending(text)))
+        .filter(|(_, text)| !text.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+
+    let commented = lines
+        .iter()
+        .all(|(_, text)| text.trim_start().starts_with(token));
+
+    if commented {
+        let ranges = lines
+            .iter()
+            .map(|(n, text)| {
+                let start = indentation(text);
+                let mut end = start + token.len();
+                if text[end..].starts_with(' ') {
+                    end += 1;
+                }
+                (*n, start as u64, end as u64)
+            })
+            .collect();
+        Some(CommentEdit::Delete { ranges })
+    } else {
+        // Keep the comment markers aligned to the least indented line
+        let col = lines
+            .iter()
+            .map(|(_, text)| indentation(text))
+            .min()
+            .unwrap_or(0) as u64;
+        Some(CommentEdit::Insert {
+            positions: lines.iter().map(|(n, _)| (*n, col)).collect(),
+            text: format!("{} ", token),
+        })
+    }
+}
+
+/// Wrap the `lines` (line number and text, including line endings) in a block comment delimited
+/// by `start` and `end`, or unwrap them if they already are.
+pub fn toggle_block_comment(lines: &[(u64, &str)], start: &str, end: &str) -> Option<CommentEdit> {
+    let first = lines.iter().position(|(_, t)| !t.trim().is_empty())?;
+    let last = lines.iter().rposition(|(_, t)| !t.trim().is_empty())?;
+    let lines = &lines[first..=last];
+
+    let first_text = strip_line_ending(lines[0].1);
+    let last_text = strip_line_ending(lines[lines.len() - 1].1);
+    let start_pos = (lines[0].0, indentation(first_text) as u64);
+    let end_pos = (lines[lines.len() - 1].0, last_text.trim_end().len() as u64);
+
+    // The text between `start_pos` and `end_pos`
+    let mut text = String::new();
+    for (i, (_, line)) in lines.iter().enumerate() {
+        let mut line = *line;
+        if i == lines.len() - 1 {
+            line = line.trim_end();
+        }
+        if i == 0 {
+            line = line.trim_start();
+        }
+        text.push_str(line);
+    }
+
+    let text = if text.starts_with(start)
+        && text.ends_with(end)
+        && text.len() >= start.len() + end.len()
+    {
+        let inner = &text[start.len()..text.len() - end.len()];
+        let inner = if inner.starts_with(' ') {
+            &inner[1..]
+        } else {
+            inner
+        };
+        let inner = if inner.ends_with(' ') {
+            &inner[..inner.len() - 1]
+        } else {
+            inner
+        };
+        inner.to_string()
+    } else {
+        format!("{} {} {}", start, text, end)
+    };
+
+    Some(CommentEdit::Replace {
+        start: start_pos,
+        end: end_pos,
+        text,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn line_comment() {
+        let lines = [(3, "    let a = 1;\n"), (4, "\n"), (5, "  b();\n")];
+        assert_eq!(
+            toggle_line_comment(&lines, "//"),
+            Some(CommentEdit::Insert {
+                positions: vec![(3, 2), (5, 2)],
+                text: "// ".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn line_uncomment() {
+        let lines = [(0, "  // a\n"), (1, "  //b\n")];
+        assert_eq!(
+            toggle_line_comment(&lines, "//"),
+            Some(CommentEdit::Delete {
+                ranges: vec![(0, 2, 5), (1, 2, 4)],
+            })
+        );
+        assert_eq!(toggle_line_comment(&[(0, "  \n")], "//"), None);
+    }
+
+    #[test]
+    fn block_comment() {
+        let lines = [(1, "  a\n"), (2, "  b  \n")];
+        assert_eq!(
+            toggle_block_comment(&lines, "/*", "*/"),
+            Some(CommentEdit::Replace {
+                start: (1, 2),
+                end: (2, 3),
+                text: "/* a\n  b */".to_string(),
+            })
+        );
+
+        let lines = [(1, "  /* a\n"), (2, "  b */\n")];
+        assert_eq!(
+            toggle_block_comment(&lines, "/*", "*/"),
+            Some(CommentEdit::Replace {
+                start: (1, 2),
+                end: (2, 6),
+                text: "a\n  b".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::syntaxes::SYNTAX_SET;
+use log::debug;
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
+use syntect::parsing::syntax_definition::Pattern;
+use syntect::parsing::SyntaxSetBuilder;
+use tau_linecache::LineCache;
+
+thread_local! {
+    // The definitions are only accessible via a builder, which is created once keywords are
+    // requested for the first time
+    static SYNTAX_DEFINITIONS: SyntaxSetBuilder = SYNTAX_SET.clone().into_builder();
+}
+
+/// How many completions are offered at most
+const MAX_ITEMS: usize = 50;
+
+/// Words shorter than this aren't worth completing
+const MIN_WORD_LEN: usize = 3;
+
+/// A single completion the user can choose
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionItem {
+    /// The text which replaces the word prefix when the completion is accepted
+    pub label: String,
+    /// Where the completion comes from, e.g. "keyword"
+    pub detail: String,
+    /// The text inserted instead of `label`, if it differs, e.g. for language server completions
+    pub insert_text: Option<String>,
+}
+
+/// What the user wants completions for
+#[derive(Debug)]
+pub struct CompletionRequest<'a> {
+    /// The part of the word in front of the cursor
+    pub prefix: &'a str,
+    /// The syntax of the document, as reported by xi
+    pub language: &'a str,
+}
+
+/// Something which can offer completions, e.g. the words in open documents. Implement this to
+/// add completions from other sources, e.g. plugins.
+pub trait CompletionProvider {
+    /// Returns candidates for the request. These don't have to match the prefix, they're matched
+    /// and sorted afterwards.
+    fn candidates(&self, request: &CompletionRequest) -> Vec<CompletionItem>;
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the word part at the end of `text`
+pub fn word_prefix(text: &str) -> &str {
+    let start = text
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word_char(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| text.len());
+    &text[start..]
+}
+
+/// Offers the words of all open documents
+#[derive(Default)]
+pub struct DocumentWords {
+    documents: RefCell<Vec<Weak<Mutex<LineCache>>>>,
+}
+
+impl DocumentWords {
+    /// Harvest words from `document` from now on, until it's dropped
+    pub fn register(&self, document: &Arc<Mutex<LineCache>>) {
+        let mut documents = self.documents.borrow_mut();
+        documents.retain(|d| d.strong_count() > 0);
+        documents.push(Arc::downgrade(document));
+    }
+}
+
+impl CompletionProvider for DocumentWords {
+    fn candidates(&self, request: &CompletionRequest) -> Vec<CompletionItem> {
+        let mut words = HashSet::new();
+        for document in self.documents.borrow().iter().filter_map(Weak::upgrade) {
+            let line_cache = document.lock();
+            for line in line_cache.lines.iter().filter_map(Option::as_ref) {
+                words.extend(
+                    line.text
+                        .split(|c: char| !is_word_char(c))
+                        .filter(|w| w.chars().count() >= MIN_WORD_LEN && w != &request.prefix)
+                        .filter(|w| !w.starts_with(|c: char| c.is_numeric()))
+                        .map(str::to_string),
+                );
+            }
+        }
+
+        words
+            .into_iter()
+            .map(|label| CompletionItem {
+                label,
+                detail: String::new(),
+                insert_text: None,
+            })
+            .collect()
+    }
+}
+
+/// Offers the keywords of the current syntax, as far as they can be determined from syntect's
+/// syntax definitions
+#[derive(Default)]
+pub struct SyntaxKeywords {
+    keywords: RefCell<HashMap<String, Rc<Vec<String>>>>,
+}
+
+impl SyntaxKeywords {
+    fn keywords_for(&self, language: &str) -> Rc<Vec<String>> {
+        if let Some(keywords) = self.keywords.borrow().get(language) {
+            return keywords.clone();
+        }
+
+        let keywords = SYNTAX_DEFINITIONS.with(|builder| {
+            let mut keywords: Vec<String> = builder
+                .syntaxes()
+                .iter()
+                .filter(|s| s.name == language)
+                .flat_map(|s| s.contexts.values())
+                .flat_map(|c| c.patterns.iter())
+                .filter_map(|p| match p {
+                    Pattern::Match(m) => Some(m),
+                    Pattern::Include(_) => None,
+                })
+                .filter(|m| {
+                    m.scope.iter().any(|s| {
+                        let s = s.build_string();
+                        s.starts_with("keyword")
+                            || s.starts_with("storage")
+                            || s.starts_with("constant.language")
+                            || s.starts_with("support")
+                    })
+                })
+                .flat_map(|m| keywords_from_regex(m.regex.regex_str()))
+                .collect();
+            keywords.sort();
+            keywords.dedup();
+            keywords
+        });
+        debug!("Found {} keywords for '{}'", keywords.len(), language);
+
+        let keywords = Rc::new(keywords);
+        self.keywords
+            .borrow_mut()
+            .insert(language.to_string(), keywords.clone());
+        keywords
+    }
+}
+
+impl CompletionProvider for SyntaxKeywords {
+    fn candidates(&self, request: &CompletionRequest) -> Vec<CompletionItem> {
+        self.keywords_for(request.language)
+            .iter()
+            .map(|k| CompletionItem {
+                label: k.clone(),
+                detail: "keyword".to_string(),
+                insert_text: None,
+            })
+            .collect()
+    }
+}
+
+/// Extract the words of regexes which only match a list of words, like `\b(?:fn|let|mut)\b`
+pub fn keywords_from_regex(regex: &str) -> Vec<String> {
+    let stripped = regex.replace("\\b", "").replace("(?:", "(");
+    if !stripped
+        .chars()
+        .all(|c| is_word_char(c) || c == '|' || c == '(' || c == ')')
+    {
+        return Vec::new();
+    }
+
+    stripped
+        .split(|c| c == '|' || c == '(' || c == ')')
+        .filter(|w| w.len() >= 2 && !w.starts_with(|c: char| c.is_numeric()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns how well `pattern` matches `candidate`, or `None` if it doesn't match at all. All chars
+/// of the pattern have to appear in the candidate in order, ignoring case. Matches at the start of
+/// the candidate, at word boundaries and of consecutive chars score higher.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut candidate_chars = candidate.chars().enumerate().peekable();
+    let mut prev_match: Option<usize> = None;
+    let mut prev_char: Option<char> = None;
+
+    for p in pattern.chars() {
+        let p_lower = p.to_lowercase().next().unwrap_or(p);
+        loop {
+            let (i, c) = candidate_chars.next()?;
+            let boundary = match prev_char {
+                None => true,
+                Some(prev) => {
+                    (!is_word_char(prev) && is_word_char(c))
+                        || (prev.is_lowercase() && c.is_uppercase())
+                }
+            };
+            prev_char = Some(c);
+
+            if c.to_lowercase().next().unwrap_or(c) == p_lower {
+                score += 1;
+                if i == 0 {
+                    score += 8;
+                } else if boundary {
+                    score += 4;
+                }
+                if prev_match.map(|m| m + 1 == i).unwrap_or(false) {
+                    score += 5;
+                }
+                if c == p {
+                    score += 1;
+                }
+                prev_match = Some(i);
+                break;
+            }
+        }
+    }
+
+    // Prefer shorter candidates if everything else is equal
+    Some(score * 100 - candidate.chars().count() as i64)
+}
+
+/// Filter the `items` by how well they match `pattern` and sort them, best match first
+pub fn rank(pattern: &str, items: Vec<CompletionItem>) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+    let mut scored: Vec<(i64, CompletionItem)> = items
+        .into_iter()
+        .filter(|item| item.label != pattern)
+        .filter_map(|item| fuzzy_score(pattern, &item.label).map(|score| (score, item)))
+        .collect();
+    scored.sort_by_key(|(score, item)| (Reverse(*score), item.label.clone()));
+    scored
+        .into_iter()
+        .map(|(_, item)| item)
+        .filter(|item| seen.insert(item.label.clone()))
+        .take(MAX_ITEMS)
+        .collect()
+}
+
+/// All `CompletionProvider`s, shared between all `EditView`s
+pub struct Completion {
+    documents: Rc<DocumentWords>,
+    providers: Vec<Rc<dyn CompletionProvider>>,
+}
+
+impl Default for Completion {
+    fn default() -> Self {
+        let documents = Rc::new(DocumentWords::default());
+        Self {
+            providers: vec![documents.clone(), Rc::new(SyntaxKeywords::default())],
+            documents,
+        }
+    }
+}
+
+impl Completion {
+    /// Offer the words of `document` as completions
+    pub fn register_document(&self, document: &Arc<Mutex<LineCache>>) {
+        self.documents.register(document);
+    }
+
+    pub fn add_provider(&mut self, provider: Rc<dyn CompletionProvider>) {
+        self.providers.push(provider);
+    }
+
+    /// Ask all providers for completions and return the best matches for the prefix
+    pub fn complete(&self, request: &CompletionRequest) -> Vec<CompletionItem> {
+        let items = self
+            .providers
+            .iter()
+            .flat_map(|p| p.candidates(request))
+            .collect();
+        rank(request.prefix, items)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(label: &str) -> CompletionItem {
+        CompletionItem {
+            label: label.to_string(),
+            detail: String::new(),
+            insert_text: None,
+        }
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!(word_prefix("let foo_ba"), "foo_ba");
+        assert_eq!(word_prefix("foo("), "");
+        assert_eq!(word_prefix("äöü"), "äöü");
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert!(fuzzy_score("gl", "goto_line").is_some());
+        assert!(fuzzy_score("lg", "goto_line").is_none());
+        assert!(fuzzy_score("got", "goto_line") > fuzzy_score("gtl", "goto_line"));
+        assert!(fuzzy_score("gl", "goto_line") > fuzzy_score("gl", "angle"));
+        assert!(fuzzy_score("ec", "EditCache") > fuzzy_score("ec", "electric"));
+    }
+
+    #[test]
+    fn ranking() {
+        let items = vec![
+            item("angle"),
+            item("goto_line"),
+            item("gl"),
+            item("goto_line"),
+            item("xyz"),
+        ];
+        assert_eq!(rank("gl", items), vec![item("goto_line"), item("angle")]);
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            keywords_from_regex("\\b(?:fn|let|mut)\\b"),
+            vec!["fn", "let", "mut"]
+        );
+        assert!(keywords_from_regex("[a-z]+").is_empty());
+    }
+}
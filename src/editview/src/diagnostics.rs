@@ -0,0 +1,209 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::text::{Position, Range};
+use cairo::Context;
+use std::f64::consts::PI;
+
+/// How far the wavy underline of a diagnostic goes up and down, in pixels
+const WAVE_HEIGHT: f64 = 2.0;
+
+/// How severe a `Diagnostic` is, the most severe one first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// The colour diagnostics of this severity are underlined with, as (r, g, b)
+    pub fn color(self) -> (f64, f64, f64) {
+        match self {
+            Severity::Error => (0.88, 0.11, 0.14),
+            Severity::Warning => (0.90, 0.60, 0.0),
+            Severity::Information => (0.21, 0.52, 0.89),
+            Severity::Hint => (0.55, 0.55, 0.55),
+        }
+    }
+}
+
+/// A problem in a document, e.g. an error a language server has found
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The part of the document it's about, with cols in bytes
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+    /// What has found the problem, e.g. "rustc"
+    pub source: Option<String>,
+}
+
+impl Diagnostic {
+    /// Whether the diagnostic is about the char at `position`
+    pub fn contains(&self, position: Position) -> bool {
+        let (start, end) = self.range;
+        start <= position && (position < end || (start == end && position == start))
+    }
+
+    /// The columns of `line` which are underlined, given that `text` is the line's text. Empty
+    /// ranges cover the char they start at, at the end of a line they stay empty.
+    pub fn columns(&self, line: u64, text: &str) -> Option<(u64, u64)> {
+        let ((start_line, start_col), (end_line, end_col)) = self.range;
+        if line < start_line || line > end_line {
+            return None;
+        }
+
+        let len = text.trim_end_matches(|c| c == '\n' || c == '\r').len() as u64;
+        let start = if line == start_line {
+            start_col.min(len)
+        } else {
+            0
+        };
+        let end = if line == end_line {
+            end_col.min(len)
+        } else {
+            len
+        };
+
+        if start < end {
+            Some((start, end))
+        } else if line == start_line {
+            let next = text
+                .get(start as usize..)
+                .and_then(|rest| rest.chars().next())
+                .filter(|c| *c != '\n' && *c != '\r')
+                .map(|c| start + c.len_utf8() as u64);
+            Some((start, next.unwrap_or(start)))
+        } else {
+            None
+        }
+    }
+
+    /// The text shown in the tooltip of the diagnostic
+    pub fn tooltip(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{}: {}", source, self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+/// Draw a wavy line from `x0` to `x1` whose lower edge is at `y`, with the current source colour
+pub fn draw_wave(cr: &Context, x0: f64, x1: f64, y: f64) {
+    cr.set_line_width(1.0);
+    cr.move_to(x0, y);
+    let mut x = x0;
+    let mut up = true;
+    while x < x1 {
+        x = (x + WAVE_HEIGHT).min(x1);
+        cr.line_to(x, if up { y - WAVE_HEIGHT } else { y });
+        up = !up;
+    }
+    cr.stroke();
+}
+
+/// Draw the icon of `severity` into the square with the top left corner at `x`|`y` and the side
+/// length `size`: a circle for errors, a triangle for warnings, a diamond for information and a
+/// ring for hints
+pub fn draw_icon(cr: &Context, severity: Severity, x: f64, y: f64, size: f64) {
+    let (r, g, b) = severity.color();
+    cr.set_source_rgb(r, g, b);
+    let center = (x + size / 2.0, y + size / 2.0);
+    match severity {
+        Severity::Error => {
+            cr.arc(center.0, center.1, size / 2.0, 0.0, 2.0 * PI);
+            cr.fill();
+        }
+        Severity::Warning => {
+            cr.move_to(center.0, y);
+            cr.line_to(x + size, y + size);
+            cr.line_to(x, y + size);
+            cr.close_path();
+            cr.fill();
+        }
+        Severity::Information => {
+            cr.move_to(center.0, y);
+            cr.line_to(x + size, center.1);
+            cr.line_to(center.0, y + size);
+            cr.line_to(x, center.1);
+            cr.close_path();
+            cr.fill();
+        }
+        Severity::Hint => {
+            cr.set_line_width(1.5);
+            cr.arc(center.0, center.1, size / 2.0 - 1.0, 0.0, 2.0 * PI);
+            cr.stroke();
+        }
+    }
+}
+
+/// How many diagnostics of each severity there are, as (errors, warnings, others)
+pub fn count(diagnostics: &[Diagnostic]) -> (usize, usize, usize) {
+    diagnostics
+        .iter()
+        .fold((0, 0, 0), |(errors, warnings, others), d| {
+            match d.severity {
+                Severity::Error => (errors + 1, warnings, others),
+                Severity::Warning => (errors, warnings + 1, others),
+                _ => (errors, warnings, others + 1),
+            }
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn diagnostic(range: Range) -> Diagnostic {
+        Diagnostic {
+            range,
+            severity: Severity::Error,
+            message: "mismatched types".to_string(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let d = diagnostic(((1, 4), (3, 2)));
+        assert_eq!(d.columns(0, "fn main() {\n"), None);
+        assert_eq!(d.columns(1, "let x = 1;\n"), Some((4, 10)));
+        assert_eq!(d.columns(2, "foo();\r\n"), Some((0, 6)));
+        assert_eq!(d.columns(3, "bar();\n"), Some((0, 2)));
+        assert_eq!(d.columns(4, "baz();\n"), None);
+    }
+
+    #[test]
+    fn empty_ranges() {
+        let d = diagnostic(((0, 4), (0, 4)));
+        assert_eq!(d.columns(0, "let äb = 1;\n"), Some((4, 6)));
+        assert!(d.contains((0, 4)));
+        assert!(!d.contains((0, 5)));
+
+        // At the end of the line there's no char to cover
+        assert_eq!(d.columns(0, "let\n"), Some((3, 3)));
+    }
+
+    #[test]
+    fn contains() {
+        let d = diagnostic(((1, 4), (2, 2)));
+        assert!(!d.contains((1, 3)));
+        assert!(d.contains((1, 4)));
+        assert!(d.contains((1, 100)));
+        assert!(d.contains((2, 1)));
+        assert!(!d.contains((2, 2)));
+    }
+
+    #[test]
+    fn counts() {
+        let mut warning = diagnostic(((0, 0), (0, 1)));
+        warning.severity = Severity::Warning;
+        let mut hint = warning.clone();
+        hint.severity = Severity::Hint;
+        let diagnostics = vec![diagnostic(((1, 0), (1, 2))), warning.clone(), warning, hint];
+        assert_eq!(count(&diagnostics), (1, 2, 1));
+        assert_eq!(count(&[]), (0, 0, 0));
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use std::iter;
+use std::ops::Range;
+
+/// If two texts differ in more than this many lines, we don't look for the shortest diff anymore
+/// (which gets expensive) and simply treat everything in between as changed.
+const MAX_EDIT_COST: usize = 4096;
+
+/// What happened to the lines of a `Hunk`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HunkKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A region in which two texts differ, given as the range of lines in the old and the new text
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl Hunk {
+    pub fn kind(&self) -> HunkKind {
+        if self.old.start == self.old.end {
+            HunkKind::Added
+        } else if self.new.start == self.new.end {
+            HunkKind::Removed
+        } else {
+            HunkKind::Changed
+        }
+    }
+}
+
+/// Split `text` into lines, keeping their line endings so joining them gives back `text`
+pub fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, _) in text.match_indices('\n') {
+        lines.push(&text[start..=i]);
+        start = i + 1;
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Compute the hunks in which the lines `new` differ from the lines `old`, in ascending order
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    let common = common_lines(old, new).unwrap_or_default();
+
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for (common_x, common_y) in common.into_iter().chain(iter::once((old.len(), new.len()))) {
+        if common_x > x || common_y > y {
+            hunks.push(Hunk {
+                old: prefix + x..prefix + common_x,
+                new: prefix + y..prefix + common_y,
+            });
+        }
+        x = common_x + 1;
+        y = common_y + 1;
+    }
+    hunks
+}
+
+/// Find the indices of the lines `a` and `b` have in common with Myers' diff algorithm. Returns
+/// `None` if they differ in more than `MAX_EDIT_COST` lines.
+fn common_lines<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<(usize, usize)>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+
+    // The furthest x reached on each diagonal k = x - y, offset by `max`
+    let mut v = vec![0_isize; 2 * max + 2];
+    let offset = max as isize;
+    // The relevant part of `v` before each round, needed to walk back the path we've taken
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDIT_COST {
+            return None;
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+
+        let mut k = -d;
+        while k <= d {
+            let ix = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[ix - 1] < v[ix + 1]) {
+                v[ix + 1]
+            } else {
+                v[ix - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[ix] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    let mut common = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { get(prev_k) };
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            common.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    common.reverse();
+    Some(common)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hunk(old: Range<usize>, new: Range<usize>) -> Hunk {
+        Hunk { old, new }
+    }
+
+    fn diff(old: &str, new: &str) -> Vec<Hunk> {
+        diff_lines(&split_lines(old), &split_lines(new))
+    }
+
+    #[test]
+    fn split_keeps_line_endings() {
+        assert_eq!(split_lines("a\nb\r\nc"), vec!["a\n", "b\r\n", "c"]);
+        assert_eq!(split_lines("a\n"), vec!["a\n"]);
+        assert!(split_lines("").is_empty());
+    }
+
+    #[test]
+    fn identical() {
+        assert!(diff("a\nb\nc\n", "a\nb\nc\n").is_empty());
+        assert!(diff("", "").is_empty());
+    }
+
+    #[test]
+    fn added() {
+        let hunks = diff("a\nc\n", "a\nb\nc\n");
+        assert_eq!(hunks, vec![hunk(1..1, 1..2)]);
+        assert_eq!(hunks[0].kind(), HunkKind::Added);
+        assert_eq!(diff("", "a\n"), vec![hunk(0..0, 0..1)]);
+    }
+
+    #[test]
+    fn removed() {
+        let hunks = diff("a\nb\nc\n", "a\nc\n");
+        assert_eq!(hunks, vec![hunk(1..2, 1..1)]);
+        assert_eq!(hunks[0].kind(), HunkKind::Removed);
+    }
+
+    #[test]
+    fn changed() {
+        let hunks = diff("a\nb\nc\n", "a\nx\ny\nc\n");
+        assert_eq!(hunks, vec![hunk(1..2, 1..3)]);
+        assert_eq!(hunks[0].kind(), HunkKind::Changed);
+    }
+
+    #[test]
+    fn several_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\n";
+        let new = "x\na\nc\nd\ny\nf\ng\n";
+        assert_eq!(
+            diff(old, new),
+            vec![
+                hunk(0..0, 0..1),
+                hunk(1..2, 2..2),
+                hunk(4..5, 4..5),
+                hunk(6..6, 6..7),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_newline_at_end() {
+        assert_eq!(diff("a\nb\n", "a\nb"), vec![hunk(1..2, 1..2)]);
+    }
+}
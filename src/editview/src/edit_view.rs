@@ -3,12 +3,25 @@
 // Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
 // SPDX-License-Identifier: MIT
 
+use crate::auto_pairs::{AutoPairs, CursorContext, InsertAction};
+use crate::comment::{self, CommentEdit, CommentTokens};
+use crate::completion::{self, CompletionItem, CompletionRequest};
+use crate::diagnostics::{self, Diagnostic};
+use crate::diff::{Hunk, HunkKind};
 use crate::draw_invisible;
+use crate::encoding::Encoding;
+use crate::file_watch::{self, DiskChange, DiskState, FileChangedAction, FileEvent};
 use crate::fonts::Font;
-use crate::i18n::ni18n_f;
+use crate::git_gutter::{self, BlameLine, GitDiff, GitMsg, GutterMarker};
+use crate::i18n::{freplace, ni18n_f};
+use crate::language_features::{FeatureMsg, LanguageFeatures};
+use crate::line_ending::{conversion_edits, LineEnding};
 use crate::main_state::{MainState, ShowInvisibles};
+use crate::selections::{self, SelectedLine};
+use crate::snippets::{self, Expansion, Snippet, SnippetSession};
+use crate::text::{merge_edits, shift_ranges, Position, Range};
 use crate::theme::{color_from_u32, set_margin_source_color, set_source_color, PangoColor};
-use crate::view_item::{FindReplace, TopBar, ViewItem};
+use crate::view_item::{FileChangedBar, FindReplace, TopBar, ViewItem};
 use cairo::Context;
 use crossbeam_channel::{unbounded, Sender};
 use gdk::{
@@ -17,22 +30,51 @@ use gdk::{
 };
 use gettextrs::gettext;
 use gio::prelude::*;
+use gio::{FileMonitor, FileMonitorEvent, FileMonitorFlags};
 use glib::{clone, source::Continue, MainContext, PRIORITY_HIGH};
 use gtk::prelude::*;
-use gtk::{ApplicationWindow, Clipboard, CssProvider, Grid, IMContextSimple, MenuButton, TreePath};
-use log::{debug, info, trace, warn};
+use gtk::{
+    ApplicationWindow, Clipboard, CssProvider, Grid, IMContextSimple, MenuButton, ResponseType,
+    Tooltip, TreePath,
+};
+use log::{debug, error, info, trace, warn};
 use pango::{Attribute, Direction, FontDescription, TabAlign, TabArray};
 use pangocairo::functions as pangocairofuncs;
 use parking_lot::Mutex;
+use serde_json::json;
 use std::cell::{Cell, RefCell};
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::SystemTime;
 use std::u32;
 use tau_linecache::{Line, LineCache};
 use tau_rpc::{Client, ConfigChanges, Query, Status, ThemeChanged, Update, ViewId};
 use unicode_segmentation::UnicodeSegmentation;
 
+/// How many chars of a word have to be typed before completions are offered
+const MIN_PREFIX_LEN: usize = 2;
+
+/// How long to wait for xi to process typed chars before refreshing the completions
+const COMPLETION_DELAY_MS: u32 = 100;
+
+/// How often to check if xi has sent all lines of the document after requesting them
+const TEXT_POLL_INTERVAL_MS: u32 = 20;
+
+/// How often to check for the lines of the document before giving up
+const TEXT_POLL_ATTEMPTS: u32 = 250;
+
+/// How wide the git markers at the right edge of the linecount are
+const GIT_MARKER_WIDTH: f64 = 3.0;
+
+/// How far the diagnostic icons are from the left edge of the linecount
+const DIAGNOSTIC_ICON_MARGIN: f64 = 2.0;
+
+/// How many chars of space there are between the end of a line and its blame annotation
+const BLAME_MARGIN_CHARS: f64 = 4.0;
+
 /// Returned by `EditView::get_text_size()` and used to adjust the scrollbars.
 pub struct TextSize {
     /// The height of the entire document
@@ -66,6 +108,84 @@ pub struct EditView {
     pub(crate) tab_size: RefCell<Option<u32>>,
     style_provider: CssProvider,
     in_multicursor_edit: Cell<bool>,
+    /// The cursor positions before each multicursor addition, used to undo them again
+    cursor_history: RefCell<Vec<Vec<(u64, u64)>>>,
+    /// The brackets and quotes which are closed automatically, `None` if disabled for this syntax
+    auto_pairs: RefCell<Option<AutoPairs>>,
+    /// The syntax xi is currently using for this view, as reported in `language_changed`
+    language: RefCell<String>,
+    /// The syntax the user has chosen for this view, if it's not the detected one
+    language_override: RefCell<Option<String>>,
+    /// The encoding of the file, which xi works on decoded to UTF-8
+    encoding: Cell<Encoding>,
+    /// The line ending xi uses for the document, as reported in `config_changed`
+    line_ending: Cell<LineEnding>,
+    /// Whether the file contained other line endings too when it was opened
+    mixed_line_endings: Cell<bool>,
+    /// The line which should be scrolled to the top once the document has been loaded
+    pending_scroll: Cell<Option<f64>>,
+    /// The snippets available for the current syntax
+    snippets: RefCell<Vec<Snippet>>,
+    /// The tab stops of the snippet the user is currently filling in
+    snippet_session: RefCell<Option<SnippetSession>>,
+    /// The word prefix the completion popover is currently offering completions for
+    completion_range: Cell<Option<Range>>,
+    /// The pending refresh of the completion popover
+    completion_timeout: RefCell<Option<glib::SourceId>>,
+    /// Watches `file_name` for changes made by other programs
+    file_monitor: RefCell<Option<FileMonitor>>,
+    file_event_sender: glib::Sender<FileEvent>,
+    /// What happened to `file_name` on disk
+    disk_state: RefCell<DiskState>,
+    /// The modification time of `file_name` when we've last loaded or saved it
+    disk_mtime: Cell<Option<SystemTime>>,
+    /// Set when the user saves, so we don't mistake the resulting write for someone else's
+    saving: Cell<bool>,
+    /// Called once the save the user has started has been written to disk
+    saved_handlers: RefCell<Vec<Box<dyn FnOnce()>>>,
+    /// Gives up on the save the user has started if it doesn't show up on disk
+    save_timeout: RefCell<Option<glib::SourceId>>,
+    /// The pending look at the file on disk after the `FileMonitor` has noticed changes
+    file_check_timeout: RefCell<Option<glib::SourceId>>,
+    /// Called when the user wants to reload or compare the file after it's been changed on disk
+    file_changed_handler: RefCell<Option<Rc<dyn Fn(FileChangedAction)>>>,
+    /// How the buffer differs from the version of `file_name` in HEAD, if it's in a git repository
+    git_diff: RefCell<Option<GitDiff>>,
+    git_sender: glib::Sender<GitMsg>,
+    /// Incremented for every comparison with HEAD, so results of outdated ones are dropped
+    git_generation: Cell<u64>,
+    /// The pending comparison with HEAD after the buffer has changed
+    git_timeout: RefCell<Option<glib::SourceId>>,
+    /// The text we've last compared with HEAD
+    git_text: RefCell<Option<String>>,
+    /// Set when the file or HEAD have changed, so the file on disk has to be compared with HEAD
+    /// again if the document is too big to compare its buffer, see `GIT_LIVE_REFRESH_MAX_LINES`
+    git_stale: Cell<bool>,
+    /// The hunk the `git_hunk` popover is currently shown for
+    git_hunk: RefCell<Option<Hunk>>,
+    /// The pending hiding of the `git_hunk` popover after the pointer has left it
+    git_hunk_timeout: RefCell<Option<glib::SourceId>>,
+    /// Whether who has last changed each line is shown next to it
+    blame_visible: Cell<bool>,
+    /// Who has last changed each line of HEAD
+    blame: RefCell<Option<Vec<BlameLine>>>,
+    /// The problems each producer has found in the document, e.g. a language server or a linter
+    diagnostic_sources: RefCell<BTreeMap<String, Vec<Diagnostic>>>,
+    /// The problems of all producers, the most severe ones first
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    /// Called when the problems in the document have changed
+    diagnostics_changed_handler: RefCell<Option<Rc<dyn Fn()>>>,
+    /// Offers hover texts and completions for the current syntax, e.g. a language server
+    language_features: RefCell<Option<Rc<dyn LanguageFeatures>>>,
+    feature_sender: glib::Sender<FeatureMsg>,
+    /// The position we've last asked `language_features` for a hover text
+    hover_position: Cell<Option<Position>>,
+    /// The hover text for `hover_position`, once it has arrived
+    hover: RefCell<Option<String>>,
+    /// The start of the word we've last asked `language_features` for completions
+    feature_completion_start: Cell<Option<Position>>,
+    /// The completions for the word at `feature_completion_start`, once they have arrived
+    feature_completions: RefCell<Vec<CompletionItem>>,
 }
 
 impl EditView {
@@ -98,6 +218,9 @@ impl EditView {
         }
 
         let (update_sender, update_recv) = unbounded();
+        let (file_event_sender, file_event_recv) = MainContext::channel(glib::PRIORITY_DEFAULT);
+        let (git_sender, git_recv) = MainContext::channel(glib::PRIORITY_DEFAULT);
+        let (feature_sender, feature_recv) = MainContext::channel(glib::PRIORITY_DEFAULT);
 
         let edit_view = Rc::new(Self {
             core,
@@ -121,8 +244,53 @@ impl EditView {
             tab_size: RefCell::new(None),
             style_provider: CssProvider::new(),
             in_multicursor_edit: Cell::new(false),
+            cursor_history: RefCell::new(Vec::new()),
+            auto_pairs: RefCell::new(Some(AutoPairs::default())),
+            language: RefCell::new(String::new()),
+            language_override: RefCell::new(None),
+            encoding: Cell::new(Encoding::Utf8),
+            line_ending: Cell::new(LineEnding::default()),
+            mixed_line_endings: Cell::new(false),
+            pending_scroll: Cell::new(None),
+            snippets: RefCell::new(Vec::new()),
+            snippet_session: RefCell::new(None),
+            completion_range: Cell::new(None),
+            completion_timeout: RefCell::new(None),
+            file_monitor: RefCell::new(None),
+            file_event_sender,
+            disk_state: RefCell::new(DiskState::Present),
+            disk_mtime: Cell::new(None),
+            saving: Cell::new(false),
+            saved_handlers: RefCell::new(Vec::new()),
+            save_timeout: RefCell::new(None),
+            file_check_timeout: RefCell::new(None),
+            file_changed_handler: RefCell::new(None),
+            git_diff: RefCell::new(None),
+            git_sender,
+            git_generation: Cell::new(0),
+            git_timeout: RefCell::new(None),
+            git_text: RefCell::new(None),
+            git_stale: Cell::new(false),
+            git_hunk: RefCell::new(None),
+            git_hunk_timeout: RefCell::new(None),
+            blame_visible: Cell::new(false),
+            blame: RefCell::new(None),
+            diagnostic_sources: RefCell::new(BTreeMap::new()),
+            diagnostics: RefCell::new(Vec::new()),
+            diagnostics_changed_handler: RefCell::new(None),
+            language_features: RefCell::new(None),
+            feature_sender,
+            hover_position: Cell::new(None),
+            hover: RefCell::new(None),
+            feature_completion_start: Cell::new(None),
+            feature_completions: RefCell::new(Vec::new()),
         });
 
+        main_state
+            .borrow()
+            .completion
+            .register_document(&edit_view.line_cache);
+
         view_item
             .edit_area
             .get_style_context()
@@ -130,6 +298,71 @@ impl EditView {
 
         edit_view.update_title();
 
+        file_event_recv.attach(
+            None,
+            clone!(@weak edit_view => @default-return Continue(false), move |event| {
+                if let FileEvent::Renamed(new_name) = event {
+                    edit_view.disk_state.replace(DiskState::Renamed(new_name));
+                    edit_view.update_title();
+                }
+                edit_view.schedule_file_check();
+                Continue(true)
+            }),
+        );
+        edit_view.watch_file();
+
+        git_recv.attach(
+            None,
+            clone!(@weak edit_view => @default-return Continue(false), move |msg| {
+                match msg {
+                    GitMsg::Refresh(force) => edit_view.schedule_git_refresh(force),
+                    GitMsg::Diff(generation, diff) => {
+                        if generation == edit_view.git_generation.get() {
+                            edit_view.git_diff.replace(diff);
+                            edit_view.view_item.linecount.queue_draw();
+                            if edit_view.blame_visible.get() {
+                                edit_view.view_item.edit_area.queue_draw();
+                            }
+                        }
+                    }
+                    GitMsg::Blame(blame) => {
+                        if edit_view.blame_visible.get() {
+                            edit_view.blame.replace(blame);
+                            edit_view.view_item.edit_area.queue_draw();
+                        }
+                    }
+                }
+                Continue(true)
+            }),
+        );
+
+        feature_recv.attach(
+            None,
+            clone!(@weak edit_view => @default-return Continue(false), move |msg| {
+                match msg {
+                    FeatureMsg::Hover(position, text) => {
+                        if edit_view.hover_position.get() == Some(position) {
+                            edit_view.hover.replace(text);
+                            edit_view.view_item.edit_area.trigger_tooltip_query();
+                        }
+                    }
+                    FeatureMsg::Completions(start, items) => {
+                        if edit_view.feature_completion_start.get() == Some(start) {
+                            edit_view.feature_completions.replace(items);
+                            let still_there = edit_view
+                                .completion_prefix()
+                                .map(|(word_start, _, _)| word_start == start)
+                                .unwrap_or(false);
+                            if still_there {
+                                edit_view.refresh_completion(true);
+                            }
+                        }
+                    }
+                }
+                Continue(true)
+            }),
+        );
+
         view_item.connect_events(&edit_view);
         find_replace.connect_events(&edit_view);
         EditView::connect_im_events(&edit_view, &im_context);
@@ -170,7 +403,16 @@ impl EditView {
 
     fn connect_im_events(edit_view: &Rc<EditView>, im_context: &IMContextSimple) {
         im_context.connect_commit(clone!(@weak edit_view => @default-panic, move |_, text| {
-            edit_view.core.insert(edit_view.view_id, text);
+            edit_view.insert_chars(text);
+            let triggers = match (edit_view.language_features.borrow().as_ref(), text.chars().last()) {
+                (Some(features), Some(c)) => features.triggers_completion(c),
+                _ => false,
+            };
+            if triggers || text.chars().all(completion::is_word_char) {
+                edit_view.schedule_completion();
+            } else {
+                edit_view.hide_completion();
+            }
         }));
     }
 }
@@ -183,10 +425,306 @@ impl EditView {
             self.view_id,
             file_name
         );
+        let renamed = self.file_name.borrow().as_deref() != Some(file_name);
         self.file_name.replace(Some(file_name.to_string()));
+        if renamed {
+            self.watch_file();
+            self.refresh_git_diff();
+        }
         self.update_title();
     }
 
+    /// Start watching `file_name` for changes by other programs, e.g. `git checkout` or a formatter
+    fn watch_file(&self) {
+        if let Some(monitor) = self.file_monitor.borrow_mut().take() {
+            monitor.cancel();
+        }
+        self.disk_state.replace(DiskState::Present);
+        self.view_item.file_changed.info_bar.hide();
+
+        let file_name = match self.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        self.disk_mtime
+            .set(file_watch::modified_time(Path::new(&file_name)));
+
+        let monitor = match gio::File::new_for_path(&file_name)
+            .monitor_file(FileMonitorFlags::WATCH_MOVES, None::<&gio::Cancellable>)
+        {
+            Ok(monitor) => monitor,
+            Err(e) => {
+                warn!("Failed to watch file '{}' due to error: {}", file_name, e);
+                return;
+            }
+        };
+
+        let sender = self.file_event_sender.clone();
+        monitor.connect_changed(move |_, _, other_file, event| {
+            let event = match event {
+                FileMonitorEvent::Renamed | FileMonitorEvent::MovedOut => other_file
+                    .and_then(|f| f.get_path())
+                    .map(|p| FileEvent::Renamed(p.to_string_lossy().into_owned()))
+                    .unwrap_or(FileEvent::Changed),
+                FileMonitorEvent::Changed
+                | FileMonitorEvent::ChangesDoneHint
+                | FileMonitorEvent::Created
+                | FileMonitorEvent::Deleted
+                | FileMonitorEvent::MovedIn => FileEvent::Changed,
+                _ => return,
+            };
+            sender.send(event).ok();
+        });
+        self.file_monitor.replace(Some(monitor));
+    }
+
+    /// Look at `file_name` on disk after the `FileMonitor` has noticed changes. Reloads the file if
+    /// there are no unsaved changes, otherwise asks the user what to do.
+    fn check_file(&self) {
+        let file_name = match self.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        let mtime = file_watch::modified_time(Path::new(&file_name));
+
+        match file_watch::disk_change(self.disk_mtime.get(), mtime, self.saving.get()) {
+            DiskChange::Missing => {
+                if *self.disk_state.borrow() == DiskState::Present {
+                    debug!("File '{}' has been deleted", file_name);
+                    self.disk_state.replace(DiskState::Deleted);
+                    self.update_title();
+                }
+                return;
+            }
+            DiskChange::None => {}
+            DiskChange::Saved => {
+                self.saving.set(false);
+                if let Some(id) = self.save_timeout.borrow_mut().take() {
+                    glib::source_remove(id);
+                }
+                self.refresh_git_diff();
+                let handlers: Vec<_> = self.saved_handlers.borrow_mut().drain(..).collect();
+                for handler in handlers {
+                    handler();
+                }
+            }
+            DiskChange::Modified => {
+                if *self.pristine.borrow() {
+                    info!("File '{}' has been changed on disk, reloading", file_name);
+                    self.emit_file_changed(FileChangedAction::Reload);
+                } else {
+                    debug!("File '{}' has been changed on disk", file_name);
+                    self.view_item.file_changed.info_bar.show();
+                }
+            }
+        }
+
+        self.disk_mtime.set(mtime);
+        if *self.disk_state.borrow() != DiskState::Present {
+            self.disk_state.replace(DiskState::Present);
+            self.update_title();
+        }
+    }
+
+    /// Forget about the save announced via `expect_save` because it has failed, so the next write
+    /// is taken for a change by another program again
+    pub fn cancel_save(&self) {
+        if let Some(id) = self.save_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        self.saving.set(false);
+        self.saved_handlers.borrow_mut().clear();
+    }
+
+    /// Call `f` once the save which has been announced via `expect_save` has been written to disk
+    pub fn connect_saved_once<F: FnOnce() + 'static>(&self, f: F) {
+        self.saved_handlers.borrow_mut().push(Box::new(f));
+    }
+
+    /// What happened to the file of this EditView on disk
+    pub fn disk_state(&self) -> DiskState {
+        self.disk_state.borrow().clone()
+    }
+
+    /// Set the function which is called when the file should be reloaded or compared with the
+    /// version on disk after it's been changed by another program.
+    pub fn connect_file_changed<F: Fn(FileChangedAction) + 'static>(&self, f: F) {
+        self.file_changed_handler.replace(Some(Rc::new(f)));
+    }
+
+    fn emit_file_changed(&self, action: FileChangedAction) {
+        // Clone the handler, reloading might drop this EditView
+        let handler = self.file_changed_handler.borrow().clone();
+        if let Some(handler) = handler {
+            handler(action);
+        }
+    }
+
+    pub(crate) fn handle_file_changed_response(&self, response: ResponseType) {
+        match response {
+            FileChangedBar::RELOAD => {
+                self.view_item.file_changed.info_bar.hide();
+                self.emit_file_changed(FileChangedAction::Reload);
+            }
+            FileChangedBar::COMPARE => self.emit_file_changed(FileChangedAction::Compare),
+            // We've already remembered the new modification time, so saving will simply overwrite it
+            _ => self.view_item.file_changed.info_bar.hide(),
+        }
+    }
+
+    /// Compare the buffer with the version of its file in HEAD again, e.g. after a commit
+    pub fn refresh_git_diff(&self) {
+        self.git_sender.send(GitMsg::Refresh(true)).unwrap();
+        self.refresh_blame();
+    }
+
+    /// Show who has last changed each line, and when, next to it
+    pub fn set_blame_visible(&self, visible: bool) {
+        self.blame_visible.set(visible);
+        if visible {
+            self.refresh_blame();
+        } else {
+            self.blame.replace(None);
+            self.view_item.edit_area.queue_draw();
+        }
+    }
+
+    pub fn blame_visible(&self) -> bool {
+        self.blame_visible.get()
+    }
+
+    /// Find out who has last changed each line of the file in a separate thread
+    fn refresh_blame(&self) {
+        if !self.blame_visible.get() {
+            return;
+        }
+        let file_name = match self.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+
+        let sender = self.git_sender.clone();
+        std::thread::spawn(move || {
+            let blame = git_gutter::blame(Path::new(&file_name)).unwrap_or_else(|e| {
+                warn!("Failed to blame file '{}' due to error: {}", file_name, e);
+                None
+            });
+            sender.send(GitMsg::Blame(blame)).ok();
+        });
+    }
+
+    /// The annotation shown next to `line` if blame annotations are enabled
+    fn blame_annotation(&self, line: usize) -> Option<String> {
+        let blame = self.blame.borrow();
+        let blame = blame.as_ref()?;
+        let head_line = match *self.git_diff.borrow() {
+            Some(ref diff) => diff.head_line(line),
+            None => Some(line),
+        };
+
+        Some(match head_line.and_then(|l| blame.get(l)) {
+            Some(blame_line) => {
+                let date = glib::DateTime::new_from_unix_local(blame_line.time)
+                    .format("%x")
+                    .map(|d| d.to_string())
+                    .unwrap_or_default();
+                format!("{}, {} • {}", blame_line.author, date, blame_line.summary)
+            }
+            None => gettext("Not committed yet"),
+        })
+    }
+
+    /// Returns the (logical) line shown in the row of the linecount at `y` pixels
+    fn line_at_linecount_px(&self, y: f64) -> Option<usize> {
+        let row =
+            ((y + self.view_item.vadj.get_value()) / self.edit_font.borrow().font_height) as u64;
+        let line_cache = self.line_cache.lock();
+        if row >= line_cache.height() {
+            return None;
+        }
+        // Wrapped lines only have a line number in their first row
+        (0..=row)
+            .rev()
+            .find_map(|n| line_cache.get_line(n).and_then(|l| l.line_num))
+            .map(|ln| ln as usize - 1)
+    }
+
+    /// Returns the row of the line cache (logical) `line` starts in
+    fn line_to_row(&self, line: usize) -> u64 {
+        let line_cache = self.line_cache.lock();
+        (0..line_cache.height())
+            .find(|n| line_cache.get_line(*n).and_then(|l| l.line_num) == Some(line as u64 + 1))
+            .unwrap_or(line as u64)
+    }
+
+    /// Show the committed version of `hunk` next to the row of the linecount at `row_y` pixels
+    fn show_hunk_popover(&self, hunk: Hunk, row_y: f64) {
+        let head_text = match *self.git_diff.borrow() {
+            Some(ref diff) => diff.head_text(&hunk),
+            None => return,
+        };
+        let popup = &self.view_item.git_hunk;
+        if head_text.is_empty() {
+            popup.label.set_markup(&format!(
+                "<i>{}</i>",
+                glib::markup_escape_text(&gettext("These lines have been added"))
+            ));
+        } else {
+            popup.label.set_markup(&format!(
+                "<tt>{}</tt>",
+                glib::markup_escape_text(head_text.trim_end_matches(|c| c == '\n' || c == '\r'))
+            ));
+        }
+
+        let font_height = self.edit_font.borrow().font_height;
+        let linecount_width = self.view_item.linecount.get_allocated_width();
+        popup.popover.set_pointing_to(&gdk::Rectangle {
+            x: linecount_width - GIT_MARKER_WIDTH as i32,
+            y: row_y as i32,
+            width: GIT_MARKER_WIDTH as i32,
+            height: font_height as i32,
+        });
+        popup.popover.popup();
+        self.git_hunk.replace(Some(hunk));
+    }
+
+    pub(crate) fn cancel_hunk_popover_hide(&self) {
+        if let Some(id) = self.git_hunk_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+    }
+
+    fn hide_hunk_popover(&self) {
+        self.cancel_hunk_popover_hide();
+        self.view_item.git_hunk.popover.popdown();
+        self.git_hunk.replace(None);
+    }
+
+    /// Replace the lines of the hunk the popover is shown for with their committed version
+    pub(crate) fn revert_hunk(&self) {
+        let hunk = match self.git_hunk.borrow().clone() {
+            Some(hunk) => hunk,
+            None => return,
+        };
+        let head_text = match *self.git_diff.borrow() {
+            Some(ref diff) => diff.head_text(&hunk),
+            None => return,
+        };
+        self.hide_hunk_popover();
+
+        self.core
+            .gesture_point_select(self.view_id, self.line_to_row(hunk.new.start), 0);
+        if hunk.kind() != HunkKind::Removed {
+            self.core
+                .gesture_range_select(self.view_id, self.line_to_row(hunk.new.end), 0);
+        }
+        if head_text.is_empty() {
+            self.core.delete_backward(self.view_id);
+        } else {
+            self.core.insert(self.view_id, &head_text);
+        }
+    }
+
     /// Update the title of the EditView to the currently set file_name
     fn update_title(&self) {
         let title = match *self.file_name.borrow() {
@@ -215,6 +753,18 @@ impl EditView {
             });
         }
 
+        let mut tooltip = self.file_name.borrow().clone();
+        match *self.disk_state.borrow() {
+            DiskState::Present => {}
+            DiskState::Deleted => {
+                full_title = freplace(gettext("{} (deleted)"), &[&full_title]);
+            }
+            DiskState::Renamed(ref new_name) => {
+                full_title = freplace(gettext("{} (renamed)"), &[&full_title]);
+                tooltip = Some(freplace(gettext("Moved to {}"), &[new_name]));
+            }
+        }
+
         trace!(
             "Setting title for EditView '{}': {}",
             self.view_id,
@@ -223,7 +773,7 @@ impl EditView {
         self.top_bar.label.set_text(&full_title);
         self.top_bar
             .tab_widget
-            .set_tooltip_text(self.file_name.borrow().as_ref().map(|x| x.as_ref()));
+            .set_tooltip_text(tooltip.as_ref().map(|x| x.as_ref()));
     }
 
     /// If xi-editor sends us a [theme_changed](https://xi-editor.io/docs/frontend-protocol.html#theme_changed)
@@ -283,6 +833,16 @@ impl EditView {
             ));
             self.view_item.edit_area.queue_draw();
         }
+
+        if let Some(line_ending) = &changes.line_ending {
+            match LineEnding::parse(line_ending) {
+                Some(line_ending) => {
+                    self.line_ending.set(line_ending);
+                    self.update_line_ending();
+                }
+                None => warn!("Unknown line ending {:?}", line_ending),
+            }
+        }
     }
 
     /// If xi-editor sends us a [update](https://xi-editor.io/docs/frontend-protocol.html#config_changed)
@@ -299,6 +859,7 @@ impl EditView {
         self.update_title();
 
         self.update_sender.send(params).unwrap();
+        self.git_sender.send(GitMsg::Refresh(false)).unwrap();
 
         // update scrollbars to the new text width and height
         let text_size = self.get_text_size();
@@ -313,6 +874,14 @@ impl EditView {
             .edit_area
             .set_size(text_width as u32, text_height as u32);
 
+        if !self.is_empty() {
+            if let Some(first_line) = self.pending_scroll.take() {
+                self.view_item
+                    .vadj
+                    .set_value(first_line * self.edit_font.borrow().font_height);
+            }
+        }
+
         self.view_item.edit_area.queue_draw();
         self.view_item.linecount.queue_draw();
     }
@@ -361,6 +930,24 @@ impl EditView {
         (index, (y / self.edit_font.borrow().font_height) as u64)
     }
 
+    /// Maps the line num and col to the x|y pixel coordinates of the top left corner of that cell,
+    /// relative to the visible area. This is the inverse of `da_px_to_cell`.
+    pub fn cell_to_da_px(&self, line: u64, col: u64) -> (f64, f64) {
+        let x = if let Some(l) = self.line_cache.lock().get_line(line) {
+            let pango_ctx = self.view_item.get_pango_ctx();
+            let layout = self.create_layout_for_line(&pango_ctx, l, &self.get_tabs());
+            f64::from(layout.index_to_pos(col as i32).x / pango::SCALE)
+        } else {
+            0.0
+        };
+        let y = self.edit_font.borrow().font_height * line as f64;
+
+        (
+            x - self.view_item.hadj.get_value(),
+            y - self.view_item.vadj.get_value(),
+        )
+    }
+
     /// Allocate the space our DrawingArea needs.
     pub(crate) fn da_size_allocate(&self, da_width: i32, da_height: i32) {
         debug!(
@@ -551,6 +1138,28 @@ impl EditView {
                 pangocairofuncs::update_layout(cr, &layout);
                 pangocairofuncs::show_layout(cr, &layout);
 
+                self.draw_diagnostics(
+                    cr,
+                    &layout,
+                    i,
+                    &line.text,
+                    self.edit_font.borrow().font_height * ((i + 1) as f64) - vadj.get_value(),
+                );
+                set_source_color(cr, theme.foreground);
+
+                if let Some(annotation) = line
+                    .line_num
+                    .and_then(|ln| self.blame_annotation(ln as usize - 1))
+                {
+                    self.draw_blame_annotation(
+                        cr,
+                        &pango_ctx,
+                        &annotation,
+                        f64::from(layout.get_pixel_extents().1.width) - hadj.get_value(),
+                        self.edit_font.borrow().font_height * (i as f64) - vadj.get_value(),
+                    );
+                }
+
                 // make invisibles more transparent and less distractive
                 match theme.foreground {
                     Some(mut color) => {
@@ -737,17 +1346,38 @@ impl EditView {
         let center_diff =
             (self.edit_font.borrow().font_height - self.interface_font.font_height) / 2.0;
 
-        set_source_color(cr, theme.foreground);
+        let git_diff = self.git_diff.borrow();
         for i in first_line..last_line {
             // Keep track of the starting x position
             if let Some(line) = self.line_cache.lock().get_line(i) {
+                let first_visual_line = line.line_num.is_some();
                 if let Some(ln) = line.line_num {
                     if let Some(ref mut cl) = current_line {
                         *cl += 1;
                     } else {
                         current_line = Some(ln)
                     }
+                }
+
+                if let (Some(diff), Some(cl)) = (git_diff.as_ref(), current_line) {
+                    self.draw_git_marker(
+                        cr,
+                        diff,
+                        cl as usize - 1,
+                        linecount_width,
+                        self.edit_font.borrow().font_height * (i as f64) - vadj.get_value(),
+                        first_visual_line,
+                    );
+                }
+
+                self.draw_diagnostic_icon(
+                    cr,
+                    i,
+                    self.edit_font.borrow().font_height * (i as f64) - vadj.get_value(),
+                );
 
+                if first_visual_line {
+                    set_source_color(cr, theme.foreground);
                     cr.move_to(
                         0.0,
                         self.edit_font.borrow().font_height * (i as f64) - vadj.get_value()
@@ -772,6 +1402,113 @@ impl EditView {
         Inhibit(false)
     }
 
+    /// Draw the icon of the most severe diagnostic starting in `row` into the left of the gutter,
+    /// whose top is at `y`
+    fn draw_diagnostic_icon(&self, cr: &Context, row: u64, y: f64) {
+        let diagnostics = self.diagnostics.borrow();
+        // They're sorted by severity, so the first one is the most severe
+        if let Some(diagnostic) = diagnostics.iter().find(|d| (d.range.0).0 == row) {
+            let font_height = self.edit_font.borrow().font_height;
+            let size = (font_height * 0.6).min(self.interface_font.font_width * 1.5);
+            diagnostics::draw_icon(
+                cr,
+                diagnostic.severity,
+                DIAGNOSTIC_ICON_MARGIN,
+                y + (font_height - size) / 2.0,
+                size,
+            );
+        }
+    }
+
+    /// Underline the diagnostics in `row`, whose text is `text` and whose bottom is at `y`
+    fn draw_diagnostics(&self, cr: &Context, layout: &pango::Layout, row: u64, text: &str, y: f64) {
+        let hadj = self.view_item.hadj.get_value();
+        let char_width = self.edit_font.borrow().font_width;
+        // Draw the less severe ones first, so the most severe one ends up on top
+        for diagnostic in self.diagnostics.borrow().iter().rev() {
+            if let Some((start, end)) = diagnostic.columns(row, text) {
+                let x0 = f64::from(layout.index_to_pos(start as i32).x) / f64::from(pango::SCALE);
+                let x1 = if start == end {
+                    // Make diagnostics at the end of the line visible
+                    x0 + char_width
+                } else {
+                    f64::from(layout.index_to_pos(end as i32).x) / f64::from(pango::SCALE)
+                };
+                let (r, g, b) = diagnostic.severity.color();
+                cr.set_source_rgb(r, g, b);
+                diagnostics::draw_wave(cr, x0 - hadj, x1 - hadj, y - 1.0);
+            }
+        }
+    }
+
+    /// Draw the blame `annotation` of a line whose text ends at `x`, in the row at `y`
+    fn draw_blame_annotation(
+        &self,
+        cr: &Context,
+        pango_ctx: &pango::Context,
+        annotation: &str,
+        x: f64,
+        y: f64,
+    ) {
+        let theme = &self.main_state.borrow().theme;
+        match theme.foreground {
+            Some(mut color) => {
+                color.a /= 2u8;
+                set_source_color(cr, Some(color));
+            }
+            None => set_source_color(cr, theme.caret),
+        }
+
+        let layout = pango::Layout::new(pango_ctx);
+        layout.set_font_description(Some(&self.interface_font.font_desc));
+        layout.set_text(annotation);
+        let center_diff =
+            (self.edit_font.borrow().font_height - self.interface_font.font_height) / 2.0;
+        cr.move_to(
+            x + self.edit_font.borrow().font_width * BLAME_MARGIN_CHARS,
+            y + center_diff,
+        );
+        pangocairofuncs::update_layout(cr, &layout);
+        pangocairofuncs::show_layout(cr, &layout);
+    }
+
+    /// Draw the marker of `line` of `diff` at the right edge of the linecount. `y` is the top of
+    /// the row we're drawing, which is the first one of `line` if `first_visual_line` is true.
+    fn draw_git_marker(
+        &self,
+        cr: &Context,
+        diff: &GitDiff,
+        line: usize,
+        linecount_width: f64,
+        y: f64,
+        first_visual_line: bool,
+    ) {
+        let font_height = self.edit_font.borrow().font_height;
+        let x = linecount_width - GIT_MARKER_WIDTH;
+        match diff.marker(line) {
+            Some(GutterMarker::Added) => {
+                cr.set_source_rgb(46.0 / 255.0, 194.0 / 255.0, 126.0 / 255.0);
+                cr.rectangle(x, y, GIT_MARKER_WIDTH, font_height);
+            }
+            Some(GutterMarker::Modified) => {
+                cr.set_source_rgb(53.0 / 255.0, 132.0 / 255.0, 228.0 / 255.0);
+                cr.rectangle(x, y, GIT_MARKER_WIDTH, font_height);
+            }
+            // Deleted lines don't take up any rows, so point at the gap they've left instead
+            Some(GutterMarker::Deleted) if first_visual_line => {
+                let deleted_after = diff.hunk_at(line).map_or(false, |h| h.new.start > line);
+                let gap = if deleted_after { y + font_height } else { y };
+                cr.set_source_rgb(224.0 / 255.0, 27.0 / 255.0, 36.0 / 255.0);
+                cr.move_to(x - GIT_MARKER_WIDTH, gap - GIT_MARKER_WIDTH);
+                cr.line_to(linecount_width, gap);
+                cr.line_to(x - GIT_MARKER_WIDTH, gap + GIT_MARKER_WIDTH);
+                cr.close_path();
+            }
+            _ => return,
+        }
+        cr.fill();
+    }
+
     /// Creates a pango layout for a particular linecount (the count on the left) in the linecache
     fn create_layout_for_linecount(
         &self,
@@ -957,6 +1694,11 @@ impl EditView {
             self.in_multicursor_edit.set(false);
             return;
         }
+        // The scroll position of the view is about to be restored, which takes precedence
+        if self.pending_scroll.get().is_some() {
+            trace!("Aborting scrolling since the scroll position is about to be restored");
+            return;
+        }
 
         // xi initially sends a 'scroll_to' with line == 0 when the linecache doesn't have lines in
         // it yet, so the below function would keep going forever. Make sure line isn't 0 so it's actually
@@ -1106,6 +1848,9 @@ impl EditView {
                     self.core.gesture_line_select(self.view_id, line, col);
                     self.do_copy_primary();
                 } else {
+                    self.cursor_history.borrow_mut().clear();
+                    self.snippet_session.replace(None);
+                    self.hide_completion();
                     self.core.gesture_point_select(self.view_id, line, col);
                 }
             }
@@ -1152,6 +1897,11 @@ impl EditView {
         let meta = ek.get_state().contains(ModifierType::META_MASK);
         let shift = ek.get_state().contains(ModifierType::SHIFT_MASK);
         let norm = !alt && !ctrl && !meta;
+
+        if let Some(inhibit) = self.handle_completion_key(ek) {
+            return inhibit;
+        }
+
         match ek.get_keyval() {
             key::Delete if !shift => {
                 self.core.delete_forward(view_id);
@@ -1166,7 +1916,7 @@ impl EditView {
                 self.do_paste();
             }
             key::BackSpace if norm => {
-                self.core.delete_backward(view_id);
+                self.delete_backward();
             }
             key::BackSpace if ctrl => {
                 self.core.delete_word_backward(view_id);
@@ -1175,10 +1925,14 @@ impl EditView {
                 self.core.insert_newline(view_id);
             }
             key::Tab if norm && !shift => {
-                self.core.insert_tab(view_id);
+                if !self.next_tab_stop() && !self.expand_snippet_trigger() {
+                    self.core.insert_tab(view_id);
+                }
             }
             key::Tab | key::ISO_Left_Tab if norm && shift => {
-                self.core.outdent(view_id);
+                if !self.previous_tab_stop() {
+                    self.core.outdent(view_id);
+                }
             }
             key::Up | key::KP_Up if norm && !shift => {
                 self.core.up(view_id);
@@ -1265,6 +2019,8 @@ impl EditView {
                 self.do_copy_primary();
             }
             key::Escape => {
+                self.cursor_history.borrow_mut().clear();
+                self.snippet_session.replace(None);
                 self.stop_search();
             }
             key::F7 => {
@@ -1272,7 +2028,10 @@ impl EditView {
                 let draw_cursor = main_state.settings.draw_cursor;
                 main_state.settings.draw_cursor = !draw_cursor;
             }
-            key::a | key::backslash | key::slash if ctrl => {
+            key::space if ctrl => {
+                self.refresh_completion(true);
+            }
+            key::a if ctrl => {
                 self.core.select_all(view_id);
                 self.do_copy_primary();
             }
@@ -1593,11 +2352,56 @@ impl EditView {
 
     pub fn set_language(&self, lang: &str) {
         debug!("Changing language to '{:?}'", lang);
+        self.language_override.replace(Some(lang.to_string()));
         self.core.set_language(self.view_id, lang);
     }
 
+    /// The syntax xi is using for this view, as reported in `language_changed`
+    pub fn language(&self) -> String {
+        self.language.borrow().clone()
+    }
+
+    /// The syntax the user has chosen for this view via `set_language`, if any
+    pub fn language_override(&self) -> Option<String> {
+        self.language_override.borrow().clone()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.get()
+    }
+
+    /// Set the encoding the file is saved in and show it in the statusbar
+    pub fn set_encoding(&self, encoding: Encoding) {
+        self.encoding.set(encoding);
+        self.view_item
+            .statusbar
+            .encoding_label
+            .set_text(encoding.name());
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending.get()
+    }
+
+    pub fn mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings.get()
+    }
+
+    /// Warn in the statusbar that the file contains more than one line ending
+    pub fn set_mixed_line_endings(&self, mixed: bool) {
+        self.mixed_line_endings.set(mixed);
+        self.update_line_ending();
+    }
+
+    fn update_line_ending(&self) {
+        self.view_item
+            .statusbar
+            .set_line_ending(self.line_ending.get(), self.mixed_line_endings.get());
+    }
+
     pub fn language_changed(&self, syntax: &str) {
         debug!("Language has been changed to '{:?}'", syntax);
+        self.language.replace(syntax.to_string());
         // https://github.com/xi-editor/xi-editor/issues/1194
         let lang = if syntax == "" || syntax == "Plain Text" {
             gettext("Plain Text")
@@ -1661,41 +2465,1165 @@ impl EditView {
     pub fn go_to_line(&self, line: u64) {
         self.core.goto_line(self.view_id, line - 1);
     }
-}
 
-// An extension trait for `EditViewExt, used when we need a Rc<EditView> for things like callbacks
-pub trait EditViewExt {
-    fn multicursor_select_all(&self);
-}
+    /// Move the cursor to `position`, e.g. the definition of a symbol
+    pub fn go_to_position(&self, position: Position) {
+        self.select_ranges(&[(position, position)]);
+    }
 
-impl EditViewExt for Rc<EditView> {
-    /// Select all occurences of what's currently selected
-    fn multicursor_select_all(&self) {
-        let (clipboard_tx, clipboard_rx) =
-            MainContext::sync_channel::<serde_json::value::Value>(PRIORITY_HIGH, 1);
+    /// Replace each range of `text`, the whole document, with its new text, e.g. to rename a
+    /// symbol. The ranges may not overlap. The replacements are a single step the user can undo
+    /// and the selections are kept.
+    pub fn replace_ranges(&self, text: &str, edits: &[(Range, String)]) {
+        let mut edits = edits.to_vec();
+        edits.sort_by_key(|(range, _)| *range);
+        let (range, new) = match merge_edits(text, &edits) {
+            Some(merged) => merged,
+            None => return,
+        };
+        let selections = shift_ranges(&self.selections(), &edits);
 
-        clipboard_rx.attach(
-            None,
-            clone!(@weak self as edit_view => @default-panic, move |val| {
-                edit_view.in_multicursor_edit.set(true);
-                edit_view.core.find(
-                    edit_view.view_id,
-                    &val.as_str().unwrap_or_default(),
-                    false,
-                    false,
-                    false,
-                );
-                edit_view.core.find_all(edit_view.view_id);
-                edit_view.do_copy_primary();
+        self.select_ranges(&[range]);
+        self.core.insert(self.view_id, &new);
+        if !selections.is_empty() {
+            self.select_ranges(&selections);
+        }
+    }
 
-                Continue(false)
-            }),
-        );
+    /// Replace the whole document with `text`, e.g. to restore unsaved changes. The change can be
+    /// undone and leaves the file untouched until the user saves it.
+    pub fn replace_text(&self, text: &str) {
+        self.core.select_all(self.view_id);
+        self.core.insert(self.view_id, text);
+    }
 
-        self.core.copy(self.view_id, move |res| {
-            if let Ok(val) = res {
-                clipboard_tx.send(val).unwrap();
-            }
+    /// Set the problems `producer` has found in the document, e.g. "language-server" or the name
+    /// of a linter. They replace the ones it has set before, those of other producers are kept.
+    pub fn set_diagnostics(&self, producer: &str, diagnostics: Vec<Diagnostic>) {
+        {
+            let mut sources = self.diagnostic_sources.borrow_mut();
+            if diagnostics.is_empty() {
+                if sources.remove(producer).is_none() {
+                    return;
+                }
+            } else {
+                sources.insert(producer.to_string(), diagnostics);
+            }
+            let mut all: Vec<Diagnostic> = sources.values().flatten().cloned().collect();
+            all.sort_by_key(|d| (d.severity, d.range));
+            self.diagnostics.replace(all);
+        }
+        self.view_item.edit_area.queue_draw();
+        self.view_item.linecount.queue_draw();
+
+        // Clone the handler, it might replace itself
+        let handler = self.diagnostics_changed_handler.borrow().clone();
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+
+    /// The problems all producers have found in the document, the most severe ones first
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.borrow().clone()
+    }
+
+    /// Set the function which is called whenever the problems in the document change
+    pub fn connect_diagnostics_changed<F: Fn() + 'static>(&self, f: F) {
+        self.diagnostics_changed_handler.replace(Some(Rc::new(f)));
+    }
+
+    /// Set where hover texts and additional completions come from, e.g. a language server
+    pub fn set_language_features(&self, features: Option<Rc<dyn LanguageFeatures>>) {
+        self.language_features.replace(features);
+        self.hover_position.set(None);
+        self.hover.replace(None);
+        self.feature_completion_start.set(None);
+        self.feature_completions.borrow_mut().clear();
+    }
+
+    /// Returns the number of lines of the document, as far as xi has told us about them
+    pub fn line_count(&self) -> u64 {
+        self.line_cache.lock().height()
+    }
+
+    /// Returns the text of `row` without its line ending, if we have it cached
+    pub fn line_text(&self, row: u64) -> Option<String> {
+        self.line_cache.lock().get_line(row).map(|l| {
+            l.text
+                .trim_end_matches(|c| c == '\n' || c == '\r')
+                .to_string()
+        })
+    }
+
+    /// Returns the text of the whole document, or `None` if xi hasn't sent us all of its lines
+    pub fn text(&self) -> Option<String> {
+        let line_cache = self.line_cache.lock();
+        (0..line_cache.height())
+            .map(|n| line_cache.get_line(n).map(|l| l.text.as_str()))
+            .collect()
+    }
+
+    /// Returns the positions (line, column) of all cursors in the lines we currently have cached
+    pub fn cursor_positions(&self) -> Vec<(u64, u64)> {
+        let line_cache = self.line_cache.lock();
+        line_cache
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, line)| {
+                line.as_ref()
+                    .map(|l| (line_cache.n_invalid_before + ix as u64, l))
+            })
+            .flat_map(|(ln, line)| line.cursor.iter().map(move |c| (ln, *c)))
+            .collect()
+    }
+
+    /// Returns the selections and cursors in the lines we currently have cached, see
+    /// `selections::ranges`
+    pub fn selections(&self) -> Vec<Range> {
+        let line_cache = self.line_cache.lock();
+        let lines: Vec<SelectedLine> = line_cache
+            .lines
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, line)| {
+                line.as_ref().map(|l| SelectedLine {
+                    line: line_cache.n_invalid_before + ix as u64,
+                    text: &l.text,
+                    styles: &l.styles,
+                    cursors: &l.cursor,
+                })
+            })
+            .collect();
+        selections::ranges(&lines)
+    }
+
+    /// Replace the current selections with `ranges`, as returned by `selections`
+    pub fn set_selections(&self, ranges: &[Range]) {
+        self.select_ranges(ranges);
+    }
+
+    /// The line at the top of the visible area. It's fractional if the line is only partially
+    /// visible.
+    pub fn first_visible_line(&self) -> f64 {
+        self.view_item.vadj.get_value() / self.edit_font.borrow().font_height
+    }
+
+    /// Scroll `first_line` to the top of the visible area once the document has been loaded
+    pub fn restore_scroll(&self, first_line: f64) {
+        self.pending_scroll.set(Some(first_line));
+    }
+
+    /// Returns the chars right before and after each cursor in the lines we currently have cached
+    fn cursor_contexts(&self) -> Vec<CursorContext> {
+        let line_cache = self.line_cache.lock();
+        let mut contexts = Vec::new();
+        for line in line_cache.lines.iter().filter_map(Option::as_ref) {
+            let text = line.text.trim_end_matches(|c| c == '\n' || c == '\r');
+            for c in &line.cursor {
+                let c = *c as usize;
+                let prev = text.get(..c).and_then(|s| s.chars().next_back());
+                let next = text.get(c..).and_then(|s| s.chars().next());
+                contexts.push((prev, next));
+            }
+        }
+        contexts
+    }
+
+    /// Returns true if any of the lines we currently have cached contains a selection
+    fn has_selection(&self) -> bool {
+        self.line_cache
+            .lock()
+            .lines
+            .iter()
+            .filter_map(Option::as_ref)
+            .any(|line| {
+                line.styles
+                    .iter()
+                    .any(|style| style.style_id == 0 && style.length > 0)
+            })
+    }
+
+    /// Set which brackets and quotes should be closed automatically, `None` disables it
+    pub fn set_auto_pairs(&self, auto_pairs: Option<AutoPairs>) {
+        self.auto_pairs.replace(auto_pairs);
+    }
+
+    /// Insert text the user has typed, closing brackets and quotes or typing over closing
+    /// ones as required.
+    pub(crate) fn insert_chars(&self, text: &str) {
+        let mut chars = text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            // xi surrounds selections with the `surrounding_pairs` itself
+            let action = match &*self.auto_pairs.borrow() {
+                Some(auto_pairs) if !self.has_selection() => {
+                    auto_pairs.on_insert(c, &self.cursor_contexts())
+                }
+                _ => InsertAction::Insert,
+            };
+
+            match action {
+                InsertAction::Overtype => {
+                    self.core.right(self.view_id);
+                    return;
+                }
+                InsertAction::Close(close) => {
+                    self.core.insert(self.view_id, &format!("{}{}", c, close));
+                    self.core.left(self.view_id);
+                    return;
+                }
+                InsertAction::Insert => {}
+            }
+        }
+
+        self.core.insert(self.view_id, text);
+    }
+
+    /// Delete the char before the cursors, and the closing char after them if they're in an
+    /// empty pair of brackets or quotes.
+    fn delete_backward(&self) {
+        let delete_pair = match &*self.auto_pairs.borrow() {
+            Some(auto_pairs) if !self.has_selection() => {
+                auto_pairs.on_backspace(&self.cursor_contexts())
+            }
+            _ => false,
+        };
+
+        if delete_pair {
+            self.core.delete_forward(self.view_id);
+        }
+        self.core.delete_backward(self.view_id);
+    }
+
+    /// Returns the number and text of all lines which contain a cursor or a selection, or `None`
+    /// if some of them aren't cached.
+    fn selected_lines(&self) -> Option<Vec<(u64, String)>> {
+        let line_cache = self.line_cache.lock();
+        let mut selected: Vec<(u64, String)> = Vec::new();
+        let mut prev_has_selection = false;
+        for (ix, line) in line_cache.lines.iter().enumerate() {
+            let line_num = line_cache.n_invalid_before + ix as u64;
+            let line = match line {
+                Some(line) => line,
+                None => {
+                    // We can't (un)comment lines we don't know the text of
+                    if prev_has_selection {
+                        return None;
+                    }
+                    continue;
+                }
+            };
+
+            let has_selection = line
+                .styles
+                .iter()
+                .any(|style| style.style_id == 0 && style.length > 0);
+            // A selection ending at the start of this line doesn't select anything in it
+            let only_selection_end =
+                prev_has_selection && !has_selection && line.cursor.iter().all(|c| *c == 0);
+            if has_selection || (!line.cursor.is_empty() && !only_selection_end) {
+                selected.push((line_num, line.text.clone()));
+            }
+            prev_has_selection = has_selection;
+        }
+
+        if prev_has_selection && line_cache.n_invalid_after > 0 {
+            None
+        } else {
+            Some(selected)
+        }
+    }
+
+    fn comment_tokens(&self) -> CommentTokens {
+        CommentTokens::for_syntax(&self.language.borrow())
+    }
+
+    /// Comment out the lines containing a cursor or a selection with the line comment marker of
+    /// the current syntax, or uncomment them if all of them are commented out already.
+    pub fn toggle_comment(&self) {
+        let tokens = self.comment_tokens();
+        match (&tokens.line, &tokens.block) {
+            (Some(token), _) => {
+                if let Some(lines) = self.selected_lines() {
+                    let lines: Vec<_> = lines.iter().map(|(n, t)| (*n, t.as_str())).collect();
+                    if let Some(edit) = comment::toggle_line_comment(&lines, token) {
+                        self.apply_comment_edit(edit);
+                    }
+                } else {
+                    warn!("Not all selected lines are cached, can't toggle comment");
+                }
+            }
+            (None, Some(_)) => self.toggle_block_comment(),
+            (None, None) => debug!("'{}' has no comment markers", self.language.borrow()),
+        }
+    }
+
+    /// Wrap the lines containing a cursor or a selection in a block comment, or unwrap them if
+    /// they already are one.
+    pub fn toggle_block_comment(&self) {
+        let tokens = self.comment_tokens();
+        match (&tokens.line, &tokens.block) {
+            (_, Some((start, end))) => {
+                if let Some(lines) = self.selected_lines() {
+                    let (first, last) = match (lines.first(), lines.last()) {
+                        (Some(first), Some(last)) => (first.0, last.0),
+                        _ => return,
+                    };
+                    // Block comments span everything in between, too
+                    let edit = {
+                        let line_cache = self.line_cache.lock();
+                        let lines: Option<Vec<_>> = (first..=last)
+                            .map(|n| line_cache.get_line(n).map(|l| (n, l.text.as_str())))
+                            .collect();
+                        lines.and_then(|l| comment::toggle_block_comment(&l, start, end))
+                    };
+                    if let Some(edit) = edit {
+                        self.apply_comment_edit(edit);
+                    }
+                } else {
+                    warn!("Not all selected lines are cached, can't toggle block comment");
+                }
+            }
+            (Some(_), None) => self.toggle_comment(),
+            (None, None) => debug!("'{}' has no comment markers", self.language.borrow()),
+        }
+    }
+
+    /// Send a `CommentEdit` to xi. Each of these is a single edit for xi, so undoing it only
+    /// takes one step.
+    fn apply_comment_edit(&self, edit: CommentEdit) {
+        debug!("Applying comment edit: {:?}", edit);
+        match edit {
+            CommentEdit::Insert { positions, text } => {
+                let ranges: Vec<Range> = positions.into_iter().map(|pos| (pos, pos)).collect();
+                self.select_ranges(&ranges);
+                self.core.insert(self.view_id, &text);
+            }
+            CommentEdit::Delete { ranges } => {
+                let ranges: Vec<Range> = ranges
+                    .into_iter()
+                    .map(|(line, start, end)| ((line, start), (line, end)))
+                    .collect();
+                self.select_ranges(&ranges);
+                self.core.delete_backward(self.view_id);
+            }
+            CommentEdit::Replace { start, end, text } => {
+                self.select_ranges(&[(start, end)]);
+                self.core.insert(self.view_id, &text);
+            }
+        }
+    }
+
+    /// Replace the current selections with the `ranges`, which have to be sorted
+    fn select_ranges(&self, ranges: &[Range]) {
+        for (i, (start, end)) in ranges.iter().enumerate() {
+            if i == 0 {
+                self.core
+                    .gesture_point_select(self.view_id, start.0, start.1);
+            } else {
+                self.core
+                    .gesture_toggle_caret(self.view_id, start.0, start.1);
+            }
+            if start != end {
+                // Extends the last selection, which is the caret we just placed
+                self.core.gesture_toggle_sel(self.view_id, end.0, end.1);
+            }
+        }
+    }
+
+    /// Set the snippets which are available for the current syntax
+    pub fn set_snippets(&self, snippets: Vec<Snippet>) {
+        self.view_item.statusbar.set_snippets(&snippets);
+        self.snippets.replace(snippets);
+    }
+
+    /// Insert the snippet called `name` at the cursor
+    pub(crate) fn insert_snippet(&self, name: &str) {
+        let snippet = match self.snippets.borrow().iter().find(|s| s.name == name) {
+            Some(snippet) => snippet.clone(),
+            None => return,
+        };
+        if let Some(&(line, col)) = self.cursor_positions().first() {
+            self.expand_snippet(&snippet, ((line, col), (line, col)));
+        }
+    }
+
+    /// Expand the snippet whose prefix is right before the cursor, if there is one. Returns false
+    /// if there is no such snippet.
+    fn expand_snippet_trigger(&self) -> bool {
+        let cursors = self.cursor_positions();
+        if cursors.len() != 1 || self.has_selection() {
+            return false;
+        }
+        let (line, col) = cursors[0];
+
+        let snippet = {
+            let line_cache = self.line_cache.lock();
+            let text = match line_cache.get_line(line) {
+                Some(l) => &l.text,
+                None => return false,
+            };
+            let before_cursor = match text.get(..col as usize) {
+                Some(t) => t,
+                None => return false,
+            };
+            match snippets::find_trigger(&self.snippets.borrow(), before_cursor) {
+                Some(snippet) => snippet.clone(),
+                None => return false,
+            }
+        };
+
+        debug!("Expanding snippet '{}'", snippet.name);
+        let prefix_start = (line, col - snippet.prefix.len() as u64);
+        self.expand_snippet(&snippet, (prefix_start, (line, col)));
+        true
+    }
+
+    /// Replace `range` with the snippet and select its first tab stop
+    fn expand_snippet(&self, snippet: &Snippet, range: Range) {
+        let (start, end) = range;
+        let indent: String = self
+            .line_cache
+            .lock()
+            .get_line(start.0)
+            .map(|l| {
+                l.text
+                    .chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let expansion = Expansion::new(&snippet.body, &indent);
+        self.select_ranges(&[(start, end)]);
+        self.core.insert(self.view_id, &expansion.text);
+
+        let session = SnippetSession::new(expansion.ranges(start));
+        self.select_ranges(session.current());
+        if session.is_finished() {
+            self.snippet_session.replace(None);
+        } else {
+            self.snippet_session.replace(Some(session));
+        }
+    }
+
+    /// Returns the cursor at the end of the first range of the current tab stop
+    fn snippet_cursor(&self, session: &SnippetSession) -> Option<Position> {
+        let (start, _) = session.current()[0];
+        self.cursor_positions()
+            .into_iter()
+            .filter(|(line, col)| *line == start.0 && *col >= start.1)
+            .min()
+    }
+
+    /// Move to the next tab stop of the current snippet. Returns false if no snippet is active.
+    fn next_tab_stop(&self) -> bool {
+        self.move_tab_stop(SnippetSession::next)
+    }
+
+    /// Move to the previous tab stop of the current snippet. Returns false if no snippet is active.
+    fn previous_tab_stop(&self) -> bool {
+        self.move_tab_stop(SnippetSession::previous)
+    }
+
+    fn move_tab_stop<F>(&self, f: F) -> bool
+    where
+        F: for<'a> Fn(&'a mut SnippetSession, Position) -> Option<&'a [Range]>,
+    {
+        let mut session = match self.snippet_session.borrow_mut().take() {
+            Some(session) => session,
+            None => return false,
+        };
+
+        let cursor = match self.snippet_cursor(&session) {
+            Some(cursor) => cursor,
+            None => {
+                debug!("Lost track of the snippet's tab stops, ending it");
+                return false;
+            }
+        };
+
+        match f(&mut session, cursor) {
+            Some(ranges) => {
+                self.select_ranges(ranges);
+            }
+            None => {
+                debug!("Lost track of the snippet's tab stops, ending it");
+                return false;
+            }
+        }
+
+        if !session.is_finished() {
+            self.snippet_session.replace(Some(session));
+        }
+        true
+    }
+
+    pub(crate) fn completion_visible(&self) -> bool {
+        self.view_item.completion.popover.is_visible()
+    }
+
+    pub(crate) fn hide_completion(&self) {
+        self.completion_range.set(None);
+        if self.completion_visible() {
+            self.view_item.completion.popover.popdown();
+        }
+    }
+
+    /// Returns where the word in front of the last cursor starts, the part of it in front of the
+    /// cursor and the char in front of the word
+    fn completion_prefix(&self) -> Option<(Position, String, Option<char>)> {
+        let (line, col) = *self.cursor_positions().last()?;
+        let line_cache = self.line_cache.lock();
+        let before = line_cache.get_line(line)?.text.get(..col as usize)?;
+        let prefix = completion::word_prefix(before);
+        let start_col = col - prefix.len() as u64;
+        let previous = before[..start_col as usize].chars().last();
+        Some(((line, start_col), prefix.to_string(), previous))
+    }
+
+    /// Show the completions for the word in front of the cursor. Unless `explicit` is set (the
+    /// user asked for completions), this only happens if there are at least `MIN_PREFIX_LEN`
+    /// chars in front of the cursor or the language features want completions after the char
+    /// in front of the word, e.g. a `.`.
+    fn refresh_completion(&self, explicit: bool) {
+        let ((line, start_col), prefix, previous) = match self.completion_prefix() {
+            Some(prefix) => prefix,
+            None => return self.hide_completion(),
+        };
+        let col = start_col + prefix.len() as u64;
+        let features = self.language_features.borrow().clone();
+        let triggered = match (previous, &features) {
+            (Some(c), Some(features)) => prefix.is_empty() && features.triggers_completion(c),
+            _ => false,
+        };
+        if !triggered
+            && (prefix.is_empty() || (!explicit && prefix.chars().count() < MIN_PREFIX_LEN))
+        {
+            return self.hide_completion();
+        }
+
+        if let Some(features) = features {
+            let start = (line, start_col);
+            if self.feature_completion_start.get() != Some(start) {
+                self.feature_completion_start.set(Some(start));
+                self.feature_completions.borrow_mut().clear();
+                let sender = self.feature_sender.clone();
+                features.complete(
+                    (line, col),
+                    Box::new(move |items| {
+                        sender.send(FeatureMsg::Completions(start, items)).ok();
+                    }),
+                );
+            }
+        }
+
+        // The language features know best, so their completions win over words with the same label
+        let mut items = self.feature_completions.borrow().clone();
+        items.extend(
+            self.main_state
+                .borrow()
+                .completion
+                .complete(&CompletionRequest {
+                    prefix: &prefix,
+                    language: &self.language.borrow(),
+                }),
+        );
+        let items = completion::rank(&prefix, items);
+        if items.is_empty() {
+            return self.hide_completion();
+        }
+
+        let popup = &self.view_item.completion;
+        popup.list_model.clear();
+        for item in &items {
+            let insert_text = item.insert_text.as_ref().unwrap_or(&item.label);
+            popup.list_model.insert_with_values(
+                None,
+                &[0, 1, 2],
+                &[&item.label, &item.detail, insert_text],
+            );
+        }
+        if let Some(iter) = popup.list_model.get_iter_first() {
+            popup.treeview.get_selection().select_iter(&iter);
+        }
+
+        self.completion_range
+            .set(Some(((line, start_col), (line, col))));
+
+        let (x, y) = self.cell_to_da_px(line, start_col);
+        popup.popover.set_pointing_to(&gdk::Rectangle {
+            x: x as i32,
+            y: y as i32,
+            width: 1,
+            height: self.edit_font.borrow().font_height as i32,
+        });
+        popup.popover.popup();
+    }
+
+    /// Replace the word prefix with the completion at `path`, or the selected one
+    pub(crate) fn accept_completion(&self, path: Option<&TreePath>) {
+        let popup = &self.view_item.completion;
+        let iter = match path {
+            Some(path) => popup.list_model.get_iter(path),
+            None => popup
+                .treeview
+                .get_selection()
+                .get_selected()
+                .map(|(_, iter)| iter),
+        };
+        let text = iter.and_then(|iter| {
+            popup
+                .list_model
+                .get_value(&iter, 2)
+                .get::<String>()
+                .ok()
+                .flatten()
+        });
+
+        if let (Some(text), Some(range)) = (text, self.completion_range.get()) {
+            debug!("Accepting completion '{}'", text);
+            self.select_ranges(&[range]);
+            self.core.insert(self.view_id, &text);
+        }
+        self.forget_feature_completions();
+        self.hide_completion();
+    }
+
+    /// Drop the completions of the language features, so they're asked again next time
+    fn forget_feature_completions(&self) {
+        self.feature_completion_start.set(None);
+        self.feature_completions.borrow_mut().clear();
+    }
+
+    /// Show the diagnostics and the hover text of the language features for the text at `x`|`y`
+    pub(crate) fn handle_query_tooltip(&self, x: i32, y: i32, tooltip: &Tooltip) -> bool {
+        let (col, line) = self.da_px_to_cell(f64::from(x), f64::from(y));
+        if line >= self.line_cache.lock().height() {
+            return false;
+        }
+
+        let position = (line, col);
+        let mut texts: Vec<String> = self
+            .diagnostics
+            .borrow()
+            .iter()
+            .filter(|d| d.contains(position))
+            .map(Diagnostic::tooltip)
+            .collect();
+
+        if let Some(features) = self.language_features.borrow().as_ref() {
+            if self.hover_position.get() != Some(position) {
+                self.hover_position.set(Some(position));
+                self.hover.replace(None);
+                let sender = self.feature_sender.clone();
+                features.hover(
+                    position,
+                    Box::new(move |text| {
+                        sender.send(FeatureMsg::Hover(position, text)).ok();
+                    }),
+                );
+            } else if let Some(hover) = self.hover.borrow().as_ref() {
+                texts.push(hover.clone());
+            }
+        }
+
+        if texts.is_empty() {
+            return false;
+        }
+        tooltip.set_text(Some(&texts.join("\n\n")));
+        true
+    }
+
+    /// Handle keys which navigate the completion popover while it's open
+    fn handle_completion_key(&self, ek: &EventKey) -> Option<Inhibit> {
+        if !self.completion_visible() {
+            return None;
+        }
+
+        let popup = &self.view_item.completion;
+        let selection = popup.treeview.get_selection();
+        let n_items = popup.list_model.iter_n_children(None);
+        let selected = selection
+            .get_selected()
+            .and_then(|(model, iter)| model.get_path(&iter))
+            .and_then(|path| path.get_indices().first().copied())
+            .unwrap_or(0);
+
+        let select = |index: i32| {
+            let path = TreePath::new_from_indicesv(&[index]);
+            selection.select_path(&path);
+            popup.treeview.scroll_to_cell(
+                Some(&path),
+                None::<&gtk::TreeViewColumn>,
+                false,
+                0.0,
+                0.0,
+            );
+        };
+
+        match ek.get_keyval() {
+            key::Down | key::KP_Down => select((selected + 1) % n_items.max(1)),
+            key::Up | key::KP_Up => select((selected + n_items - 1) % n_items.max(1)),
+            key::Return | key::KP_Enter | key::Tab => self.accept_completion(None),
+            key::Escape => {
+                self.forget_feature_completions();
+                self.hide_completion();
+            }
+            _ => return None,
+        }
+        Some(Inhibit(true))
+    }
+
+    /// Remember where the cursors are right now, so `undo_cursor_addition` can restore that state
+    fn push_cursor_history(&self) {
+        let positions = self.cursor_positions();
+        self.cursor_history.borrow_mut().push(positions);
+    }
+
+    /// Add a cursor on the line above each cursor (Ctrl+Alt+Up)
+    pub fn add_cursor_above(&self) {
+        self.push_cursor_history();
+        self.core.add_selection_above(self.view_id);
+    }
+
+    /// Add a cursor on the line below each cursor (Ctrl+Alt+Down)
+    pub fn add_cursor_below(&self) {
+        self.push_cursor_history();
+        self.core.add_selection_below(self.view_id);
+    }
+
+    /// Remove the cursors which have been added by the last multicursor command
+    pub fn undo_cursor_addition(&self) {
+        let previous = match self.cursor_history.borrow_mut().pop() {
+            Some(previous) => previous,
+            None => return,
+        };
+
+        let current = self.cursor_positions();
+        let added: Vec<_> = current.iter().filter(|c| !previous.contains(*c)).collect();
+
+        // Don't remove every cursor if we can't tell them apart, e.g. because the previous ones
+        // have scrolled out of the line cache in the meantime
+        if added.len() == current.len() {
+            debug!("Couldn't determine which cursor has been added last");
+            return;
+        }
+
+        for (line, col) in added {
+            self.core.gesture_toggle_caret(self.view_id, *line, *col);
+        }
+    }
+}
+
+// An extension trait for `EditViewExt, used when we need a Rc<EditView> for things like callbacks
+pub trait EditViewExt {
+    fn multicursor_select_all(&self);
+
+    fn multicursor_add_next(&self);
+
+    fn multicursor_skip_next(&self);
+
+    fn schedule_completion(&self);
+
+    fn update_completion_after_key(&self, ek: &EventKey);
+
+    fn schedule_file_check(&self);
+
+    fn expect_save(&self);
+
+    fn convert_line_endings(&self, line_ending: LineEnding);
+
+    fn with_text<F: FnOnce(Result<String, String>) + 'static>(&self, f: F);
+
+    fn schedule_git_refresh(&self, force: bool);
+
+    fn compare_with_head(&self, text: Option<String>);
+
+    fn handle_gutter_motion(&self, y: f64);
+
+    fn schedule_hunk_popover_hide(&self);
+
+    fn stage_hunk(&self);
+}
+
+impl EditViewExt for Rc<EditView> {
+    /// Select all occurences of what's currently selected
+    fn multicursor_select_all(&self) {
+        let (clipboard_tx, clipboard_rx) =
+            MainContext::sync_channel::<serde_json::value::Value>(PRIORITY_HIGH, 1);
+
+        clipboard_rx.attach(
+            None,
+            clone!(@weak self as edit_view => @default-panic, move |val| {
+                edit_view.in_multicursor_edit.set(true);
+                edit_view.core.find(
+                    edit_view.view_id,
+                    &val.as_str().unwrap_or_default(),
+                    false,
+                    false,
+                    false,
+                );
+                edit_view.core.find_all(edit_view.view_id);
+                edit_view.do_copy_primary();
+
+                Continue(false)
+            }),
+        );
+
+        self.core.copy(self.view_id, move |res| {
+            if let Ok(val) = res {
+                clipboard_tx.send(val).unwrap();
+            }
+        });
+    }
+
+    /// Add a cursor at the next occurrence of the current selection. If nothing is selected, this
+    /// selects the word under the cursor first.
+    fn multicursor_add_next(&self) {
+        let (clipboard_tx, clipboard_rx) =
+            MainContext::sync_channel::<serde_json::value::Value>(PRIORITY_HIGH, 1);
+
+        clipboard_rx.attach(
+            None,
+            clone!(@weak self as edit_view => @default-panic, move |val| {
+                let has_selection = val.as_str().map(|s| !s.is_empty()).unwrap_or(false);
+                if has_selection {
+                    edit_view.push_cursor_history();
+                    edit_view.core.selection_for_find(edit_view.view_id, true);
+                    edit_view.core.find_next(
+                        edit_view.view_id,
+                        Some(true),
+                        Some(false),
+                        Some(tau_rpc::ModifySelection::Add),
+                    );
+                } else if let Some((line, col)) = edit_view.cursor_positions().last() {
+                    edit_view.core.gesture_word_select(edit_view.view_id, *line, *col);
+                }
+                edit_view.do_copy_primary();
+
+                Continue(false)
+            }),
+        );
+
+        self.core.copy(self.view_id, move |res| {
+            if let Ok(val) = res {
+                clipboard_tx.send(val).unwrap();
+            }
         });
     }
+
+    /// Move the most recently added cursor to the next occurrence of the current selection
+    fn multicursor_skip_next(&self) {
+        self.push_cursor_history();
+        self.core.selection_for_find(self.view_id, true);
+        self.core.find_next(
+            self.view_id,
+            Some(true),
+            Some(false),
+            Some(tau_rpc::ModifySelection::AddRemoveCurrent),
+        );
+        self.do_copy_primary();
+    }
+
+    /// Refresh the completions once xi has processed what the user just typed
+    fn schedule_completion(&self) {
+        if let Some(id) = self.completion_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+
+        let id = glib::timeout_add_local(
+            COMPLETION_DELAY_MS,
+            clone!(@weak self as edit_view => @default-return Continue(false), move || {
+                edit_view.completion_timeout.replace(None);
+                edit_view.refresh_completion(false);
+                Continue(false)
+            }),
+        );
+        self.completion_timeout.replace(Some(id));
+    }
+
+    /// Call `f` with the text of the whole document once xi has sent us all of its lines. Usually
+    /// we only know about the lines which are visible. `f` gets an error if xi doesn't send them
+    /// in time or the EditView is closed in the meantime.
+    fn with_text<F: FnOnce(Result<String, String>) + 'static>(&self, f: F) {
+        if let Some(text) = self.text() {
+            f(Ok(text));
+            return;
+        }
+
+        let height = self.line_cache.lock().height();
+        self.core.request_lines(self.view_id, 0, height);
+
+        let edit_view = Rc::downgrade(self);
+        let mut f = Some(f);
+        let mut attempts = 0;
+        glib::timeout_add_local(TEXT_POLL_INTERVAL_MS, move || {
+            let result = match edit_view.upgrade() {
+                Some(edit_view) => match edit_view.text() {
+                    Some(text) => Ok(text),
+                    None if attempts + 1 < TEXT_POLL_ATTEMPTS => {
+                        attempts += 1;
+                        return Continue(true);
+                    }
+                    None => {
+                        warn!(
+                            "xi hasn't sent all lines of EditView '{}'",
+                            edit_view.view_id
+                        );
+                        Err(gettext(
+                            "The editor hasn’t sent the whole document in time.",
+                        ))
+                    }
+                },
+                None => Err(gettext("The document has been closed.")),
+            };
+            if let Some(f) = f.take() {
+                f(result);
+            }
+            Continue(false)
+        });
+    }
+
+    /// Look at the file on disk once the `FileMonitor` hasn't noticed any further changes for a bit
+    fn schedule_file_check(&self) {
+        if let Some(id) = self.file_check_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+
+        let id = glib::timeout_add_local(
+            file_watch::FILE_CHECK_DELAY_MS,
+            clone!(@weak self as edit_view => @default-return Continue(false), move || {
+                edit_view.file_check_timeout.replace(None);
+                edit_view.check_file();
+                Continue(false)
+            }),
+        );
+        self.file_check_timeout.replace(Some(id));
+    }
+
+    /// Convert all lines of the document to `line_ending` and let xi use it for new lines. The
+    /// conversion is a single step the user can undo.
+    fn convert_line_endings(&self, line_ending: LineEnding) {
+        if !line_ending.is_convertible() {
+            warn!("Can't convert the line endings to {}", line_ending);
+            return;
+        }
+        debug!(
+            "Converting line endings of EditView '{}' to {}",
+            self.view_id, line_ending
+        );
+        self.core.modify_user_config_domain_user_override(
+            self.view_id,
+            &json!({ "line_ending": line_ending.as_str() }),
+        );
+        self.with_text(
+            clone!(@weak self as edit_view => @default-return (), move |text| {
+                match text {
+                    Ok(text) => {
+                        edit_view.replace_ranges(&text, &conversion_edits(&text, line_ending));
+                        edit_view.line_ending.set(line_ending);
+                        edit_view.set_mixed_line_endings(false);
+                    }
+                    Err(e) => error!("Failed to convert the line endings: {}", e),
+                }
+            }),
+        );
+    }
+
+    /// Tell the EditView that the user is about to save it, so it doesn't mistake writing the file
+    /// for a change by another program. If the save doesn't show up on disk in time, it's assumed
+    /// to have failed.
+    fn expect_save(&self) {
+        self.saving.set(true);
+        self.view_item.file_changed.info_bar.hide();
+
+        if let Some(id) = self.save_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        let id = glib::timeout_add_local(
+            file_watch::SAVE_TIMEOUT_MS,
+            clone!(@weak self as edit_view => @default-return Continue(false), move || {
+                edit_view.save_timeout.replace(None);
+                if edit_view.saving.get() {
+                    warn!("Save of EditView '{}' hasn't shown up on disk", edit_view.view_id);
+                    edit_view.cancel_save();
+                }
+                Continue(false)
+            }),
+        );
+        self.save_timeout.replace(Some(id));
+    }
+
+    /// Compare the buffer with HEAD once the user has stopped typing for a bit. Unless `force` is
+    /// set, nothing is done if the buffer hasn't changed since the last comparison.
+    fn schedule_git_refresh(&self, force: bool) {
+        if force {
+            self.git_text.replace(None);
+            self.git_stale.set(true);
+        }
+        if let Some(id) = self.git_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        if self.file_name.borrow().is_none() {
+            return;
+        }
+
+        let id = glib::timeout_add_local(
+            git_gutter::GIT_REFRESH_DELAY_MS,
+            clone!(@weak self as edit_view => @default-return Continue(false), move || {
+                edit_view.git_timeout.replace(None);
+                let height = edit_view.line_cache.lock().height();
+                if height <= git_gutter::GIT_LIVE_REFRESH_MAX_LINES {
+                    edit_view.with_text(clone!(@weak edit_view => @default-return (), move |text| {
+                        match text {
+                            Ok(text) => edit_view.compare_with_head(Some(text)),
+                            Err(e) => warn!("Failed to compare EditView with HEAD: {}", e),
+                        }
+                    }));
+                } else if edit_view.git_stale.get() && *edit_view.pristine.borrow() {
+                    // The file on disk is what the user sees
+                    edit_view.git_stale.set(false);
+                    edit_view.compare_with_head(None);
+                }
+                Continue(false)
+            }),
+        );
+        self.git_timeout.replace(Some(id));
+    }
+
+    /// Compare `text`, or the file on disk if it's `None`, with HEAD in a thread. Nothing is done
+    /// if `text` has already been compared last time.
+    fn compare_with_head(&self, text: Option<String>) {
+        if text.is_some() && *self.git_text.borrow() == text {
+            return;
+        }
+        let file_name = match self.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        let generation = self.git_generation.get() + 1;
+        self.git_generation.set(generation);
+        self.git_text.replace(text.clone());
+
+        let sender = self.git_sender.clone();
+        std::thread::spawn(move || {
+            let text = match text {
+                Some(text) => text,
+                None => match std::fs::read_to_string(&file_name) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        warn!("Failed to read file '{}' due to error: {}", file_name, e);
+                        return;
+                    }
+                },
+            };
+            let diff = GitDiff::new(Path::new(&file_name), &text).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to compare file '{}' with HEAD due to error: {}",
+                    file_name, e
+                );
+                None
+            });
+            sender.send(GitMsg::Diff(generation, diff)).ok();
+        });
+    }
+
+    /// Show the hunk popover if the pointer is over a git marker in the linecount
+    fn handle_gutter_motion(&self, y: f64) {
+        let hunk = self.line_at_linecount_px(y).and_then(|line| {
+            self.git_diff
+                .borrow()
+                .as_ref()
+                .and_then(|diff| diff.hunk_at(line).cloned())
+        });
+
+        match hunk {
+            Some(hunk) => {
+                self.cancel_hunk_popover_hide();
+                if self.git_hunk.borrow().as_ref() != Some(&hunk) {
+                    let font_height = self.edit_font.borrow().font_height;
+                    let vadj = self.view_item.vadj.get_value();
+                    let row_y = ((y + vadj) / font_height).floor() * font_height - vadj;
+                    self.show_hunk_popover(hunk, row_y);
+                }
+            }
+            None => self.schedule_hunk_popover_hide(),
+        }
+    }
+
+    /// Hide the hunk popover unless the pointer comes back to it soon, e.g. after crossing the
+    /// gap between the linecount and the popover.
+    fn schedule_hunk_popover_hide(&self) {
+        if self.git_hunk.borrow().is_none() || self.git_hunk_timeout.borrow().is_some() {
+            return;
+        }
+
+        let id = glib::timeout_add_local(
+            git_gutter::HUNK_POPOVER_HIDE_DELAY_MS,
+            clone!(@weak self as edit_view => @default-return Continue(false), move || {
+                edit_view.git_hunk_timeout.replace(None);
+                edit_view.hide_hunk_popover();
+                Continue(false)
+            }),
+        );
+        self.git_hunk_timeout.replace(Some(id));
+    }
+
+    /// Add the hunk the popover is shown for to the git index
+    fn stage_hunk(&self) {
+        let hunk = match self.git_hunk.borrow().clone() {
+            Some(hunk) => hunk,
+            None => return,
+        };
+        let file_name = match self.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        self.hide_hunk_popover();
+
+        self.with_text(move |text| {
+            let result = text.and_then(|text| {
+                git_gutter::stage_hunk(Path::new(&file_name), &text, &hunk)
+                    .map_err(|e| e.to_string())
+            });
+            if let Err(e) = result {
+                error!(
+                    "Failed to stage changes of file '{}' due to error: {}",
+                    file_name, e
+                );
+            }
+        });
+    }
+
+    /// Keep the completion popover up to date with keys which don't insert text, e.g. hide it
+    /// when the cursor is moved.
+    fn update_completion_after_key(&self, ek: &EventKey) {
+        if !self.completion_visible() {
+            return;
+        }
+
+        match ek.get_keyval() {
+            key::BackSpace => self.schedule_completion(),
+            key::Up | key::KP_Up | key::Down | key::KP_Down => {}
+            key::Shift_L
+            | key::Shift_R
+            | key::Control_L
+            | key::Control_R
+            | key::Alt_L
+            | key::Alt_R
+            | key::ISO_Level3_Shift => {}
+            keyval => {
+                let ctrl = ek.get_state().contains(ModifierType::CONTROL_MASK);
+                // Typed chars are taken care of once the IMContext commits them
+                let printable = ::gdk::keyval_to_unicode(keyval)
+                    .map(|c| !c.is_control())
+                    .unwrap_or(false);
+                if ctrl || !printable {
+                    self.hide_completion();
+                }
+            }
+        }
+    }
 }
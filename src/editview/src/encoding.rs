@@ -0,0 +1,263 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The character encodings of files. Xi only works on UTF-8, so files in other encodings are
+//! decoded before xi opens them and encoded again when they're saved.
+
+use gettextrs::gettext;
+use std::fmt;
+
+/// The characters of Windows-1252 between 0x80 and 0x9F, where it differs from ISO-8859-1. The
+/// bytes which aren't assigned map to the C1 control characters, like in ISO-8859-1.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// How many bytes are looked at to guess whether a file without BOM is UTF-16
+const UTF16_SAMPLE_LEN: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark
+    Utf8Bom,
+    /// UTF-16 little endian, written with a byte order mark
+    Utf16Le,
+    /// UTF-16 big endian, written with a byte order mark
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Utf8
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 6] = [
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Latin1,
+        Encoding::Windows1252,
+    ];
+
+    /// The name of the encoding as shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16 LE",
+            Encoding::Utf16Be => "UTF-16 BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    /// Whether xi can open files in this encoding without us decoding them first
+    pub fn is_utf8(self) -> bool {
+        self == Encoding::Utf8
+    }
+
+    /// Decode `bytes`. A byte order mark matching the encoding is dropped.
+    pub fn decode(self, bytes: &[u8]) -> Result<String, String> {
+        let invalid = || format!("{} {}", gettext("The file isn’t valid"), self.name());
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = if bytes.starts_with(b"\xEF\xBB\xBF") {
+                    &bytes[3..]
+                } else {
+                    bytes
+                };
+                String::from_utf8(bytes.to_vec()).map_err(|_| invalid())
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                if bytes.len() % 2 != 0 {
+                    return Err(invalid());
+                }
+                let units = bytes.chunks(2).map(|pair| {
+                    if self == Encoding::Utf16Le {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                });
+                let text: String = std::char::decode_utf16(units)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?;
+                Ok(text.trim_start_matches('\u{feff}').to_string())
+            }
+            Encoding::Latin1 => Ok(bytes.iter().map(|b| char::from(*b)).collect()),
+            Encoding::Windows1252 => Ok(bytes
+                .iter()
+                .map(|b| match b {
+                    0x80..=0x9F => WINDOWS_1252[usize::from(b - 0x80)],
+                    _ => char::from(*b),
+                })
+                .collect()),
+        }
+    }
+
+    /// Encode `text`, with a byte order mark for the encodings which have one. Fails if `text`
+    /// contains characters the encoding can't represent.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([b"\xEF\xBB\xBF", text.as_bytes()].concat()),
+            Encoding::Utf16Le => Ok(std::iter::once('\u{feff}')
+                .chain(text.chars())
+                .collect::<String>()
+                .encode_utf16()
+                .flat_map(|unit| unit.to_le_bytes().to_vec())
+                .collect()),
+            Encoding::Utf16Be => Ok(std::iter::once('\u{feff}')
+                .chain(text.chars())
+                .collect::<String>()
+                .encode_utf16()
+                .flat_map(|unit| unit.to_be_bytes().to_vec())
+                .collect()),
+            Encoding::Latin1 | Encoding::Windows1252 => text
+                .chars()
+                .map(|c| self.encode_char(c))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| {
+                    format!(
+                        "{} {}",
+                        gettext("The document contains characters which can’t be saved as"),
+                        self.name()
+                    )
+                }),
+        }
+    }
+
+    /// The byte `c` is encoded as in the single byte encodings, if they can represent it
+    fn encode_char(self, c: char) -> Option<u8> {
+        if self == Encoding::Windows1252 {
+            if let Some(i) = WINDOWS_1252.iter().position(|w| *w == c) {
+                return Some(0x80 + i as u8);
+            }
+            if ('\u{80}'..='\u{9f}').contains(&c) {
+                return None;
+            }
+        }
+        if (c as u32) < 0x100 {
+            Some(c as u32 as u8)
+        } else {
+            None
+        }
+    }
+}
+
+/// Guess the encoding of `bytes` from their byte order mark, or else from what they look like
+pub fn detect(bytes: &[u8]) -> Encoding {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        return Encoding::Utf8Bom;
+    }
+    if bytes.starts_with(b"\xFF\xFE") {
+        return Encoding::Utf16Le;
+    }
+    if bytes.starts_with(b"\xFE\xFF") {
+        return Encoding::Utf16Be;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        // Mostly ASCII text in UTF-16 is still valid UTF-8, since the high bytes are zero
+        if let Some(encoding) = guess_utf16(bytes) {
+            return encoding;
+        }
+        return Encoding::Utf8;
+    }
+    if let Some(encoding) = guess_utf16(bytes) {
+        return encoding;
+    }
+    // ISO-8859-1 only has control characters there, which text files rarely contain
+    if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) {
+        Encoding::Windows1252
+    } else {
+        Encoding::Latin1
+    }
+}
+
+/// UTF-16 without byte order mark, if most characters have a zero byte on the same side
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    let sample = &bytes[..bytes.len().min(UTF16_SAMPLE_LEN)];
+    if sample.len() < 2 || sample.len() % 2 != 0 {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|b| **b == 0)
+        .count();
+    if odd_zeros * 10 >= pairs * 7 && even_zeros * 10 <= pairs {
+        Some(Encoding::Utf16Le)
+    } else if even_zeros * 10 >= pairs * 7 && odd_zeros * 10 <= pairs {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        assert_eq!(detect(b"plain text\n"), Encoding::Utf8);
+        assert_eq!(detect("grüße\n".as_bytes()), Encoding::Utf8);
+        assert_eq!(detect(b"\xEF\xBB\xBFtext"), Encoding::Utf8Bom);
+        assert_eq!(detect(b"\xFF\xFEt\0"), Encoding::Utf16Le);
+        assert_eq!(detect(b"\xFE\xFF\0t"), Encoding::Utf16Be);
+        assert_eq!(detect(b"t\0e\0x\0t\0"), Encoding::Utf16Le);
+        assert_eq!(detect(b"\0t\0e\0x\0t"), Encoding::Utf16Be);
+        assert_eq!(detect(b"gr\xFC\xDFe"), Encoding::Latin1);
+        assert_eq!(detect(b"\x93quoted\x94"), Encoding::Windows1252);
+        assert_eq!(detect(b""), Encoding::Utf8);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = "grüße €\n";
+        for encoding in &[
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Windows1252,
+        ] {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(detect(&bytes), *encoding);
+            assert_eq!(encoding.decode(&bytes).unwrap(), text);
+        }
+        assert_eq!(Encoding::Latin1.encode("grüße").unwrap(), b"gr\xFC\xDFe");
+        assert!(Encoding::Latin1.encode("€").is_err());
+        assert!(Encoding::Windows1252.encode("\u{80}").is_err());
+        assert_eq!(Encoding::Windows1252.decode(b"\x81").unwrap(), "\u{81}");
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert!(Encoding::Utf8.decode(b"\xFC").is_err());
+        assert!(Encoding::Utf16Le.decode(b"t\0e").is_err());
+        assert!(Encoding::Utf16Le.decode(b"\0\xD8").is_err());
+        assert_eq!(Encoding::from_name("UTF-16 BE"), Some(Encoding::Utf16Be));
+        assert_eq!(Encoding::from_name("EBCDIC"), None);
+    }
+}
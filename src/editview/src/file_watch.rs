@@ -0,0 +1,122 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// How long to wait for further events of the `FileMonitor` before looking at the file on disk.
+/// Tools like formatters usually write files in several steps (or replace them by renaming a
+/// temporary file over them), so we don't want to act on the first event already.
+pub(crate) const FILE_CHECK_DELAY_MS: u32 = 250;
+
+/// How long to wait for a save to show up on disk. If it hasn't by then, it has failed and later
+/// writes are made by other programs again.
+pub(crate) const SAVE_TIMEOUT_MS: u32 = 10_000;
+
+/// What happened to the file an `EditView` is editing, as far as we know
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiskState {
+    /// The file is where we expect it to be
+    Present,
+    /// The file has been deleted from disk
+    Deleted,
+    /// The file has been moved to the contained path
+    Renamed(String),
+}
+
+/// What the user wants to do about a file which has been changed on disk. This is passed on to the
+/// callback set via `EditView::connect_file_changed`, since reloading the file and comparing it
+/// requires opening new views.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileChangedAction {
+    /// Discard the contents of the buffer and load the file from disk again
+    Reload,
+    /// Show the version on disk next to the buffer
+    Compare,
+}
+
+/// What the `FileMonitor` of an `EditView` has noticed
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum FileEvent {
+    /// The file has been written, created or deleted
+    Changed,
+    /// The file has been moved to the contained path
+    Renamed(String),
+}
+
+/// What the `EditView` should do after looking at the file on disk
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum DiskChange {
+    /// The file is still the same as the last time we've looked at it
+    None,
+    /// We've written the file ourselves
+    Saved,
+    /// Another program has changed the file
+    Modified,
+    /// The file is gone
+    Missing,
+}
+
+/// Returns the time `path` was last modified, or `None` if it doesn't exist (anymore)
+pub(crate) fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Compare the modification time we know of (`known`) with the one on disk (`current`).
+/// `saving` is true if we're expecting a write because the user has saved the file.
+pub(crate) fn disk_change(
+    known: Option<SystemTime>,
+    current: Option<SystemTime>,
+    saving: bool,
+) -> DiskChange {
+    match current {
+        None => DiskChange::Missing,
+        Some(_) if current == known => DiskChange::None,
+        Some(_) if saving => DiskChange::Saved,
+        Some(_) => DiskChange::Modified,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn unchanged_file() {
+        let time = SystemTime::now();
+        assert_eq!(disk_change(Some(time), Some(time), false), DiskChange::None);
+    }
+
+    #[test]
+    fn modified_file() {
+        let time = SystemTime::now();
+        let later = time + Duration::from_secs(1);
+        assert_eq!(
+            disk_change(Some(time), Some(later), false),
+            DiskChange::Modified
+        );
+        // The file didn't exist when we've opened the view
+        assert_eq!(disk_change(None, Some(later), false), DiskChange::Modified);
+    }
+
+    #[test]
+    fn saved_file() {
+        let time = SystemTime::now();
+        let later = time + Duration::from_secs(1);
+        assert_eq!(
+            disk_change(Some(time), Some(later), true),
+            DiskChange::Saved
+        );
+        // xi hasn't written the file yet
+        assert_eq!(disk_change(Some(time), Some(time), true), DiskChange::None);
+    }
+
+    #[test]
+    fn missing_file() {
+        let time = SystemTime::now();
+        assert_eq!(disk_change(Some(time), None, false), DiskChange::Missing);
+        assert_eq!(disk_change(Some(time), None, true), DiskChange::Missing);
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::diff::{diff_lines, split_lines, Hunk, HunkKind};
+use gettextrs::gettext;
+use git2::{Oid, Repository};
+use std::collections::HashMap;
+use std::iter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// How long to wait after the last edit before comparing the buffer with HEAD again
+pub(crate) const GIT_REFRESH_DELAY_MS: u32 = 500;
+
+/// Documents with more lines than this are only compared with HEAD once they've been saved, since
+/// fetching all of their lines from xi after every edit is too slow
+pub(crate) const GIT_LIVE_REFRESH_MAX_LINES: u64 = 5000;
+
+/// How long the hunk popover stays open after the pointer has left the gutter
+pub(crate) const HUNK_POPOVER_HIDE_DELAY_MS: u32 = 300;
+
+/// The marker drawn next to a line in the gutter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GutterMarker {
+    /// The line isn't in HEAD
+    Added,
+    /// The line replaces lines in HEAD
+    Modified,
+    /// Lines of HEAD have been deleted right before this line
+    Deleted,
+}
+
+/// Sent from the thread comparing the buffer with HEAD to the `EditView`
+pub(crate) enum GitMsg {
+    /// Compare the buffer with HEAD again. If true, do so even if the buffer hasn't changed since
+    /// the last time, e.g. because HEAD has changed.
+    Refresh(bool),
+    /// The result of the comparison started with the contained generation
+    Diff(u64, Option<GitDiff>),
+    /// Who has last changed the lines of HEAD
+    Blame(Option<Vec<BlameLine>>),
+}
+
+/// Who has last changed a line of HEAD, shown next to it if blame annotations are enabled
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlameLine {
+    pub author: String,
+    /// When the line has been committed, in seconds since the Unix epoch
+    pub time: i64,
+    /// The first line of the commit message
+    pub summary: String,
+}
+
+/// The differences between the buffer of an `EditView` and the version of its file in HEAD
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GitDiff {
+    /// The lines of the file in HEAD
+    pub head: Vec<String>,
+    /// How many lines the buffer has
+    pub lines: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+impl GitDiff {
+    /// Compare `text` with the HEAD version of the file at `path`. Returns `Ok(None)` if the file
+    /// isn't in a git repository or hasn't been committed yet.
+    pub fn new(path: &Path, text: &str) -> Result<Option<Self>, git2::Error> {
+        let (repo, rel_path) = match open_repo(path) {
+            Some(repo) => repo,
+            None => return Ok(None),
+        };
+        let tree = match repo.head() {
+            Ok(head) => head.peel_to_tree()?,
+            // There are no commits yet
+            Err(_) => return Ok(None),
+        };
+        let entry = match tree.get_path(&rel_path) {
+            Ok(entry) => entry,
+            Err(_) => return Ok(None),
+        };
+        let blob = entry.to_object(&repo)?.peel_to_blob()?;
+        let head = match std::str::from_utf8(blob.content()) {
+            Ok(head) => head,
+            Err(_) => return Ok(None),
+        };
+
+        Ok(Some(Self::from_texts(head, text)))
+    }
+
+    pub(crate) fn from_texts(head: &str, text: &str) -> Self {
+        let head_lines = split_lines(head);
+        let lines = split_lines(text);
+        let hunks = diff_lines(&head_lines, &lines);
+        Self {
+            head: head_lines.into_iter().map(String::from).collect(),
+            lines: lines.len(),
+            hunks,
+        }
+    }
+
+    /// The line next to which the marker of `hunk` is drawn
+    fn marker_line(&self, hunk: &Hunk) -> usize {
+        if hunk.kind() == HunkKind::Removed && hunk.new.start >= self.lines {
+            self.lines.saturating_sub(1)
+        } else {
+            hunk.new.start
+        }
+    }
+
+    /// The hunk whose marker is drawn next to `line`
+    pub fn hunk_at(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|hunk| {
+            hunk.new.contains(&line)
+                || (hunk.kind() == HunkKind::Removed && self.marker_line(hunk) == line)
+        })
+    }
+
+    /// The marker drawn next to `line`, if any
+    pub fn marker(&self, line: usize) -> Option<GutterMarker> {
+        self.hunk_at(line).map(|hunk| match hunk.kind() {
+            HunkKind::Added => GutterMarker::Added,
+            HunkKind::Changed => GutterMarker::Modified,
+            HunkKind::Removed => GutterMarker::Deleted,
+        })
+    }
+
+    /// The line of HEAD `line` of the buffer corresponds to, or `None` if it has been added or
+    /// changed since
+    pub fn head_line(&self, line: usize) -> Option<usize> {
+        let mut offset = 0_isize;
+        for hunk in &self.hunks {
+            if hunk.new.contains(&line) {
+                return None;
+            }
+            if hunk.new.start > line {
+                break;
+            }
+            offset += hunk.old.len() as isize - hunk.new.len() as isize;
+        }
+        Some((line as isize + offset) as usize)
+    }
+
+    /// The lines of HEAD which have been replaced by `hunk`
+    pub fn head_text(&self, hunk: &Hunk) -> String {
+        self.head[hunk.old.clone()].concat()
+    }
+}
+
+/// Open the repository `path` is in, and return it together with the path of the file relative
+/// to the repository's working directory
+fn open_repo(path: &Path) -> Option<(Repository, PathBuf)> {
+    let path = path.canonicalize().ok()?;
+    let repo = Repository::discover(path.parent()?).ok()?;
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let rel_path = path.strip_prefix(workdir).ok()?.to_path_buf();
+    Some((repo, rel_path))
+}
+
+/// Find out who has last changed each line of the HEAD version of the file at `path`. Returns
+/// `Ok(None)` if the file isn't in a git repository or hasn't been committed yet.
+pub fn blame(path: &Path) -> Result<Option<Vec<BlameLine>>, git2::Error> {
+    let (repo, rel_path) = match open_repo(path) {
+        Some(repo) => repo,
+        None => return Ok(None),
+    };
+    let committed = repo
+        .head()
+        .and_then(|head| head.peel_to_tree())
+        .map(|tree| tree.get_path(&rel_path).is_ok())
+        .unwrap_or(false);
+    if !committed {
+        return Ok(None);
+    }
+
+    let blame = repo.blame_file(&rel_path, None)?;
+    let mut summaries: HashMap<Oid, String> = HashMap::new();
+    let mut lines = Vec::new();
+    for hunk in blame.iter() {
+        let id = hunk.final_commit_id();
+        if !summaries.contains_key(&id) {
+            let commit = repo.find_commit(id)?;
+            summaries.insert(id, commit.summary().unwrap_or_default().to_string());
+        }
+        let signature = hunk.final_signature();
+        let line = BlameLine {
+            author: signature.name().unwrap_or_default().to_string(),
+            time: signature.when().seconds(),
+            summary: summaries[&id].clone(),
+        };
+        lines.extend(iter::repeat(line).take(hunk.lines_in_hunk()));
+    }
+    Ok(Some(lines))
+}
+
+/// Whether the ranges overlap or touch each other, which also works for empty ranges
+fn touches(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start <= b.end && b.start <= a.end
+}
+
+/// Apply the changes which turn `old` into `new` to `old`, but only those which touch the lines
+/// `range` of `new`
+fn apply_changes(old: &str, new: &str, range: &Range<usize>) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+
+    let mut result = String::new();
+    let mut x = 0;
+    for hunk in diff_lines(&old_lines, &new_lines)
+        .iter()
+        .filter(|h| touches(&h.new, range))
+    {
+        result.push_str(&old_lines[x..hunk.old.start].concat());
+        result.push_str(&new_lines[hunk.new.clone()].concat());
+        x = hunk.old.end;
+    }
+    result.push_str(&old_lines[x..].concat());
+    result
+}
+
+/// Add the change of `hunk` in `text`, the buffer of the file at `path`, to the git index. Other
+/// changes which have already been staged are kept.
+pub fn stage_hunk(path: &Path, text: &str, hunk: &Hunk) -> Result<(), git2::Error> {
+    let (repo, rel_path) = open_repo(path)
+        .ok_or_else(|| git2::Error::from_str(&gettext("The file isn’t in a git repository")))?;
+    let mut index = repo.index()?;
+    let mut entry = index
+        .get_path(&rel_path, 0)
+        .ok_or_else(|| git2::Error::from_str(&gettext("The file isn’t tracked by git")))?;
+
+    let blob = repo.find_blob(entry.id)?;
+    let staged = String::from_utf8_lossy(blob.content());
+    let content = apply_changes(&staged, text, &hunk.new);
+
+    entry.id = repo.blob(content.as_bytes())?;
+    entry.file_size = content.len() as u32;
+    index.add(&entry)?;
+    index.write()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn markers() {
+        let diff = GitDiff::from_texts("a\nb\nc\nd\n", "a\nx\nc\nnew\n");
+        assert_eq!(diff.marker(0), None);
+        assert_eq!(diff.marker(1), Some(GutterMarker::Modified));
+        assert_eq!(diff.marker(2), None);
+        assert_eq!(diff.marker(3), Some(GutterMarker::Modified));
+
+        let diff = GitDiff::from_texts("a\nb\n", "a\nb\nc\n");
+        assert_eq!(diff.marker(2), Some(GutterMarker::Added));
+        assert_eq!(diff.head_text(diff.hunk_at(2).unwrap()), "");
+    }
+
+    #[test]
+    fn deleted_markers() {
+        let diff = GitDiff::from_texts("a\nb\nc\n", "a\nc\n");
+        assert_eq!(diff.marker(0), None);
+        assert_eq!(diff.marker(1), Some(GutterMarker::Deleted));
+        assert_eq!(diff.head_text(diff.hunk_at(1).unwrap()), "b\n");
+
+        // Deleted lines at the end of the file are shown next to the last line
+        let diff = GitDiff::from_texts("a\nb\nc\n", "a\n");
+        assert_eq!(diff.marker(0), Some(GutterMarker::Deleted));
+        assert_eq!(diff.head_text(diff.hunk_at(0).unwrap()), "b\nc\n");
+    }
+
+    #[test]
+    fn head_lines() {
+        let diff = GitDiff::from_texts("a\nb\nc\nd\n", "new\na\nc\nD\n");
+        assert_eq!(diff.head_line(0), None);
+        assert_eq!(diff.head_line(1), Some(0));
+        // "b" has been deleted
+        assert_eq!(diff.head_line(2), Some(2));
+        assert_eq!(diff.head_line(3), None);
+    }
+
+    #[test]
+    fn apply_single_change() {
+        let staged = "a\nb\nc\nd\ne\n";
+        let buffer = "a\nB\nc\nd\nE\n";
+        assert_eq!(apply_changes(staged, buffer, &(1..2)), "a\nB\nc\nd\ne\n");
+        assert_eq!(apply_changes(staged, buffer, &(4..5)), "a\nb\nc\nd\nE\n");
+
+        // Deleted lines
+        assert_eq!(
+            apply_changes(staged, "a\nc\nd\ne\n", &(1..1)),
+            "a\nc\nd\ne\n"
+        );
+    }
+}
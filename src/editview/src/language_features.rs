@@ -0,0 +1,26 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::completion::CompletionItem;
+use crate::text::Position;
+
+/// Features an `EditView` can get from something which understands the document's language, e.g.
+/// a language server. Replies may arrive later, but have to be delivered on the main thread.
+pub trait LanguageFeatures {
+    /// Look up the documentation of the symbol at `position` and call `reply` with it
+    fn hover(&self, position: Position, reply: Box<dyn FnOnce(Option<String>)>);
+
+    /// Look up completions for the cursor at `position` and call `reply` with them
+    fn complete(&self, position: Position, reply: Box<dyn FnOnce(Vec<CompletionItem>)>);
+
+    /// Whether typing `c` should open the completion popover, e.g. `.` in many languages
+    fn triggers_completion(&self, c: char) -> bool;
+}
+
+/// The replies of `LanguageFeatures`, sent back to the `EditView` which has asked for them
+#[derive(Debug)]
+pub(crate) enum FeatureMsg {
+    Hover(Position, Option<String>),
+    /// Completions for the word starting at the position
+    Completions(Position, Vec<CompletionItem>),
+}
@@ -4,14 +4,29 @@
 
 #![deny(clippy::all)]
 
+pub mod auto_pairs;
+pub mod comment;
+pub mod completion;
+pub mod diagnostics;
+pub mod diff;
 pub mod draw_invisible;
 pub mod edit_view;
+pub mod encoding;
+pub mod file_watch;
 pub mod fonts;
+pub mod git_gutter;
 pub mod i18n;
+pub mod language_features;
+pub mod line_ending;
 pub mod main_state;
+pub mod selections;
+pub mod snippets;
+pub mod syntaxes;
+pub mod text;
 pub mod theme;
 mod view_item;
 
 pub use crate::edit_view::{EditView, EditViewExt};
+pub use crate::file_watch::{DiskState, FileChangedAction};
 pub use crate::main_state::{MainState, Settings};
 pub use crate::view_item::TopBar;
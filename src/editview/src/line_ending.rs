@@ -0,0 +1,210 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The line endings of documents. Xi detects the line ending of a file when opening it and uses
+//! it for new lines. It leaves the existing lines alone when it's changed via the user override,
+//! so they're converted with `conversion_edits`.
+
+use crate::text::Range;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, used by Linux and macOS
+    Lf,
+    /// `\r\n`, used by Windows
+    CrLf,
+    /// `\r`, used by classic Mac OS. Xi only breaks lines at `\n`, so documents are never
+    /// converted to it, it's only detected and shown.
+    Cr,
+}
+
+impl Default for LineEnding {
+    /// The line ending of the platform Tau runs on
+    fn default() -> Self {
+        if cfg!(windows) {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl LineEnding {
+    pub const ALL: [LineEnding; 3] = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr];
+
+    /// Whether documents can be converted to the line ending, see `LineEnding::Cr`
+    pub fn is_convertible(self) -> bool {
+        self != LineEnding::Cr
+    }
+
+    /// The chars ending a line, as used in xi's `line_ending` config
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// The name of the line ending as shown to the user
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    /// The line ending of xi's `line_ending` config
+    pub fn parse(chars: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|l| l.as_str() == chars)
+    }
+
+    /// The line ending of the `line-ending` key of the `GSchema`, where `system` is the line ending
+    /// of the platform
+    pub fn from_setting(setting: &str) -> Self {
+        match setting {
+            "lf" => LineEnding::Lf,
+            "crlf" => LineEnding::CrLf,
+            _ => LineEnding::default(),
+        }
+    }
+}
+
+/// The edits which convert all line endings of `text` to `line_ending`, as ranges of xi's lines,
+/// which only end at `\n`
+pub fn conversion_edits(text: &str, line_ending: LineEnding) -> Vec<(Range, String)> {
+    let mut edits = Vec::new();
+    let mut lines = text.split('\n').enumerate().peekable();
+    while let Some((n, line)) = lines.next() {
+        let n = n as u64;
+        let ends_with_lf = lines.peek().is_some();
+        let mut push = |range: Range, old: LineEnding| {
+            if old != line_ending {
+                edits.push((range, line_ending.as_str().to_string()));
+            }
+        };
+        for (col, _) in line.match_indices('\r') {
+            let col = col as u64;
+            if ends_with_lf && col + 1 == line.len() as u64 {
+                push(((n, col), (n + 1, 0)), LineEnding::CrLf);
+            } else {
+                push(((n, col), (n, col + 1)), LineEnding::Cr);
+            }
+        }
+        if ends_with_lf && !line.ends_with('\r') {
+            push(((n, line.len() as u64), (n + 1, 0)), LineEnding::Lf);
+        }
+    }
+    edits
+}
+
+/// How often each line ending occurs in a text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineEndingCount {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl LineEndingCount {
+    pub fn new(text: &str) -> Self {
+        let mut count = Self::default();
+        let mut bytes = text.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'\n' => count.lf += 1,
+                b'\r' if bytes.peek() == Some(&b'\n') => {
+                    bytes.next();
+                    count.crlf += 1;
+                }
+                b'\r' => count.cr += 1,
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// The line ending used most often, `None` if the text is a single line
+    pub fn dominant(&self) -> Option<LineEnding> {
+        [
+            (LineEnding::Lf, self.lf),
+            (LineEnding::CrLf, self.crlf),
+            (LineEnding::Cr, self.cr),
+        ]
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .max_by_key(|(_, n)| *n)
+        .map(|(l, _)| *l)
+    }
+
+    /// Whether the text uses more than one line ending
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr]
+            .iter()
+            .filter(|n| **n > 0)
+            .count()
+            > 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn count_line_endings() {
+        let count = LineEndingCount::new("a\nb\r\nc\rd\r\n\r\n");
+        assert_eq!(
+            count,
+            LineEndingCount {
+                lf: 1,
+                crlf: 3,
+                cr: 1
+            }
+        );
+        assert!(count.is_mixed());
+        assert_eq!(count.dominant(), Some(LineEnding::CrLf));
+
+        let count = LineEndingCount::new("a\nb\n");
+        assert!(!count.is_mixed());
+        assert_eq!(count.dominant(), Some(LineEnding::Lf));
+        assert_eq!(LineEndingCount::new("a").dominant(), None);
+        assert_eq!(LineEndingCount::new("\r").dominant(), Some(LineEnding::Cr));
+    }
+
+    #[test]
+    fn parse_line_endings() {
+        assert_eq!(LineEnding::parse("\r\n"), Some(LineEnding::CrLf));
+        assert_eq!(LineEnding::parse("\n\r"), None);
+        assert_eq!(LineEnding::from_setting("cr"), LineEnding::default());
+        assert_eq!(LineEnding::from_setting("system"), LineEnding::default());
+    }
+
+    #[test]
+    fn convert_line_endings() {
+        let text = "a\nb\r\nc\rd";
+        assert_eq!(
+            conversion_edits(text, LineEnding::Lf),
+            vec![
+                (((1, 1), (2, 0)), "\n".to_string()),
+                (((2, 1), (2, 2)), "\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            conversion_edits(text, LineEnding::CrLf),
+            vec![
+                (((0, 1), (1, 0)), "\r\n".to_string()),
+                (((2, 1), (2, 2)), "\r\n".to_string()),
+            ]
+        );
+        assert!(conversion_edits("a\nb\n", LineEnding::Lf).is_empty());
+    }
+}
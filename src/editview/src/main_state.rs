@@ -2,6 +2,7 @@
 // Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
 // SPDX-License-Identifier: MIT
 
+use crate::completion::Completion;
 use std::collections::HashMap;
 use syntect::highlighting::ThemeSettings;
 
@@ -38,4 +39,6 @@ pub struct MainState {
     pub avail_languages: Vec<String>,
     pub selected_language: String,
     pub settings: Settings,
+    /// Provides completions for all `EditView`s
+    pub completion: Completion,
 }
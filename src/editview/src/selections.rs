@@ -0,0 +1,155 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Reconstructs the selections of a document from the lines xi sends us. Xi only tells us which
+//! parts of each line are selected, so selections spanning several lines are joined again here.
+
+use crate::text::{Position, Range};
+use tau_rpc::StyleDef;
+
+/// The style xi uses for selections
+const SELECTION_STYLE: u64 = 0;
+
+/// A line of the line cache
+pub struct SelectedLine<'a> {
+    /// The number of the line in the line cache
+    pub line: u64,
+    pub text: &'a str,
+    pub styles: &'a [StyleDef],
+    pub cursors: &'a [u64],
+}
+
+/// The selected parts of a line as (start, end) byte offsets. The offsets of xi's styles are
+/// relative to the end of the previous style.
+fn selected_spans(styles: &[StyleDef]) -> Vec<(u64, u64)> {
+    let mut end = 0i64;
+    let mut spans = Vec::new();
+    for style in styles {
+        end += style.offset + style.length as i64;
+        if style.style_id == SELECTION_STYLE && style.length > 0 {
+            let start = (end - style.length as i64).max(0) as u64;
+            spans.push((start, end as u64));
+        }
+    }
+    spans
+}
+
+/// The selections and carets in `lines`, which have to be consecutive. Each range goes from the
+/// anchor of the selection to its caret, so ranges whose caret is at their start are reversed.
+/// Carets without a selection are empty ranges.
+pub fn ranges(lines: &[SelectedLine]) -> Vec<Range> {
+    let mut selections: Vec<Range> = Vec::new();
+    let mut carets: Vec<Position> = Vec::new();
+
+    for line in lines {
+        let len = line.text.trim_end_matches(|c| c == '\n' || c == '\r').len() as u64;
+        for (start, end) in selected_spans(line.styles) {
+            // A selection including the line break ends at the start of the next line
+            let end = if end > len {
+                (line.line + 1, 0)
+            } else {
+                (line.line, end)
+            };
+            match selections.last_mut() {
+                Some(last) if start == 0 && last.1 == (line.line, 0) => last.1 = end,
+                _ => selections.push(((line.line, start), end)),
+            }
+        }
+        carets.extend(line.cursors.iter().map(|c| (line.line, *c)));
+    }
+
+    let mut ranges: Vec<Range> = selections
+        .into_iter()
+        .map(|(start, end)| {
+            if carets.contains(&start) && !carets.contains(&end) {
+                (end, start)
+            } else {
+                (start, end)
+            }
+        })
+        .collect();
+    for caret in carets {
+        let selected = ranges.iter().any(|(a, b)| *a == caret || *b == caret);
+        if !selected {
+            ranges.push((caret, caret));
+        }
+    }
+    ranges.sort_by_key(|(a, b)| *a.min(b));
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn style(offset: i64, length: u64, style_id: u64) -> StyleDef {
+        StyleDef {
+            offset,
+            length,
+            style_id,
+        }
+    }
+
+    #[test]
+    fn spans() {
+        let styles = [style(2, 3, 0), style(1, 2, 5), style(-2, 1, 0)];
+        assert_eq!(selected_spans(&styles), vec![(2, 5), (6, 7)]);
+    }
+
+    #[test]
+    fn multi_line_selection() {
+        let first = [style(4, 4, 0)];
+        let second = [style(0, 9, 0)];
+        let third = [style(0, 3, 0)];
+        let lines = [
+            SelectedLine {
+                line: 3,
+                text: "let a = 1;\n",
+                styles: &[],
+                cursors: &[],
+            },
+            SelectedLine {
+                line: 4,
+                text: "foo bar\n",
+                styles: &first,
+                cursors: &[],
+            },
+            SelectedLine {
+                line: 5,
+                text: "baz quux\n",
+                styles: &second,
+                cursors: &[],
+            },
+            SelectedLine {
+                line: 6,
+                text: "end of it\n",
+                styles: &third,
+                cursors: &[3],
+            },
+        ];
+        assert_eq!(ranges(&lines), vec![((4, 4), (6, 3))]);
+    }
+
+    #[test]
+    fn reversed_selections_and_carets() {
+        let styles = [style(0, 3, 0)];
+        let lines = [
+            SelectedLine {
+                line: 0,
+                text: "abcdef\n",
+                styles: &styles,
+                cursors: &[0, 5],
+            },
+            SelectedLine {
+                line: 1,
+                text: "\n",
+                styles: &[],
+                cursors: &[0],
+            },
+        ];
+        assert_eq!(
+            ranges(&lines),
+            vec![((0, 3), (0, 0)), ((0, 5), (0, 5)), ((1, 0), (1, 0))]
+        );
+    }
+}
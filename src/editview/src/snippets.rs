@@ -0,0 +1,434 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::text::{Position, Range};
+use log::{debug, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The name of the snippet file which applies to all syntaxes
+const GLOBAL_SNIPPETS: &str = "global";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SnippetBody {
+    Single(String),
+    Lines(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct SnippetDef {
+    prefix: String,
+    body: SnippetBody,
+    #[serde(default)]
+    description: String,
+}
+
+/// A VS Code-style snippet, which is inserted when its prefix is typed and Tab is pressed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub name: String,
+    pub prefix: String,
+    pub body: String,
+    pub description: String,
+}
+
+/// All snippets the user has configured, keyed by the (lowercase) syntax they apply to
+#[derive(Debug, Default)]
+pub struct SnippetLibrary {
+    snippets: HashMap<String, Vec<Snippet>>,
+}
+
+impl SnippetLibrary {
+    /// Load all `<syntax>.json` files in `dir`, e.g. `rust.json`. Snippets in `global.json` are
+    /// available for every syntax.
+    pub fn load(dir: &Path) -> Self {
+        let mut snippets = HashMap::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Not loading snippets from '{:?}': {}", dir, e);
+                return Self::default();
+            }
+        };
+
+        for path in entries.filter_map(Result::ok).map(|e| e.path()) {
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let syntax = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(s) => s.to_lowercase(),
+                None => continue,
+            };
+
+            match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| {
+                    serde_json::from_str::<BTreeMap<String, SnippetDef>>(&s)
+                        .map_err(|e| e.to_string())
+                }) {
+                Ok(defs) => {
+                    debug!("Loaded {} snippets from '{:?}'", defs.len(), path);
+                    snippets.insert(
+                        syntax,
+                        defs.into_iter()
+                            .map(|(name, def)| Snippet {
+                                name,
+                                prefix: def.prefix,
+                                body: match def.body {
+                                    SnippetBody::Single(s) => s,
+                                    SnippetBody::Lines(l) => l.join("\n"),
+                                },
+                                description: def.description,
+                            })
+                            .collect(),
+                    );
+                }
+                Err(e) => warn!("Failed to load snippets from '{:?}': {}", path, e),
+            }
+        }
+
+        Self { snippets }
+    }
+
+    /// Returns the snippets for `syntax` and the global ones
+    pub fn for_syntax(&self, syntax: &str) -> Vec<Snippet> {
+        let syntax = syntax.to_lowercase();
+        let mut ret: Vec<Snippet> = self
+            .snippets
+            .get(&syntax)
+            .into_iter()
+            .chain(
+                self.snippets
+                    .get(GLOBAL_SNIPPETS)
+                    .filter(|_| syntax != GLOBAL_SNIPPETS),
+            )
+            .flatten()
+            .cloned()
+            .collect();
+        ret.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        ret
+    }
+}
+
+/// Find the snippet whose prefix the text before the cursor ends with. The prefix has to start
+/// at a word boundary, the longest prefix wins.
+pub fn find_trigger<'a>(snippets: &'a [Snippet], before_cursor: &str) -> Option<&'a Snippet> {
+    snippets
+        .iter()
+        .filter(|s| !s.prefix.is_empty() && before_cursor.ends_with(&s.prefix))
+        .filter(|s| {
+            let rest = &before_cursor[..before_cursor.len() - s.prefix.len()];
+            match (rest.chars().next_back(), s.prefix.chars().next()) {
+                (Some(p), Some(f)) => !(is_word_char(p) && is_word_char(f)),
+                _ => true,
+            }
+        })
+        .max_by_key(|s| s.prefix.len())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+enum Token {
+    Text(String),
+    TabStop(u32, Option<String>),
+}
+
+fn tokenize(body: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = body.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('$') | Some('}') | Some('\\') => text.push(chars.next().unwrap()),
+                _ => text.push(c),
+            },
+            '$' => {
+                let braced = chars.peek() == Some(&'{');
+                let mut lookahead = chars.clone();
+                if braced {
+                    lookahead.next();
+                }
+                let mut digits = String::new();
+                while let Some(d) = lookahead.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    lookahead.next();
+                }
+                let index = match digits.parse::<u32>() {
+                    Ok(i) => i,
+                    Err(_) => {
+                        text.push(c);
+                        continue;
+                    }
+                };
+
+                let default = if braced {
+                    match lookahead.next() {
+                        Some('}') => None,
+                        Some(':') => {
+                            let mut default = String::new();
+                            let mut closed = false;
+                            while let Some(d) = lookahead.next() {
+                                match d {
+                                    '\\' if lookahead.peek() == Some(&'}') => {
+                                        default.push(lookahead.next().unwrap())
+                                    }
+                                    '}' => {
+                                        closed = true;
+                                        break;
+                                    }
+                                    _ => default.push(d),
+                                }
+                            }
+                            if !closed {
+                                text.push(c);
+                                continue;
+                            }
+                            Some(default)
+                        }
+                        _ => {
+                            text.push(c);
+                            continue;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                chars = lookahead;
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(Token::TabStop(index, default));
+            }
+            _ => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    tokens
+}
+
+/// A snippet's body with its tab stops resolved
+#[derive(Debug, PartialEq)]
+pub struct Expansion {
+    /// The text to insert
+    pub text: String,
+    /// The byte ranges of each tab stop in `text`, in the order they're visited. The last one is
+    /// where the cursor ends up after the snippet is done.
+    pub tab_stops: Vec<Vec<(usize, usize)>>,
+}
+
+impl Expansion {
+    /// Resolve the placeholders in `body`. Every line but the first is indented with `indent`.
+    pub fn new(body: &str, indent: &str) -> Self {
+        let tokens = tokenize(body);
+        let indented = |s: &str| s.replace('\n', &format!("\n{}", indent));
+
+        // Mirrors get the default of whichever occurrence of the tab stop has one
+        let mut defaults: HashMap<u32, String> = HashMap::new();
+        for token in &tokens {
+            if let Token::TabStop(i, Some(default)) = token {
+                defaults.entry(*i).or_insert_with(|| indented(default));
+            }
+        }
+
+        let mut text = String::new();
+        let mut stops: BTreeMap<u32, Vec<(usize, usize)>> = BTreeMap::new();
+        for token in tokens {
+            match token {
+                Token::Text(t) => text.push_str(&indented(&t)),
+                Token::TabStop(i, _) => {
+                    let start = text.len();
+                    text.push_str(defaults.get(&i).map(String::as_str).unwrap_or(""));
+                    stops
+                        .entry(i)
+                        .or_insert_with(Vec::new)
+                        .push((start, text.len()));
+                }
+            }
+        }
+
+        // $0 is visited last, and is the end of the snippet if it isn't specified
+        let end = stops
+            .remove(&0)
+            .unwrap_or_else(|| vec![(text.len(), text.len())]);
+        let mut tab_stops: Vec<_> = stops.into_iter().map(|(_, r)| r).collect();
+        tab_stops.push(end);
+
+        Self { text, tab_stops }
+    }
+
+    /// Returns the position of the byte `offset` of `text` if `text` is inserted at `start`
+    fn position(&self, start: Position, offset: usize) -> Position {
+        let before = &self.text[..offset];
+        match before.rfind('\n') {
+            Some(nl) => (
+                start.0 + before.matches('\n').count() as u64,
+                (offset - nl - 1) as u64,
+            ),
+            None => (start.0, start.1 + offset as u64),
+        }
+    }
+
+    /// Returns the document ranges of the tab stops if the text is inserted at `start`
+    pub fn ranges(&self, start: Position) -> Vec<Vec<Range>> {
+        self.tab_stops
+            .iter()
+            .map(|stop| {
+                stop.iter()
+                    .map(|(s, e)| (self.position(start, *s), self.position(start, *e)))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Keeps track of the tab stops of a snippet which is currently being filled in
+#[derive(Debug, PartialEq)]
+pub struct SnippetSession {
+    stops: Vec<Vec<Range>>,
+    current: usize,
+}
+
+impl SnippetSession {
+    pub fn new(stops: Vec<Vec<Range>>) -> Self {
+        Self { stops, current: 0 }
+    }
+
+    /// The ranges of the tab stop the user is currently at
+    pub fn current(&self) -> &[Range] {
+        &self.stops[self.current]
+    }
+
+    /// Returns true if the user is at the final tab stop, after which the snippet is done
+    pub fn is_finished(&self) -> bool {
+        self.current + 1 >= self.stops.len()
+    }
+
+    /// Account for the text the user has typed into the current tab stop, given the cursor
+    /// position at the end of the first of its ranges. Returns false if we lost track of the tab
+    /// stops, e.g. because the user typed a newline.
+    fn update(&mut self, cursor: Position) -> bool {
+        let ranges: Vec<Range> = self.current().to_vec();
+        let (first_start, first_end) = ranges[0];
+        if cursor.0 != first_end.0 || cursor.1 < first_start.1 {
+            return false;
+        }
+        let delta = cursor.1 as i64 - first_end.1 as i64;
+        if delta == 0 {
+            return true;
+        }
+
+        // Every range of the current tab stop has been edited the same way, so everything behind
+        // them on the same line moves by `delta` for every one of them
+        let shift = |pos: Position, own: Option<usize>| -> Position {
+            let n = ranges
+                .iter()
+                .enumerate()
+                .filter(|(i, (_, end))| end.0 == pos.0 && end.1 <= pos.1 && Some(*i) != own)
+                .count() as i64;
+            (pos.0, (pos.1 as i64 + n * delta).max(0) as u64)
+        };
+
+        let current = self.current;
+        for (stop_ix, stop) in self.stops.iter_mut().enumerate() {
+            for (range_ix, (start, end)) in stop.iter_mut().enumerate() {
+                let own = if stop_ix == current {
+                    Some(range_ix)
+                } else {
+                    None
+                };
+                *start = shift(*start, own);
+                *end = shift(*end, None);
+            }
+        }
+        true
+    }
+
+    /// Move to the next tab stop. Returns its ranges, or `None` if we lost track of them.
+    pub fn next(&mut self, cursor: Position) -> Option<&[Range]> {
+        if !self.update(cursor) || self.is_finished() {
+            return None;
+        }
+        self.current += 1;
+        Some(self.current())
+    }
+
+    /// Move to the previous tab stop. Returns its ranges, or `None` if we lost track of them.
+    pub fn previous(&mut self, cursor: Position) -> Option<&[Range]> {
+        if !self.update(cursor) {
+            return None;
+        }
+        self.current = self.current.saturating_sub(1);
+        Some(self.current())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snippet(prefix: &str) -> Snippet {
+        Snippet {
+            name: prefix.to_string(),
+            prefix: prefix.to_string(),
+            body: String::new(),
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn expand_placeholders() {
+        let exp = Expansion::new("fn ${1:name}($2) {\n\t$0\n} // $1", "    ");
+        assert_eq!(exp.text, "fn name() {\n    \t\n    } // name");
+        assert_eq!(
+            exp.tab_stops,
+            vec![vec![(3, 7), (27, 31)], vec![(8, 8)], vec![(17, 17)]]
+        );
+        assert_eq!(exp.ranges((2, 4))[2], vec![((3, 5), (3, 5))],);
+    }
+
+    #[test]
+    fn expand_escapes() {
+        let exp = Expansion::new("\\$1 costs $ ${2:\\}}", "");
+        assert_eq!(exp.text, "$1 costs $ }");
+        assert_eq!(exp.tab_stops, vec![vec![(11, 12)], vec![(12, 12)]]);
+    }
+
+    #[test]
+    fn trigger() {
+        let snippets = vec![snippet("fn"), snippet("pfn"), snippet("<")];
+        assert_eq!(find_trigger(&snippets, "    pfn"), Some(&snippets[1]));
+        assert_eq!(find_trigger(&snippets, "fn"), Some(&snippets[0]));
+        assert_eq!(find_trigger(&snippets, "xfn"), None);
+        assert_eq!(find_trigger(&snippets, "a<"), Some(&snippets[2]));
+    }
+
+    #[test]
+    fn session_tracks_edits() {
+        // "${1:ab} + $1 = $2" inserted at (0, 0)
+        let mut session = SnippetSession::new(vec![
+            vec![((0, 0), (0, 2)), ((0, 5), (0, 7))],
+            vec![((0, 10), (0, 10))],
+            vec![((0, 10), (0, 10))],
+        ]);
+        // The user replaced "ab" with "abcd" in both mirrors
+        assert_eq!(session.next((0, 4)), Some(&[((0, 14), (0, 14))][..]));
+        assert!(!session.is_finished());
+        assert_eq!(
+            session.previous((0, 14)),
+            Some(&[((0, 0), (0, 4)), ((0, 7), (0, 11))][..])
+        );
+        assert_eq!(session.next((1, 0)), None);
+    }
+}
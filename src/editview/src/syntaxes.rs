@@ -0,0 +1,11 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use lazy_static::lazy_static;
+use syntect::parsing::SyntaxSet;
+
+lazy_static! {
+    /// syntect's default syntaxes, for their metadata (e.g. comment markers) and definitions.
+    /// Loading them takes a moment, so it's done once, when they're needed for the first time.
+    pub static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Positions and ranges in a document and edits of them, as selections, snippets, diagnostics
+//! and replacements use them.
+
+/// A (line, col) position in the document, with cols in bytes
+pub type Position = (u64, u64);
+
+/// A range in the document, from the first to the second position
+pub type Range = (Position, Position);
+
+/// The byte offset of each line's start in `text`
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    starts
+}
+
+/// The byte offset of `position` in `text`, clamped to the end of the text
+fn offset(text: &str, starts: &[usize], position: Position) -> usize {
+    starts
+        .get(position.0 as usize)
+        .map_or(text.len(), |start| start + position.1 as usize)
+        .min(text.len())
+}
+
+/// Join the `edits` of `text`, which have to be sorted and may not overlap, into a single edit of
+/// the range from the start of the first to the end of the last one. Makes them a single step
+/// which can be undone at once. Returns `None` if there are no edits.
+pub fn merge_edits(text: &str, edits: &[(Range, String)]) -> Option<(Range, String)> {
+    let first = edits.first()?;
+    let last = edits.last()?;
+    let starts = line_starts(text);
+
+    let mut merged = String::new();
+    let mut end = offset(text, &starts, (first.0).0);
+    for ((start, stop), new) in edits {
+        let start = offset(text, &starts, *start).max(end);
+        merged.push_str(&text[end..start]);
+        merged.push_str(new);
+        end = offset(text, &starts, *stop).max(start);
+    }
+    Some((((first.0).0, (last.0).1), merged))
+}
+
+/// Where `position` is once `range` has been replaced by `new`. Positions inside of `range` move
+/// to the end of the new text.
+fn shift_position(position: Position, (start, end): Range, new: &str) -> Position {
+    if position < start || (position == start && start != end) {
+        return position;
+    }
+    let new_end = match new.rfind('\n') {
+        Some(i) => (
+            start.0 + new.matches('\n').count() as u64,
+            (new.len() - i - 1) as u64,
+        ),
+        None => (start.0, start.1 + new.len() as u64),
+    };
+    if position <= end {
+        new_end
+    } else if position.0 == end.0 {
+        (new_end.0, new_end.1 + position.1 - end.1)
+    } else {
+        (position.0 - end.0 + new_end.0, position.1)
+    }
+}
+
+/// Where the `ranges` are once the `edits`, which have to be sorted and may not overlap, have
+/// been made, e.g. to keep the selections of the user
+pub fn shift_ranges(ranges: &[Range], edits: &[(Range, String)]) -> Vec<Range> {
+    let shift = |position: Position| {
+        // Later edits don't move the positions of earlier ones
+        edits.iter().rev().fold(position, |position, (range, new)| {
+            shift_position(position, *range, new)
+        })
+    };
+    ranges
+        .iter()
+        .map(|(start, end)| (shift(*start), shift(*end)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edits() -> Vec<(Range, String)> {
+        vec![
+            (((0, 4), (0, 7)), "x".to_string()),
+            (((1, 0), (1, 3)), "one\ntwo".to_string()),
+        ]
+    }
+
+    #[test]
+    fn merge() {
+        let text = "let foo = 1;\nfoo + 2\n";
+        assert_eq!(
+            merge_edits(text, &edits()),
+            Some((((0, 4), (1, 3)), "x = 1;\none\ntwo".to_string()))
+        );
+        assert_eq!(merge_edits(text, &[]), None);
+    }
+
+    #[test]
+    fn shift() {
+        let ranges = [
+            ((0, 0), (0, 3)),
+            ((0, 5), (0, 5)),
+            ((0, 8), (0, 10)),
+            ((1, 5), (1, 7)),
+            ((2, 0), (2, 0)),
+        ];
+        assert_eq!(
+            shift_ranges(&ranges, &edits()),
+            vec![
+                ((0, 0), (0, 3)),
+                ((0, 5), (0, 5)),
+                ((0, 6), (0, 8)),
+                ((2, 5), (2, 7)),
+                ((3, 0), (3, 0)),
+            ]
+        );
+    }
+}
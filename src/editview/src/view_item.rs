@@ -2,17 +2,20 @@
 // Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
 // SPDX-License-Identifier: MIT
 
-use crate::edit_view::EditView;
-use gdk::{Cursor, CursorType, DisplayManager, WindowExt};
+use crate::edit_view::{EditView, EditViewExt};
+use crate::line_ending::LineEnding;
+use crate::snippets::Snippet;
+use gdk::{Cursor, CursorType, DisplayManager, EventMask, NotifyType, WindowExt};
 use gettextrs::gettext;
 use gio::prelude::*;
 use gio::Resource;
 use glib::{clone, Bytes};
 use gtk::prelude::*;
 use gtk::{
-    Adjustment, Box, Builder, Button, CheckButton, EventBox, GestureDrag, GestureZoom, Grid,
-    Inhibit, Label, Layout, ListStore, Menu, MenuButton, Popover, PositionType, Revealer,
-    ScrolledWindow, SearchBar, SearchEntry, SpinButton, Statusbar, ToggleButton, TreeView, Widget,
+    Adjustment, Box, Builder, Button, CheckButton, EventBox, GestureDrag, GestureZoom, Grid, Image,
+    InfoBar, Inhibit, Label, Layout, ListStore, Menu, MenuButton, ModelButton, Popover,
+    PositionType, ResponseType, Revealer, ScrolledWindow, SearchBar, SearchEntry, SpinButton,
+    Statusbar, ToggleButton, TreeView, Widget,
 };
 use log::{debug, error, trace};
 use serde_json::json;
@@ -30,12 +33,23 @@ pub struct EvBar {
     syntax_popover: Popover,
     pub line_label: Label,
     pub column_label: Label,
+    pub encoding_label: Label,
+    line_ending_menu_button: MenuButton,
+    line_ending_label: Label,
+    line_ending_warning_image: Image,
+    line_ending_popover: Popover,
+    line_ending_mixed_label: Label,
+    line_ending_buttons: Vec<(LineEnding, ModelButton)>,
     list_model: ListStore,
     edit_settings_popover: Popover,
     pub auto_indentation_button: ToggleButton,
     pub insert_spaces_button: ToggleButton,
     pub tab_size_button: SpinButton,
     pub tab_width_label: Label,
+    snippets_menu_button: MenuButton,
+    snippets_popover: Popover,
+    snippets_treeview: TreeView,
+    snippets_list_model: ListStore,
 }
 
 #[derive(Clone)]
@@ -50,6 +64,36 @@ struct DragData {
     start_y: f64,
 }
 
+/// The infobar which is shown if the file has been changed on disk while there are unsaved changes
+#[derive(Clone)]
+pub(crate) struct FileChangedBar {
+    pub info_bar: InfoBar,
+    pub label: Label,
+}
+
+impl FileChangedBar {
+    pub const RELOAD: ResponseType = ResponseType::Other(0);
+    pub const KEEP: ResponseType = ResponseType::Other(1);
+    pub const COMPARE: ResponseType = ResponseType::Other(2);
+}
+
+/// The popover showing the committed version of a hunk marked in the line-number gutter
+#[derive(Clone)]
+pub(crate) struct GitHunkPopup {
+    pub popover: Popover,
+    pub label: Label,
+    pub revert_button: Button,
+    pub stage_button: Button,
+}
+
+/// The popover listing completions at the cursor
+#[derive(Clone)]
+pub(crate) struct CompletionPopup {
+    pub popover: Popover,
+    pub treeview: TreeView,
+    pub list_model: ListStore,
+}
+
 #[derive(Clone)]
 pub struct GoToLine {
     pub popover: Popover,
@@ -70,6 +114,9 @@ pub struct ViewItem {
     pub(crate) context_menu: Menu,
     pub(crate) gestures: Gestures,
     pub(crate) go_to_line: GoToLine,
+    pub(crate) completion: CompletionPopup,
+    pub(crate) file_changed: FileChangedBar,
+    pub(crate) git_hunk: GitHunkPopup,
 }
 
 impl ViewItem {
@@ -93,6 +140,22 @@ impl ViewItem {
             syntax_menu_button: builder.get_object("syntax_menu_button").unwrap(),
             line_label: builder.get_object("line_label").unwrap(),
             column_label: builder.get_object("column_label").unwrap(),
+            encoding_label: builder.get_object("encoding_label").unwrap(),
+            line_ending_menu_button: builder.get_object("line_ending_menu_button").unwrap(),
+            line_ending_label: builder.get_object("line_ending_label").unwrap(),
+            line_ending_warning_image: builder.get_object("line_ending_warning_image").unwrap(),
+            line_ending_popover: builder.get_object("line_ending_popover").unwrap(),
+            line_ending_mixed_label: builder.get_object("line_ending_mixed_label").unwrap(),
+            line_ending_buttons: vec![
+                (
+                    LineEnding::Lf,
+                    builder.get_object("line_ending_lf_button").unwrap(),
+                ),
+                (
+                    LineEnding::CrLf,
+                    builder.get_object("line_ending_crlf_button").unwrap(),
+                ),
+            ],
             list_model: builder.get_object("syntax_liststore").unwrap(),
             edit_settings_popover: builder.get_object("edit_settings_popover").unwrap(),
             auto_indentation_button: builder
@@ -105,6 +168,10 @@ impl ViewItem {
                 .get_object("edit_settings_tab_size_spinbutton")
                 .unwrap(),
             tab_width_label: builder.get_object("tab_width_label").unwrap(),
+            snippets_menu_button: builder.get_object("snippets_menu_button").unwrap(),
+            snippets_popover: builder.get_object("snippets_popover").unwrap(),
+            snippets_treeview: builder.get_object("snippets_treeview").unwrap(),
+            snippets_list_model: builder.get_object("snippets_liststore").unwrap(),
         };
         statusbar
             .tab_width_label
@@ -119,6 +186,37 @@ impl ViewItem {
         go_to_line.popover.set_position(PositionType::Bottom);
         go_to_line.popover.set_relative_to(Some(btn));
 
+        let completion = CompletionPopup {
+            popover: builder.get_object("completion_popover").unwrap(),
+            treeview: builder.get_object("completion_treeview").unwrap(),
+            list_model: builder.get_object("completion_liststore").unwrap(),
+        };
+
+        let file_changed = FileChangedBar {
+            info_bar: builder.get_object("file_changed_info_bar").unwrap(),
+            label: builder.get_object("file_changed_label").unwrap(),
+        };
+        file_changed
+            .info_bar
+            .add_button(&gettext("_Compare"), FileChangedBar::COMPARE);
+        file_changed
+            .info_bar
+            .add_button(&gettext("_Keep Mine"), FileChangedBar::KEEP);
+        file_changed
+            .info_bar
+            .add_button(&gettext("_Reload"), FileChangedBar::RELOAD);
+
+        let git_hunk = GitHunkPopup {
+            popover: builder.get_object("git_hunk_popover").unwrap(),
+            label: builder.get_object("git_hunk_label").unwrap(),
+            revert_button: builder.get_object("git_hunk_revert_button").unwrap(),
+            stage_button: builder.get_object("git_hunk_stage_button").unwrap(),
+        };
+        git_hunk.popover.set_relative_to(Some(&linecount));
+        git_hunk
+            .popover
+            .add_events(EventMask::ENTER_NOTIFY_MASK | EventMask::LEAVE_NOTIFY_MASK);
+
         let context_menu_builder =
             Builder::new_from_resource("/org/gnome/Tau/editview/context_menu.glade");
         let gmenu: gio::Menu = context_menu_builder.get_object("context_menu").unwrap();
@@ -131,6 +229,9 @@ impl ViewItem {
         let zoom = GestureZoom::new(&ev_scrolled_window);
         let hbox: Grid = builder.get_object("ev_root_widget").unwrap();
         hbox.show_all();
+        completion
+            .popover
+            .set_relative_to(Some(&ev_scrolled_window));
 
         Self {
             edit_area,
@@ -142,6 +243,9 @@ impl ViewItem {
             context_menu,
             root_box: hbox,
             go_to_line,
+            completion,
+            file_changed,
+            git_hunk,
             gestures: Gestures {
                 drag,
                 drag_data: Rc::new(RefCell::new(DragData {
@@ -171,9 +275,30 @@ impl ViewItem {
                 edit_view.handle_da_draw(ctx)
             }));
 
+        self.edit_area.set_has_tooltip(true);
+        self.edit_area.connect_query_tooltip(
+            clone!(@weak edit_view => @default-panic, move |_, x, y, _, tooltip| {
+                edit_view.handle_query_tooltip(x, y, tooltip)
+            }),
+        );
+
         self.ev_scrolled_window.connect_key_press_event(
             clone!(@weak edit_view => @default-panic, move |_, ek| {
-                edit_view.handle_key_press_event(ek)
+                let inhibit = edit_view.handle_key_press_event(ek);
+                edit_view.update_completion_after_key(ek);
+                inhibit
+            }),
+        );
+
+        self.completion.treeview.connect_row_activated(
+            clone!(@weak edit_view => @default-panic, move |_, path, _| {
+                edit_view.accept_completion(Some(path));
+            }),
+        );
+
+        self.file_changed.info_bar.connect_response(
+            clone!(@weak edit_view => @default-panic, move |_, response| {
+                edit_view.handle_file_changed_response(response);
             }),
         );
 
@@ -226,6 +351,52 @@ impl ViewItem {
                 edit_view.handle_linecount_draw(ctx)
             }));
 
+        self.linecount
+            .add_events(EventMask::POINTER_MOTION_MASK | EventMask::LEAVE_NOTIFY_MASK);
+
+        self.linecount.connect_motion_notify_event(
+            clone!(@weak edit_view => @default-panic, move |_, em| {
+                edit_view.handle_gutter_motion(em.get_position().1);
+                Inhibit(false)
+            }),
+        );
+
+        self.linecount.connect_leave_notify_event(
+            clone!(@weak edit_view => @default-panic, move |_, _| {
+                edit_view.schedule_hunk_popover_hide();
+                Inhibit(false)
+            }),
+        );
+
+        self.git_hunk.popover.connect_enter_notify_event(
+            clone!(@weak edit_view => @default-panic, move |_, _| {
+                edit_view.cancel_hunk_popover_hide();
+                Inhibit(false)
+            }),
+        );
+
+        self.git_hunk.popover.connect_leave_notify_event(
+            clone!(@weak edit_view => @default-panic, move |_, ec| {
+                // Moving onto one of the buttons doesn't mean the pointer has left the popover
+                if ec.get_detail() != NotifyType::Inferior {
+                    edit_view.schedule_hunk_popover_hide();
+                }
+                Inhibit(false)
+            }),
+        );
+
+        self.git_hunk.revert_button.connect_clicked(
+            clone!(@weak edit_view => @default-panic, move |_| {
+                edit_view.revert_hunk();
+            }),
+        );
+
+        self.git_hunk.stage_button.connect_clicked(
+            clone!(@weak edit_view => @default-panic, move |_| {
+                edit_view.stage_hunk();
+            }),
+        );
+
         self.statusbar
             .syntax_treeview
             .get_selection()
@@ -250,6 +421,27 @@ impl ViewItem {
                 }
             }));
 
+        self.statusbar.snippets_treeview.connect_row_activated(
+            clone!(@weak edit_view => @default-panic, move |tv, path, _| {
+                let statusbar = &edit_view.view_item.statusbar;
+                if let Some(iter) = statusbar.snippets_list_model.get_iter(path) {
+                    if let Ok(Some(name)) = statusbar.snippets_list_model.get_value(&iter, 2).get::<String>() {
+                        statusbar.snippets_popover.popdown();
+                        edit_view.insert_snippet(&name);
+                    }
+                }
+                tv.get_selection().unselect_all();
+            }),
+        );
+
+        for (line_ending, button) in &self.statusbar.line_ending_buttons {
+            let line_ending = *line_ending;
+            button.connect_clicked(clone!(@weak edit_view => @default-panic, move |_| {
+                edit_view.view_item.statusbar.line_ending_popover.popdown();
+                edit_view.convert_line_endings(line_ending);
+            }));
+        }
+
         self.statusbar.tab_size_button.connect_value_changed(
             clone!(@weak edit_view => @default-panic, move |sb| {
                 // We only allow vals that fit in a u32 via es_tab_size_spinbutton_adj
@@ -367,6 +559,43 @@ impl ViewItem {
     }
 }
 
+impl EvBar {
+    /// Fill the snippet browser with `snippets`, hiding it if there are none
+    pub(crate) fn set_snippets(&self, snippets: &[Snippet]) {
+        self.snippets_list_model.clear();
+        for snippet in snippets {
+            let description = if snippet.description.is_empty() {
+                &snippet.name
+            } else {
+                &snippet.description
+            };
+            self.snippets_list_model.insert_with_values(
+                None,
+                &[0, 1, 2],
+                &[&snippet.prefix, description, &snippet.name],
+            );
+        }
+        self.snippets_menu_button.set_visible(!snippets.is_empty());
+    }
+
+    /// Show `line_ending` in the statusbar, with a warning if the document also contains others
+    pub(crate) fn set_line_ending(&self, line_ending: LineEnding, mixed: bool) {
+        self.line_ending_label.set_text(line_ending.name());
+        self.line_ending_warning_image.set_visible(mixed);
+        self.line_ending_mixed_label.set_visible(mixed);
+        let tooltip = if mixed {
+            gettext("The document mixes line endings")
+        } else {
+            gettext("Line Ending")
+        };
+        self.line_ending_menu_button
+            .set_tooltip_text(Some(tooltip.as_str()));
+        for (l, button) in &self.line_ending_buttons {
+            button.set_property_active(*l == line_ending && !mixed);
+        }
+    }
+}
+
 /// Contains the top part of the `EditView`, tab widget and top bar.
 pub struct TopBar {
     pub tab_widget: gtk::Box,
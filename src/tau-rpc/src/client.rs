@@ -277,6 +277,19 @@ impl Client {
         );
     }
 
+    /// Invoke a command a plugin has advertised via `update_cmds`
+    pub fn plugin_rpc(&self, view_id: ViewId, receiver: &str, rpc: &PlaceholderRpc) {
+        self.send_notification(
+            "plugin",
+            &json!({
+                "command": "plugin_rpc",
+                "view_id": view_id,
+                "receiver": receiver,
+                "rpc": rpc,
+            }),
+        );
+    }
+
     pub fn set_theme(&self, theme_name: &str) {
         self.send_notification("set_theme", &json!({ "theme_name": theme_name }));
     }
@@ -292,10 +305,11 @@ impl Client {
         );
     }
 
+    /// Move the cursor to the start of `line`, starting at 0
     pub fn goto_line(&self, view_id: ViewId, line: u64) {
         self.send_edit_cmd(
             view_id,
-            "insert",
+            "goto_line",
             &json!({
                 "line": line,
             }),
@@ -456,6 +470,28 @@ impl Client {
         self.send_edit_cmd(view_id, "select_all", &json!({}))
     }
 
+    /// Adds a cursor on the line above every current cursor
+    pub fn add_selection_above(&self, view_id: ViewId) {
+        self.send_edit_cmd(view_id, "add_selection_above", &json!({}))
+    }
+
+    /// Adds a cursor on the line below every current cursor
+    pub fn add_selection_below(&self, view_id: ViewId) {
+        self.send_edit_cmd(view_id, "add_selection_below", &json!({}))
+    }
+
+    /// Sets the search query to the last selection, or the word under the cursor if
+    /// nothing is selected.
+    pub fn selection_for_find(&self, view_id: ViewId, case_sensitive: bool) {
+        self.send_edit_cmd(
+            view_id,
+            "selection_for_find",
+            &json!({
+                "case_sensitive": case_sensitive,
+            }),
+        )
+    }
+
     /// moves the cursor to a point (click)
     pub fn gesture_point_select(&self, view_id: ViewId, line: u64, col: u64) {
         self.send_edit_cmd(
@@ -489,6 +525,23 @@ impl Client {
             }),
         )
     }
+    /// adds a caret at a point, or removes the selection containing it
+    pub fn gesture_toggle_caret(&self, view_id: ViewId, line: u64, col: u64) {
+        self.send_edit_cmd(
+            view_id,
+            "gesture",
+            &json!({
+                "line": line,
+                "col": col,
+                "ty": {
+                    "select": {
+                        "granularity": "point",
+                        "multi": true,
+                    },
+                },
+            }),
+        )
+    }
     /// modifies the selection to include a point (shift+click)
     pub fn gesture_range_select(&self, view_id: ViewId, line: u64, col: u64) {
         self.send_edit_cmd(
@@ -558,6 +611,12 @@ impl Client {
         self.send_edit_cmd(view_id, "scroll", &json!([first, last]))
     }
 
+    /// Asks the back-end to send the lines from `first` to `last` (non-inclusive), even if they're
+    /// outside of the visible scroll region.
+    pub fn request_lines(&self, view_id: ViewId, first: u64, last: u64) {
+        self.send_edit_cmd(view_id, "request_lines", &json!([first, last]))
+    }
+
     pub fn drag(&self, view_id: ViewId, line: u64, col: u64) {
         self.send_edit_cmd(
             view_id,
@@ -11,8 +11,9 @@ mod structs;
 pub use crate::client::{Callback, Client};
 pub use crate::message::Message;
 pub use crate::structs::{
-    Alert, AvailableLanguages, AvailablePlugins, AvailableThemes, ConfigChanged, ConfigChanges,
-    FindStatus, LanguageChanged, Line, MeasureWidth, ModifySelection, Operation, OperationType,
-    PluginStarted, PluginStopped, Position, Query, ReplaceStatus, RpcOperations, ScrollTo, Status,
-    Style, StyleDef, ThemeChanged, ThemeSettings, Update, UpdateCmds, ViewId,
+    Alert, AvailableLanguages, AvailablePlugins, AvailableThemes, Command, CommandArgument,
+    ConfigChanged, ConfigChanges, FindStatus, LanguageChanged, Line, MeasureWidth, ModifySelection,
+    Operation, OperationType, PlaceholderRpc, PluginStarted, PluginStopped, Position, Query,
+    ReplaceStatus, RpcOperations, RpcType, ScrollTo, Status, Style, StyleDef, ThemeChanged,
+    ThemeSettings, Update, UpdateCmds, ViewId,
 };
@@ -26,9 +26,13 @@ pub use self::line::{Line, StyleDef};
 pub use self::modifyselection::ModifySelection;
 pub use self::operation::{Operation, OperationType};
 pub use self::plugins::AvailablePlugins;
+pub use self::plugins::Command;
+pub use self::plugins::CommandArgument;
+pub use self::plugins::PlaceholderRpc;
 pub use self::plugins::Plugin;
 pub use self::plugins::PluginStarted;
 pub use self::plugins::PluginStopped;
+pub use self::plugins::RpcType;
 pub use self::plugins::UpdateCmds;
 pub use self::position::Position;
 pub use self::scroll_to::ScrollTo;
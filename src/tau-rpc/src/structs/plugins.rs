@@ -4,6 +4,7 @@
 
 use crate::ViewId;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Plugin {
@@ -29,9 +30,72 @@ pub struct PluginStopped {
     pub plugin: String,
 }
 
+/// Whether xi expects a response to a `PlaceholderRpc`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RpcType {
+    Notification,
+    Request,
+}
+
+/// The RPC a plugin wants to receive when one of its `Command`s is invoked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaceholderRpc {
+    pub method: String,
+    pub params: Value,
+    pub rpc_type: RpcType,
+}
+
+/// An argument the user has to fill in before a `Command` can be invoked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandArgument {
+    pub title: String,
+    pub description: String,
+    pub key: String,
+}
+
+/// A command a plugin offers to the user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub title: String,
+    pub description: String,
+    pub rpc_cmd: PlaceholderRpc,
+    #[serde(default)]
+    pub args: Vec<CommandArgument>,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct UpdateCmds {
-    pub cmds: Vec<String>,
+    pub cmds: Vec<Command>,
     pub plugin: String,
     pub view_id: ViewId,
 }
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn test_updatecmds() {
+        use crate::structs::plugins::{Command, PlaceholderRpc, RpcType, UpdateCmds};
+        use serde_json::{self, json};
+        use std::str::FromStr;
+
+        let s = r#"{"view_id": "view-id-1", "plugin": "spellcheck", "cmds": [{"title": "Check spelling", "description": "Checks the whole document", "rpc_cmd": {"rpc_type": "notification", "method": "check", "params": {"all": true}}, "args": []}]}"#;
+        let deserialized: Result<UpdateCmds, _> = serde_json::from_str(s);
+        let update_cmds = UpdateCmds {
+            view_id: FromStr::from_str("view-id-1").unwrap(),
+            plugin: "spellcheck".to_string(),
+            cmds: vec![Command {
+                title: "Check spelling".to_string(),
+                description: "Checks the whole document".to_string(),
+                rpc_cmd: PlaceholderRpc {
+                    method: "check".to_string(),
+                    params: json!({"all": true}),
+                    rpc_type: RpcType::Notification,
+                },
+                args: Vec::new(),
+            }],
+        };
+
+        assert_eq!(deserialized.unwrap(), update_cmds);
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::fuzzy_list::{rank, FuzzyList};
+use editview::completion::fuzzy_score;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{Builder, Widget};
+use log::trace;
+use std::cell::RefCell;
+use std::rc::Rc;
+use tau_rpc::Command;
+
+/// What happens when an entry of the `CommandPalette` is chosen
+#[derive(Debug, Clone)]
+pub enum PaletteCommand {
+    /// Activate the `app.` action of this name
+    Action(String),
+    /// Invoke a command which a plugin offers for the current `EditView`
+    Plugin { plugin: String, command: Command },
+    /// Switch the current `EditView` to this syntax
+    Language(String),
+    /// Switch to this theme
+    Theme(String),
+}
+
+/// A single row of the `CommandPalette`
+#[derive(Debug, Clone)]
+pub struct PaletteEntry {
+    /// What the user searches for
+    pub title: String,
+    /// Shown next to the title, e.g. the keyboard shortcut of an action
+    pub detail: String,
+    pub command: PaletteCommand,
+}
+
+/// Turn the name of an action, e.g. `toggle_block_comment`, into something readable, e.g.
+/// `Toggle block comment`
+pub fn action_title(name: &str) -> String {
+    let mut title = name.replace('_', " ");
+    if let Some(first) = title.get(0..1) {
+        let upper = first.to_uppercase();
+        title.replace_range(0..1, &upper);
+    }
+    title
+}
+
+/// A popover which fuzzy-searches all actions, plugin commands, languages and themes, opened with
+/// Ctrl+Shift+P
+pub struct CommandPalette {
+    list: Rc<FuzzyList>,
+    /// All entries the user can choose from
+    entries: RefCell<Vec<PaletteEntry>>,
+}
+
+impl CommandPalette {
+    /// Create a new `CommandPalette`, which calls `on_command` with the `PaletteCommand` the user
+    /// has chosen
+    pub fn new<W, F>(relative_to: &W, on_command: F) -> Rc<Self>
+    where
+        W: IsA<Widget>,
+        F: Fn(PaletteCommand) + 'static,
+    {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/command_palette.glade");
+
+        let command_palette = Rc::new(Self {
+            list: FuzzyList::new(&builder, "command_palette", relative_to),
+            entries: Default::default(),
+        });
+
+        command_palette.list.connect_search_changed(
+            clone!(@weak command_palette => @default-panic, move || {
+                command_palette.filter();
+            }),
+        );
+
+        command_palette.list.connect_activated(
+            clone!(@weak command_palette => @default-panic, move |index| {
+                let command = command_palette
+                    .entries
+                    .borrow()
+                    .get(index)
+                    .map(|entry| entry.command.clone());
+                if let Some(command) = command {
+                    on_command(command);
+                }
+            }),
+        );
+
+        command_palette
+    }
+
+    /// Show the popover at the top of the widget it's relative to, offering `entries`
+    pub fn show(&self, entries: Vec<PaletteEntry>) {
+        trace!("Showing command palette with {} entries", entries.len());
+
+        self.entries.replace(entries);
+        self.list.show();
+    }
+
+    /// Show the entries which match the text of the search entry, best match first
+    fn filter(&self) {
+        let pattern = self.list.pattern();
+        let entries = self.entries.borrow();
+
+        let shown = rank(&pattern, &entries, entries.len(), |e| {
+            fuzzy_score(&pattern, &e.title)
+        });
+        let rows: Vec<(&str, &str)> = shown
+            .iter()
+            .map(|i| (entries[*i].title.as_str(), entries[*i].detail.as_str()))
+            .collect();
+        self.list.set_rows(shown, rows);
+    }
+}
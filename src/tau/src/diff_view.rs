@@ -0,0 +1,231 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use editview::diff::{diff_lines, split_lines, Hunk, HunkKind};
+use editview::i18n::ni18n_f;
+use editview::TopBar;
+use gdk::RGBA;
+use gettextrs::gettext;
+use gtk::prelude::*;
+use gtk::{Builder, Grid, Label, TextBuffer, TextTag, TextTagTable, TextView};
+use std::cmp::max;
+
+/// How the lines of a row of the `DiffView` are highlighted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowKind {
+    Equal,
+    Removed,
+    Added,
+    Changed,
+}
+
+/// A row of the `DiffView`, holding the lines of the left and the right text shown in it. One
+/// side is `None` if lines have only been added or removed on the other one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub kind: RowKind,
+}
+
+/// Line up the `left_len` lines of the left text with the `right_len` lines of the right text,
+/// so that lines which are the same on both sides end up in the same row
+pub fn align_rows(left_len: usize, right_len: usize, hunks: &[Hunk]) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let (mut left, mut right) = (0, 0);
+
+    for hunk in hunks {
+        while left < hunk.old.start {
+            rows.push(Row {
+                left: Some(left),
+                right: Some(right),
+                kind: RowKind::Equal,
+            });
+            left += 1;
+            right += 1;
+        }
+
+        let kind = match hunk.kind() {
+            HunkKind::Added => RowKind::Added,
+            HunkKind::Removed => RowKind::Removed,
+            HunkKind::Changed => RowKind::Changed,
+        };
+        for i in 0..max(hunk.old.len(), hunk.new.len()) {
+            rows.push(Row {
+                left: Some(hunk.old.start + i).filter(|l| *l < hunk.old.end),
+                right: Some(hunk.new.start + i).filter(|r| *r < hunk.new.end),
+                kind,
+            });
+        }
+        left = hunk.old.end;
+        right = hunk.new.end;
+    }
+
+    while left < left_len && right < right_len {
+        rows.push(Row {
+            left: Some(left),
+            right: Some(right),
+            kind: RowKind::Equal,
+        });
+        left += 1;
+        right += 1;
+    }
+    rows
+}
+
+/// Shows the differences between two texts side by side, e.g. between the version of a file on
+/// disk and the unsaved changes to it. Both sides scroll together.
+pub struct DiffView {
+    pub widget: Grid,
+    pub top_bar: TopBar,
+}
+
+impl DiffView {
+    pub fn new(
+        title: &str,
+        left_title: &str,
+        left_text: &str,
+        right_title: &str,
+        right_text: &str,
+    ) -> Self {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/diff_view.glade");
+        let widget: Grid = builder.get_object("diff_view_grid").unwrap();
+        let left_label: Label = builder.get_object("diff_view_left_label").unwrap();
+        let right_label: Label = builder.get_object("diff_view_right_label").unwrap();
+        let left_view: TextView = builder.get_object("diff_view_left_textview").unwrap();
+        let right_view: TextView = builder.get_object("diff_view_right_textview").unwrap();
+        let status_label: Label = builder.get_object("diff_view_status_label").unwrap();
+
+        left_label.set_text(left_title);
+        right_label.set_text(right_title);
+
+        let left_lines = split_lines(left_text);
+        let right_lines = split_lines(right_text);
+        let hunks = diff_lines(&left_lines, &right_lines);
+        let rows = align_rows(left_lines.len(), right_lines.len(), &hunks);
+
+        let tag_table = Self::tag_table();
+        let left_buffer = TextBuffer::new(Some(&tag_table));
+        Self::fill_buffer(&left_buffer, &rows, |row| row.left.map(|l| left_lines[l]));
+        left_view.set_buffer(Some(&left_buffer));
+        let right_buffer = TextBuffer::new(Some(&tag_table));
+        Self::fill_buffer(&right_buffer, &rows, |row| {
+            row.right.map(|r| right_lines[r])
+        });
+        right_view.set_buffer(Some(&right_buffer));
+
+        if hunks.is_empty() {
+            status_label.set_text(&gettext("No differences"));
+        } else {
+            status_label.set_text(&ni18n_f(
+                "{} change",
+                "{} changes",
+                hunks.len() as u32,
+                &[&hunks.len().to_string()],
+            ));
+        }
+
+        let top_bar = TopBar::new();
+        top_bar.label.set_text(title);
+        top_bar
+            .tab_widget
+            .set_tooltip_text(Some(&format!("{} ↔ {}", left_title, right_title)));
+
+        Self { widget, top_bar }
+    }
+
+    /// The tags used to highlight the rows of both sides
+    fn tag_table() -> TextTagTable {
+        let tag_table = TextTagTable::new();
+        for (name, red, green, blue, alpha) in &[
+            ("removed", 224, 27, 36, 0.25),
+            ("added", 46, 194, 126, 0.25),
+            ("changed", 53, 132, 228, 0.25),
+            ("filler", 127, 127, 127, 0.12),
+        ] {
+            let tag = TextTag::new(Some(*name));
+            tag.set_property_paragraph_background_rgba(Some(&RGBA {
+                red: f64::from(*red) / 255.0,
+                green: f64::from(*green) / 255.0,
+                blue: f64::from(*blue) / 255.0,
+                alpha: *alpha,
+            }));
+            tag_table.add(&tag);
+        }
+        tag_table
+    }
+
+    /// Fill `buffer` with one line per row, taking the line of either side via `line`
+    fn fill_buffer<'a, F>(buffer: &TextBuffer, rows: &[Row], line: F)
+    where
+        F: Fn(&Row) -> Option<&'a str>,
+    {
+        let mut text = String::new();
+        let mut tags = Vec::new();
+        for (ix, row) in rows.iter().enumerate() {
+            let line = line(row);
+            if let Some(line) = line {
+                text.push_str(line.trim_end_matches(|c| c == '\n' || c == '\r'));
+            }
+            text.push('\n');
+
+            let tag = match (line.is_some(), row.kind) {
+                (_, RowKind::Equal) => continue,
+                (false, _) => "filler",
+                (true, RowKind::Removed) => "removed",
+                (true, RowKind::Added) => "added",
+                (true, RowKind::Changed) => "changed",
+            };
+            tags.push((ix as i32, tag));
+        }
+        text.pop();
+        buffer.set_text(&text);
+
+        for (ix, tag) in tags {
+            let start = buffer.get_iter_at_line(ix);
+            let mut end = start.clone();
+            end.forward_line();
+            buffer.apply_tag_by_name(tag, &start, &end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn row(left: Option<usize>, right: Option<usize>, kind: RowKind) -> Row {
+        Row { left, right, kind }
+    }
+
+    #[test]
+    fn equal_texts() {
+        let lines = ["a\n", "b\n"];
+        let hunks = diff_lines(&lines, &lines);
+        assert_eq!(
+            align_rows(2, 2, &hunks),
+            vec![
+                row(Some(0), Some(0), RowKind::Equal),
+                row(Some(1), Some(1), RowKind::Equal),
+            ]
+        );
+    }
+
+    #[test]
+    fn filler_rows() {
+        let left = ["a\n", "b\n", "c\n", "d\n"];
+        let right = ["x\n", "a\n", "c\n", "y\n", "z\n"];
+        let hunks = diff_lines(&left, &right);
+        assert_eq!(
+            align_rows(left.len(), right.len(), &hunks),
+            vec![
+                row(None, Some(0), RowKind::Added),
+                row(Some(0), Some(1), RowKind::Equal),
+                row(Some(1), None, RowKind::Removed),
+                row(Some(2), Some(2), RowKind::Equal),
+                row(Some(3), Some(3), RowKind::Changed),
+                row(None, Some(4), RowKind::Changed),
+            ]
+        );
+    }
+}
@@ -0,0 +1,956 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::project::index_files;
+use editview::i18n::{freplace, ni18n_f};
+use gettextrs::gettext;
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{
+    Builder, Button, CellRendererToggle, CheckButton, Entry, Label, Revealer, SearchEntry,
+    TreeIter, TreeStore, TreeView,
+};
+use log::{debug, trace, warn};
+use regex::{Regex, RegexBuilder};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Stop searching once this many lines match
+const MAX_MATCHING_LINES: usize = 5_000;
+
+/// Files bigger than this aren't searched, they're most likely not text
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// How many bytes of a matching line are shown before its first match
+const PREVIEW_CONTEXT: usize = 40;
+
+/// The columns of the `find_in_files_treestore`
+const COLUMN_MARKUP: u32 = 0;
+const COLUMN_PATH: u32 = 1;
+const COLUMN_LINE: u32 = 2;
+const COLUMN_COL: u32 = 3;
+const COLUMN_IS_FILE: u32 = 4;
+const COLUMN_INCLUDE: u32 = 5;
+const COLUMN_SHOW_INCLUDE: u32 = 6;
+
+/// What to search for. These are the same options a `Query` of the search in a single `EditView`
+/// has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOptions {
+    pub chars: String,
+    pub case_sensitive: bool,
+    pub is_regex: bool,
+    pub whole_words: bool,
+}
+
+impl SearchOptions {
+    pub fn to_regex(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.is_regex {
+            self.chars.clone()
+        } else {
+            regex::escape(&self.chars)
+        };
+        let pattern = if self.whole_words {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+    }
+}
+
+/// A line which contains at least one match
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchingLine {
+    /// The line number, starting at 0
+    pub line: u64,
+    /// The text of the line, without the line ending
+    pub text: String,
+    /// The byte ranges of the matches in `text`
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// Split `text` into lines, separating the line endings (`\n` or `\r\n`) from their content
+fn split_lines(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut pieces = text.split('\n').peekable();
+    std::iter::from_fn(move || {
+        let piece = pieces.next()?;
+        let is_last = pieces.peek().is_none();
+        Some(match (piece.ends_with('\r'), is_last) {
+            (_, true) => (piece, ""),
+            (true, false) => (&piece[..piece.len() - 1], "\r\n"),
+            (false, false) => (piece, "\n"),
+        })
+    })
+}
+
+/// Find all non-empty matches of `regex` in `line`, along with what each of them is replaced with
+pub fn replacements(
+    regex: &Regex,
+    line: &str,
+    replacement: &str,
+    is_regex: bool,
+) -> Vec<(Range<usize>, String)> {
+    regex
+        .captures_iter(line)
+        .filter_map(|caps| {
+            let m = caps.get(0)?;
+            if m.start() == m.end() {
+                return None;
+            }
+            let mut new = String::new();
+            if is_regex {
+                caps.expand(replacement, &mut new);
+            } else {
+                new.push_str(replacement);
+            }
+            Some((m.start()..m.end(), new))
+        })
+        .collect()
+}
+
+/// Find the lines of `text` which `regex` matches
+pub fn find_matches(regex: &Regex, text: &str) -> Vec<MatchingLine> {
+    split_lines(text)
+        .enumerate()
+        .filter_map(|(i, (line, _))| {
+            let ranges: Vec<Range<usize>> = regex
+                .find_iter(line)
+                .filter(|m| m.start() != m.end())
+                .map(|m| m.start()..m.end())
+                .collect();
+            if ranges.is_empty() {
+                None
+            } else {
+                Some(MatchingLine {
+                    line: i as u64,
+                    text: line.to_string(),
+                    ranges,
+                })
+            }
+        })
+        .collect()
+}
+
+/// Replace all matches of `regex` in `text`, keeping line endings as they are. Returns the new
+/// text and how many matches have been replaced.
+pub fn replace_in_text(
+    regex: &Regex,
+    text: &str,
+    replacement: &str,
+    is_regex: bool,
+) -> (String, usize) {
+    let mut result = String::with_capacity(text.len());
+    let mut count = 0;
+    for (line, ending) in split_lines(text) {
+        let mut last = 0;
+        for (range, new) in replacements(regex, line, replacement, is_regex) {
+            result.push_str(&line[last..range.start]);
+            result.push_str(&new);
+            last = range.end;
+            count += 1;
+        }
+        result.push_str(&line[last..]);
+        result.push_str(ending);
+    }
+    (result, count)
+}
+
+/// The same replacements as `replace_in_text` makes, as edits of the (line, byte column) ranges
+/// of the matches, e.g. to replace them in an open document where they can be undone
+pub fn replacement_edits(
+    regex: &Regex,
+    text: &str,
+    replacement: &str,
+    is_regex: bool,
+) -> Vec<(editview::text::Range, String)> {
+    split_lines(text)
+        .enumerate()
+        .flat_map(|(i, (line, _))| {
+            let line_number = i as u64;
+            replacements(regex, line, replacement, is_regex)
+                .into_iter()
+                .map(move |(range, new)| {
+                    (
+                        (
+                            (line_number, range.start as u64),
+                            (line_number, range.end as u64),
+                        ),
+                        new,
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Replace all matches of `regex` in the file at `path` on disk
+pub fn replace_in_file(
+    path: &Path,
+    regex: &Regex,
+    replacement: &str,
+    is_regex: bool,
+) -> Result<usize, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let (new_text, count) = replace_in_text(regex, &text, replacement, is_regex);
+    if count > 0 {
+        fs::write(path, new_text).map_err(|e| e.to_string())?;
+    }
+    Ok(count)
+}
+
+/// Search all files under `root` which `index_files` finds. Calls `send` with the matches of each
+/// file, stops early if it returns false. Returns whether the search stopped because too many
+/// lines matched.
+fn search_files<F>(root: &Path, regex: &Regex, mut send: F) -> bool
+where
+    F: FnMut(PathBuf, Vec<MatchingLine>) -> bool,
+{
+    let mut n_lines = 0;
+    let mut truncated = false;
+    index_files(root, |batch| {
+        for relative in batch {
+            let path = root.join(relative);
+            let too_big = fs::metadata(&path)
+                .map(|m| m.len() > MAX_FILE_SIZE)
+                .unwrap_or(true);
+            if too_big {
+                continue;
+            }
+            // Skip binary files and files which aren't UTF-8
+            let text = match fs::read(&path).map(String::from_utf8) {
+                Ok(Ok(text)) if !text.contains('\0') => text,
+                _ => continue,
+            };
+
+            let matches = find_matches(regex, &text);
+            if matches.is_empty() {
+                continue;
+            }
+            n_lines += matches.len();
+            if !send(path, matches) {
+                return false;
+            }
+            if n_lines >= MAX_MATCHING_LINES {
+                truncated = true;
+                return false;
+            }
+        }
+        true
+    });
+    truncated
+}
+
+/// Sent from the searching thread to the `FindInFiles` panel
+enum SearchMsg {
+    /// A file with matches has been found by the search with this generation
+    File(u64, PathBuf, Vec<MatchingLine>),
+    /// The search with this generation is complete. The bool is true if not all matches have
+    /// been found because there are too many.
+    Done(u64, bool),
+}
+
+/// Escape `text` for Pango markup
+fn escape(text: &str) -> String {
+    glib::markup_escape_text(text).to_string()
+}
+
+/// The Pango markup for a matching line, highlighting the matches. If `replaced` is set, the
+/// matches are struck through and followed by what they're replaced with.
+fn line_markup(line: &MatchingLine, replaced: Option<&[(Range<usize>, String)]>) -> String {
+    let highlights: Vec<(Range<usize>, Option<&str>)> = match replaced {
+        Some(replaced) => replaced
+            .iter()
+            .map(|(range, new)| (range.clone(), Some(new.as_str())))
+            .collect(),
+        None => line
+            .ranges
+            .iter()
+            .map(|range| (range.clone(), None))
+            .collect(),
+    };
+
+    // Leave out leading whitespace and, for long lines, most of what comes before the first match
+    let text = &line.text;
+    let mut start = text.len() - text.trim_start().len();
+    if let Some((first, _)) = highlights.first() {
+        let context_start = first.start.saturating_sub(PREVIEW_CONTEXT);
+        if context_start > start {
+            start = (context_start..first.start)
+                .find(|i| text.is_char_boundary(*i))
+                .unwrap_or(first.start);
+        }
+        start = start.min(first.start);
+    }
+
+    let mut markup = format!("<span alpha=\"60%\">{}</span>  ", line.line + 1);
+    if start > 0 && !text[..start].trim().is_empty() {
+        markup.push('…');
+    }
+    let mut last = start;
+    for (range, new) in highlights {
+        markup.push_str(&escape(&text[last..range.start]));
+        match new {
+            Some(new) => {
+                markup.push_str("<span strikethrough=\"true\" alpha=\"60%\">");
+                markup.push_str(&escape(&text[range.clone()]));
+                markup.push_str("</span><b>");
+                markup.push_str(&escape(new));
+                markup.push_str("</b>");
+            }
+            None => {
+                markup.push_str("<b>");
+                markup.push_str(&escape(&text[range.clone()]));
+                markup.push_str("</b>");
+            }
+        }
+        last = range.end;
+    }
+    markup.push_str(&escape(&text[last..]));
+    markup
+}
+
+/// The "Find in Files" panel in the sidebar, which searches all files of the project directory
+/// and replaces matches in them
+pub struct FindInFiles {
+    /// The panel, to be added to the sidebar
+    pub widget: gtk::Box,
+    search_entry: SearchEntry,
+    replace_entry: Entry,
+    regex_button: CheckButton,
+    case_sensitive_button: CheckButton,
+    whole_word_button: CheckButton,
+    status_label: Label,
+    apply_revealer: Revealer,
+    treeview: TreeView,
+    tree_store: TreeStore,
+    sender: Sender<SearchMsg>,
+    /// The directory which is searched
+    root: RefCell<Option<PathBuf>>,
+    /// The options of the last search and the files it found
+    results: RefCell<Vec<(PathBuf, Vec<MatchingLine>)>>,
+    options: RefCell<Option<SearchOptions>>,
+    /// Whether the replacements are being previewed right now
+    previewing: Cell<bool>,
+    /// Incremented for every search, so outdated searches stop and their results are dropped
+    generation: Arc<AtomicU64>,
+    /// Called with the file, line and column of the match the user activates
+    on_result: RefCell<Option<Box<dyn Fn(PathBuf, u64, u64)>>>,
+    /// Called with the files, options and replacement when the user applies the replacements.
+    /// Returns the errors which occurred.
+    on_replace: RefCell<Option<Box<dyn Fn(&[PathBuf], &SearchOptions, &str) -> Vec<String>>>>,
+    /// The errors of the last replacement and the generation of the search run after it, which
+    /// shows them below its status
+    replace_errors: RefCell<(u64, Vec<String>)>,
+}
+
+impl FindInFiles {
+    pub fn new() -> Rc<Self> {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/find_in_files.glade");
+
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+
+        let find_in_files = Rc::new(Self {
+            widget: builder.get_object("find_in_files_box").unwrap(),
+            search_entry: builder.get_object("find_in_files_search_entry").unwrap(),
+            replace_entry: builder.get_object("find_in_files_replace_entry").unwrap(),
+            regex_button: builder.get_object("find_in_files_regex_button").unwrap(),
+            case_sensitive_button: builder
+                .get_object("find_in_files_case_sensitive_button")
+                .unwrap(),
+            whole_word_button: builder
+                .get_object("find_in_files_whole_word_button")
+                .unwrap(),
+            status_label: builder.get_object("find_in_files_status_label").unwrap(),
+            apply_revealer: builder.get_object("find_in_files_apply_revealer").unwrap(),
+            treeview: builder.get_object("find_in_files_treeview").unwrap(),
+            tree_store: builder.get_object("find_in_files_treestore").unwrap(),
+            sender,
+            root: Default::default(),
+            results: Default::default(),
+            options: Default::default(),
+            previewing: Cell::new(false),
+            generation: Default::default(),
+            on_result: Default::default(),
+            on_replace: Default::default(),
+            replace_errors: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak find_in_files => @default-return glib::Continue(false), move |msg| {
+                find_in_files.receive(msg);
+                glib::Continue(true)
+            }),
+        );
+
+        find_in_files.search_entry.connect_search_changed(
+            clone!(@weak find_in_files => @default-panic, move |_| {
+                find_in_files.search();
+            }),
+        );
+
+        find_in_files.search_entry.connect_activate(
+            clone!(@weak find_in_files => @default-panic, move |_| {
+                find_in_files.search();
+            }),
+        );
+
+        for button in &[
+            &find_in_files.regex_button,
+            &find_in_files.case_sensitive_button,
+            &find_in_files.whole_word_button,
+        ] {
+            button.connect_toggled(clone!(@weak find_in_files => @default-panic, move |_| {
+                find_in_files.search();
+            }));
+        }
+
+        find_in_files.replace_entry.connect_activate(
+            clone!(@weak find_in_files => @default-panic, move |_| {
+                find_in_files.preview();
+            }),
+        );
+
+        let preview_button: Button = builder.get_object("find_in_files_preview_button").unwrap();
+        preview_button.connect_clicked(clone!(@weak find_in_files => @default-panic, move |_| {
+            find_in_files.preview();
+        }));
+
+        let cancel_button: Button = builder.get_object("find_in_files_cancel_button").unwrap();
+        cancel_button.connect_clicked(clone!(@weak find_in_files => @default-panic, move |_| {
+            find_in_files.show_results();
+        }));
+
+        let apply_button: Button = builder.get_object("find_in_files_apply_button").unwrap();
+        apply_button.connect_clicked(clone!(@weak find_in_files => @default-panic, move |_| {
+            find_in_files.apply();
+        }));
+
+        let include_renderer: CellRendererToggle = builder
+            .get_object("find_in_files_include_renderer")
+            .unwrap();
+        include_renderer.connect_toggled(
+            clone!(@weak find_in_files => @default-panic, move |_, path| {
+                if let Some(iter) = find_in_files.tree_store.get_iter(&path) {
+                    let include = find_in_files
+                        .tree_store
+                        .get_value(&iter, COLUMN_INCLUDE as i32)
+                        .get_some::<bool>()
+                        .unwrap_or(false);
+                    find_in_files
+                        .tree_store
+                        .set_value(&iter, COLUMN_INCLUDE, &(!include).to_value());
+                }
+            }),
+        );
+
+        find_in_files.treeview.connect_row_activated(
+            clone!(@weak find_in_files => @default-panic, move |treeview, path, _| {
+                let iter = match find_in_files.tree_store.get_iter(path) {
+                    Some(iter) => iter,
+                    None => return,
+                };
+                if find_in_files.is_file(&iter) {
+                    if treeview.row_expanded(path) {
+                        treeview.collapse_row(path);
+                    } else {
+                        treeview.expand_row(path, false);
+                    }
+                } else {
+                    find_in_files.open(&iter);
+                }
+            }),
+        );
+
+        find_in_files
+    }
+
+    /// Call `f` with the file, line and column of the matches the user activates
+    pub fn connect_result_activated<F: Fn(PathBuf, u64, u64) + 'static>(&self, f: F) {
+        self.on_result.replace(Some(Box::new(f)));
+    }
+
+    /// Call `f` to replace the matches in the files the user chose. It returns the errors which
+    /// occurred, those which occur later on are reported with `replace_failed`.
+    pub fn connect_replace<F>(&self, f: F)
+    where
+        F: Fn(&[PathBuf], &SearchOptions, &str) -> Vec<String> + 'static,
+    {
+        self.on_replace.replace(Some(Box::new(f)));
+    }
+
+    /// Show that replacing in a file failed after the replacement has been applied, e.g. because
+    /// the text of its open document couldn't be fetched
+    pub fn replace_failed(&self, error: String) {
+        warn!("{}", error);
+        let mut replace_errors = self.replace_errors.borrow_mut();
+        if replace_errors.0 != self.generation.load(Ordering::SeqCst) {
+            return;
+        }
+        self.append_status(&error);
+        replace_errors.1.push(error);
+    }
+
+    /// Add the errors of the replacement which started the current search to the status
+    fn show_replace_errors(&self) {
+        let replace_errors = self.replace_errors.borrow();
+        let (generation, errors) = &*replace_errors;
+        if errors.is_empty() || *generation != self.generation.load(Ordering::SeqCst) {
+            return;
+        }
+        self.append_status(&errors.join("\n"));
+    }
+
+    /// Show `text` in a new line below the status
+    fn append_status(&self, text: &str) {
+        let status = self
+            .status_label
+            .get_text()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        if status.is_empty() {
+            self.status_label.set_text(text);
+        } else {
+            self.status_label.set_text(&format!("{}\n{}", status, text));
+        }
+    }
+
+    /// Search the files in `root` from now on
+    pub fn set_root(&self, root: PathBuf) {
+        if self.root.borrow().as_ref() == Some(&root) {
+            return;
+        }
+        self.root.replace(Some(root));
+        self.search();
+    }
+
+    /// Focus the search entry
+    pub fn start(&self) {
+        self.search_entry.grab_focus();
+    }
+
+    fn search_options(&self) -> SearchOptions {
+        SearchOptions {
+            chars: self
+                .search_entry
+                .get_text()
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            case_sensitive: self.case_sensitive_button.get_active(),
+            is_regex: self.regex_button.get_active(),
+            whole_words: self.whole_word_button.get_active(),
+        }
+    }
+
+    /// Search the project directory in a separate thread, stopping any search which is still
+    /// running
+    fn search(&self) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        self.previewing.set(false);
+        self.apply_revealer.set_reveal_child(false);
+        self.tree_store.clear();
+        self.results.borrow_mut().clear();
+        self.options.replace(None);
+
+        let options = self.search_options();
+        if options.chars.is_empty() {
+            self.status_label.set_text("");
+            return;
+        }
+        let root = match self.root.borrow().clone() {
+            Some(root) => root,
+            None => {
+                self.status_label
+                    .set_text(&gettext("Open a file or folder to search its directory"));
+                return;
+            }
+        };
+        let regex = match options.to_regex() {
+            Ok(regex) if !regex.is_match("") => regex,
+            Ok(_) => {
+                self.status_label
+                    .set_text(&gettext("The search matches empty text"));
+                return;
+            }
+            Err(e) => {
+                self.status_label.set_text(&e.to_string());
+                return;
+            }
+        };
+
+        debug!("Searching for {:?} in {:?}", options, root);
+        self.status_label.set_text(&gettext("Searching…"));
+        self.options.replace(Some(options));
+
+        let current_generation = self.generation.clone();
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let truncated = search_files(&root, &regex, |path, matches| {
+                current_generation.load(Ordering::SeqCst) == generation
+                    && sender
+                        .send(SearchMsg::File(generation, path, matches))
+                        .is_ok()
+            });
+            sender.send(SearchMsg::Done(generation, truncated)).ok();
+        });
+    }
+
+    fn receive(&self, msg: SearchMsg) {
+        let current_generation = self.generation.load(Ordering::SeqCst);
+        match msg {
+            SearchMsg::File(generation, path, matches) if generation == current_generation => {
+                self.insert_file(&path, &matches, None);
+                self.results.borrow_mut().push((path, matches));
+            }
+            SearchMsg::Done(generation, truncated) if generation == current_generation => {
+                self.update_status(truncated);
+            }
+            _ => {}
+        }
+    }
+
+    /// Show how many matches have been found
+    fn update_status(&self, truncated: bool) {
+        let results = self.results.borrow();
+        let n_matches: usize = results
+            .iter()
+            .flat_map(|(_, lines)| lines.iter())
+            .map(|line| line.ranges.len())
+            .sum();
+        trace!("Found {} matches in {} files", n_matches, results.len());
+
+        let mut status = if n_matches == 0 {
+            gettext("No Results")
+        } else {
+            format!(
+                "{} {}",
+                ni18n_f(
+                    "{} match",
+                    "{} matches",
+                    n_matches as u32,
+                    &[&n_matches.to_string()]
+                ),
+                ni18n_f(
+                    "in {} file",
+                    "in {} files",
+                    results.len() as u32,
+                    &[&results.len().to_string()]
+                )
+            )
+        };
+        if truncated {
+            status.push_str(&format!(" ({})", gettext("stopped, too many matches")));
+        }
+        self.status_label.set_text(&status);
+        self.show_replace_errors();
+    }
+
+    /// Add a row for `path` with a child for every matching line. If `replacement` is set, the
+    /// rows preview the replacements instead.
+    fn insert_file(
+        &self,
+        path: &Path,
+        matches: &[MatchingLine],
+        replacement: Option<(&Regex, &str, bool)>,
+    ) {
+        let root = self.root.borrow().clone().unwrap_or_default();
+        let relative = path.strip_prefix(&root).unwrap_or(path);
+        let name = relative
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let dir = relative
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let n_matches: usize = matches.iter().map(|line| line.ranges.len()).sum();
+        let markup = format!(
+            "<b>{}</b>  <span alpha=\"60%\">{}</span>  <small>({})</small>",
+            escape(&name),
+            escape(&dir),
+            n_matches
+        );
+
+        let path_string = path.to_string_lossy().to_string();
+        let file_iter = self.tree_store.insert_with_values(
+            None,
+            None,
+            &[
+                COLUMN_MARKUP,
+                COLUMN_PATH,
+                COLUMN_IS_FILE,
+                COLUMN_INCLUDE,
+                COLUMN_SHOW_INCLUDE,
+            ],
+            &[&markup, &path_string, &true, &true, &replacement.is_some()],
+        );
+
+        for line in matches {
+            let markup = match replacement {
+                Some((regex, replacement, is_regex)) => {
+                    let replaced = replacements(regex, &line.text, replacement, is_regex);
+                    line_markup(line, Some(&replaced))
+                }
+                None => line_markup(line, None),
+            };
+            let col = line.ranges.first().map(|r| r.start as u64).unwrap_or(0);
+            self.tree_store.insert_with_values(
+                Some(&file_iter),
+                None,
+                &[
+                    COLUMN_MARKUP,
+                    COLUMN_PATH,
+                    COLUMN_LINE,
+                    COLUMN_COL,
+                    COLUMN_IS_FILE,
+                ],
+                &[&markup, &path_string, &line.line, &col, &false],
+            );
+        }
+
+        if let Some(tree_path) = self.tree_store.get_path(&file_iter) {
+            self.treeview.expand_row(&tree_path, false);
+        }
+    }
+
+    /// Show the references to `symbol` a language server has found instead of search results
+    pub fn show_references(&self, symbol: &str, results: Vec<(PathBuf, Vec<MatchingLine>)>) {
+        // Stop the search which might still be running, its results would be mixed in otherwise
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.previewing.set(false);
+        self.apply_revealer.set_reveal_child(false);
+        self.options.replace(None);
+        self.tree_store.clear();
+
+        for (path, matches) in &results {
+            self.insert_file(path, matches, None);
+        }
+        let n_references: usize = results
+            .iter()
+            .flat_map(|(_, lines)| lines.iter())
+            .map(|line| line.ranges.len())
+            .sum();
+        let status = if n_references == 0 {
+            freplace(gettext("No references to “{}”"), &[symbol])
+        } else {
+            ni18n_f(
+                "{} reference to “{}”",
+                "{} references to “{}”",
+                n_references as u32,
+                &[&n_references.to_string(), symbol],
+            )
+        };
+        self.status_label.set_text(&status);
+        self.results.replace(results);
+    }
+
+    /// Show the results of the last search again, e.g. after the preview has been cancelled
+    fn show_results(&self) {
+        self.previewing.set(false);
+        self.apply_revealer.set_reveal_child(false);
+        self.tree_store.clear();
+        for (path, matches) in self.results.borrow().iter() {
+            self.insert_file(path, matches, None);
+        }
+    }
+
+    /// Show what the matches of the last search would be replaced with
+    fn preview(&self) {
+        let options = match self.options.borrow().clone() {
+            Some(options) => options,
+            None => return,
+        };
+        let regex = match options.to_regex() {
+            Ok(regex) => regex,
+            Err(_) => return,
+        };
+        if self.results.borrow().is_empty() {
+            return;
+        }
+        let replacement = self
+            .replace_entry
+            .get_text()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        self.previewing.set(true);
+        self.tree_store.clear();
+        for (path, matches) in self.results.borrow().iter() {
+            self.insert_file(
+                path,
+                matches,
+                Some((&regex, &replacement, options.is_regex)),
+            );
+        }
+        self.apply_revealer.set_reveal_child(true);
+    }
+
+    /// Replace the matches in the files which are still checked in the preview
+    fn apply(&self) {
+        if !self.previewing.get() {
+            return;
+        }
+        let options = match self.options.borrow().clone() {
+            Some(options) => options,
+            None => return,
+        };
+        let replacement = self
+            .replace_entry
+            .get_text()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+
+        let mut files = Vec::new();
+        if let Some(iter) = self.tree_store.iter_children(None) {
+            loop {
+                let include = self
+                    .tree_store
+                    .get_value(&iter, COLUMN_INCLUDE as i32)
+                    .get_some::<bool>()
+                    .unwrap_or(false);
+                if include {
+                    if let Some(path) = self.get_path(&iter) {
+                        files.push(path);
+                    }
+                }
+                if !self.tree_store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        debug!(
+            "Replacing {:?} with {:?} in {:?}",
+            options, replacement, files
+        );
+
+        let errors = match self.on_replace.borrow().as_ref() {
+            Some(on_replace) => on_replace(&files, &options, &replacement),
+            None => Vec::new(),
+        };
+        for e in &errors {
+            warn!("{}", e);
+        }
+
+        self.search();
+        self.replace_errors
+            .replace((self.generation.load(Ordering::SeqCst), errors));
+        self.show_replace_errors();
+    }
+
+    fn get_path(&self, iter: &TreeIter) -> Option<PathBuf> {
+        self.tree_store
+            .get_value(iter, COLUMN_PATH as i32)
+            .get::<String>()
+            .ok()
+            .flatten()
+            .map(PathBuf::from)
+    }
+
+    fn is_file(&self, iter: &TreeIter) -> bool {
+        self.tree_store
+            .get_value(iter, COLUMN_IS_FILE as i32)
+            .get_some::<bool>()
+            .unwrap_or(false)
+    }
+
+    /// Open the match in the row at `iter`
+    fn open(&self, iter: &TreeIter) {
+        let path = match self.get_path(iter) {
+            Some(path) => path,
+            None => return,
+        };
+        let line = self
+            .tree_store
+            .get_value(iter, COLUMN_LINE as i32)
+            .get_some::<u64>()
+            .unwrap_or(0);
+        let col = self
+            .tree_store
+            .get_value(iter, COLUMN_COL as i32)
+            .get_some::<u64>()
+            .unwrap_or(0);
+        if let Some(on_result) = self.on_result.borrow().as_ref() {
+            on_result(path, line, col);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(chars: &str, case_sensitive: bool, is_regex: bool, whole_words: bool) -> Regex {
+        SearchOptions {
+            chars: chars.to_string(),
+            case_sensitive,
+            is_regex,
+            whole_words,
+        }
+        .to_regex()
+        .unwrap()
+    }
+
+    #[test]
+    fn search_options() {
+        assert!(options("a.b", true, false, false).is_match("xa.bx"));
+        assert!(!options("a.b", true, false, false).is_match("axb"));
+        assert!(options("a.b", true, true, false).is_match("axb"));
+        assert!(options("Foo", false, false, false).is_match("foo"));
+        assert!(!options("Foo", true, false, false).is_match("foo"));
+        assert!(options("foo", true, false, true).is_match("a foo b"));
+        assert!(!options("foo", true, false, true).is_match("foobar"));
+        assert!(!options("a|b", true, true, true).is_match("ab"));
+    }
+
+    #[test]
+    fn matches() {
+        let regex = options("foo", false, false, false);
+        let matches = find_matches(&regex, "foo bar\r\nbaz\nFoo foo\n");
+        assert_eq!(
+            matches,
+            vec![
+                MatchingLine {
+                    line: 0,
+                    text: "foo bar".to_string(),
+                    ranges: vec![0..3],
+                },
+                MatchingLine {
+                    line: 2,
+                    text: "Foo foo".to_string(),
+                    ranges: vec![0..3, 4..7],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replace() {
+        let regex = options("(\\w+)=(\\w+)", true, true, false);
+        assert_eq!(
+            replace_in_text(&regex, "a=b\r\nc=d\n", "$2=$1", true),
+            ("b=a\r\nd=c\n".to_string(), 2)
+        );
+        assert_eq!(
+            replacement_edits(&regex, "a=b\r\nxx c=d\n", "$2=$1", true),
+            vec![
+                (((0, 0), (0, 3)), "b=a".to_string()),
+                (((1, 3), (1, 6)), "d=c".to_string()),
+            ]
+        );
+
+        let regex = options("x", true, false, false);
+        assert_eq!(
+            replace_in_text(&regex, "x\ny", "$0", false),
+            ("$0\ny".to_string(), 1)
+        );
+    }
+}
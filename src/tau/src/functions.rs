@@ -6,6 +6,10 @@ use editview::main_state::ShowInvisibles;
 use editview::Settings;
 use gettextrs::gettext;
 use gio::prelude::*;
+use log::warn;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 
 pub fn get_font_properties(font: &str) -> Option<(String, f32)> {
     let font_vec = font.split_whitespace().collect::<Vec<_>>();
@@ -67,6 +71,79 @@ pub fn new_settings() -> editview::Settings {
     }
 }
 
+/// The config dir we pass to xi in `client_started`, if the user has set one
+pub fn xi_config_dir() -> Option<String> {
+    std::env::var("XI_CONFIG_DIR").ok()
+}
+
+/// The dir the user's snippets are loaded from. This is in the same config dir xi uses.
+pub fn snippets_dir() -> Option<PathBuf> {
+    xi_config_dir()
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|d| d.join("xi")))
+        .map(|d| d.join("snippets"))
+}
+
+/// The file configuring which language server is used for which syntax, see `lsp::parse_configs`
+pub fn language_servers_file() -> Option<PathBuf> {
+    xi_config_dir()
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|d| d.join("xi")))
+        .map(|d| d.join("language_servers.json"))
+}
+
+/// The file configuring the profiles of the embedded terminal, see `terminals::parse_profiles`
+pub fn terminal_profiles_file() -> Option<PathBuf> {
+    xi_config_dir()
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|d| d.join("xi")))
+        .map(|d| d.join("terminal_profiles.json"))
+}
+
+/// The dir the named sessions are saved in, see `sessions::Session`
+pub fn sessions_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("tau").join("sessions"))
+}
+
+/// The dir the backups of unsaved documents are kept in, see `recovery::Backup`
+pub fn recovery_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("tau").join("recovery"))
+}
+
+/// The dir the snapshots of the local history are kept in, see `history::add_snapshot`
+pub fn history_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("tau").join("history"))
+}
+
+/// The dir holding the UTF-8 copies of the open files in other encodings, which xi works on
+pub fn transcoded_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("tau")
+        .join(format!("transcoded-{}", std::process::id()))
+}
+
+/// Where the UTF-8 copy of `file_name` is kept. It has the same name as the file, so xi picks
+/// the same syntax for it.
+pub fn transcoded_copy(file_name: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    file_name.hash(&mut hasher);
+    let name = Path::new(file_name).file_name().unwrap_or_default();
+    transcoded_dir()
+        .join(format!("{:016x}", hasher.finish()))
+        .join(name)
+}
+
+/// Delete the UTF-8 copies of the files in other encodings, e.g. when Tau is closed
+pub fn remove_transcoded_dir() {
+    let dir = transcoded_dir();
+    if dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            warn!("Failed to remove decoded copies {:?}: {}", dir, e);
+        }
+    }
+}
+
 /// Run in terminal once it has finished initializing
 pub fn vte_callback() {
     println!("{}", gettext("Welcome to Tau's terminal."));
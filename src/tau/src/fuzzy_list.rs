@@ -0,0 +1,242 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The popover with a search entry above a list of fuzzy-matched items, which the `QuickOpen` and
+//! the `CommandPalette` are built on. They own the items and fill the list via `set_rows`, the
+//! `FuzzyList` takes care of showing the popover, moving the selection and activating rows.
+
+use gdk::enums::key;
+use gdk::Rectangle;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{Builder, Inhibit, ListStore, Popover, SearchEntry, TreePath, TreeView, Widget};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::rc::Rc;
+
+/// The indices of the `items` which match `pattern`, best match first and at most `max` of them.
+/// `score` returns how well an item matches, see `fuzzy_score`. If `pattern` is empty, the first
+/// `max` items are returned in their order.
+pub fn rank<T, F>(pattern: &str, items: &[T], max: usize, score: F) -> Vec<usize>
+where
+    F: Fn(&T) -> Option<i64>,
+{
+    if pattern.is_empty() {
+        return (0..items.len().min(max)).collect();
+    }
+    let mut scored: Vec<(i64, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| score(item).map(|score| (score, i)))
+        .collect();
+    scored.sort_by_key(|(score, i)| (Reverse(*score), *i));
+    scored.into_iter().take(max).map(|(_, i)| i).collect()
+}
+
+pub struct FuzzyList {
+    popover: Popover,
+    search_entry: SearchEntry,
+    treeview: TreeView,
+    list_store: ListStore,
+    /// The indices into the owner's items of the rows which are currently shown
+    shown: RefCell<Vec<usize>>,
+    /// Fills the list again after the search text has changed
+    on_search_changed: RefCell<Option<Rc<dyn Fn()>>>,
+    /// Called with the index of the item the user has chosen
+    on_activated: RefCell<Option<Rc<dyn Fn(usize)>>>,
+}
+
+impl FuzzyList {
+    /// Create the `FuzzyList` from the `{prefix}_popover`, `{prefix}_search_entry`,
+    /// `{prefix}_treeview` and `{prefix}_liststore` objects of `builder`. The list store has two
+    /// string columns, the title and the detail of a row.
+    pub fn new<W: IsA<Widget>>(builder: &Builder, prefix: &str, relative_to: &W) -> Rc<Self> {
+        let popover: Popover = builder.get_object(&format!("{}_popover", prefix)).unwrap();
+        popover.set_relative_to(Some(relative_to));
+
+        let fuzzy_list = Rc::new(Self {
+            popover,
+            search_entry: builder
+                .get_object(&format!("{}_search_entry", prefix))
+                .unwrap(),
+            treeview: builder.get_object(&format!("{}_treeview", prefix)).unwrap(),
+            list_store: builder
+                .get_object(&format!("{}_liststore", prefix))
+                .unwrap(),
+            shown: Default::default(),
+            on_search_changed: Default::default(),
+            on_activated: Default::default(),
+        });
+
+        fuzzy_list.search_entry.connect_search_changed(
+            clone!(@weak fuzzy_list => @default-panic, move |_| {
+                fuzzy_list.emit_search_changed();
+            }),
+        );
+
+        fuzzy_list.search_entry.connect_key_press_event(
+            clone!(@weak fuzzy_list => @default-return Inhibit(false), move |_, ek| {
+                match ek.get_keyval() {
+                    key::Down | key::KP_Down => fuzzy_list.move_selection(1),
+                    key::Up | key::KP_Up => fuzzy_list.move_selection(-1),
+                    _ => return Inhibit(false),
+                }
+                Inhibit(true)
+            }),
+        );
+
+        fuzzy_list.search_entry.connect_activate(
+            clone!(@weak fuzzy_list => @default-panic, move |_| {
+                fuzzy_list.activate(None);
+            }),
+        );
+
+        fuzzy_list.treeview.connect_row_activated(
+            clone!(@weak fuzzy_list => @default-panic, move |_, path, _| {
+                fuzzy_list.activate(Some(path));
+            }),
+        );
+
+        fuzzy_list
+    }
+
+    /// Call `f` when the search text has changed, it should call `set_rows` with the items which
+    /// match `pattern`
+    pub fn connect_search_changed<F: Fn() + 'static>(&self, f: F) {
+        self.on_search_changed.replace(Some(Rc::new(f)));
+    }
+
+    fn emit_search_changed(&self) {
+        let handler = self.on_search_changed.borrow().clone();
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+
+    /// Call `f` with the index of the item the user has chosen, after the popover has been hidden
+    pub fn connect_activated<F: Fn(usize) + 'static>(&self, f: F) {
+        self.on_activated.replace(Some(Rc::new(f)));
+    }
+
+    /// Hide the popover and choose the item in the row at `path`, or in the selected row
+    fn activate(&self, path: Option<&TreePath>) {
+        let index = match self.selected_item(path) {
+            Some(index) => index,
+            None => return,
+        };
+        self.popover.popdown();
+        let handler = self.on_activated.borrow().clone();
+        if let Some(handler) = handler {
+            handler(index);
+        }
+    }
+
+    /// Show the popover at the top of the widget it's relative to, with an empty search
+    pub fn show(&self) {
+        self.search_entry.set_text("");
+        self.emit_search_changed();
+
+        if let Some(relative_to) = self.popover.get_relative_to() {
+            let width = relative_to.get_allocated_width();
+            self.popover.set_pointing_to(&Rectangle {
+                x: width / 2,
+                y: 0,
+                width: 1,
+                height: 1,
+            });
+        }
+        self.popover.popup();
+        self.search_entry.grab_focus();
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.popover.is_visible()
+    }
+
+    /// The text the user searches for
+    pub fn pattern(&self) -> String {
+        self.search_entry
+            .get_text()
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Show `rows`, the titles and details of the items with the indices `shown`, and select the
+    /// first one
+    pub fn set_rows<'a, I>(&self, shown: Vec<usize>, rows: I)
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        self.list_store.clear();
+        for (title, detail) in rows {
+            self.list_store
+                .insert_with_values(None, &[0, 1], &[&title, &detail]);
+        }
+        if let Some(iter) = self.list_store.get_iter_first() {
+            self.treeview.get_selection().select_iter(&iter);
+            self.treeview.scroll_to_cell(
+                Some(&TreePath::new_first()),
+                None::<&gtk::TreeViewColumn>,
+                false,
+                0.0,
+                0.0,
+            );
+        }
+
+        self.shown.replace(shown);
+    }
+
+    /// Move the selection `offset` rows down, wrapping around at the ends
+    fn move_selection(&self, offset: i32) {
+        let n_items = self.list_store.iter_n_children(None);
+        if n_items == 0 {
+            return;
+        }
+
+        let selection = self.treeview.get_selection();
+        let selected = selection
+            .get_selected()
+            .and_then(|(model, iter)| model.get_path(&iter))
+            .and_then(|path| path.get_indices().first().copied())
+            .unwrap_or(0);
+        let path = TreePath::new_from_indicesv(&[(selected + offset + n_items) % n_items]);
+        selection.select_path(&path);
+        self.treeview
+            .scroll_to_cell(Some(&path), None::<&gtk::TreeViewColumn>, false, 0.0, 0.0);
+    }
+
+    /// Get the index of the item in the row at `path`, or in the selected row
+    fn selected_item(&self, path: Option<&TreePath>) -> Option<usize> {
+        let row = match path {
+            Some(path) => path.get_indices().first().copied(),
+            None => self
+                .treeview
+                .get_selection()
+                .get_selected()
+                .and_then(|(model, iter)| model.get_path(&iter))
+                .and_then(|path| path.get_indices().first().copied()),
+        }?;
+
+        self.shown.borrow().get(row as usize).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rank_items() {
+        let items = ["b", "ab", "abc", "c"];
+        let score = |item: &&str| {
+            if item.contains('b') {
+                Some(item.len() as i64)
+            } else {
+                None
+            }
+        };
+        assert_eq!(rank("b", &items, 10, score), vec![2, 1, 0]);
+        assert_eq!(rank("b", &items, 2, score), vec![2, 1]);
+        assert_eq!(rank("", &items, 3, score), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,624 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use editview::i18n::ni18n_f;
+use gettextrs::gettext;
+use git2::{ErrorCode, ObjectType, Oid, Repository, Status, StatusOptions};
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{Builder, Button, CellRendererToggle, Label, ListStore, TextView, TreeView};
+use log::{debug, trace};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The columns of the `git_panel_liststore`
+const COLUMN_STAGED: u32 = 0;
+const COLUMN_PARTIALLY_STAGED: u32 = 1;
+const COLUMN_STATUS_MARKUP: u32 = 2;
+const COLUMN_PATH: u32 = 3;
+const COLUMN_TOOLTIP: u32 = 4;
+
+/// What has happened to a file, either in the index or in the working directory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    TypeChanged,
+    /// The file isn't tracked by git yet
+    Untracked,
+    /// The file has merge conflicts
+    Conflicted,
+}
+
+impl ChangeKind {
+    /// The letter shown next to files with this change, like `git status --short` does
+    pub fn letter(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "A",
+            ChangeKind::Modified => "M",
+            ChangeKind::Deleted => "D",
+            ChangeKind::Renamed => "R",
+            ChangeKind::TypeChanged => "T",
+            ChangeKind::Untracked => "?",
+            ChangeKind::Conflicted => "!",
+        }
+    }
+
+    pub fn description(self) -> String {
+        match self {
+            ChangeKind::Added => gettext("Added"),
+            ChangeKind::Modified => gettext("Modified"),
+            ChangeKind::Deleted => gettext("Deleted"),
+            ChangeKind::Renamed => gettext("Renamed"),
+            ChangeKind::TypeChanged => gettext("Type changed"),
+            ChangeKind::Untracked => gettext("Untracked"),
+            ChangeKind::Conflicted => gettext("Conflicted"),
+        }
+    }
+}
+
+/// A file which differs from HEAD, in the index, the working directory or both
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileChange {
+    /// The path of the file relative to the working directory of the repository
+    pub path: String,
+    /// The change which has been staged for the next commit
+    pub staged: Option<ChangeKind>,
+    /// The change which hasn't been staged yet
+    pub unstaged: Option<ChangeKind>,
+}
+
+impl FileChange {
+    pub fn new(path: String, status: Status) -> Self {
+        if status.is_conflicted() {
+            return Self {
+                path,
+                staged: None,
+                unstaged: Some(ChangeKind::Conflicted),
+            };
+        }
+
+        let staged = if status.is_index_new() {
+            Some(ChangeKind::Added)
+        } else if status.is_index_modified() {
+            Some(ChangeKind::Modified)
+        } else if status.is_index_deleted() {
+            Some(ChangeKind::Deleted)
+        } else if status.is_index_renamed() {
+            Some(ChangeKind::Renamed)
+        } else if status.is_index_typechange() {
+            Some(ChangeKind::TypeChanged)
+        } else {
+            None
+        };
+        let unstaged = if status.is_wt_new() {
+            Some(ChangeKind::Untracked)
+        } else if status.is_wt_modified() {
+            Some(ChangeKind::Modified)
+        } else if status.is_wt_deleted() {
+            Some(ChangeKind::Deleted)
+        } else if status.is_wt_renamed() {
+            Some(ChangeKind::Renamed)
+        } else if status.is_wt_typechange() {
+            Some(ChangeKind::TypeChanged)
+        } else {
+            None
+        };
+
+        Self {
+            path,
+            staged,
+            unstaged,
+        }
+    }
+
+    /// The change which is shown for the file. Unstaged changes are what the user sees in the
+    /// editor, so they take precedence.
+    pub fn kind(&self) -> ChangeKind {
+        self.unstaged
+            .or(self.staged)
+            .unwrap_or(ChangeKind::Modified)
+    }
+}
+
+/// Open the (non-bare) repository `dir` is in
+pub fn open_repo(dir: &Path) -> Option<Repository> {
+    Repository::discover(dir).ok().filter(|r| !r.is_bare())
+}
+
+/// The name of the branch which is checked out, or the abbreviated id of the commit if HEAD is
+/// detached
+pub fn current_branch(repo: &Repository) -> Option<String> {
+    match repo.head() {
+        Ok(head) if head.is_branch() => head.shorthand().map(String::from),
+        Ok(head) => head
+            .target()
+            .map(|id| id.to_string().chars().take(7).collect()),
+        // There are no commits on the branch yet
+        Err(ref e) if e.code() == ErrorCode::UnbornBranch => repo
+            .find_reference("HEAD")
+            .ok()?
+            .symbolic_target()
+            .map(|t| t.trim_start_matches("refs/heads/").to_string()),
+        Err(_) => None,
+    }
+}
+
+/// All files of `repo` which have been changed, sorted by their path
+pub fn changes(repo: &Repository) -> Result<Vec<FileChange>, git2::Error> {
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    let mut changes: Vec<FileChange> = repo
+        .statuses(Some(&mut options))?
+        .iter()
+        .filter_map(|entry| {
+            let path = entry.path()?.to_string();
+            Some(FileChange::new(path, entry.status()))
+        })
+        .filter(|change| change.staged.is_some() || change.unstaged.is_some())
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// Add the changes to the file at `path` (relative to the working directory) to the index
+pub fn stage(repo: &Repository, path: &str) -> Result<(), git2::Error> {
+    let mut index = repo.index()?;
+    let exists = repo
+        .workdir()
+        .map(|dir| dir.join(path).exists())
+        .unwrap_or(false);
+    if exists {
+        index.add_path(Path::new(path))?;
+    } else {
+        index.remove_path(Path::new(path))?;
+    }
+    index.write()
+}
+
+/// Reset the file at `path` (relative to the working directory) in the index to HEAD, keeping
+/// the changes in the working directory
+pub fn unstage(repo: &Repository, path: &str) -> Result<(), git2::Error> {
+    match repo.head() {
+        Ok(head) => {
+            let commit = head.peel(ObjectType::Commit)?;
+            repo.reset_default(Some(&commit), std::iter::once(path))
+        }
+        // Without any commits, unstaging means removing the file from the index again
+        Err(ref e) if e.code() == ErrorCode::UnbornBranch => {
+            let mut index = repo.index()?;
+            index.remove_path(Path::new(path))?;
+            index.write()
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Commit what has been staged with `message`, using the name and email from the git config
+pub fn commit(repo: &Repository, message: &str) -> Result<Oid, git2::Error> {
+    let signature = repo.signature()?;
+    let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e),
+    };
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+}
+
+/// The contents of the file at `path` (relative to the working directory) in HEAD
+pub fn head_text(repo: &Repository, path: &str) -> Option<String> {
+    let tree = repo.head().ok()?.peel_to_tree().ok()?;
+    let blob = tree.get_path(Path::new(path)).ok()?.to_object(repo).ok()?;
+    let blob = blob.peel_to_blob().ok()?;
+    String::from_utf8(blob.content().to_vec()).ok()
+}
+
+/// What the thread looking at the repository of the project directory has found
+enum RepoStatus {
+    /// The project directory isn't in a git repository
+    NoRepo,
+    Found {
+        workdir: Option<PathBuf>,
+        branch: Option<String>,
+        changes: Result<Vec<FileChange>, String>,
+    },
+}
+
+impl RepoStatus {
+    fn new(root: Option<&Path>) -> Self {
+        match root.and_then(open_repo) {
+            Some(repo) => RepoStatus::Found {
+                workdir: repo.workdir().map(Path::to_path_buf),
+                branch: current_branch(&repo),
+                changes: changes(&repo).map_err(|e| e.message().to_string()),
+            },
+            None => RepoStatus::NoRepo,
+        }
+    }
+}
+
+/// The "Git" panel in the sidebar, which lists the changed files of the repository the project
+/// directory is in. Files can be staged, unstaged, compared with HEAD and committed.
+pub struct GitPanel {
+    /// The panel, to be added to the sidebar
+    pub widget: gtk::Box,
+    branch_label: Label,
+    status_label: Label,
+    treeview: TreeView,
+    list_store: ListStore,
+    message_view: TextView,
+    commit_button: Button,
+    /// The project directory
+    root: RefCell<Option<PathBuf>>,
+    /// The working directory of the repository `root` is in
+    workdir: RefCell<Option<PathBuf>>,
+    /// The branch which is checked out
+    branch: RefCell<Option<String>>,
+    changes: RefCell<Vec<FileChange>>,
+    /// Receives the `RepoStatus` from the threads started by `refresh`
+    sender: Sender<(u64, RepoStatus)>,
+    /// Incremented for every refresh, so results of outdated ones are dropped
+    generation: Cell<u64>,
+    /// Called with the file the user wants to compare with HEAD and its contents in HEAD
+    on_file: RefCell<Option<Box<dyn Fn(PathBuf, String)>>>,
+    /// Called when HEAD has changed, e.g. because the user has committed or switched branches
+    on_head_changed: RefCell<Option<Box<dyn Fn()>>>,
+}
+
+impl GitPanel {
+    pub fn new() -> Rc<Self> {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/git_panel.glade");
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+
+        let git_panel = Rc::new(Self {
+            widget: builder.get_object("git_panel_box").unwrap(),
+            branch_label: builder.get_object("git_panel_branch_label").unwrap(),
+            status_label: builder.get_object("git_panel_status_label").unwrap(),
+            treeview: builder.get_object("git_panel_treeview").unwrap(),
+            list_store: builder.get_object("git_panel_liststore").unwrap(),
+            message_view: builder.get_object("git_panel_message_textview").unwrap(),
+            commit_button: builder.get_object("git_panel_commit_button").unwrap(),
+            root: Default::default(),
+            workdir: Default::default(),
+            branch: Default::default(),
+            changes: Default::default(),
+            sender,
+            generation: Cell::new(0),
+            on_file: Default::default(),
+            on_head_changed: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak git_panel => @default-return glib::Continue(false), move |msg| {
+                git_panel.receive(msg);
+                glib::Continue(true)
+            }),
+        );
+
+        let refresh_button: Button = builder.get_object("git_panel_refresh_button").unwrap();
+        refresh_button.connect_clicked(clone!(@weak git_panel => @default-panic, move |_| {
+            git_panel.refresh();
+        }));
+
+        let staged_renderer: CellRendererToggle =
+            builder.get_object("git_panel_staged_renderer").unwrap();
+        staged_renderer.connect_toggled(clone!(@weak git_panel => @default-panic, move |_, path| {
+            if let Some(row) = path.get_indices().first() {
+                git_panel.toggle_staged(*row as usize);
+            }
+        }));
+
+        git_panel.treeview.connect_row_activated(
+            clone!(@weak git_panel => @default-panic, move |_, path, _| {
+                if let Some(row) = path.get_indices().first() {
+                    git_panel.open(*row as usize);
+                }
+            }),
+        );
+
+        if let Some(buffer) = git_panel.message_view.get_buffer() {
+            buffer.connect_changed(clone!(@weak git_panel => @default-panic, move |_| {
+                git_panel.update_commit_button();
+            }));
+        }
+
+        git_panel.commit_button.connect_clicked(
+            clone!(@weak git_panel => @default-panic, move |_| {
+                git_panel.commit();
+            }),
+        );
+
+        git_panel
+    }
+
+    /// Call `f` with the files the user wants to compare with HEAD and their contents in HEAD,
+    /// which are empty for new files
+    pub fn connect_file_activated<F: Fn(PathBuf, String) + 'static>(&self, f: F) {
+        self.on_file.replace(Some(Box::new(f)));
+    }
+
+    /// Call `f` when HEAD has changed, e.g. after a commit
+    pub fn connect_head_changed<F: Fn() + 'static>(&self, f: F) {
+        self.on_head_changed.replace(Some(Box::new(f)));
+    }
+
+    /// Show the repository `root` is in from now on
+    pub fn set_root(&self, root: PathBuf) {
+        if self.root.borrow().as_ref() == Some(&root) {
+            return;
+        }
+        self.root.replace(Some(root));
+        self.refresh();
+    }
+
+    /// The branch which is checked out in the repository of the project directory
+    pub fn branch(&self) -> Option<String> {
+        self.branch.borrow().clone()
+    }
+
+    fn repo(&self) -> Option<Repository> {
+        self.workdir
+            .borrow()
+            .as_ref()
+            .and_then(|dir| Repository::open(dir).ok())
+    }
+
+    /// Look at the repository again, e.g. after files have been saved. Collecting the changes of
+    /// a big repository takes a while, so it's done in a thread.
+    pub fn refresh(&self) {
+        trace!("Refreshing the git panel");
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        let root = self.root.borrow().clone();
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let status = RepoStatus::new(root.as_deref());
+            sender.send((generation, status)).ok();
+        });
+    }
+
+    fn receive(&self, (generation, status): (u64, RepoStatus)) {
+        if generation == self.generation.get() {
+            self.show_status(status);
+        }
+    }
+
+    /// Show the state of the repository which `refresh` has found
+    fn show_status(&self, status: RepoStatus) {
+        self.list_store.clear();
+        self.changes.borrow_mut().clear();
+
+        let (workdir, branch, changes) = match status {
+            RepoStatus::Found {
+                workdir,
+                branch,
+                changes,
+            } => (workdir, branch, changes),
+            RepoStatus::NoRepo => {
+                self.workdir.replace(None);
+                self.set_branch(None);
+                self.status_label
+                    .set_text(&gettext("The project isn’t a git repository"));
+                self.update_commit_button();
+                return;
+            }
+        };
+        self.workdir.replace(workdir);
+        self.set_branch(branch);
+
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(e) => {
+                self.status_label.set_text(&e);
+                self.update_commit_button();
+                return;
+            }
+        };
+
+        for change in &changes {
+            let kind = change.kind();
+            let tooltip = match (change.staged, change.unstaged) {
+                (Some(staged), Some(unstaged)) => format!(
+                    "{}: {}\n{}: {}",
+                    gettext("Staged"),
+                    staged.description(),
+                    gettext("Not staged"),
+                    unstaged.description()
+                ),
+                (Some(staged), None) => format!("{}: {}", gettext("Staged"), staged.description()),
+                _ => kind.description(),
+            };
+            self.list_store.insert_with_values(
+                None,
+                &[
+                    COLUMN_STAGED,
+                    COLUMN_PARTIALLY_STAGED,
+                    COLUMN_STATUS_MARKUP,
+                    COLUMN_PATH,
+                    COLUMN_TOOLTIP,
+                ],
+                &[
+                    &change.staged.is_some(),
+                    &(change.staged.is_some() && change.unstaged.is_some()),
+                    &format!("<tt><b>{}</b></tt>", kind.letter()),
+                    &change.path,
+                    &tooltip,
+                ],
+            );
+        }
+
+        if changes.is_empty() {
+            self.status_label.set_text(&gettext("No changes"));
+        } else {
+            self.status_label.set_text(&ni18n_f(
+                "{} changed file",
+                "{} changed files",
+                changes.len() as u32,
+                &[&changes.len().to_string()],
+            ));
+        }
+        self.changes.replace(changes);
+        self.update_commit_button();
+    }
+
+    fn set_branch(&self, branch: Option<String>) {
+        self.branch_label
+            .set_text(branch.as_deref().unwrap_or_default());
+        let changed = *self.branch.borrow() != branch;
+        self.branch.replace(branch);
+        if changed {
+            self.emit_head_changed();
+        }
+    }
+
+    fn emit_head_changed(&self) {
+        if let Some(f) = &*self.on_head_changed.borrow() {
+            f();
+        }
+    }
+
+    /// Stage the file in `row` if it has unstaged changes, otherwise unstage it
+    fn toggle_staged(&self, row: usize) {
+        let change = match self.changes.borrow().get(row) {
+            Some(change) => change.clone(),
+            None => return,
+        };
+        let repo = match self.repo() {
+            Some(repo) => repo,
+            None => return,
+        };
+
+        let res = if change.unstaged.is_some() {
+            debug!("Staging '{}'", change.path);
+            stage(&repo, &change.path)
+        } else {
+            debug!("Unstaging '{}'", change.path);
+            unstage(&repo, &change.path)
+        };
+        if let Err(e) = res {
+            ErrorDialog::new(ErrorMsg::new(
+                format!(
+                    "{} “{}”: {}",
+                    gettext("Couldn’t stage"),
+                    change.path,
+                    e.message()
+                ),
+                false,
+            ));
+        }
+        self.refresh();
+    }
+
+    fn open(&self, row: usize) {
+        let path = match self.changes.borrow().get(row) {
+            Some(change) => change.path.clone(),
+            None => return,
+        };
+        let repo = match self.repo() {
+            Some(repo) => repo,
+            None => return,
+        };
+        let head = head_text(&repo, &path).unwrap_or_default();
+        if let (Some(workdir), Some(f)) = (repo.workdir(), &*self.on_file.borrow()) {
+            f(workdir.join(&path), head);
+        }
+    }
+
+    fn message(&self) -> String {
+        self.message_view
+            .get_buffer()
+            .and_then(|buffer| {
+                let (start, end) = buffer.get_bounds();
+                buffer.get_text(&start, &end, false)
+            })
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Only allow committing if something has been staged and there is a message
+    fn update_commit_button(&self) {
+        let has_staged = self.changes.borrow().iter().any(|c| c.staged.is_some());
+        self.commit_button
+            .set_sensitive(has_staged && !self.message().is_empty());
+    }
+
+    fn commit(&self) {
+        let repo = match self.repo() {
+            Some(repo) => repo,
+            None => return,
+        };
+        match commit(&repo, &self.message()) {
+            Ok(id) => {
+                debug!("Created commit {}", id);
+                if let Some(buffer) = self.message_view.get_buffer() {
+                    buffer.set_text("");
+                }
+                self.refresh();
+                self.emit_head_changed();
+            }
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(
+                    format!("{}: {}", gettext("Couldn’t commit"), e.message()),
+                    false,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn staged_and_unstaged_changes() {
+        let change = FileChange::new(
+            "src/main.rs".to_string(),
+            Status::INDEX_MODIFIED | Status::WT_MODIFIED,
+        );
+        assert_eq!(change.staged, Some(ChangeKind::Modified));
+        assert_eq!(change.unstaged, Some(ChangeKind::Modified));
+
+        let change = FileChange::new("new.rs".to_string(), Status::INDEX_NEW);
+        assert_eq!(change.staged, Some(ChangeKind::Added));
+        assert_eq!(change.unstaged, None);
+        assert_eq!(change.kind(), ChangeKind::Added);
+
+        let change = FileChange::new("old.rs".to_string(), Status::WT_DELETED);
+        assert_eq!(change.staged, None);
+        assert_eq!(change.kind(), ChangeKind::Deleted);
+    }
+
+    #[test]
+    fn untracked_and_conflicted_files() {
+        let change = FileChange::new("notes.txt".to_string(), Status::WT_NEW);
+        assert_eq!(change.kind(), ChangeKind::Untracked);
+        assert_eq!(change.kind().letter(), "?");
+
+        let change = FileChange::new(
+            "merge.rs".to_string(),
+            Status::CONFLICTED | Status::INDEX_MODIFIED,
+        );
+        assert_eq!(change.staged, None);
+        assert_eq!(change.kind(), ChangeKind::Conflicted);
+    }
+}
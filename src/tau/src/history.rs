@@ -0,0 +1,191 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The local history of the files Tau saves, so earlier versions can be restored even if a file
+//! isn't under version control. Each file has a dir in `functions::history_dir`, named after a
+//! hash of its path, which holds the path in `path` and a snapshot of each save, named after the
+//! time of the save in milliseconds since the Unix epoch.
+
+use log::error;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The extension of the snapshot files
+const SNAPSHOT_EXTENSION: &str = "snapshot";
+
+/// How many snapshots are kept for how long. A limit of 0 means there's no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub max_snapshots: usize,
+    pub max_days: u64,
+}
+
+/// A saved version of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// The file the snapshot is stored in
+    pub path: PathBuf,
+    /// When the file was saved, in milliseconds since the Unix epoch
+    pub saved_at: u64,
+}
+
+impl Snapshot {
+    pub fn read(&self) -> Result<String, String> {
+        std::fs::read_to_string(&self.path).map_err(|e| format!("{:?}: {}", self.path, e))
+    }
+}
+
+/// The current time in milliseconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// The FNV-1a hash of `path`, which unlike `DefaultHasher` stays the same across Rust versions
+fn path_hash(path: &Path) -> u64 {
+    path.to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// The dir the snapshots of `file` are kept in
+fn file_dir(history_dir: &Path, file: &Path) -> PathBuf {
+    history_dir.join(format!("{:016x}", path_hash(file)))
+}
+
+/// The snapshots in `dir`, the newest first
+fn snapshots_in(dir: &Path) -> Vec<Snapshot> {
+    let mut snapshots: Vec<Snapshot> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |e| e == SNAPSHOT_EXTENSION))
+        .filter_map(|path| {
+            let saved_at = path.file_stem()?.to_str()?.parse().ok()?;
+            Some(Snapshot { path, saved_at })
+        })
+        .collect();
+    snapshots.sort_by(|a, b| b.saved_at.cmp(&a.saved_at));
+    snapshots
+}
+
+/// The snapshots of `file`, the newest first
+pub fn snapshots(history_dir: &Path, file: &Path) -> Vec<Snapshot> {
+    snapshots_in(&file_dir(history_dir, file))
+}
+
+/// Add a snapshot of `file` with `text`, unless it's the same as the newest one, and delete the
+/// snapshots `retention` doesn't keep anymore
+pub fn add_snapshot(
+    history_dir: &Path,
+    file: &Path,
+    text: &str,
+    retention: Retention,
+) -> Result<(), String> {
+    let dir = file_dir(history_dir, file);
+    let snapshots = snapshots_in(&dir);
+    if let Some(newest) = snapshots.first() {
+        if newest.read().ok().as_deref() == Some(text) {
+            return Ok(());
+        }
+    }
+
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{:?}: {}", dir, e))?;
+    std::fs::write(dir.join("path"), file.to_string_lossy().as_bytes())
+        .map_err(|e| format!("{:?}: {}", dir, e))?;
+    // Saving twice within a millisecond mustn't overwrite the first snapshot
+    let newest = snapshots.first().map_or(0, |s| s.saved_at + 1);
+    let saved_at = now().max(newest);
+    let path = dir.join(format!("{}.{}", saved_at, SNAPSHOT_EXTENSION));
+    std::fs::write(&path, text).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    prune_dir(&dir, retention, now());
+    Ok(())
+}
+
+/// Delete the snapshots in `dir` which `retention` doesn't keep, and `dir` itself if none are left
+fn prune_dir(dir: &Path, retention: Retention, now: u64) {
+    let max_age = retention.max_days * 24 * 60 * 60 * 1000;
+    let snapshots = snapshots_in(dir);
+    let mut kept = 0;
+    for (i, snapshot) in snapshots.iter().enumerate() {
+        let too_many = retention.max_snapshots != 0 && i >= retention.max_snapshots;
+        let too_old = retention.max_days != 0 && now.saturating_sub(snapshot.saved_at) > max_age;
+        if !too_many && !too_old {
+            kept += 1;
+        } else if let Err(e) = std::fs::remove_file(&snapshot.path) {
+            error!("Failed to remove snapshot {:?}: {}", snapshot.path, e);
+        }
+    }
+    if kept == 0 {
+        if let Err(e) = std::fs::remove_dir_all(dir) {
+            error!("Failed to remove local history {:?}: {}", dir, e);
+        }
+    }
+}
+
+/// Delete the snapshots of all files which `retention` doesn't keep, e.g. those of files which
+/// haven't been saved for a long time
+pub fn prune(history_dir: &Path, retention: Retention) {
+    let now = now();
+    for dir in std::fs::read_dir(history_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+    {
+        prune_dir(&dir, retention, now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn snapshots_and_retention() {
+        let dir = TempDir::new("history");
+        let file = Path::new("/home/user/notes.txt");
+        let retention = Retention {
+            max_snapshots: 2,
+            max_days: 0,
+        };
+
+        assert!(snapshots(&dir, file).is_empty());
+        add_snapshot(&dir, file, "one", retention).unwrap();
+        add_snapshot(&dir, file, "one", retention).unwrap();
+        assert_eq!(snapshots(&dir, file).len(), 1);
+        add_snapshot(&dir, file, "two", retention).unwrap();
+        add_snapshot(&dir, file, "three", retention).unwrap();
+        add_snapshot(&dir, Path::new("/other.txt"), "other", retention).unwrap();
+
+        let texts: Vec<String> = snapshots(&dir, file)
+            .iter()
+            .map(|s| s.read().unwrap())
+            .collect();
+        assert_eq!(texts, vec!["three", "two"]);
+        let file_dir = file_dir(&dir, file);
+        assert_eq!(
+            std::fs::read_to_string(file_dir.join("path")).unwrap(),
+            "/home/user/notes.txt"
+        );
+
+        // Snapshots older than a day are dropped
+        let old = now() - 2 * 24 * 60 * 60 * 1000;
+        std::fs::write(file_dir.join(format!("{}.snapshot", old)), "old").unwrap();
+        let retention = Retention {
+            max_snapshots: 0,
+            max_days: 1,
+        };
+        prune(&dir, retention);
+        assert_eq!(snapshots(&dir, file).len(), 2);
+        prune_dir(&file_dir, retention, now() + 2 * 24 * 60 * 60 * 1000);
+        assert!(!file_dir.exists());
+    }
+}
@@ -0,0 +1,746 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Connects the `EditView`s with the language servers of their syntax, see `lsp` for the
+//! protocol itself
+
+use crate::find_in_files::MatchingLine;
+use crate::lsp::{self, Connection, LineIndex, Location, LspRange, Message, ServerConfig};
+use editview::completion::CompletionItem;
+use editview::diagnostics::Diagnostic;
+use editview::language_features::LanguageFeatures;
+use editview::text::{Position, Range};
+use editview::{EditView, EditViewExt};
+use glib::{clone, MainContext};
+use log::{debug, info, trace, warn};
+use serde_json::{json, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use tau_rpc::ViewId;
+
+/// How long to wait after the last change of a document before sending it to the server
+const CHANGE_DELAY_MS: u32 = 300;
+
+/// Documents with more lines aren't sent to language servers, the whole text is sent after each
+/// change and fetching it from xi would make typing slow
+const SYNC_MAX_LINES: u64 = 20_000;
+
+/// The producer the diagnostics of language servers are set on the `EditView`s as
+pub const DIAGNOSTICS_PRODUCER: &str = "language-server";
+
+type Callback = Box<dyn FnOnce(Result<Value, String>)>;
+
+/// Called with the diagnostics a server has published for a file
+type DiagnosticsHandler = Rc<dyn Fn(&Path, Vec<Diagnostic>)>;
+
+/// What we send once the server has been initialized
+enum Queued {
+    Request(String, Value, Callback),
+    Notification(String, Value),
+}
+
+/// A document the server knows about
+struct Document {
+    /// How many views show the document
+    views: usize,
+    /// The version and text we've last sent, `None` before `textDocument/didOpen`
+    synced: Option<(i64, String)>,
+}
+
+/// A running language server, shared by all views of its syntax in the same project
+pub struct LanguageServer {
+    command: String,
+    connection: Connection,
+    /// What the server supports, `None` until it has been initialized
+    capabilities: RefCell<Option<Value>>,
+    queue: RefCell<Vec<Queued>>,
+    /// The callbacks of the requests the server hasn't answered yet
+    pending: RefCell<HashMap<u64, Callback>>,
+    documents: RefCell<HashMap<PathBuf, Document>>,
+    exited: Cell<bool>,
+    on_diagnostics: DiagnosticsHandler,
+}
+
+impl LanguageServer {
+    fn new(
+        config: &ServerConfig,
+        root: &Path,
+        on_diagnostics: DiagnosticsHandler,
+    ) -> std::io::Result<Rc<Self>> {
+        info!(
+            "Starting language server '{}' in {:?}",
+            config.command, root
+        );
+        let (sender, receiver) = MainContext::channel(glib::PRIORITY_DEFAULT);
+        let connection = Connection::spawn(config, root, move |message| {
+            sender.send(message).ok();
+        })?;
+
+        let server = Rc::new(Self {
+            command: config.command.clone(),
+            connection,
+            capabilities: RefCell::new(None),
+            queue: RefCell::new(Vec::new()),
+            pending: RefCell::new(HashMap::new()),
+            documents: RefCell::new(HashMap::new()),
+            exited: Cell::new(false),
+            on_diagnostics,
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak server => @default-return glib::Continue(false), move |message| {
+                server.receive(message);
+                glib::Continue(true)
+            }),
+        );
+
+        let params = lsp::initialize_params(root, config.initialization_options.as_ref());
+        server.send_request(
+            "initialize",
+            params,
+            Box::new(
+                clone!(@weak server => @default-return (), move |result: Result<Value, String>| {
+                    server.initialized(result);
+                }),
+            ),
+        );
+
+        Ok(server)
+    }
+
+    fn initialized(&self, result: Result<Value, String>) {
+        let capabilities = match result {
+            Ok(mut result) => result
+                .get_mut("capabilities")
+                .map(Value::take)
+                .unwrap_or_default(),
+            Err(e) => {
+                warn!(
+                    "Failed to initialize language server '{}' due to error: {}",
+                    self.command, e
+                );
+                return;
+            }
+        };
+        debug!(
+            "Language server '{}' has been initialized: {}",
+            self.command, capabilities
+        );
+        self.capabilities.replace(Some(capabilities));
+        self.send_notification("initialized", json!({}));
+
+        let queue: Vec<Queued> = self.queue.borrow_mut().drain(..).collect();
+        for queued in queue {
+            match queued {
+                Queued::Request(method, params, callback) => {
+                    self.send_request(&method, params, callback)
+                }
+                Queued::Notification(method, params) => self.send_notification(&method, params),
+            }
+        }
+    }
+
+    fn receive(&self, message: Option<Message>) {
+        match message {
+            Some(Message::Response { id, result }) => {
+                let callback = self.pending.borrow_mut().remove(&id);
+                if let Some(callback) = callback {
+                    callback(result);
+                }
+            }
+            Some(Message::Notification { method, params }) => match method.as_str() {
+                "textDocument/publishDiagnostics" => self.publish_diagnostics(&params),
+                "window/showMessage" | "window/logMessage" => {
+                    debug!("{}: {}", self.command, params["message"]);
+                }
+                _ => trace!("Ignoring notification '{}' of '{}'", method, self.command),
+            },
+            Some(Message::Request { id, method, params }) => {
+                // We don't have any settings, so answer every item of the request with `null`
+                let result = if method == "workspace/configuration" {
+                    let n_items = params["items"].as_array().map(Vec::len).unwrap_or(0);
+                    Value::Array(vec![Value::Null; n_items])
+                } else {
+                    Value::Null
+                };
+                if let Err(e) = self.connection.respond(id, result) {
+                    warn!("Failed to answer language server '{}': {}", self.command, e);
+                }
+            }
+            None => {
+                warn!("Language server '{}' has exited", self.command);
+                self.exited.set(true);
+                let pending: Vec<Callback> = self
+                    .pending
+                    .borrow_mut()
+                    .drain()
+                    .map(|(_, callback)| callback)
+                    .collect();
+                for callback in pending {
+                    callback(Err(format!("{} has exited", self.command)));
+                }
+            }
+        }
+    }
+
+    fn publish_diagnostics(&self, params: &Value) {
+        let (path, diagnostics) = match lsp::parse_diagnostics(params) {
+            Some(diagnostics) => diagnostics,
+            None => return,
+        };
+        let text = self.text(&path).unwrap_or_default();
+        let index = LineIndex::new(&text);
+        let diagnostics = diagnostics.iter().map(|d| index.diagnostic(d)).collect();
+        (self.on_diagnostics)(&path, diagnostics);
+    }
+
+    fn send_request(&self, method: &str, params: Value, callback: Callback) {
+        if self.exited.get() {
+            return callback(Err(format!("{} has exited", self.command)));
+        }
+        match self.connection.request(method, params) {
+            Ok(id) => {
+                self.pending.borrow_mut().insert(id, callback);
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to send '{}' to language server '{}': {}",
+                    method, self.command, e
+                );
+                callback(Err(e.to_string()));
+            }
+        }
+    }
+
+    fn send_notification(&self, method: &str, params: Value) {
+        if self.exited.get() {
+            return;
+        }
+        if let Err(e) = self.connection.notify(method, params) {
+            warn!(
+                "Failed to send '{}' to language server '{}': {}",
+                method, self.command, e
+            );
+        }
+    }
+
+    /// Send a request once the server has been initialized and call `callback` with its result
+    pub fn request<F>(&self, method: &str, params: Value, callback: F)
+    where
+        F: FnOnce(Result<Value, String>) + 'static,
+    {
+        if self.capabilities.borrow().is_some() {
+            self.send_request(method, params, Box::new(callback));
+        } else {
+            self.queue.borrow_mut().push(Queued::Request(
+                method.to_string(),
+                params,
+                Box::new(callback),
+            ));
+        }
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        if self.capabilities.borrow().is_some() {
+            self.send_notification(method, params);
+        } else {
+            self.queue
+                .borrow_mut()
+                .push(Queued::Notification(method.to_string(), params));
+        }
+    }
+
+    /// Whether the server supports the request of `capability`, e.g. "hoverProvider"
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities
+            .borrow()
+            .as_ref()
+            .map(|c| lsp::has_capability(c, capability))
+            .unwrap_or(false)
+    }
+
+    fn add_view(&self, path: &Path) {
+        self.documents
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_insert(Document {
+                views: 0,
+                synced: None,
+            })
+            .views += 1;
+    }
+
+    /// Returns whether this was the last view of any document
+    fn remove_view(&self, path: &Path) -> bool {
+        let mut documents = self.documents.borrow_mut();
+        let closed = match documents.get_mut(path) {
+            Some(document) => {
+                document.views -= 1;
+                document.views == 0
+            }
+            None => false,
+        };
+        if closed {
+            let synced = documents.remove(path).and_then(|d| d.synced).is_some();
+            if synced {
+                self.notify(
+                    "textDocument/didClose",
+                    json!({ "textDocument": { "uri": lsp::path_to_uri(path) } }),
+                );
+            }
+        }
+        documents.is_empty()
+    }
+
+    /// Tell the server about the current `text` of the document at `path`
+    fn sync(&self, path: &Path, language_id: &str, text: String) {
+        let mut documents = self.documents.borrow_mut();
+        let document = match documents.get_mut(path) {
+            Some(document) => document,
+            // The view has been closed in the meantime
+            None => return,
+        };
+        let uri = lsp::path_to_uri(path);
+        match &document.synced {
+            Some((_, synced_text)) if *synced_text == text => {}
+            Some((version, _)) => {
+                let version = version + 1;
+                self.notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": { "uri": uri, "version": version },
+                        "contentChanges": [{ "text": text }],
+                    }),
+                );
+                document.synced = Some((version, text));
+            }
+            None => {
+                self.notify(
+                    "textDocument/didOpen",
+                    json!({ "textDocument": {
+                        "uri": uri,
+                        "languageId": language_id,
+                        "version": 0,
+                        "text": text,
+                    }}),
+                );
+                document.synced = Some((0, text));
+            }
+        }
+    }
+
+    /// The text we've last sent of the document at `path`, or the text on disk
+    fn text(&self, path: &Path) -> Option<String> {
+        self.documents
+            .borrow()
+            .get(path)
+            .and_then(|d| d.synced.as_ref())
+            .map(|(_, text)| text.clone())
+            .or_else(|| std::fs::read_to_string(path).ok())
+    }
+
+    /// The parameters of requests about `position` in the document at `path`
+    fn position_params(&self, path: &Path, position: Position) -> Value {
+        let text = self.text(path).unwrap_or_default();
+        json!({
+            "textDocument": { "uri": lsp::path_to_uri(path) },
+            "position": LineIndex::new(&text).to_lsp(position),
+        })
+    }
+}
+
+impl Drop for LanguageServer {
+    fn drop(&mut self) {
+        info!("Stopping language server '{}'", self.command);
+        if !self.exited.get() && self.capabilities.borrow().is_some() {
+            self.connection.request("shutdown", Value::Null).ok();
+            self.connection.notify("exit", Value::Null).ok();
+        }
+    }
+}
+
+/// The language features of a document for its `EditView`s
+struct DocumentFeatures {
+    server: Weak<LanguageServer>,
+    edit_view: Weak<EditView>,
+    path: PathBuf,
+    language_id: String,
+}
+
+impl DocumentFeatures {
+    /// Returns the server after making sure it knows about the latest changes
+    fn server(&self) -> Option<Rc<LanguageServer>> {
+        let server = self.server.upgrade()?;
+        if let Some(text) = self.edit_view.upgrade().and_then(|ev| ev.text()) {
+            server.sync(&self.path, &self.language_id, text);
+        }
+        Some(server)
+    }
+}
+
+impl LanguageFeatures for DocumentFeatures {
+    fn hover(&self, position: Position, reply: Box<dyn FnOnce(Option<String>)>) {
+        let server = match self.server() {
+            Some(server) if server.supports("hoverProvider") => server,
+            _ => return reply(None),
+        };
+        let params = server.position_params(&self.path, position);
+        server.request("textDocument/hover", params, move |result| {
+            reply(result.ok().and_then(|r| lsp::hover_text(&r)));
+        });
+    }
+
+    fn complete(&self, position: Position, reply: Box<dyn FnOnce(Vec<CompletionItem>)>) {
+        let server = match self.server() {
+            Some(server) if server.supports("completionProvider") => server,
+            _ => return reply(Vec::new()),
+        };
+        let params = server.position_params(&self.path, position);
+        server.request("textDocument/completion", params, move |result| {
+            reply(
+                result
+                    .map(|r| lsp::parse_completions(&r))
+                    .unwrap_or_default(),
+            );
+        });
+    }
+
+    fn triggers_completion(&self, c: char) -> bool {
+        self.server
+            .upgrade()
+            .and_then(|server| {
+                server
+                    .capabilities
+                    .borrow()
+                    .as_ref()
+                    .map(|c| lsp::trigger_characters(c))
+            })
+            .map(|chars| chars.contains(&c))
+            .unwrap_or(false)
+    }
+}
+
+/// Which server a view is attached to
+struct Attachment {
+    server: Rc<LanguageServer>,
+    key: (String, PathBuf),
+    path: PathBuf,
+    language_id: String,
+    /// The pending `textDocument/didChange` after the document has changed
+    timeout: Rc<RefCell<Option<glib::SourceId>>>,
+}
+
+/// All running language servers, keyed by their command and project directory
+pub struct LanguageServers {
+    configs: HashMap<String, ServerConfig>,
+    servers: RefCell<HashMap<(String, PathBuf), Rc<LanguageServer>>>,
+    views: RefCell<HashMap<ViewId, Attachment>>,
+    /// The servers which couldn't be started, so we only complain once
+    failed: RefCell<HashSet<String>>,
+    /// The last diagnostics of each file
+    diagnostics: Rc<RefCell<HashMap<PathBuf, Vec<Diagnostic>>>>,
+    on_diagnostics: Rc<RefCell<Option<Box<dyn Fn(&Path, &[Diagnostic])>>>>,
+}
+
+impl LanguageServers {
+    pub fn new() -> Self {
+        let path = crate::functions::language_servers_file();
+        Self {
+            configs: lsp::load_configs(path.as_deref()),
+            servers: Default::default(),
+            views: Default::default(),
+            failed: Default::default(),
+            diagnostics: Default::default(),
+            on_diagnostics: Default::default(),
+        }
+    }
+
+    /// Call `f` with the diagnostics whenever a server publishes them for a file
+    pub fn connect_diagnostics<F: Fn(&Path, &[Diagnostic]) + 'static>(&self, f: F) {
+        self.on_diagnostics.replace(Some(Box::new(f)));
+    }
+
+    /// The last diagnostics of the file at `path`
+    pub fn diagnostics(&self, path: &Path) -> Vec<Diagnostic> {
+        self.diagnostics
+            .borrow()
+            .get(path)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Connect `edit_view` to the language server of `syntax`, starting it if necessary. Servers
+    /// run in the project directory if the file is in it, otherwise in the file's directory.
+    pub fn attach(&self, edit_view: &Rc<EditView>, syntax: &str, project_root: Option<&Path>) {
+        self.detach(edit_view);
+
+        let path = match edit_view.file_name.borrow().as_ref() {
+            Some(file_name) => PathBuf::from(file_name),
+            None => return,
+        };
+        if edit_view.line_count() > SYNC_MAX_LINES {
+            debug!(
+                "Not sending {:?} to a language server, it's too large",
+                path
+            );
+            return;
+        }
+        let config = match self.configs.get(syntax) {
+            Some(config) => config,
+            None => return,
+        };
+        let root = match project_root {
+            Some(root) if path.starts_with(root) => root.to_path_buf(),
+            _ => match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return,
+            },
+        };
+
+        let key = (config.command.clone(), root);
+        let server = match self.server(&key, config) {
+            Some(server) => server,
+            None => return,
+        };
+        debug!(
+            "Attaching EditView '{}' to language server '{}'",
+            edit_view.view_id, config.command
+        );
+
+        server.add_view(&path);
+        let language_id = lsp::language_id(syntax);
+        edit_view.with_text(
+            clone!(@weak server, @strong path, @strong language_id => move |text| {
+                match text {
+                    Ok(text) => server.sync(&path, &language_id, text),
+                    Err(e) => warn!("Failed to open {:?} in its language server: {}", path, e),
+                }
+            }),
+        );
+        edit_view.set_language_features(Some(Rc::new(DocumentFeatures {
+            server: Rc::downgrade(&server),
+            edit_view: Rc::downgrade(edit_view),
+            path: path.clone(),
+            language_id: language_id.clone(),
+        })));
+        edit_view.set_diagnostics(DIAGNOSTICS_PRODUCER, self.diagnostics(&path));
+
+        self.views.borrow_mut().insert(
+            edit_view.view_id,
+            Attachment {
+                server,
+                key,
+                path,
+                language_id,
+                timeout: Default::default(),
+            },
+        );
+    }
+
+    /// Returns the server of `key`, starting it if it isn't running yet
+    fn server(&self, key: &(String, PathBuf), config: &ServerConfig) -> Option<Rc<LanguageServer>> {
+        if let Some(server) = self.servers.borrow().get(key) {
+            if !server.exited.get() {
+                return Some(server.clone());
+            }
+        }
+        if self.failed.borrow().contains(&config.command) {
+            return None;
+        }
+
+        let diagnostics = self.diagnostics.clone();
+        let on_diagnostics = self.on_diagnostics.clone();
+        let handler: DiagnosticsHandler = Rc::new(move |path, new_diagnostics| {
+            if let Some(f) = on_diagnostics.borrow().as_ref() {
+                f(path, &new_diagnostics);
+            }
+            diagnostics
+                .borrow_mut()
+                .insert(path.to_path_buf(), new_diagnostics);
+        });
+
+        match LanguageServer::new(config, &key.1, handler) {
+            Ok(server) => {
+                self.servers
+                    .borrow_mut()
+                    .insert(key.clone(), server.clone());
+                Some(server)
+            }
+            Err(e) => {
+                // Most likely it isn't installed, which is fine
+                info!(
+                    "Couldn't start language server '{}' due to error: {}",
+                    config.command, e
+                );
+                self.failed.borrow_mut().insert(config.command.clone());
+                None
+            }
+        }
+    }
+
+    /// Disconnect `edit_view` from its language server, e.g. when it's closed. The server is
+    /// stopped once no view uses it anymore.
+    pub fn detach(&self, edit_view: &EditView) {
+        let attachment = match self.views.borrow_mut().remove(&edit_view.view_id) {
+            Some(attachment) => attachment,
+            None => return,
+        };
+        if let Some(id) = attachment.timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        edit_view.set_language_features(None);
+        edit_view.set_diagnostics(DIAGNOSTICS_PRODUCER, Vec::new());
+
+        if attachment.server.remove_view(&attachment.path) {
+            self.servers.borrow_mut().remove(&attachment.key);
+        }
+        let still_open = self
+            .views
+            .borrow()
+            .values()
+            .any(|a| a.path == attachment.path);
+        if !still_open {
+            self.diagnostics.borrow_mut().remove(&attachment.path);
+        }
+    }
+
+    /// Send the changed text of `edit_view` to its server once the user stops typing
+    pub fn changed(&self, edit_view: &Rc<EditView>) {
+        if edit_view.line_count() > SYNC_MAX_LINES
+            && self.views.borrow().contains_key(&edit_view.view_id)
+        {
+            info!(
+                "Detaching EditView '{}' from its language server, it has become too large",
+                edit_view.view_id
+            );
+            self.detach(edit_view);
+            return;
+        }
+        let views = self.views.borrow();
+        let attachment = match views.get(&edit_view.view_id) {
+            Some(attachment) => attachment,
+            None => return,
+        };
+        if let Some(id) = attachment.timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+
+        let server = Rc::downgrade(&attachment.server);
+        let path = attachment.path.clone();
+        let language_id = attachment.language_id.clone();
+        let timeout = attachment.timeout.clone();
+        let id = glib::timeout_add_local(
+            CHANGE_DELAY_MS,
+            clone!(@weak edit_view, @weak timeout => @default-return glib::Continue(false), move || {
+                timeout.replace(None);
+                let server = server.clone();
+                let path = path.clone();
+                let language_id = language_id.clone();
+                edit_view.with_text(move |text| match (server.upgrade(), text) {
+                    (Some(server), Ok(text)) => server.sync(&path, &language_id, text),
+                    (_, Err(e)) => warn!("Failed to send the changes of {:?}: {}", path, e),
+                    (None, _) => (),
+                });
+                glib::Continue(false)
+            }),
+        );
+        attachment.timeout.replace(Some(id));
+    }
+
+    /// Tell the server of `edit_view` that its file has been saved
+    pub fn saved(&self, edit_view: &EditView) {
+        if let Some(attachment) = self.views.borrow().get(&edit_view.view_id) {
+            attachment.server.notify(
+                "textDocument/didSave",
+                json!({ "textDocument": { "uri": lsp::path_to_uri(&attachment.path) } }),
+            );
+        }
+    }
+
+    /// Send the request `method` about the last cursor of `edit_view` to its server, if it
+    /// supports `capability`. `params` are added to the parameters describing the position.
+    /// Returns `false` if there's no such server.
+    pub fn request_at_cursor<F>(
+        &self,
+        edit_view: &EditView,
+        capability: &str,
+        method: &str,
+        params: Value,
+        callback: F,
+    ) -> bool
+    where
+        F: FnOnce(Result<Value, String>) + 'static,
+    {
+        let views = self.views.borrow();
+        let attachment = match views.get(&edit_view.view_id) {
+            Some(attachment) if attachment.server.supports(capability) => attachment,
+            _ => return false,
+        };
+        let position = match edit_view.cursor_positions().last() {
+            Some(position) => *position,
+            None => return false,
+        };
+
+        if let Some(text) = edit_view.text() {
+            attachment
+                .server
+                .sync(&attachment.path, &attachment.language_id, text);
+        }
+        let mut request = attachment
+            .server
+            .position_params(&attachment.path, position);
+        if let (Some(request), Value::Object(params)) = (request.as_object_mut(), params) {
+            request.extend(params);
+        }
+        attachment.server.request(method, request, callback);
+        true
+    }
+
+    /// The text of the file at `path`, as the servers know it
+    pub fn text(&self, path: &Path) -> Option<String> {
+        self.views
+            .borrow()
+            .values()
+            .find(|a| a.path == path)
+            .and_then(|a| a.server.text(path))
+            .or_else(|| std::fs::read_to_string(path).ok())
+    }
+
+    /// Converts the range of `location` to our positions
+    pub fn resolve(&self, location: &Location) -> Range {
+        let text = self.text(&location.path).unwrap_or_default();
+        LineIndex::new(&text).range(location.range)
+    }
+
+    /// The lines of `ranges` in the file at `path`, e.g. references to a symbol, for listing
+    /// them like search results
+    pub fn matching_lines(&self, path: &Path, ranges: &[LspRange]) -> Vec<MatchingLine> {
+        let text = self.text(path).unwrap_or_default();
+        let index = LineIndex::new(&text);
+        let mut ranges = ranges.to_vec();
+        ranges.sort();
+        let mut lines: Vec<MatchingLine> = Vec::new();
+        for range in ranges {
+            let ((line, start), (end_line, end)) = index.range(range);
+            let line_text = index.line(line);
+            let end = if end_line == line {
+                end as usize
+            } else {
+                line_text.len()
+            };
+            let range = start as usize..end.max(start as usize);
+            match lines.last_mut() {
+                Some(last) if last.line == line => last.ranges.push(range),
+                _ => lines.push(MatchingLine {
+                    line,
+                    text: line_text.to_string(),
+                    ranges: vec![range],
+                }),
+            }
+        }
+        lines
+    }
+}
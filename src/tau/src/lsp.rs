@@ -0,0 +1,1029 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Talking to language servers like rust-analyzer via the Language Server Protocol. This only
+//! deals with the protocol itself, `LanguageServer` connects it with the `EditView`s.
+
+use editview::completion::CompletionItem;
+use editview::diagnostics::{Diagnostic, Severity};
+use editview::text::{Position, Range};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// A position as language servers see it: the line and the UTF-16 code unit in the line
+pub type LspPosition = (u64, u64);
+
+pub type LspRange = (LspPosition, LspPosition);
+
+/// How to start the language server of a syntax
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Sent to the server as `initializationOptions`, see the server's documentation
+    #[serde(default)]
+    pub initialization_options: Option<Value>,
+}
+
+impl ServerConfig {
+    fn new(command: &str, args: &[&str]) -> Self {
+        Self {
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            initialization_options: None,
+        }
+    }
+}
+
+/// The servers which are used if they're installed and the user hasn't configured anything
+/// else, keyed by the name xi uses for the syntax
+pub fn default_configs() -> HashMap<String, ServerConfig> {
+    let mut configs = HashMap::new();
+    configs.insert("Rust".to_string(), ServerConfig::new("rust-analyzer", &[]));
+    for syntax in &["C", "C++", "Objective-C", "Objective-C++"] {
+        configs.insert(syntax.to_string(), ServerConfig::new("clangd", &[]));
+    }
+    configs.insert("Python".to_string(), ServerConfig::new("pylsp", &[]));
+    configs.insert("Go".to_string(), ServerConfig::new("gopls", &[]));
+    for syntax in &["JavaScript", "TypeScript", "TypeScriptReact"] {
+        configs.insert(
+            syntax.to_string(),
+            ServerConfig::new("typescript-language-server", &["--stdio"]),
+        );
+    }
+    configs
+}
+
+/// The default servers, overridden by the ones the user has configured in `text`. Mapping a
+/// syntax to `null` disables its server, e.g. `{ "Python": null }`.
+pub fn parse_configs(text: &str) -> Result<HashMap<String, ServerConfig>, serde_json::Error> {
+    let user: HashMap<String, Option<ServerConfig>> = serde_json::from_str(text)?;
+    let mut configs = default_configs();
+    for (syntax, config) in user {
+        match config {
+            Some(config) => configs.insert(syntax, config),
+            None => configs.remove(&syntax),
+        };
+    }
+    Ok(configs)
+}
+
+/// Load the servers configured in the file at `path`, falling back to the default ones if it
+/// doesn't exist or is invalid
+pub fn load_configs(path: Option<&Path>) -> HashMap<String, ServerConfig> {
+    let text = match path.map(std::fs::read_to_string) {
+        Some(Ok(text)) => text,
+        Some(Err(ref e)) if e.kind() == io::ErrorKind::NotFound => return default_configs(),
+        Some(Err(e)) => {
+            warn!("Failed to read language server config due to error: {}", e);
+            return default_configs();
+        }
+        None => return default_configs(),
+    };
+    parse_configs(&text).unwrap_or_else(|e| {
+        warn!("Failed to parse language server config due to error: {}", e);
+        default_configs()
+    })
+}
+
+/// The identifier language servers use for the syntax xi calls `syntax`
+pub fn language_id(syntax: &str) -> String {
+    match syntax {
+        "C++" => "cpp".to_string(),
+        "C#" => "csharp".to_string(),
+        "Objective-C" => "objective-c".to_string(),
+        "Objective-C++" => "objective-cpp".to_string(),
+        "Bourne Again Shell (bash)" | "Shell-Unix-Generic" => "shellscript".to_string(),
+        _ => syntax.to_lowercase().replace(' ', ""),
+    }
+}
+
+/// Write `message` to `writer`, with the header the protocol requires
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+/// Read the next message from `reader`, `None` if the other side has closed it
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            // Tolerate empty lines between messages
+            continue;
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or_default().trim();
+        let value = parts.next().unwrap_or_default().trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<usize>().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", header, e))
+            })?);
+        }
+    }
+
+    let mut content = vec![0; content_length.unwrap_or_default()];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// A message from the language server
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// The answer to one of our requests, with the error message if it failed
+    Response {
+        id: u64,
+        result: Result<Value, String>,
+    },
+    Notification {
+        method: String,
+        params: Value,
+    },
+    /// The server wants something from us. The id may be a number or a string.
+    Request {
+        id: Value,
+        method: String,
+        params: Value,
+    },
+}
+
+impl Message {
+    pub fn parse(mut value: Value) -> Option<Self> {
+        let params = value
+            .get_mut("params")
+            .map(Value::take)
+            .unwrap_or(Value::Null);
+        let id = value.get_mut("id").map(Value::take);
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .map(String::from);
+        match (id, method) {
+            (Some(id), Some(method)) => Some(Message::Request { id, method, params }),
+            (None, Some(method)) => Some(Message::Notification { method, params }),
+            (Some(id), None) => {
+                let result = match value.get("error") {
+                    Some(error) => Err(error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .unwrap_or("Unknown error")
+                        .to_string()),
+                    None => Ok(value.get_mut("result").map(Value::take).unwrap_or_default()),
+                };
+                Some(Message::Response {
+                    id: id.as_u64()?,
+                    result,
+                })
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+/// A running language server. It's killed when the `Connection` is dropped.
+pub struct Connection {
+    /// The messages for the server, which a background thread writes to its stdin, so a server
+    /// which doesn't read them fast enough can't block us
+    sender: Sender<Value>,
+    next_id: Cell<u64>,
+}
+
+impl Connection {
+    /// Start the server of `config` in `root`. `handler` is called on a background thread for
+    /// every message the server sends and with `None` once it has exited.
+    pub fn spawn<F>(config: &ServerConfig, root: &Path, handler: F) -> io::Result<Self>
+    where
+        F: Fn(Option<Message>) + Send + 'static,
+    {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        let command = config.command.clone();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            loop {
+                match read_message(&mut reader) {
+                    Ok(Some(value)) => match Message::parse(value) {
+                        Some(message) => handler(Some(message)),
+                        None => warn!("Language server '{}' sent an invalid message", command),
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!(
+                            "Failed to read from language server '{}' due to error: {}",
+                            command, e
+                        );
+                        break;
+                    }
+                }
+            }
+            handler(None);
+        });
+
+        let command = config.command.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                match line {
+                    Ok(line) => debug!("{}: {}", command, line),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        let command = config.command.clone();
+        std::thread::spawn(move || write_messages(&command, child, stdin, receiver));
+
+        Ok(Self {
+            sender,
+            next_id: Cell::new(0),
+        })
+    }
+
+    /// Send a request and return its id, which the `Message::Response` will have
+    pub fn request(&self, method: &str, params: Value) -> io::Result<u64> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))?;
+        Ok(id)
+    }
+
+    pub fn notify(&self, method: &str, params: Value) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }))
+    }
+
+    /// Answer the `Message::Request` with `id`
+    pub fn respond(&self, id: Value, result: Value) -> io::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result,
+        }))
+    }
+
+    fn send(&self, message: Value) -> io::Result<()> {
+        self.sender
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The server has exited"))
+    }
+}
+
+/// Write the messages `receiver` gets to the `stdin` of the server `child` until the `Connection`
+/// is dropped, then stop the server. Runs on a background thread.
+fn write_messages(
+    command: &str,
+    mut child: Child,
+    mut stdin: ChildStdin,
+    receiver: Receiver<Value>,
+) {
+    for message in receiver {
+        if let Err(e) = write_message(&mut stdin, &message) {
+            warn!(
+                "Failed to write to language server '{}' due to error: {}",
+                command, e
+            );
+            break;
+        }
+    }
+    drop(stdin);
+
+    // Give the server the chance to exit on its own after `exit`, it might be writing caches
+    for _ in 0..10 {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    child.kill().ok();
+    child.wait().ok();
+}
+
+/// What we tell the server about ourselves in the `initialize` request
+pub fn initialize_params(root: &Path, initialization_options: Option<&Value>) -> Value {
+    json!({
+        "processId": std::process::id(),
+        "rootPath": root,
+        "rootUri": path_to_uri(root),
+        "initializationOptions": initialization_options,
+        "capabilities": {
+            "textDocument": {
+                "synchronization": { "didSave": true },
+                "hover": { "contentFormat": ["plaintext", "markdown"] },
+                "completion": {
+                    "completionItem": { "snippetSupport": false },
+                },
+                "definition": { "linkSupport": true },
+                "references": {},
+                "rename": { "prepareSupport": false },
+                "publishDiagnostics": { "relatedInformation": false },
+            },
+            "workspace": {
+                "workspaceEdit": { "documentChanges": true },
+            },
+        },
+        "workspaceFolders": [{
+            "uri": path_to_uri(root),
+            "name": root.file_name().map(|n| n.to_string_lossy()).unwrap_or_default(),
+        }],
+    })
+}
+
+/// Whether the server supports the request of `capability`, e.g. "hoverProvider"
+pub fn has_capability(capabilities: &Value, capability: &str) -> bool {
+    match capabilities.get(capability) {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(_) => true,
+    }
+}
+
+/// The chars after which the server wants to offer completions, e.g. `.`
+pub fn trigger_characters(capabilities: &Value) -> Vec<char> {
+    capabilities
+        .pointer("/completionProvider/triggerCharacters")
+        .and_then(Value::as_array)
+        .map(|chars| {
+            chars
+                .iter()
+                .filter_map(Value::as_str)
+                .filter_map(|s| s.chars().next())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The `file://` URI of `path`
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// The path of a `file://` URI, `None` for other URIs
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix_compat("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' && i + 2 < encoded.len() {
+            let hex = std::str::from_utf8(&encoded[i + 1..i + 3]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// `str::strip_prefix` is too new for the Rust versions we support
+trait StripPrefix {
+    fn strip_prefix_compat(&self, prefix: &str) -> Option<&str>;
+}
+
+impl StripPrefix for str {
+    fn strip_prefix_compat(&self, prefix: &str) -> Option<&str> {
+        if self.starts_with(prefix) {
+            Some(&self[prefix.len()..])
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_position(value: &Value) -> Option<LspPosition> {
+    Some((
+        value.get("line")?.as_u64()?,
+        value.get("character")?.as_u64()?,
+    ))
+}
+
+pub fn parse_range(value: &Value) -> Option<LspRange> {
+    Some((
+        parse_position(value.get("start")?)?,
+        parse_position(value.get("end")?)?,
+    ))
+}
+
+/// Converts between our positions, whose columns are byte offsets, and the ones of the server,
+/// which count UTF-16 code units
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// The byte offset each line starts at
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    /// The text of `line` without its line ending
+    pub fn line(&self, line: u64) -> &str {
+        let start = match self.starts.get(line as usize) {
+            Some(start) => *start,
+            None => return "",
+        };
+        let end = self
+            .starts
+            .get(line as usize + 1)
+            .copied()
+            .unwrap_or_else(|| self.text.len());
+        self.text[start..end].trim_end_matches(|c| c == '\n' || c == '\r')
+    }
+
+    pub fn to_lsp(&self, (line, col): Position) -> Value {
+        let text = self.line(line);
+        let character: usize = text
+            .char_indices()
+            .take_while(|(i, _)| *i < col as usize)
+            .map(|(_, c)| c.len_utf16())
+            .sum();
+        json!({ "line": line, "character": character })
+    }
+
+    pub fn position(&self, (line, character): LspPosition) -> Position {
+        let mut units = 0;
+        let mut col = 0;
+        for c in self.line(line).chars() {
+            if units >= character {
+                break;
+            }
+            units += c.len_utf16() as u64;
+            col += c.len_utf8() as u64;
+        }
+        (line, col)
+    }
+
+    pub fn range(&self, (start, end): LspRange) -> Range {
+        (self.position(start), self.position(end))
+    }
+
+    /// The byte offset of `position` in the text
+    fn offset(&self, (line, col): Position) -> usize {
+        match self.starts.get(line as usize) {
+            Some(start) => start + (col as usize).min(self.line(line).len()),
+            None => self.text.len(),
+        }
+    }
+
+    pub fn diagnostic(&self, diagnostic: &LspDiagnostic) -> Diagnostic {
+        Diagnostic {
+            range: self.range(diagnostic.range),
+            severity: diagnostic.severity,
+            message: diagnostic.message.clone(),
+            source: diagnostic.source.clone(),
+        }
+    }
+}
+
+/// A diagnostic as sent by the server, before its range has been converted
+#[derive(Clone, Debug, PartialEq)]
+pub struct LspDiagnostic {
+    pub range: LspRange,
+    pub severity: Severity,
+    pub message: String,
+    pub source: Option<String>,
+}
+
+/// The file and diagnostics of a `textDocument/publishDiagnostics` notification
+pub fn parse_diagnostics(params: &Value) -> Option<(PathBuf, Vec<LspDiagnostic>)> {
+    let path = uri_to_path(params.get("uri")?.as_str()?)?;
+    let diagnostics = params
+        .get("diagnostics")?
+        .as_array()?
+        .iter()
+        .filter_map(|d| {
+            Some(LspDiagnostic {
+                range: parse_range(d.get("range")?)?,
+                severity: match d.get("severity").and_then(Value::as_u64) {
+                    Some(2) => Severity::Warning,
+                    Some(3) => Severity::Information,
+                    Some(4) => Severity::Hint,
+                    _ => Severity::Error,
+                },
+                message: d.get("message")?.as_str()?.to_string(),
+                source: d.get("source").and_then(Value::as_str).map(String::from),
+            })
+        })
+        .collect();
+    Some((path, diagnostics))
+}
+
+/// The text of a `textDocument/hover` result, without markdown code fences
+pub fn hover_text(result: &Value) -> Option<String> {
+    fn marked_string(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Object(_) => value.get("value")?.as_str().map(String::from),
+            _ => None,
+        }
+    }
+
+    let contents = result.get("contents")?;
+    let text = match contents {
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(marked_string)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => marked_string(contents)?,
+    };
+    let text = text
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+/// A place in a file, e.g. the definition of a symbol
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub range: LspRange,
+}
+
+/// The locations of a `textDocument/definition` or `textDocument/references` result
+pub fn parse_locations(result: &Value) -> Vec<Location> {
+    fn location(value: &Value) -> Option<Location> {
+        // `LocationLink`s point to the whole definition, we want its name
+        let uri = value.get("uri").or_else(|| value.get("targetUri"))?;
+        let range = value
+            .get("range")
+            .or_else(|| value.get("targetSelectionRange"))?;
+        Some(Location {
+            path: uri_to_path(uri.as_str()?)?,
+            range: parse_range(range)?,
+        })
+    }
+
+    match result {
+        Value::Array(locations) => locations.iter().filter_map(location).collect(),
+        Value::Null => Vec::new(),
+        _ => location(result).into_iter().collect(),
+    }
+}
+
+/// The completions of a `textDocument/completion` result
+pub fn parse_completions(result: &Value) -> Vec<CompletionItem> {
+    let items = match result {
+        Value::Array(items) => items,
+        _ => match result.get("items").and_then(Value::as_array) {
+            Some(items) => items,
+            None => return Vec::new(),
+        },
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            let label = item.get("label")?.as_str()?.to_string();
+            let detail = item
+                .get("detail")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            // Snippets would insert their placeholders literally, so fall back to the label
+            let is_snippet = item.get("insertTextFormat").and_then(Value::as_u64) == Some(2);
+            let insert_text = item
+                .pointer("/textEdit/newText")
+                .or_else(|| item.get("insertText"))
+                .and_then(Value::as_str)
+                .filter(|text| !is_snippet && *text != label)
+                .map(String::from);
+            Some(CompletionItem {
+                label,
+                detail,
+                insert_text,
+            })
+        })
+        .collect()
+}
+
+/// A change to a file, e.g. one of the renames of a `WorkspaceEdit`
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: LspRange,
+    pub new_text: String,
+}
+
+fn parse_text_edits(value: &Value) -> Vec<TextEdit> {
+    value
+        .as_array()
+        .map(|edits| {
+            edits
+                .iter()
+                .filter_map(|edit| {
+                    Some(TextEdit {
+                        range: parse_range(edit.get("range")?)?,
+                        new_text: edit.get("newText")?.as_str()?.to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The edits of a `WorkspaceEdit`, e.g. the result of `textDocument/rename`, by file
+pub fn parse_workspace_edit(result: &Value) -> Vec<(PathBuf, Vec<TextEdit>)> {
+    let mut files = Vec::new();
+    if let Some(changes) = result.get("changes").and_then(Value::as_object) {
+        for (uri, edits) in changes {
+            if let Some(path) = uri_to_path(uri) {
+                files.push((path, parse_text_edits(edits)));
+            }
+        }
+    }
+    if let Some(changes) = result.get("documentChanges").and_then(Value::as_array) {
+        // Creating, renaming and deleting files isn't supported, we've told the server so
+        for change in changes {
+            let path = change
+                .pointer("/textDocument/uri")
+                .and_then(Value::as_str)
+                .and_then(uri_to_path);
+            if let Some(path) = path {
+                files.push((path, parse_text_edits(&change["edits"])));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+/// Converts the ranges of `edits` to our positions in `text`
+pub fn convert_edits(text: &str, edits: &[TextEdit]) -> Vec<(Range, String)> {
+    let index = LineIndex::new(text);
+    edits
+        .iter()
+        .map(|edit| (index.range(edit.range), edit.new_text.clone()))
+        .collect()
+}
+
+/// Apply `edits` to `text`, e.g. for files which aren't open
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let index = LineIndex::new(text);
+    let mut edits: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            let (start, end) = index.range(edit.range);
+            (
+                index.offset(start),
+                index.offset(end),
+                edit.new_text.as_str(),
+            )
+        })
+        .collect();
+    edits.sort_by_key(|(start, end, _)| (*start, *end));
+
+    let mut result = text.to_string();
+    for (start, end, new_text) in edits.into_iter().rev() {
+        let end = end.min(result.len());
+        result.replace_range(start.min(end)..end, new_text);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn framing() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+        write_message(&mut buf, &json!({"jsonrpc": "2.0", "id": 1, "result": "ä"})).unwrap();
+        assert!(String::from_utf8_lossy(&buf).starts_with("Content-Length: 33\r\n\r\n{"));
+
+        let mut reader = io::Cursor::new(buf);
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({"jsonrpc": "2.0", "method": "exit"}))
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            Some(json!({"jsonrpc": "2.0", "id": 1, "result": "ä"}))
+        );
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            Message::parse(json!({"jsonrpc": "2.0", "id": 3, "result": null})),
+            Some(Message::Response {
+                id: 3,
+                result: Ok(Value::Null)
+            })
+        );
+        assert_eq!(
+            Message::parse(json!({"id": 4, "error": {"code": -32601, "message": "nope"}})),
+            Some(Message::Response {
+                id: 4,
+                result: Err("nope".to_string())
+            })
+        );
+        assert_eq!(
+            Message::parse(json!({"method": "window/logMessage", "params": {"message": "hi"}})),
+            Some(Message::Notification {
+                method: "window/logMessage".to_string(),
+                params: json!({"message": "hi"}),
+            })
+        );
+        assert_eq!(
+            Message::parse(json!({"id": "a", "method": "workspace/configuration"})),
+            Some(Message::Request {
+                id: json!("a"),
+                method: "workspace/configuration".to_string(),
+                params: Value::Null,
+            })
+        );
+    }
+
+    #[test]
+    fn configs() {
+        let configs = parse_configs(
+            r#"{ "Python": null, "Rust": { "command": "ra", "args": ["--log"] }, "Zig": { "command": "zls" } }"#,
+        )
+        .unwrap();
+        assert!(!configs.contains_key("Python"));
+        assert_eq!(configs["Rust"], ServerConfig::new("ra", &["--log"]));
+        assert_eq!(configs["Zig"].command, "zls");
+        assert_eq!(configs["C++"].command, "clangd");
+        assert!(parse_configs("[]").is_err());
+
+        assert_eq!(language_id("Rust"), "rust");
+        assert_eq!(language_id("C++"), "cpp");
+        assert_eq!(language_id("TypeScriptReact"), "typescriptreact");
+    }
+
+    #[test]
+    fn uris() {
+        let path = Path::new("/home/user/my project/ä.rs");
+        let uri = path_to_uri(path);
+        assert_eq!(uri, "file:///home/user/my%20project/%C3%A4.rs");
+        assert_eq!(uri_to_path(&uri).as_deref(), Some(path));
+        assert_eq!(uri_to_path("untitled:1"), None);
+    }
+
+    #[test]
+    fn positions() {
+        let text = "fn main() {\n    let 𝕏 = \"ä\"; x\r\n}\n";
+        let index = LineIndex::new(text);
+        // 𝕏 is 4 bytes, but two UTF-16 code units, ä is 2 bytes and one code unit
+        assert_eq!(index.to_lsp((1, 8)), json!({"line": 1, "character": 8}));
+        assert_eq!(index.to_lsp((1, 12)), json!({"line": 1, "character": 10}));
+        assert_eq!(index.to_lsp((1, 21)), json!({"line": 1, "character": 18}));
+        assert_eq!(index.position((1, 10)), (1, 12));
+        assert_eq!(index.position((1, 18)), (1, 21));
+        assert_eq!(index.position((1, 100)), (1, 22));
+        assert_eq!(index.position((5, 3)), (5, 0));
+    }
+
+    #[test]
+    fn edits() {
+        let text = "let foo = 1;\r\nfoo + foo\n";
+        let edits = parse_workspace_edit(&json!({
+            "changes": {
+                "file:///tmp/a.rs": [
+                    { "range": { "start": {"line": 1, "character": 6}, "end": {"line": 1, "character": 9} }, "newText": "bar" },
+                    { "range": { "start": {"line": 0, "character": 4}, "end": {"line": 0, "character": 7} }, "newText": "bar" },
+                    { "range": { "start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 3} }, "newText": "bar" },
+                ]
+            }
+        }));
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].0, PathBuf::from("/tmp/a.rs"));
+        assert_eq!(
+            apply_edits(text, &edits[0].1),
+            "let bar = 1;\r\nbar + bar\n"
+        );
+        assert_eq!(
+            convert_edits(text, &edits[0].1)[1],
+            (((0, 4), (0, 7)), "bar".to_string())
+        );
+
+        let append = TextEdit {
+            range: ((2, 0), (2, 0)),
+            new_text: "baz\n".to_string(),
+        };
+        assert_eq!(apply_edits("a\nb\n", &[append]), "a\nb\nbaz\n");
+    }
+
+    #[test]
+    fn results() {
+        assert_eq!(
+            hover_text(
+                &json!({"contents": {"kind": "markdown", "value": "```rust\nfn main()\n```"}})
+            ),
+            Some("fn main()".to_string())
+        );
+        assert_eq!(
+            hover_text(&json!({"contents": ["a", {"language": "c", "value": "int b"}]})),
+            Some("a\n\nint b".to_string())
+        );
+        assert_eq!(hover_text(&json!({"contents": ""})), None);
+
+        let range =
+            json!({ "start": {"line": 1, "character": 2}, "end": {"line": 1, "character": 5} });
+        assert_eq!(
+            parse_locations(
+                &json!([{ "targetUri": "file:///a.rs", "targetRange": range, "targetSelectionRange": range }])
+            ),
+            vec![Location {
+                path: PathBuf::from("/a.rs"),
+                range: ((1, 2), (1, 5))
+            }]
+        );
+        assert_eq!(
+            parse_locations(&json!({ "uri": "file:///b.rs", "range": range })).len(),
+            1
+        );
+        assert!(parse_locations(&Value::Null).is_empty());
+
+        let completions = parse_completions(&json!({ "isIncomplete": false, "items": [
+            { "label": "len", "detail": "fn(&self) -> usize" },
+            { "label": "push", "insertText": "push(${1:value})", "insertTextFormat": 2 },
+            { "label": "iter()", "textEdit": { "range": range, "newText": "iter" } },
+        ]}));
+        assert_eq!(completions[0].detail, "fn(&self) -> usize");
+        assert_eq!(completions[1].insert_text, None);
+        assert_eq!(completions[2].insert_text.as_deref(), Some("iter"));
+
+        let (path, diagnostics) =
+            parse_diagnostics(&json!({ "uri": "file:///a.rs", "diagnostics": [
+                { "range": range, "severity": 2, "message": "unused variable", "source": "rustc" },
+            ]}))
+            .unwrap();
+        assert_eq!(path, PathBuf::from("/a.rs"));
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(
+            LineIndex::new("\nfn main() {}\n")
+                .diagnostic(&diagnostics[0])
+                .range,
+            ((1, 2), (1, 5))
+        );
+
+        let capabilities = json!({ "hoverProvider": true, "renameProvider": false,
+            "completionProvider": { "triggerCharacters": [".", "::"] } });
+        assert!(has_capability(&capabilities, "hoverProvider"));
+        assert!(!has_capability(&capabilities, "renameProvider"));
+        assert!(!has_capability(&capabilities, "definitionProvider"));
+        assert_eq!(trigger_characters(&capabilities), vec!['.', ':']);
+    }
+
+    /// A language server which answers `initialize`, `hover` and `shutdown` and publishes a
+    /// diagnostic for each opened document
+    const STUB_SERVER: &str = r#"
+reply() {
+    printf 'Content-Length: %s\r\n\r\n%s' "$(printf '%s' "$1" | wc -c)" "$1"
+}
+while IFS= read -r header; do
+    len=$(printf '%s' "$header" | tr -d '\r' | sed -n 's/^Content-Length: *//p')
+    [ -z "$len" ] && continue
+    IFS= read -r _
+    body=$(dd bs=1 count="$len" 2>/dev/null)
+    id=$(printf '%s' "$body" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+    method=$(printf '%s' "$body" | sed -n 's/.*"method":"\([^"]*\)".*/\1/p')
+    case "$method" in
+        initialize) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"capabilities\":{\"hoverProvider\":true}}}" ;;
+        textDocument/didOpen) reply '{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///stub.rs","diagnostics":[{"range":{"start":{"line":0,"character":3},"end":{"line":0,"character":7}},"message":"stub error"}]}}' ;;
+        textDocument/hover) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"contents\":\"stub hover\"}}" ;;
+        shutdown) reply "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":null}" ;;
+        exit) exit 0 ;;
+    esac
+done
+"#;
+
+    #[test]
+    fn stub_server() {
+        let config = ServerConfig::new("sh", &["-c", STUB_SERVER]);
+        let (sender, receiver) = mpsc::channel();
+        let connection = Connection::spawn(&config, Path::new("/"), move |message| {
+            sender.send(message).ok();
+        })
+        .unwrap();
+        let next = || receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let id = connection
+            .request("initialize", initialize_params(Path::new("/"), None))
+            .unwrap();
+        match next() {
+            Some(Message::Response {
+                id: response_id,
+                result: Ok(result),
+            }) => {
+                assert_eq!(response_id, id);
+                assert!(has_capability(&result["capabilities"], "hoverProvider"));
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+        connection.notify("initialized", json!({})).unwrap();
+
+        connection
+            .notify(
+                "textDocument/didOpen",
+                json!({ "textDocument": {
+                    "uri": "file:///stub.rs", "languageId": "rust", "version": 0, "text": "fn main() {}\n",
+                }}),
+            )
+            .unwrap();
+        match next() {
+            Some(Message::Notification { method, params }) => {
+                assert_eq!(method, "textDocument/publishDiagnostics");
+                let (path, diagnostics) = parse_diagnostics(&params).unwrap();
+                assert_eq!(path, PathBuf::from("/stub.rs"));
+                assert_eq!(diagnostics[0].message, "stub error");
+                assert_eq!(diagnostics[0].range, ((0, 3), (0, 7)));
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        let index = LineIndex::new("fn main() {}\n");
+        let id = connection
+            .request(
+                "textDocument/hover",
+                json!({ "textDocument": { "uri": "file:///stub.rs" }, "position": index.to_lsp((0, 4)) }),
+            )
+            .unwrap();
+        match next() {
+            Some(Message::Response {
+                id: response_id,
+                result: Ok(result),
+            }) => {
+                assert_eq!(response_id, id);
+                assert_eq!(hover_text(&result).as_deref(), Some("stub hover"));
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+
+        let id = connection.request("shutdown", Value::Null).unwrap();
+        assert_eq!(
+            next(),
+            Some(Message::Response {
+                id,
+                result: Ok(Value::Null)
+            })
+        );
+        connection.notify("exit", Value::Null).unwrap();
+        assert_eq!(next(), None);
+    }
+}
@@ -71,20 +71,51 @@
 #![allow(clippy::if_same_then_else)]
 
 mod about_win;
+mod command_palette;
+mod diff_view;
 mod errors;
+mod find_in_files;
 mod functions;
+mod fuzzy_list;
+mod git_panel;
 mod globals;
+mod history;
+mod language_server;
+mod lsp;
 mod main_win;
 mod main_win_builder;
+mod main_win_diff;
+mod main_win_encodings;
+mod main_win_find_in_files;
+mod main_win_history;
+mod main_win_hot_exit;
+mod main_win_lsp;
+mod main_win_splits;
+mod main_win_terminals;
+mod outline;
 mod prefs_win;
+mod problems;
+mod project;
+mod quick_open;
+mod recovery;
+mod run_command;
 mod session;
+mod sessions;
 mod shortcuts_win;
+mod sidebar;
 mod syntax_config;
+mod tasks;
+mod terminals;
+#[cfg(test)]
+mod test_util;
 mod view_history;
 
+use crate::errors::{ErrorDialog, ErrorMsg};
 use crate::main_win::MainWinExt;
 use crate::main_win_builder::MainWinBuilder;
+use crate::main_win_hot_exit::MainWinHotExitExt;
 use crate::session::SessionHandler;
+use crate::sessions::Session;
 use gettextrs::{gettext, TextDomain, TextDomainError};
 use gio::prelude::*;
 use gio::ApplicationFlags;
@@ -118,15 +149,36 @@ fn main() {
         None,
     );
 
+    application.add_main_option(
+        "session",
+        Char::new('s').unwrap(),
+        glib::OptionFlags::NONE,
+        glib::OptionArg::String,
+        &gettext("Open the session with the given name"),
+        Some(&gettext("NAME")),
+    );
+
     let args = &args().collect::<Vec<_>>();
     //FIXME: Use handle-local-options once https://github.com/gtk-rs/gtk/issues/580 is a thing
     let mut new_instance = false;
-    for arg in args {
+    let mut session_name = None;
+    let mut arg_iter = args.iter();
+    while let Some(arg) = arg_iter.next() {
         match arg.as_str() {
             "-n" | "--new-instance" => new_instance = true,
+            "-s" | "--session" => session_name = arg_iter.next().cloned(),
+            arg if arg.starts_with("--session=") => {
+                session_name = Some(arg["--session=".len()..].to_string())
+            }
             _ => (),
         }
     }
+    if let Some(name) = &session_name {
+        if let Err(e) = sessions::validate_name(name) {
+            error!("Invalid session name `{}`: {}", name, e);
+            std::process::exit(1);
+        }
+    }
 
     let main_win_builder = Rc::new(RefCell::new(MainWinBuilder::new(application.clone())));
 
@@ -157,13 +209,84 @@ fn main() {
         debug!("Activating new view");
 
         let schema = gio::Settings::new("org.gnome.Tau");
+        if let Some(name) = &session_name {
+            // The keys are the only record of a session which hasn't been saved as a file yet,
+            // e.g. the one of a version of Tau without named sessions, so keep it before clearing
+            // them
+            if let Some(dir) = functions::sessions_dir() {
+                let current: String = schema.get("current-session");
+                let current = if current.is_empty() {
+                    sessions::DEFAULT_SESSION.to_string()
+                } else {
+                    current
+                };
+                // A file we can't read is left alone rather than overwritten
+                let saved = Session::load(&dir, &current).map(|s| s.is_some());
+                if saved == Ok(false) && !schema.get_session().is_empty() {
+                    debug!("Saving the documents of session `{}` to its file", current);
+                    if let Err(e) = schema.session_from_keys(&current).save(&dir) {
+                        error!(
+                            "Failed to save session `{}` before switching to `{}`: {}",
+                            current, name, e
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }
+            schema.session_clear();
+            schema.set("current-session", name).unwrap();
+        }
+
+        // Restore the named session, with all of its documents' state
+        let restore = session_name.is_some() || (schema.get("restore-session") && !new_instance);
+        let session = match (restore, functions::sessions_dir()) {
+            (true, Some(dir)) => {
+                let name: String = schema.get("current-session");
+                Session::load(&dir, &name).unwrap_or_else(|e| {
+                    error!("Failed to restore session `{}`: {}", name, e);
+                    None
+                })
+            }
+            _ => None,
+        };
         let paths = schema.get_session();
-        if schema
-            .get("restore-session") && !new_instance && !paths.is_empty() {
+        let restore_paths = schema.get("restore-session")
+            && !new_instance
+            && session_name.is_none()
+            && !paths.is_empty();
+
+        // Restore the unsaved changes of the last run. The changes of the files which are about to
+        // be restored with the session are put back once they've been opened.
+        let opened: Vec<String> = match &session {
+            Some(session) => session.views.iter().map(|view| view.path.clone()).collect(),
+            None if restore_paths => paths.clone(),
+            None => Vec::new(),
+        };
+        let restored_backups = match main_win_builder.borrow().main_win.as_ref() {
+            Some(main_win) => main_win.restore_backups(&opened),
+            None => false,
+        };
+
+        if let Some(session) = session {
+            if let Some(main_win) = main_win_builder.borrow().main_win.as_ref() {
+                main_win.restore_session(session);
+                return;
+            }
+        }
+
+        if restore_paths {
                 for file in paths {
                     if Path::new(&file).exists() {
-                        let (tx, rx) = MainContext::channel::<Result<Value, Value>>(glib::source::PRIORITY_HIGH);
                         let main_win = main_win_builder.borrow().main_win.clone();
+                        let xi_path = match main_win.as_ref().map(|m| m.transcode(&file, None)) {
+                            Some(Ok(xi_path)) => xi_path,
+                            Some(Err(e)) => {
+                                error!("Failed to restore file: {}", e);
+                                continue;
+                            }
+                            None => file.clone(),
+                        };
+                        let (tx, rx) = MainContext::channel::<Result<Value, Value>>(glib::source::PRIORITY_HIGH);
                         rx.attach(None, clone!(@strong schema, @strong main_win, @strong file => move |res| {
                             match res {
                                 Ok(val) => main_win.as_ref().unwrap().new_view(Ok((serde_json::from_value(val).unwrap(), Some(file.clone())))),
@@ -175,7 +298,7 @@ fn main() {
                             Continue(false)
                         }));
 
-                        main_win_builder.borrow().spawn_view(Some(file.clone()), move |res| {
+                        main_win_builder.borrow().spawn_view(Some(xi_path), move |res| {
                             tx.send(res).unwrap()
                         });
                     } else {
@@ -183,7 +306,7 @@ fn main() {
                         error!("Failed to restore file `{}`", file);
                     }
                 }
-        } else {
+        } else if !restored_backups {
             let (tx, rx) = MainContext::channel::<Result<Value, Value>>(glib::source::PRIORITY_HIGH);
             let main_win = main_win_builder.borrow().main_win.clone();
             rx.attach(None, clone!(@strong schema, @strong main_win => move |res| {
@@ -216,6 +339,15 @@ fn main() {
 
             debug!("Files: {:#?}", paths);
 
+            // Directories aren't opened, they become the project root instead
+            let (dirs, mut paths): (Vec<String>, Vec<String>) =
+                paths.drain(..).partition(|p| Path::new(p).is_dir());
+            if let Some(dir) = dirs.into_iter().next() {
+                if let Some(main_win) = main_win_builder.borrow().main_win.as_ref() {
+                    main_win.set_project_root(dir.into());
+                }
+            }
+
 
             let schema = gio::Settings::new("org.gnome.Tau");
             let mut session_paths = Vec::new();
@@ -225,10 +357,22 @@ fn main() {
                     paths.extend(session_paths.iter().cloned())
                 }
 
+            if let Some(main_win) = main_win_builder.borrow().main_win.as_ref() {
+                main_win.restore_backups(&paths);
+            }
+
             let session_paths_rc = Rc::new(session_paths);
             for file in paths {
-                let (tx, rx) = MainContext::channel::<Result<Value, Value>>(glib::source::PRIORITY_HIGH);
                 let main_win = main_win_builder.borrow().main_win.clone();
+                let xi_path = match main_win.as_ref().map(|m| m.transcode(&file, None)) {
+                    Some(Ok(xi_path)) => xi_path,
+                    Some(Err(e)) => {
+                        ErrorDialog::new(ErrorMsg::new(e, false));
+                        continue;
+                    }
+                    None => file.clone(),
+                };
+                let (tx, rx) = MainContext::channel::<Result<Value, Value>>(glib::source::PRIORITY_HIGH);
                 rx.attach(None, clone!(@strong schema, @strong main_win, @strong file, @strong session_paths_rc => move |res| {
                     match res {
                         Ok(val) => main_win.as_ref().unwrap().new_view(Ok((serde_json::from_value(val).unwrap(), Some(file.clone())))),
@@ -244,7 +388,7 @@ fn main() {
                     Continue(false)
                 }));
 
-                main_win_builder.borrow().spawn_view(Some(file.clone()), move |res| {
+                main_win_builder.borrow().spawn_view(Some(xi_path), move |res| {
                     tx.send(res).unwrap()
                 });
             }
@@ -4,34 +4,63 @@
 // SPDX-License-Identifier: MIT
 
 use crate::about_win::AboutWin;
+use crate::command_palette::{action_title, CommandPalette, PaletteCommand, PaletteEntry};
 use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::find_in_files::FindInFiles;
 use crate::functions;
+use crate::git_panel::GitPanel;
+use crate::history;
+use crate::language_server::{self, LanguageServers};
+use crate::main_win_diff::MainWinDiffExt;
+use crate::main_win_encodings::{MainWinEncodingsExt, Transcoded};
+use crate::main_win_history::MainWinHistoryExt;
+use crate::main_win_hot_exit::MainWinHotExitExt;
+use crate::main_win_lsp::MainWinLspExt;
+use crate::main_win_splits::{MainWinSplitsExt, Split};
+use crate::main_win_terminals::{MainWinTerminalsExt, TerminalTab};
+use crate::outline::Outline;
 use crate::prefs_win::PrefsWin;
-use crate::session::SessionHandler;
+use crate::problems::Problems;
+use crate::quick_open::QuickOpen;
+use crate::recovery::Backup;
+use crate::session::{orientation_from_str, orientation_to_str, SessionHandler};
+use crate::sessions::{self, Geometry, SavedView, Session};
 use crate::shortcuts_win::ShortcutsWin;
+use crate::sidebar::Sidebar;
 use crate::syntax_config::SyntaxParams;
+use crate::tasks::{self, TaskRunner};
 use crate::view_history::{ViewHistory, ViewHistoryExt};
 use chrono::{DateTime, Utc};
 use editview::{
-    main_state::ShowInvisibles, theme::u32_from_color, EditView, EditViewExt, MainState,
+    auto_pairs::AutoPairs,
+    completion,
+    encoding::{self, Encoding},
+    i18n::freplace,
+    i18n::ni18n_f,
+    line_ending::LineEnding,
+    main_state::ShowInvisibles,
+    snippets::SnippetLibrary,
+    theme::u32_from_color,
+    EditView, EditViewExt, FileChangedAction, MainState,
 };
 use gdk::{enums::key, ModifierType, WindowState};
 use gdk_pixbuf::Pixbuf;
 use gettextrs::gettext;
 use gio::prelude::*;
 use gio::{ActionMapExt, ApplicationExt, Resource, Settings, SettingsExt, SimpleAction};
-use glib::{clone, Bytes, GString, MainContext, Receiver, SpawnFlags};
+use glib::{clone, Bytes, GString, MainContext, Receiver};
 use gtk::prelude::*;
 use gtk::{
-    Application, ApplicationWindow, Builder, Button, ButtonsType, DialogFlags, EventBox,
-    FileChooserAction, FileChooserNative, HeaderBar, IconSize, MenuButton, MessageDialog,
-    MessageType, Notebook, Orientation, Paned, PositionType, ResponseType, Revealer, Widget,
+    Application, ApplicationWindow, Builder, ButtonsType, ComboBoxText, DialogFlags, EventBox,
+    FileChooserAction, FileChooserNative, HeaderBar, MenuButton, MessageDialog, MessageType,
+    Notebook, Orientation, Paned, PositionType, ResponseType, Revealer, Widget,
 };
 use log::{debug, error, info, trace, warn};
 use serde_json::{self, json};
 use std::cell::{Cell, RefCell};
 use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use tau_rpc::*;
 use vte::{Terminal, TerminalExt};
@@ -71,13 +100,17 @@ impl TryFrom<i32> for SaveAction {
 
 /// The `WinProp` struct, which holds some information about the current state of the Window. It's
 /// saved to `GSettings` during shutdown to restore the window state when it's started again.
-struct WinProp {
+pub(crate) struct WinProp {
     /// Height of the MainWin
     height: i32,
     /// Width of the MainWin
     width: i32,
     /// Whether or not the MainWin is maximized
     is_maximized: bool,
+    /// Whether or not the sidebar is shown
+    sidebar_visible: bool,
+    /// Width of the sidebar
+    sidebar_width: i32,
     /// The `gio::Settings` we save the fields of the WinProp to
     gschema: Settings,
 }
@@ -94,6 +127,8 @@ impl WinProp {
             height: gschema.get("window-height"),
             width: gschema.get("window-width"),
             is_maximized: gschema.get("window-maximized"),
+            sidebar_visible: gschema.get("sidebar-visible"),
+            sidebar_width: gschema.get("sidebar-width"),
             gschema,
         }
     }
@@ -105,6 +140,12 @@ impl WinProp {
         self.gschema
             .set("window-maximized", &self.is_maximized)
             .unwrap();
+        self.gschema
+            .set("sidebar-visible", &self.sidebar_visible)
+            .unwrap();
+        self.gschema
+            .set("sidebar-width", &self.sidebar_width)
+            .unwrap();
     }
 }
 
@@ -115,30 +156,43 @@ pub struct StartedPlugins {
     pub syntect: bool,
 }
 
+/// A `Session` which is being restored. Its documents are opened asynchronously, so their view
+/// state is applied as they arrive in `new_view_response`.
+struct PendingSession {
+    /// The paths of the documents, in the order of their tabs
+    order: Vec<String>,
+    /// The path of the document whose tab should be shown
+    active: Option<String>,
+    /// The view state of the documents which haven't been opened yet
+    views: HashMap<String, SavedView>,
+}
+
 /// The `MainWin` is (as the name suggests) tau's main window. It holds buttons like `Open` and `Save`
 /// and holds the `EditViews`, which do the actual editing. Refer to [the module level docs](main/index.html)
 /// for more information.
 pub struct MainWin {
     /// The handle to communicate with Xi.
-    core: Rc<Client>,
+    pub(crate) core: Rc<Client>,
     /// The GTK Window.
-    window: ApplicationWindow,
+    pub(crate) window: ApplicationWindow,
     /// The Notebook holding all `EditView`s.
-    notebook: Notebook,
+    pub(crate) notebook: Notebook,
     /// The `Builder` from which we build the GTK Widgets.
-    builder: Builder,
+    pub(crate) builder: Builder,
     /// A Map mapping `ViewId`s to `EditView`s.
-    views: RefCell<BTreeMap<ViewId, Rc<EditView>>>,
+    pub(crate) views: RefCell<BTreeMap<ViewId, Rc<EditView>>>,
     /// A Map mapping GTK `Widget`s to `EditView`s.
-    w_to_ev: RefCell<HashMap<Widget, Rc<EditView>>>,
+    pub(crate) w_to_ev: RefCell<HashMap<Widget, Rc<EditView>>>,
     /// A map mapping `ViewId`s to GTK `Widget`s.
-    view_id_to_w: RefCell<HashMap<ViewId, Widget>>,
+    pub(crate) view_id_to_w: RefCell<HashMap<ViewId, Widget>>,
+    /// A map mapping the pages of the `GtkNotebook` which are split to their `Split`
+    pub(crate) splits: RefCell<HashMap<Widget, Rc<Split>>>,
     /// The `MainState`, which are common settings among all `EditView`s.
-    state: Rc<RefCell<MainState>>,
+    pub(crate) state: Rc<RefCell<MainState>>,
     /// The `WinProp` Struct, used for saving the window state during shutdown
-    properties: RefCell<WinProp>,
+    pub(crate) properties: RefCell<WinProp>,
     /// A `HashMap` containing the different configs for each syntax
-    syntax_config: RefCell<HashMap<String, SyntaxParams>>,
+    pub(crate) syntax_config: RefCell<HashMap<String, SyntaxParams>>,
     /// Indicates which special plugins (for which we have to do additional work) have been started
     started_plugins: RefCell<StartedPlugins>,
     /// The `GtkHeaderbar` of Tau, to set different titles
@@ -150,7 +204,7 @@ pub struct MainWin {
     /// Hamburger menu button of fullscreen headerbar
     fullscreen_hamburger_button: MenuButton,
     /// Whether or not the `MainWin` is saving right now
-    saving: RefCell<bool>,
+    pub(crate) saving: RefCell<bool>,
     /// Tab history
     view_history: Rc<RefCell<ViewHistory>>,
     /// If the window is in fullscreen mode
@@ -158,7 +212,60 @@ pub struct MainWin {
     /// The `GtkPaned` which holds the `vte::Terminal` and `GtkNoteBook`
     paned: Paned,
     /// Notebook holding the Terminals
-    term_notebook: Notebook,
+    pub(crate) term_notebook: Notebook,
+    /// The terminals of the `term_notebook`
+    pub(crate) terminals: RefCell<HashMap<Terminal, TerminalTab>>,
+    /// Whether the terminals of the last session have been restored already
+    terminals_restored: Cell<bool>,
+    /// The session whose documents are being opened
+    pending_session: RefCell<Option<PendingSession>>,
+    /// The IDs of the backups of the documents with unsaved changes, see `recovery`
+    pub(crate) backups: RefCell<HashMap<ViewId, String>>,
+    /// Backups of the last run with their IDs, which are restored once their file has been opened
+    pub(crate) pending_backups: RefCell<HashMap<String, (String, Backup)>>,
+    /// Writes the backups once the documents haven't changed for a bit
+    pub(crate) backup_timeout: RefCell<Option<glib::SourceId>>,
+    /// Whether the backups of the last run have been restored already
+    pub(crate) backups_restored: Cell<bool>,
+    /// Whether the window is being closed with hot exit, which keeps the unsaved changes
+    pub(crate) hot_exiting: Cell<bool>,
+    /// The files which aren't UTF-8, by their path
+    pub(crate) transcoded: RefCell<HashMap<String, Transcoded>>,
+    /// The encodings the user has chosen for files which are about to be reopened
+    pub(crate) forced_encodings: RefCell<HashMap<String, Encoding>>,
+    /// The `GtkPaned` at the bottom of `paned`, which holds the terminals and the `bottom_panels`
+    pub(crate) bottom_paned: Paned,
+    /// The notebook next to the terminals holding the `Problems` and the output of tasks
+    bottom_panels: Notebook,
+    /// The panel listing the diagnostics of all open documents
+    problems: Rc<Problems>,
+    /// Runs the tasks of the project, e.g. `cargo build`, and shows their output
+    task_runner: Rc<TaskRunner>,
+    /// The snippets the user has configured for the different syntaxes
+    snippets: SnippetLibrary,
+    /// The commands plugins offer for each `EditView`, by plugin name
+    pub(crate) plugin_cmds: RefCell<HashMap<ViewId, BTreeMap<String, Vec<Command>>>>,
+    /// The directory of the project the user is working on. This is the directory of the first
+    /// file which has been opened, or a directory passed on the command line.
+    project_root: RefCell<Option<PathBuf>>,
+    /// The `Sidebar` showing the files of the project directory
+    sidebar: Rc<Sidebar>,
+    /// The `GtkPaned` which holds the `Sidebar` and the `paned`
+    sidebar_paned: Paned,
+    /// The `GtkStack` switching between the `Sidebar`'s file tree and other panels
+    pub(crate) sidebar_stack: gtk::Stack,
+    /// The panel searching all files of the project directory
+    pub(crate) find_in_files: Rc<FindInFiles>,
+    /// The panel showing the changes in the git repository of the project directory
+    git_panel: Rc<GitPanel>,
+    /// The panel listing the functions, types and headings of the current document
+    outline: Rc<Outline>,
+    /// The language servers offering diagnostics, completions and more for the open documents
+    pub(crate) language_servers: LanguageServers,
+    /// The positions the files which are being opened should be opened at
+    pending_positions: RefCell<HashMap<String, (u64, u64)>>,
+    /// The tabs of the files which are being reloaded, and whether they were the current tab
+    pending_pages: RefCell<HashMap<String, (u32, bool)>>,
 }
 
 impl MainWin {
@@ -202,6 +309,7 @@ impl MainWin {
         let fullscreen_hamburger_button =
             builder.get_object("fullscreen_hamburger_button").unwrap();
         let paned: Paned = builder.get_object("paned").unwrap();
+        let sidebar_paned: Paned = builder.get_object("sidebar_paned").unwrap();
 
         let icon = Pixbuf::new_from_resource("/org/gnome/Tau/org.gnome.Tau.svg");
         window.set_icon(icon.ok().as_ref());
@@ -214,6 +322,19 @@ impl MainWin {
 
         let notebook: Notebook = builder.get_object("notebook").unwrap();
 
+        let sidebar = Sidebar::new(&builder);
+        let sidebar_box: gtk::Box = builder.get_object("sidebar").unwrap();
+        sidebar_box.set_visible(properties.borrow().sidebar_visible);
+        sidebar_paned.set_position(properties.borrow().sidebar_width);
+
+        let sidebar_stack: gtk::Stack = builder.get_object("sidebar_stack").unwrap();
+        let find_in_files = FindInFiles::new();
+        sidebar_stack.add_titled(&find_in_files.widget, "search", &gettext("Search"));
+        let git_panel = GitPanel::new();
+        sidebar_stack.add_titled(&git_panel.widget, "git", &gettext("Git"));
+        let outline = Outline::new();
+        sidebar_stack.add_titled(&outline.widget, "outline", &gettext("Outline"));
+
         let theme_name = properties.borrow().gschema.get("theme-name");
         debug!("Theme name: '{}'", &theme_name);
 
@@ -230,6 +351,7 @@ impl MainWin {
             fonts: Default::default(),
             avail_languages: Default::default(),
             selected_language: Default::default(),
+            completion: Default::default(),
         }));
 
         let syntax_changes = gschema.get_strv("syntax-config");
@@ -246,6 +368,10 @@ impl MainWin {
 
         let view_history = ViewHistory::new(&notebook);
 
+        let snippets = functions::snippets_dir()
+            .map(|dir| SnippetLibrary::load(&dir))
+            .unwrap_or_default();
+
         let main_win = Rc::new(Self {
             core,
             window,
@@ -260,6 +386,7 @@ impl MainWin {
             views: Default::default(),
             w_to_ev: Default::default(),
             view_id_to_w: Default::default(),
+            splits: Default::default(),
             state: main_state,
             syntax_config: RefCell::new(syntax_config),
             started_plugins: RefCell::new(Default::default()),
@@ -267,27 +394,71 @@ impl MainWin {
             fullscreen: Cell::new(false),
             paned,
             term_notebook: Notebook::new(),
+            terminals: Default::default(),
+            terminals_restored: Cell::new(false),
+            pending_session: Default::default(),
+            backups: Default::default(),
+            pending_backups: Default::default(),
+            backup_timeout: Default::default(),
+            backups_restored: Cell::new(false),
+            hot_exiting: Cell::new(false),
+            transcoded: Default::default(),
+            forced_encodings: Default::default(),
+            bottom_paned: Paned::new(Orientation::Horizontal),
+            bottom_panels: Notebook::new(),
+            problems: Problems::new(),
+            task_runner: TaskRunner::new(),
+            snippets,
+            plugin_cmds: Default::default(),
+            project_root: Default::default(),
+            sidebar,
+            sidebar_paned,
+            sidebar_stack,
+            find_in_files,
+            git_panel,
+            outline,
+            language_servers: LanguageServers::new(),
+            pending_positions: Default::default(),
+            pending_pages: Default::default(),
         });
 
         main_win.term_notebook.set_tab_pos(PositionType::Bottom);
+        main_win.bottom_panels.set_tab_pos(PositionType::Bottom);
+        main_win
+            .header_bar
+            .pack_end(&main_win.task_runner.status_button);
 
         main_win.connect_settings_change();
 
+        // Drop the snapshots the retention settings don't keep anymore, e.g. of files which
+        // haven't been saved for a long time
+        if let Some(dir) = functions::history_dir() {
+            history::prune(&dir, MainWin::history_retention(&gschema));
+        }
+
         main_win.window.set_application(Some(&application.clone()));
 
         // This is called when the window is closed with the 'X' or via the application menu, etc.
         main_win.window.connect_delete_event(
-            clone!(@weak main_win => @default-panic, move |window, _| {
-                // Only destroy the window when the user has saved the changes or closes without saving
-                if main_win.close_all() == SaveAction::Cancel {
-                    debug!("User chose to cancel exiting");
-                    Inhibit(true)
-                } else {
-                    debug!("User chose to close the application");
-                    main_win.properties.borrow().save();
-                    window.destroy();
-                    Inhibit(false)
+            clone!(@weak main_win => @default-panic, move |_, _| {
+                // The window is destroyed once the backups have been written
+                if main_win.hot_exiting.get() {
+                    return Inhibit(true);
                 }
+                if main_win.confirm_close_terminals() == SaveAction::Cancel {
+                    debug!("User chose to keep the processes in the terminals running");
+                    return Inhibit(true);
+                }
+                // The documents are gone once they're closed, so save the session first
+                if let Err(e) = main_win.save_session() {
+                    error!("Failed to save the session: {}", e);
+                }
+                if main_win.hot_exit_enabled() {
+                    debug!("Keeping the unsaved changes for the next start-up");
+                    main_win.hot_exit();
+                    return Inhibit(true);
+                }
+                Inhibit(!main_win.close_asking())
             }),
         );
 
@@ -306,11 +477,124 @@ impl MainWin {
             }),
         );
 
+        // Save to `WinProp` when the sidebar is resized
+        main_win.sidebar_paned.connect_property_position_notify(
+            clone!(@weak main_win => @default-panic, move |paned| {
+                if sidebar_box.get_visible() {
+                    main_win.properties.borrow_mut().sidebar_width = paned.get_position();
+                }
+            }),
+        );
+
+        main_win.sidebar.connect_file_activated(
+            clone!(@weak main_win => @default-panic, move |path| {
+                main_win.open_file(&path);
+            }),
+        );
+
+        main_win.find_in_files.connect_result_activated(
+            clone!(@weak main_win => @default-panic, move |path, line, col| {
+                main_win.open_file_at(&path, line, col);
+            }),
+        );
+
+        main_win.find_in_files.connect_replace(
+            clone!(@weak main_win => @default-return Vec::new(), move |files, options, replacement| {
+                main_win.replace_in_files(files, options, replacement)
+            }),
+        );
+
+        main_win.git_panel.connect_file_activated(
+            clone!(@weak main_win => @default-panic, move |path, head| {
+                main_win.compare_with_head(&path, &head);
+            }),
+        );
+
+        main_win
+            .git_panel
+            .connect_head_changed(clone!(@weak main_win => @default-panic, move || {
+                main_win.update_branch();
+                main_win.views.borrow().values().for_each(|view| view.refresh_git_diff());
+            }));
+
+        main_win.outline.connect_symbol_activated(
+            clone!(@weak main_win => @default-panic, move |line| {
+                if let Some(edit_view) = main_win.get_current_edit_view() {
+                    edit_view.go_to_line(line + 1);
+                    edit_view.view_item.ev_scrolled_window.grab_focus();
+                }
+            }),
+        );
+
+        main_win.problems.connect_problem_activated(
+            clone!(@weak main_win => @default-panic, move |edit_view, line| {
+                main_win.show_view(edit_view);
+                edit_view.go_to_line(line + 1);
+                edit_view.view_item.ev_scrolled_window.grab_focus();
+            }),
+        );
+
+        main_win
+            .problems
+            .connect_close(clone!(@weak main_win => @default-panic, move || {
+                main_win.hide_panel(&main_win.problems.widget);
+            }));
+
+        main_win.task_runner.connect_link_activated(
+            clone!(@weak main_win => @default-panic, move |path, line, col| {
+                main_win.open_file_at(path, line, col);
+            }),
+        );
+
+        main_win
+            .task_runner
+            .connect_close(clone!(@weak main_win => @default-panic, move || {
+                main_win.hide_panel(&main_win.task_runner.widget);
+            }));
+
+        main_win.task_runner.status_button.connect_clicked(
+            clone!(@weak main_win => @default-panic, move |_| {
+                main_win.show_panel(&main_win.task_runner.widget, &gettext("Output"));
+            }),
+        );
+
+        main_win.language_servers.connect_diagnostics(
+            clone!(@weak main_win => @default-return (), move |path, diagnostics| {
+                for edit_view in main_win.views.borrow().values() {
+                    if edit_view.file_name.borrow().as_deref().map(Path::new) == Some(path) {
+                        edit_view.set_diagnostics(
+                            language_server::DIAGNOSTICS_PRODUCER,
+                            diagnostics.to_vec(),
+                        );
+                    }
+                }
+            }),
+        );
+
+        main_win
+            .sidebar_stack
+            .connect_property_visible_child_name_notify(
+                clone!(@weak main_win => @default-panic, move |stack| {
+                    if stack.get_visible_child_name().as_deref() == Some("git") {
+                        main_win.git_panel.refresh();
+                    }
+                }),
+            );
+
         main_win.notebook.connect_switch_page(
             clone!(@weak main_win => @default-panic, move |_, w, _| {
                 // adjust headerbar title
                 main_win.update_titlebar(main_win.w_to_ev.borrow().get(w));
 
+                // select the document in the sidebar
+                main_win.reveal_in_sidebar(main_win.w_to_ev.borrow().get(w));
+
+                // show the document's symbols in the outline
+                main_win.outline.set_view(main_win.w_to_ev.borrow().get(w));
+
+                // move the terminal to the document's directory
+                main_win.follow_file_in_terminal(main_win.w_to_ev.borrow().get(w));
+
                 // stop all searches and close dialogs
                 main_win.views.borrow().values().for_each(|view| view.stop_search());
             }),
@@ -322,7 +606,9 @@ impl MainWin {
                 // document's name)
                 if notebook.get_n_pages() == 0 {
                     main_win.set_title(glib::get_application_name().unwrap().as_str());
+                    main_win.outline.set_view(None);
                 }
+                main_win.refresh_problems();
             }),
         );
 
@@ -424,6 +710,157 @@ impl MainWin {
             );
             application.add_action(&multicursor_select_all_action);
         }
+        {
+            let multicursor_add_next_action = SimpleAction::new("multicursor_add_next", None);
+            multicursor_add_next_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'multicursor_add_next'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.multicursor_add_next();
+                    }
+                }),
+            );
+            application.add_action(&multicursor_add_next_action);
+        }
+        {
+            let multicursor_skip_next_action = SimpleAction::new("multicursor_skip_next", None);
+            multicursor_skip_next_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'multicursor_skip_next'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.multicursor_skip_next();
+                    }
+                }),
+            );
+            application.add_action(&multicursor_skip_next_action);
+        }
+        {
+            let multicursor_undo_action = SimpleAction::new("multicursor_undo", None);
+            multicursor_undo_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'multicursor_undo'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.undo_cursor_addition();
+                    }
+                }),
+            );
+            application.add_action(&multicursor_undo_action);
+        }
+        {
+            let add_cursor_above_action = SimpleAction::new("add_cursor_above", None);
+            add_cursor_above_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'add_cursor_above'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.add_cursor_above();
+                    }
+                }),
+            );
+            application.add_action(&add_cursor_above_action);
+        }
+        {
+            let add_cursor_below_action = SimpleAction::new("add_cursor_below", None);
+            add_cursor_below_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'add_cursor_below'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.add_cursor_below();
+                    }
+                }),
+            );
+            application.add_action(&add_cursor_below_action);
+        }
+        {
+            let toggle_comment_action = SimpleAction::new("toggle_comment", None);
+            toggle_comment_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'toggle_comment'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.toggle_comment();
+                    }
+                }),
+            );
+            application.add_action(&toggle_comment_action);
+        }
+        {
+            let toggle_block_comment_action = SimpleAction::new("toggle_block_comment", None);
+            toggle_block_comment_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'toggle_block_comment'");
+                    if let Some(ev) = main_win.get_current_edit_view() {
+                        ev.toggle_block_comment();
+                    }
+                }),
+            );
+            application.add_action(&toggle_block_comment_action);
+        }
+        {
+            let command_palette = CommandPalette::new(
+                &main_win.notebook,
+                clone!(@weak main_win => @default-panic, move |command| {
+                    main_win.run_palette_command(command);
+                }),
+            );
+            let command_palette_action = SimpleAction::new("command_palette", None);
+            command_palette_action.connect_activate(
+                clone!(@weak main_win, @strong command_palette => @default-panic, move |_,_| {
+                    trace!("Handling action: 'command_palette'");
+                    command_palette.show(main_win.palette_entries());
+                }),
+            );
+            application.add_action(&command_palette_action);
+        }
+        {
+            let quick_open = QuickOpen::new(
+                &main_win.notebook,
+                clone!(@weak main_win => @default-panic, move |path| {
+                    main_win.open_file(&path);
+                }),
+            );
+            let quick_open_action = SimpleAction::new("quick_open", None);
+            quick_open_action.connect_activate(
+                clone!(@weak main_win, @strong quick_open => @default-panic, move |_,_| {
+                    trace!("Handling action: 'quick_open'");
+                    let project_root = main_win.project_root.borrow().clone();
+                    if let Some(project_root) = project_root {
+                        quick_open.show(&project_root);
+                    } else {
+                        main_win.handle_open_button();
+                    }
+                }),
+            );
+            application.add_action(&quick_open_action);
+        }
+        {
+            let split_horizontal_action = SimpleAction::new("split_horizontal", None);
+            split_horizontal_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'split_horizontal'");
+                    main_win.split(Orientation::Horizontal);
+                }),
+            );
+            application.add_action(&split_horizontal_action);
+        }
+        {
+            let split_vertical_action = SimpleAction::new("split_vertical", None);
+            split_vertical_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'split_vertical'");
+                    main_win.split(Orientation::Vertical);
+                }),
+            );
+            application.add_action(&split_vertical_action);
+        }
+        {
+            let unsplit_action = SimpleAction::new("unsplit", None);
+            unsplit_action.connect_activate(clone!(@weak main_win => @default-panic, move |_,_| {
+                trace!("Handling action: 'unsplit'");
+                if let Some(ev) = main_win.get_current_edit_view() {
+                    main_win.unsplit(&ev);
+                }
+            }));
+            application.add_action(&unsplit_action);
+        }
         {
             let find_action = SimpleAction::new("find", None);
             find_action.connect_activate(clone!(@weak main_win => @default-panic, move |_,_| {
@@ -575,13 +1012,9 @@ impl MainWin {
             let quit_action = SimpleAction::new("quit", None);
             quit_action.connect_activate(clone!(@weak main_win => @default-panic, move |_,_| {
                 trace!("Handling action: 'quit'");
-                // Same as in connect_destroy, only quit if the user saves or wants to close without saving
-                if main_win.close_all() == SaveAction::Cancel {
-                    debug!("User chose to not quit application");
-                } else {
-                    debug!("User chose to quit application");
-                    main_win.window.close();
-                }
+                // The user is asked about unsaved changes and running processes in
+                // connect_delete_event, so they're only asked once
+                main_win.window.close();
             }));
             application.add_action(&quit_action);
         }
@@ -624,6 +1057,198 @@ impl MainWin {
             );
             application.add_action(&show_terminal_action);
         }
+        {
+            let toggle_sidebar_action = SimpleAction::new("toggle_sidebar", None);
+            toggle_sidebar_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'toggle_sidebar'");
+                    main_win.toggle_sidebar();
+                }),
+            );
+            application.add_action(&toggle_sidebar_action);
+        }
+        {
+            let compare_with_saved_action = SimpleAction::new("compare_with_saved", None);
+            compare_with_saved_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'compare_with_saved'");
+                    if let Some(edit_view) = main_win.get_current_edit_view() {
+                        main_win.compare_with_saved(&edit_view);
+                    }
+                }),
+            );
+            application.add_action(&compare_with_saved_action);
+        }
+        {
+            let reopen_with_encoding_action = SimpleAction::new("reopen_with_encoding", None);
+            reopen_with_encoding_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'reopen_with_encoding'");
+                    main_win.reopen_with_encoding();
+                }),
+            );
+            application.add_action(&reopen_with_encoding_action);
+        }
+        {
+            let save_with_encoding_action = SimpleAction::new("save_with_encoding", None);
+            save_with_encoding_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'save_with_encoding'");
+                    main_win.save_with_encoding();
+                }),
+            );
+            application.add_action(&save_with_encoding_action);
+        }
+        {
+            let local_history_action = SimpleAction::new("local_history", None);
+            local_history_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'local_history'");
+                    main_win.local_history();
+                }),
+            );
+            application.add_action(&local_history_action);
+        }
+        {
+            let compare_with_tab_action = SimpleAction::new("compare_with_tab", None);
+            compare_with_tab_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'compare_with_tab'");
+                    main_win.compare_with_tab();
+                }),
+            );
+            application.add_action(&compare_with_tab_action);
+        }
+        {
+            let show_git_panel_action = SimpleAction::new("show_git_panel", None);
+            show_git_panel_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'show_git_panel'");
+                    main_win.show_git_panel();
+                }),
+            );
+            application.add_action(&show_git_panel_action);
+        }
+        {
+            let show_outline_action = SimpleAction::new("show_outline", None);
+            show_outline_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'show_outline'");
+                    main_win.show_outline();
+                }),
+            );
+            application.add_action(&show_outline_action);
+        }
+        {
+            let show_problems_action = SimpleAction::new("show_problems", None);
+            show_problems_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'show_problems'");
+                    main_win.show_problems();
+                }),
+            );
+            application.add_action(&show_problems_action);
+        }
+        {
+            let run_task_action = SimpleAction::new("run_task", None);
+            run_task_action.connect_activate(clone!(@weak main_win => @default-panic, move |_,_| {
+                trace!("Handling action: 'run_task'");
+                main_win.run_task();
+            }));
+            application.add_action(&run_task_action);
+        }
+        {
+            let rerun_task_action = SimpleAction::new("rerun_task", None);
+            rerun_task_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'rerun_task'");
+                    main_win.rerun_task();
+                }),
+            );
+            application.add_action(&rerun_task_action);
+        }
+        {
+            let stop_task_action = SimpleAction::new("stop_task", None);
+            stop_task_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'stop_task'");
+                    main_win.task_runner.stop();
+                }),
+            );
+            application.add_action(&stop_task_action);
+        }
+        {
+            let send_to_terminal_action = SimpleAction::new("send_to_terminal", None);
+            send_to_terminal_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'send_to_terminal'");
+                    main_win.send_to_terminal();
+                }),
+            );
+            application.add_action(&send_to_terminal_action);
+        }
+        {
+            let run_current_file_action = SimpleAction::new("run_current_file", None);
+            run_current_file_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'run_current_file'");
+                    main_win.run_current_file();
+                }),
+            );
+            application.add_action(&run_current_file_action);
+        }
+        {
+            let toggle_blame_action = SimpleAction::new("toggle_blame", None);
+            toggle_blame_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'toggle_blame'");
+                    if let Some(edit_view) = main_win.get_current_edit_view() {
+                        edit_view.set_blame_visible(!edit_view.blame_visible());
+                    }
+                }),
+            );
+            application.add_action(&toggle_blame_action);
+        }
+        {
+            let go_to_definition_action = SimpleAction::new("go_to_definition", None);
+            go_to_definition_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'go_to_definition'");
+                    main_win.go_to_definition();
+                }),
+            );
+            application.add_action(&go_to_definition_action);
+        }
+        {
+            let find_references_action = SimpleAction::new("find_references", None);
+            find_references_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'find_references'");
+                    main_win.find_references();
+                }),
+            );
+            application.add_action(&find_references_action);
+        }
+        {
+            let rename_symbol_action = SimpleAction::new("rename_symbol", None);
+            rename_symbol_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'rename_symbol'");
+                    main_win.rename_symbol();
+                }),
+            );
+            application.add_action(&rename_symbol_action);
+        }
+        {
+            let find_in_files_action = SimpleAction::new("find_in_files", None);
+            find_in_files_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'find_in_files'");
+                    main_win.show_find_in_files();
+                }),
+            );
+            application.add_action(&find_in_files_action);
+        }
         {
             let go_to_line_action = SimpleAction::new("go_to_line", None);
             go_to_line_action.connect_activate(
@@ -671,6 +1296,27 @@ impl MainWin {
             application.add_action(&term_select_all_action);
         }
 
+        {
+            let switch_session_action = SimpleAction::new("switch_session", None);
+            switch_session_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'switch_session'");
+                    main_win.choose_session();
+                }),
+            );
+            application.add_action(&switch_session_action);
+        }
+        {
+            let save_session_as_action = SimpleAction::new("save_session_as", None);
+            save_session_as_action.connect_activate(
+                clone!(@weak main_win => @default-panic, move |_,_| {
+                    trace!("Handling action: 'save_session_as'");
+                    main_win.save_session_as();
+                }),
+            );
+            application.add_action(&save_session_as_action);
+        }
+
         // Put keyboard shortcuts here
         application.set_accels_for_action("app.find", &["<Primary>f"]);
         application.set_accels_for_action("app.save", &["<Primary>s"]);
@@ -693,6 +1339,29 @@ impl MainWin {
         application.set_accels_for_action("app.toggle_fullscreen", &["F11"]);
         application.set_accels_for_action("app.go_to_line", &["<Primary>i"]);
         application.set_accels_for_action("app.multicursor_select_all", &["<Primary>l"]);
+        application.set_accels_for_action("app.multicursor_add_next", &["<Primary>d"]);
+        application.set_accels_for_action("app.multicursor_skip_next", &["<Primary>k"]);
+        application.set_accels_for_action("app.multicursor_undo", &["<Primary>u"]);
+        application.set_accels_for_action("app.add_cursor_above", &["<Primary><Alt>Up"]);
+        application.set_accels_for_action("app.add_cursor_below", &["<Primary><Alt>Down"]);
+        application.set_accels_for_action("app.toggle_comment", &["<Primary>slash"]);
+        application.set_accels_for_action("app.toggle_block_comment", &["<Shift><Alt>a"]);
+        application.set_accels_for_action("app.command_palette", &["<Primary><Shift>p"]);
+        application.set_accels_for_action("app.quick_open", &["<Primary>p"]);
+        application.set_accels_for_action("app.split_horizontal", &["<Primary>backslash"]);
+        application.set_accels_for_action("app.split_vertical", &["<Primary><Alt>backslash"]);
+        application.set_accels_for_action("app.unsplit", &["<Primary><Shift>w"]);
+        application.set_accels_for_action("app.toggle_sidebar", &["F9"]);
+        application.set_accels_for_action("app.find_in_files", &["<Primary><Shift>f"]);
+        application.set_accels_for_action("app.show_outline", &["<Primary><Shift>o"]);
+        application.set_accels_for_action("app.show_problems", &["<Primary><Shift>m"]);
+        application.set_accels_for_action("app.run_task", &["<Primary><Shift>b"]);
+        application.set_accels_for_action("app.rerun_task", &["<Primary><Alt>b"]);
+        application.set_accels_for_action("app.send_to_terminal", &["<Primary><Shift>Return"]);
+        application.set_accels_for_action("app.run_current_file", &["F5"]);
+        application.set_accels_for_action("app.go_to_definition", &["F12"]);
+        application.set_accels_for_action("app.find_references", &["<Shift>F12"]);
+        application.set_accels_for_action("app.rename_symbol", &["F2"]);
 
         main_win.window.connect_key_press_event(
             clone!(@strong main_win => @default-panic, move |_, ek| {
@@ -836,11 +1505,14 @@ impl MainWin {
             let view_id = params.view_id;
 
             ev.update(params);
+            self.language_servers.changed(ev);
 
             if let Some(w) = self.view_id_to_w.borrow().get(&view_id).map(Clone::clone) {
                 if let Some(page_num) = self.notebook.page_num(&w) {
                     if Some(page_num) == self.notebook.get_current_page() {
                         self.update_titlebar(self.views.borrow().get(&view_id));
+                        self.outline.invalidate();
+                        self.outline.follow_cursor();
                     }
                 }
             }
@@ -856,9 +1528,12 @@ impl MainWin {
 
         let views = self.views.borrow();
         if let Some(ev) = views.get(&params.view_id) {
-            let idx = self.notebook.page_num(&ev.root_widget);
-            self.notebook.set_current_page(idx);
+            if let Some(w) = self.view_id_to_w.borrow().get(&params.view_id) {
+                let idx = self.notebook.page_num(w);
+                self.notebook.set_current_page(idx);
+            }
             ev.scroll_to(params.line, params.column);
+            self.outline.follow_cursor();
         }
     }
 
@@ -878,6 +1553,16 @@ impl MainWin {
         }
     }
 
+    /// Remember the commands a plugin offers, so they can be shown in the `CommandPalette`
+    fn update_cmds(&self, params: UpdateCmds) {
+        trace!("Handling msg: 'update_cmds' {:?}", params);
+        self.plugin_cmds
+            .borrow_mut()
+            .entry(params.view_id)
+            .or_default()
+            .insert(params.plugin, params.cmds);
+    }
+
     /// Open an error dialog if a plugin has crashed
     fn plugin_stopped(&self, params: &tau_rpc::PluginStopped) {
         if params.plugin == "xi-syntect-plugin" {
@@ -971,7 +1656,31 @@ impl MainWin {
                     debug!("No tab size attached to the syntax");
                 }
             }
+            ev.set_auto_pairs(self.auto_pairs_for(&params.language_id));
+            ev.set_snippets(self.snippets.for_syntax(&params.language_id));
             ev.language_changed(&params.language_id);
+            self.language_servers.attach(
+                ev,
+                &params.language_id,
+                self.project_root.borrow().as_deref(),
+            );
+            self.outline.invalidate();
+        }
+    }
+
+    /// Returns the brackets and quotes which should be closed automatically for `language`,
+    /// `None` if the user disabled that in the syntax config.
+    fn auto_pairs_for(&self, language: &str) -> Option<AutoPairs> {
+        match self.syntax_config.borrow().get(language) {
+            Some(sc) if sc.changes.auto_pairs == Some(false) => None,
+            Some(sc) => Some(
+                sc.changes
+                    .surrounding_pairs
+                    .as_ref()
+                    .map(|pairs| AutoPairs::from_strings(pairs))
+                    .unwrap_or_default(),
+            ),
+            None => Some(AutoPairs::default()),
         }
     }
 
@@ -1013,30 +1722,351 @@ impl MainWin {
         }
     }
 
-    fn multicursor_select_all(&self) {
-        if let Some(edit_view) = self.get_current_edit_view() {
-            edit_view.multicursor_select_all();
+    fn multicursor_select_all(&self) {
+        if let Some(edit_view) = self.get_current_edit_view() {
+            edit_view.multicursor_select_all();
+        }
+    }
+
+    /// Open the find dialog of the current `EditView`
+    fn find(&self) {
+        if let Some(edit_view) = self.get_current_edit_view() {
+            edit_view.start_search();
+        }
+    }
+
+    /// Open the replace dialog of the current `EditView
+    fn replace(&self) {
+        if let Some(edit_view) = self.get_current_edit_view() {
+            edit_view.start_replace();
+        }
+    }
+
+    /// Set the directory of the project the user is working on, which quick open searches in and
+    /// the sidebar shows
+    pub fn set_project_root(&self, project_root: PathBuf) {
+        debug!("Setting project root to {:?}", project_root);
+        self.sidebar.set_root(project_root.clone());
+        self.find_in_files.set_root(project_root.clone());
+        self.git_panel.set_root(project_root.clone());
+        self.project_root.replace(Some(project_root));
+    }
+
+    /// Show the `GitPanel` in the sidebar, with the current state of the repository
+    fn show_git_panel(&self) {
+        let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+        if !sidebar_box.get_visible() {
+            self.toggle_sidebar();
+        }
+        self.sidebar_stack.set_visible_child_name("git");
+        self.git_panel.refresh();
+    }
+
+    /// Show the `Outline` of the current document in the sidebar and focus its search entry
+    fn show_outline(&self) {
+        let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+        if !sidebar_box.get_visible() {
+            self.toggle_sidebar();
+        }
+        self.sidebar_stack.set_visible_child_name("outline");
+        self.outline.start();
+    }
+
+    /// Switch to the tab of `edit_view`
+    fn show_view(&self, edit_view: &EditView) {
+        let w = self.view_id_to_w.borrow().get(&edit_view.view_id).cloned();
+        if let Some(page_num) = w.and_then(|w| self.notebook.page_num(&w)) {
+            self.notebook.set_current_page(Some(page_num));
+        }
+    }
+
+    /// Show the `Problems` next to the terminals and focus its list
+    fn show_problems(&self) {
+        self.show_panel(&self.problems.widget, &gettext("Problems"));
+        self.refresh_problems();
+        self.problems.start();
+    }
+
+    /// List the diagnostics of the open documents in the `Problems`, in the order of their tabs
+    fn refresh_problems(&self) {
+        if self.bottom_panels.page_num(&self.problems.widget).is_none() {
+            return;
+        }
+        let w_to_ev = self.w_to_ev.borrow();
+        let views: Vec<Rc<EditView>> = (0..self.notebook.get_n_pages())
+            .filter_map(|i| self.notebook.get_nth_page(Some(i)))
+            .filter_map(|w| w_to_ev.get(&w).cloned())
+            .collect();
+        self.problems.update(&views);
+    }
+
+    /// Add `panel` to the `bottom_panels` with the tab label `title` if it isn't there yet, and
+    /// switch to it
+    fn show_panel<P: IsA<Widget>>(&self, panel: &P, title: &str) {
+        let page_num = match self.bottom_panels.page_num(panel) {
+            Some(page_num) => page_num,
+            None => {
+                let label = gtk::Label::new(Some(title));
+                self.bottom_panels.append_page(panel, Some(&label))
+            }
+        };
+        if self.bottom_paned.get_child2().is_none() {
+            self.bottom_paned.pack2(&self.bottom_panels, true, false);
+        }
+        self.bottom_panels
+            .set_show_tabs(self.bottom_panels.get_n_pages() > 1);
+        self.show_bottom_area();
+        self.bottom_panels.set_current_page(Some(page_num));
+    }
+
+    /// Remove `panel` from the `bottom_panels`, and them from the bottom once they're empty
+    fn hide_panel<P: IsA<Widget>>(&self, panel: &P) {
+        if let Some(page_num) = self.bottom_panels.page_num(panel) {
+            self.bottom_panels.remove_page(Some(page_num));
+        }
+        let n_pages = self.bottom_panels.get_n_pages();
+        self.bottom_panels.set_show_tabs(n_pages > 1);
+        if n_pages == 0 && self.bottom_paned.get_child2().is_some() {
+            self.bottom_paned.remove(&self.bottom_panels);
+        }
+        self.hide_bottom_area_if_empty();
+    }
+
+    /// Let the user pick one of the project's tasks and run it
+    fn run_task(&self) {
+        let root = match self.project_root.borrow().clone() {
+            Some(root) => root,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Please open a project to run its tasks."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let tasks = match tasks::load_tasks(&root) {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(e, false));
+                return;
+            }
+        };
+        if tasks.is_empty() {
+            ErrorDialog::new(ErrorMsg::new(
+                freplace(
+                    gettext("The project has no tasks. Define them in “{}”."),
+                    &[&tasks::tasks_file(&root).to_string_lossy()],
+                ),
+                false,
+            ));
+            return;
+        }
+
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &gettext("Run a task"),
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Run"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let combo_box = ComboBoxText::new();
+        for task in &tasks {
+            combo_box.append_text(&task.name);
+        }
+        // Preselect the task which has been run last
+        let last = self.task_runner.last_task();
+        let active = last
+            .and_then(|last| tasks.iter().position(|task| *task == last))
+            .unwrap_or(0);
+        combo_box.set_active(Some(active as u32));
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&combo_box, false, true, 0);
+        }
+        combo_box.show();
+
+        self.saving.replace(true);
+        let response = dialog.run();
+        let active = combo_box.get_active();
+        dialog.destroy();
+        self.saving.replace(false);
+
+        if let Some(task) = active.and_then(|n| tasks.get(n as usize)) {
+            if response == ResponseType::Ok {
+                self.show_panel(&self.task_runner.widget, &gettext("Output"));
+                self.task_runner.run(task, &root);
+            }
+        }
+    }
+
+    /// Run the task which has been run last again, or let the user pick one if there's none
+    fn rerun_task(&self) {
+        if self.task_runner.last_task().is_none() {
+            self.run_task();
+            return;
+        }
+        self.show_panel(&self.task_runner.widget, &gettext("Output"));
+        self.task_runner.rerun();
+    }
+
+    /// Add the `bottom_paned` below the documents if it isn't there yet
+    pub(crate) fn show_bottom_area(&self) {
+        if self.paned.get_child2().is_none() {
+            self.paned.add2(&self.bottom_paned);
+        }
+        self.bottom_paned.show_all();
+    }
+
+    /// Remove the `bottom_paned` once neither the terminals nor the `Problems` are shown
+    pub(crate) fn hide_bottom_area_if_empty(&self) {
+        if self.bottom_paned.get_child1().is_none() && self.bottom_paned.get_child2().is_none() {
+            self.paned.remove(&self.bottom_paned);
+        }
+    }
+
+    /// Show the branch which is checked out in the project's repository below the title
+    fn update_branch(&self) {
+        let branch = self.git_panel.branch();
+        self.header_bar.set_subtitle(branch.as_deref());
+        self.fullscreen_bar.set_subtitle(branch.as_deref());
+    }
+
+    /// Show or hide the `Sidebar`
+    pub(crate) fn toggle_sidebar(&self) {
+        let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+        let visible = !sidebar_box.get_visible();
+        sidebar_box.set_visible(visible);
+        self.properties.borrow_mut().sidebar_visible = visible;
+
+        if visible {
+            let position = self.properties.borrow().sidebar_width;
+            self.sidebar_paned.set_position(position);
+            self.reveal_in_sidebar(self.get_current_edit_view().as_ref());
+        }
+    }
+
+    /// Select the file of `edit_view` in the `Sidebar`, if the sidebar is shown
+    fn reveal_in_sidebar(&self, edit_view: Option<&Rc<EditView>>) {
+        let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+        if !sidebar_box.get_visible() {
+            return;
+        }
+        if let Some(file_name) = edit_view.and_then(|ev| ev.file_name.borrow().clone()) {
+            self.sidebar.reveal(std::path::Path::new(&file_name));
+        }
+    }
+
+    /// Collect everything the `CommandPalette` offers: all actions, the commands plugins offer
+    /// for the current `EditView`, the available languages and the themes
+    fn palette_entries(&self) -> Vec<PaletteEntry> {
+        let mut entries = Vec::new();
+
+        if let Some(application) = self.window.get_application() {
+            let mut names = application.list_actions();
+            names.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+            for name in names.iter().filter(|n| n.as_str() != "command_palette") {
+                let offered = application
+                    .lookup_action(name)
+                    .map(|a| a.get_enabled() && a.get_parameter_type().is_none())
+                    .unwrap_or(false);
+                if !offered {
+                    continue;
+                }
+                let detail = application
+                    .get_accels_for_action(&format!("app.{}", name))
+                    .iter()
+                    .filter_map(|accel| {
+                        let (key, mods) = gtk::accelerator_parse(accel);
+                        gtk::accelerator_get_label(key, mods)
+                    })
+                    .map(|label| label.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                entries.push(PaletteEntry {
+                    title: action_title(name),
+                    detail,
+                    command: PaletteCommand::Action(name.to_string()),
+                });
+            }
+        }
+
+        let state = self.state.borrow();
+        if let Some(ev) = self.get_current_edit_view() {
+            if let Some(plugins) = self.plugin_cmds.borrow().get(&ev.view_id) {
+                for (plugin, cmds) in plugins {
+                    // Arguments can't be entered in the palette, so only offer commands without any
+                    for command in cmds.iter().filter(|c| c.args.is_empty()) {
+                        entries.push(PaletteEntry {
+                            title: command.title.clone(),
+                            detail: plugin.clone(),
+                            command: PaletteCommand::Plugin {
+                                plugin: plugin.clone(),
+                                command: command.clone(),
+                            },
+                        });
+                    }
+                }
+            }
+
+            for language in &state.avail_languages {
+                entries.push(PaletteEntry {
+                    title: format!("{}: {}", gettext("Set Language"), language),
+                    detail: String::new(),
+                    command: PaletteCommand::Language(language.clone()),
+                });
+            }
         }
-    }
 
-    /// Open the find dialog of the current `EditView`
-    fn find(&self) {
-        if let Some(edit_view) = self.get_current_edit_view() {
-            edit_view.start_search();
+        for theme in &state.themes {
+            entries.push(PaletteEntry {
+                title: format!("{}: {}", gettext("Set Theme"), theme),
+                detail: String::new(),
+                command: PaletteCommand::Theme(theme.clone()),
+            });
         }
+
+        entries
     }
 
-    /// Open the replace dialog of the current `EditView
-    fn replace(&self) {
-        if let Some(edit_view) = self.get_current_edit_view() {
-            edit_view.start_replace();
+    /// Run what the user has chosen in the `CommandPalette`
+    fn run_palette_command(&self, command: PaletteCommand) {
+        trace!("Running palette command {:?}", command);
+        match command {
+            PaletteCommand::Action(name) => {
+                if let Some(application) = self.window.get_application() {
+                    application.activate_action(&name, None);
+                }
+            }
+            PaletteCommand::Plugin { plugin, command } => {
+                if let Some(ev) = self.get_current_edit_view() {
+                    self.core.plugin_rpc(ev.view_id, &plugin, &command.rpc_cmd);
+                }
+            }
+            PaletteCommand::Language(language) => {
+                if let Some(ev) = self.get_current_edit_view() {
+                    ev.set_language(&language);
+                }
+            }
+            PaletteCommand::Theme(theme) => {
+                self.core.set_theme(&theme);
+                let gschema = self.state.borrow().settings.gschema.clone();
+                gschema.set("theme-name", &theme).unwrap();
+                self.state.borrow_mut().theme_name = theme;
+            }
         }
     }
 
-    /// Get the currently opened `EditView` in our `GtkNotebook`
-    fn get_current_edit_view(&self) -> Option<Rc<EditView>> {
+    /// Get the currently opened `EditView` in our `GtkNotebook`. If the current page is split, this
+    /// is the `EditView` which had the focus last.
+    pub(crate) fn get_current_edit_view(&self) -> Option<Rc<EditView>> {
         if let Some(idx) = self.notebook.get_current_page() {
             if let Some(w) = self.notebook.get_nth_page(Some(idx)) {
+                if let Some(split) = self.splits.borrow().get(&w) {
+                    return Some(split.focused().clone());
+                }
                 if let Some(edit_view) = self.w_to_ev.borrow().get(&w) {
                     return Some(edit_view.clone());
                 }
@@ -1046,9 +2076,37 @@ impl MainWin {
         None
     }
 
+    /// Let xi save the document of the view with `view_id` to `file_name`. The `EditView`s showing
+    /// the file are told beforehand, so they don't mistake the write for a change by another program.
+    pub(crate) fn save_view(&self, view_id: ViewId, file_name: &str) {
+        for edit_view in self.views.borrow().values() {
+            if edit_view.view_id == view_id
+                || edit_view.file_name.borrow().as_deref() == Some(file_name)
+            {
+                edit_view.expect_save();
+            }
+        }
+        let edit_view = match self.views.borrow().get(&view_id) {
+            Some(edit_view) => edit_view.clone(),
+            None => {
+                self.core.save(view_id, file_name);
+                return;
+            }
+        };
+        let old_name = edit_view.file_name.borrow().clone();
+        let transcoded = old_name.map_or(false, |f| self.transcoded.borrow().contains_key(&f));
+        if edit_view.encoding().is_utf8() && !transcoded {
+            self.core.save(view_id, file_name);
+        } else {
+            self.save_encoded(&edit_view, file_name);
+        }
+        self.language_servers.saved(&edit_view);
+        self.add_to_history(&edit_view, file_name);
+    }
+
     fn autosave_view(&self, file_name: Option<String>, view_id: ViewId) -> Result<String, String> {
         if let Some(name) = file_name {
-            self.core.save(view_id, &name);
+            self.save_view(view_id, &name);
             Ok(name)
         } else {
             let mut doc_dir = match dirs::data_dir() {
@@ -1083,7 +2141,7 @@ impl MainWin {
             }
 
             let name = doc_dir.to_string_lossy().into_owned();
-            self.core.save(view_id, &name);
+            self.save_view(view_id, &name);
             Ok(name)
         }
     }
@@ -1094,6 +2152,17 @@ impl MainWin {
         self.fullscreen_bar.set_title(Some(title));
     }
 
+    /// The file name of the document of `edit_view` without its directory, for showing to the user
+    pub(crate) fn view_name(edit_view: &EditView) -> String {
+        edit_view
+            .file_name
+            .borrow()
+            .as_ref()
+            .and_then(|f| std::path::Path::new(f).file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| gettext("Untitled"))
+    }
+
     /// Updates the title bar
     fn update_titlebar(&self, view: Option<&Rc<EditView>>) {
         if let Some(ev) = view {
@@ -1127,22 +2196,123 @@ impl MainWin {
         }
     }
 
-    fn close_terminal(&self, term_num: u32) {
-        self.term_notebook.remove_page(Some(term_num));
-        if self.term_notebook.get_n_pages() < 2 {
-            self.term_notebook.set_show_tabs(false);
+    /// The name of the session the user is working in
+    fn session_name(&self) -> String {
+        let name: String = self.state.borrow().settings.gschema.get("current-session");
+        if name.is_empty() {
+            sessions::DEFAULT_SESSION.to_string()
+        } else {
+            name
+        }
+    }
+
+    /// The state of the documents, the window and the terminals, to be saved as a `Session`
+    fn session_state(&self) -> Session {
+        let current_page = self.notebook.get_current_page();
+        let mut active = None;
+        let mut views = Vec::new();
+        for (page_num, page) in self.notebook.get_children().iter().enumerate() {
+            let split = self.splits.borrow().get(page).cloned();
+            let edit_view = match &split {
+                Some(split) => split.primary.clone(),
+                None => match self.w_to_ev.borrow().get(page) {
+                    Some(edit_view) => edit_view.clone(),
+                    None => continue,
+                },
+            };
+            let path = match edit_view.file_name.borrow().clone() {
+                Some(path) => path,
+                None => continue,
+            };
+            if current_page == Some(page_num as u32) {
+                active = Some(views.len());
+            }
+            views.push(SavedView {
+                path,
+                selections: edit_view.selections(),
+                first_line: edit_view.first_visible_line(),
+                language: edit_view.language_override(),
+                split: split.map(|s| orientation_to_str(s.paned.get_orientation()).to_string()),
+            });
+        }
+
+        let properties = self.properties.borrow();
+        Session {
+            name: self.session_name(),
+            views,
+            active,
+            project_root: self
+                .project_root
+                .borrow()
+                .as_ref()
+                .map(|root| root.to_string_lossy().to_string()),
+            window: Some(Geometry {
+                width: properties.width,
+                height: properties.height,
+                maximized: properties.is_maximized,
+                sidebar_visible: properties.sidebar_visible,
+                sidebar_width: properties.sidebar_width,
+            }),
+            terminals: self.session_terminals(),
         }
     }
 
-    fn remove_terminal_area(&self) {
-        self.paned.remove(&self.paned.get_child2().unwrap());
+    /// Save the state of the current session to its file
+    fn save_session(&self) -> Result<(), String> {
+        let dir = functions::sessions_dir()
+            .ok_or_else(|| gettext("Couldn’t find the directory to save sessions in."))?;
+        let session = self.session_state();
+        debug!(
+            "Saving session '{}' with {} documents",
+            session.name,
+            session.views.len()
+        );
+        session.save(&dir)
+    }
+
+    /// Put the tabs of the session which is being restored into their order and show the tab
+    /// which was shown when the session was saved
+    fn arrange_session_tabs(&self) {
+        let done = match &*self.pending_session.borrow() {
+            Some(pending) => {
+                let page_of = |path: &str| {
+                    let views = self.views.borrow();
+                    let edit_view = views
+                        .values()
+                        .find(|ev| ev.file_name.borrow().as_deref() == Some(path))?;
+                    self.view_id_to_w.borrow().get(&edit_view.view_id).cloned()
+                };
+                let mut position = 0;
+                for page in pending.order.iter().filter_map(|path| page_of(path)) {
+                    self.notebook.reorder_child(&page, Some(position));
+                    position += 1;
+                }
+                if let Some(page) = pending.active.as_deref().and_then(page_of) {
+                    self.notebook
+                        .set_current_page(self.notebook.page_num(&page));
+                }
+                pending.views.is_empty()
+            }
+            None => false,
+        };
+        if done {
+            self.pending_session.replace(None);
+        }
     }
 }
 
 /// An Extension trait for `MainWin`. This is implemented for `Rc<MainWin>`, allowing for a nicer
 /// API (where we can do stuff like `self.close()` instead of `Self::close(main_win)`).
 pub trait MainWinExt {
-    fn add_terminal(&self, always_create_new: bool);
+    fn restore_session(&self, session: Session);
+
+    fn switch_session(&self, name: &str);
+
+    fn choose_session(&self);
+
+    fn save_session_as(&self);
+
+    fn close_asking(&self) -> bool;
 
     fn close(&self) -> SaveAction;
 
@@ -1152,6 +2322,12 @@ pub trait MainWinExt {
 
     fn connect_settings_change(&self);
 
+    fn connect_top_bar(&self, edit_view: &Rc<EditView>);
+
+    fn connect_file_changed(&self, edit_view: &Rc<EditView>);
+
+    fn connect_diagnostics_changed(&self, edit_view: &Rc<EditView>);
+
     fn current_save_as(&self);
 
     fn handle_event(&self, ev: RpcOperations);
@@ -1160,6 +2336,12 @@ pub trait MainWinExt {
 
     fn handle_save_button(&self);
 
+    fn open_file(&self, path: &std::path::Path);
+
+    fn open_file_at(&self, path: &std::path::Path, line: u64, col: u64);
+
+    fn reload_view(&self, edit_view: &Rc<EditView>);
+
     fn req_new_view(&self, file_name: Option<String>);
 
     fn new_view(&self, res: Result<(ViewId, Option<String>), String>);
@@ -1174,160 +2356,222 @@ pub trait MainWinExt {
 }
 
 impl MainWinExt for Rc<MainWin> {
-    /// Add a Terminal to the bottom of the `MainWin`. If `always_create_new` is true, this will only
-    /// create a new terminal area if it doesn't exist yet. If it's false it adds a new terminal to the
-    /// `GtkNotebook` holding the terminals.
-    fn add_terminal(&self, always_create_new: bool) {
-        let term = Terminal::new();
-        let shell: String = self.state.borrow().settings.gschema.get("terminal-path");
-
-        let cwd = if let Some(ev) = self.get_current_edit_view() {
-            if let Some(filename) = &*ev.file_name.borrow() {
-                let path = std::path::Path::new(filename);
-                path.parent()
-                    .and_then(|p| p.to_str().map(|s| s.to_string()))
+    /// Recreate the documents, window geometry, project and terminals of `session`
+    fn restore_session(&self, session: Session) {
+        debug!("Restoring session '{}'", session.name);
+        if let Some(geometry) = &session.window {
+            if geometry.maximized {
+                self.window.maximize();
             } else {
-                None
+                self.window.unmaximize();
+                self.window.resize(geometry.width, geometry.height);
             }
-        } else {
-            None
-        };
+            let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+            sidebar_box.set_visible(geometry.sidebar_visible);
+            self.sidebar_paned.set_position(geometry.sidebar_width);
+            let mut properties = self.properties.borrow_mut();
+            properties.sidebar_visible = geometry.sidebar_visible;
+            properties.sidebar_width = geometry.sidebar_width;
+        }
 
-        term.spawn_sync(
-            vte::PtyFlags::DEFAULT,
-            cwd.as_deref(),
-            &[&std::path::Path::new(&shell)],
-            &[],
-            SpawnFlags::DEFAULT,
-            Some(&mut || functions::vte_callback()),
-            None::<&gio::Cancellable>,
-        )
-        .unwrap();
-
-        term.connect_key_press_event(move |term, ek| {
-            let ctrl = ek.get_state().contains(ModifierType::CONTROL_MASK);
-            let shift = ek.get_state().contains(ModifierType::SHIFT_MASK);
-            match ek.get_keyval() {
-                key::C if ctrl && shift => {
-                    term.copy_clipboard();
-                    return Inhibit(true);
-                }
-                key::V if ctrl && shift => {
-                    term.paste_clipboard();
-                    return Inhibit(true);
-                }
-                _ => {}
+        if let Some(root) = session.project_root.as_ref().map(PathBuf::from) {
+            if root.is_dir() {
+                self.set_project_root(root);
             }
-            Inhibit(false)
-        });
-
-        let context_menu_builder =
-            Builder::new_from_resource("/org/gnome/Tau/terminal_context_menu.glade");
-        let gmenu: gio::Menu = context_menu_builder
-            .get_object("terminal_context_menu")
-            .unwrap();
-        let context_menu = gtk::Menu::new_from_model(&gmenu);
-        context_menu.set_property_attach_widget(Some(&term));
+        }
 
-        term.connect_button_press_event(move |_, eb| {
-            if eb.get_button() == 3 {
-                context_menu.popup_at_pointer(Some(&eb));
-            }
+        self.terminals_restored.set(true);
+        for terminal in &session.terminals {
+            self.add_terminal_with_profile(false, &terminal.profile, Some(terminal));
+        }
 
-            Inhibit(false)
-        });
+        let active = session
+            .active
+            .and_then(|i| session.views.get(i))
+            .map(|view| view.path.clone());
+        let (views, missing): (Vec<SavedView>, Vec<SavedView>) = session
+            .views
+            .into_iter()
+            .partition(|view| Path::new(&view.path).is_file());
+        for view in missing {
+            warn!("Failed to restore file `{}`", view.path);
+        }
+        if views.is_empty() {
+            self.req_new_view(None);
+            return;
+        }
 
-        let top_bar = editview::TopBar::new();
+        let order: Vec<String> = views.iter().map(|view| view.path.clone()).collect();
+        self.pending_session.replace(Some(PendingSession {
+            order: order.clone(),
+            active,
+            views: views
+                .into_iter()
+                .map(|view| (view.path.clone(), view))
+                .collect(),
+        }));
+        for path in order {
+            self.open_file(Path::new(&path));
+        }
+    }
 
-        let term_box = self.paned.get_child2().map(|w| w.downcast::<gtk::Box>());
-        if term_box.as_ref().map(|x| x.as_ref().ok()).is_some() && always_create_new {
+    /// Save the current session and replace it with the session called `name`, which is created
+    /// if it doesn't exist yet
+    fn switch_session(&self, name: &str) {
+        if let Err(e) = sessions::validate_name(name) {
+            ErrorDialog::new(ErrorMsg::new(e, false));
+            return;
+        }
+        if name == self.session_name() {
+            return;
+        }
+        let dir = match functions::sessions_dir() {
+            Some(dir) => dir,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Couldn’t find the directory to save sessions in."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let session = match Session::load(&dir, name) {
+            Ok(session) => session,
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(e, false));
+                return;
+            }
+        };
+        if let Err(e) = self.save_session() {
+            ErrorDialog::new(ErrorMsg::new(e, false));
             return;
         }
+        if self.confirm_close_terminals() == SaveAction::Cancel
+            || self.close_all() == SaveAction::Cancel
+        {
+            debug!("User chose to stay in session '{}'", self.session_name());
+            return;
+        }
+        self.remove_terminal_area();
 
-        if let Some(Ok(_)) = term_box {
-            let num = self.term_notebook.get_n_pages();
+        debug!("Switching to session '{}'", name);
+        let gschema = self.state.borrow().settings.gschema.clone();
+        gschema.session_clear();
+        gschema.set("current-session", &name.to_string()).unwrap();
+        match session {
+            Some(session) => self.restore_session(session),
+            None => self.req_new_view(None),
+        }
+    }
 
-            top_bar
-                .label
-                .set_text(&format!("{} {}", gettext("Terminal"), num + 1));
-            let page_num = self
-                .term_notebook
-                .insert_page(&term, Some(&top_bar.event_box), None);
-            self.term_notebook.set_tab_reorderable(&term, true);
+    /// Let the user pick a session to switch to or enter the name of a new one
+    fn choose_session(&self) {
+        let names = functions::sessions_dir()
+            .map(|dir| sessions::list_sessions(&dir))
+            .unwrap_or_default();
+        let current = self.session_name();
 
-            top_bar.close_button.connect_clicked(
-                clone!(@weak self as main_win, @strong page_num => @default-panic, move |_| {
-                    main_win.close_terminal(page_num);
-                }),
-            );
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &gettext("Switch Session"),
+        );
+        dialog.set_property_secondary_text(Some(&gettext(
+            "The current session is saved before switching. Enter a new name to start an empty session.",
+        )));
+        let combo = ComboBoxText::new_with_entry();
+        for name in &names {
+            combo.append(Some(name), name);
+        }
+        if !combo.set_active_id(Some(&current)) {
+            combo.prepend(Some(&current), &current);
+            combo.set_active_id(Some(&current));
+        }
+        if let Some(entry) = combo
+            .get_child()
+            .and_then(|w| w.downcast::<gtk::Entry>().ok())
+        {
+            entry.set_activates_default(true);
+        }
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&combo, false, true, 0);
+        }
+        combo.show();
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Switch"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
 
-            top_bar.event_box.connect_button_press_event(
-                clone!(@weak self as main_win => @default-panic, move |_, eb| {
-                    // 2 == middle click
-                    if eb.get_button() == 2 {
-                        main_win.close_terminal(page_num);
-                    }
-                    Inhibit(false)
-                }),
-            );
-        } else {
-            let termbox = gtk::Box::new(Orientation::Horizontal, 0);
-            termbox.pack_start(&self.term_notebook, true, true, 0);
-            self.paned.add2(&termbox);
-
-            let buttonbox = gtk::Box::new(Orientation::Vertical, 0);
-            let close_button =
-                Button::new_from_icon_name(Some("window-close-symbolic"), IconSize::SmallToolbar);
-            let add_button =
-                Button::new_from_icon_name(Some("window-new-symbolic"), IconSize::SmallToolbar);
-            buttonbox.pack_start(&add_button, false, false, 0);
-            buttonbox.pack_start(&close_button, false, false, 0);
-            termbox.pack_start(&buttonbox, false, false, 0);
-
-            top_bar
-                .label
-                .set_text(&format!("{} {}", gettext("Terminal"), 1));
-            let page_num = self
-                .term_notebook
-                .insert_page(&term, Some(&top_bar.event_box), None);
-
-            close_button.connect_clicked(
-                clone!(@weak self as main_win => @default-panic, move |_| {
-                    main_win.remove_terminal_area();
-                }),
-            );
+        self.saving.replace(true);
+        let response = dialog.run();
+        let name = combo.get_active_text().map(|name| name.trim().to_string());
+        dialog.destroy();
+        self.saving.replace(false);
 
-            add_button.connect_clicked(clone!(@weak self as main_win => @default-panic,move |_| {
-                main_win.add_terminal(false);
-            }));
+        if let (ResponseType::Ok, Some(name)) = (response, name) {
+            self.switch_session(&name);
+        }
+    }
 
-            top_bar.close_button.connect_clicked(
-                clone!(@weak self as main_win, @strong page_num => @default-panic,move |_| {
-                    main_win.close_terminal(page_num);
-                }),
-            );
+    /// Save the current session under a new name and continue working in it
+    fn save_session_as(&self) {
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &gettext("Save Session As"),
+        );
+        let entry = gtk::Entry::new();
+        entry.set_text(&self.session_name());
+        entry.set_activates_default(true);
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&entry, false, true, 0);
+        }
+        entry.show();
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Save"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
 
-            top_bar.event_box.connect_button_press_event(
-                clone!(@weak self as main_win => @default-panic, move |_, eb| {
-                    // 2 == middle click
-                    if eb.get_button() == 2 {
-                        main_win.close_terminal(page_num);
-                    }
-                    Inhibit(false)
-                }),
-            );
+        self.saving.replace(true);
+        let response = dialog.run();
+        let name = entry.get_text().map(|name| name.trim().to_string());
+        dialog.destroy();
+        self.saving.replace(false);
 
-            self.paned.show_all();
+        let name = match (response, name) {
+            (ResponseType::Ok, Some(name)) => name,
+            _ => return,
+        };
+        if let Err(e) = sessions::validate_name(&name) {
+            ErrorDialog::new(ErrorMsg::new(e, false));
+            return;
         }
-        term.show();
-
-        if self.term_notebook.get_n_pages() < 2 {
-            self.term_notebook.set_show_tabs(false);
-        } else {
-            self.term_notebook.set_show_tabs(true);
+        let gschema = self.state.borrow().settings.gschema.clone();
+        let old_name = self.session_name();
+        gschema.set("current-session", &name).unwrap();
+        if let Err(e) = self.save_session() {
+            gschema.set("current-session", &old_name).unwrap();
+            ErrorDialog::new(ErrorMsg::new(e, false));
         }
     }
 
+    /// Close the window once the user has saved the unsaved changes or chosen to close without
+    /// saving them. Returns whether the window has been closed.
+    fn close_asking(&self) -> bool {
+        if self.close_all() == SaveAction::Cancel {
+            debug!("User chose to cancel exiting");
+            return false;
+        }
+        debug!("User chose to close the application");
+        self.properties.borrow().save();
+        self.save_terminal_session();
+        functions::remove_transcoded_dir();
+        self.window.destroy();
+        true
+    }
+
     /// Close the current `EditView`
     ///
     /// # Returns
@@ -1335,7 +2579,11 @@ impl MainWinExt for Rc<MainWin> {
     /// - `SaveAction` determining if the `EdtiView` has been closed.
     fn close(&self) -> SaveAction {
         trace!("Closing current Editview");
-        if let Some(edit_view) = self.get_current_edit_view() {
+        if let Some(mut edit_view) = self.get_current_edit_view() {
+            // Closing a split page closes both of its panes
+            if let Some(split) = self.split_of(edit_view.view_id) {
+                edit_view = split.primary.clone();
+            }
             let result = self.close_view(&edit_view);
             if result != SaveAction::Cancel {
                 if let Some(path) = edit_view.file_name.borrow().as_ref() {
@@ -1400,6 +2648,19 @@ impl MainWinExt for Rc<MainWin> {
     /// `SaveAction` determining which choice the user has made in the save dialog
     fn close_view(&self, edit_view: &Rc<EditView>) -> SaveAction {
         trace!("Closing Editview {}", edit_view.view_id);
+        // The EditView might have been closed already together with the other pane of its split
+        if !self.views.borrow().contains_key(&edit_view.view_id) {
+            return SaveAction::CloseWithoutSave;
+        }
+        let split = self.split_of(edit_view.view_id);
+        if let Some(split) = &split {
+            // The document stays open in the primary pane, so there's nothing to save
+            if split.secondary.view_id == edit_view.view_id {
+                self.remove_split_pane(split, edit_view);
+                return SaveAction::CloseWithoutSave;
+            }
+        }
+
         let save_action = if *edit_view.pristine.borrow() {
             // If it's pristine we don't ask the user if he really wants to quit because everything
             // is saved already and as such always close without saving
@@ -1458,6 +2719,17 @@ impl MainWinExt for Rc<MainWin> {
         debug!("SaveAction: {:?}", save_action);
 
         if save_action != SaveAction::Cancel {
+            if let Some(split) = split {
+                let secondary_id = split.secondary.view_id;
+                self.splits
+                    .borrow_mut()
+                    .remove(&split.paned.clone().upcast());
+                self.view_id_to_w.borrow_mut().remove(&secondary_id);
+                self.views.borrow_mut().remove(&secondary_id);
+                self.plugin_cmds.borrow_mut().remove(&secondary_id);
+                self.language_servers.detach(&split.secondary);
+                self.core.close_view(secondary_id);
+            }
             if let Some(w) = self
                 .view_id_to_w
                 .borrow()
@@ -1469,8 +2741,14 @@ impl MainWinExt for Rc<MainWin> {
                 }
                 self.w_to_ev.borrow_mut().remove(&w);
             }
+            self.remove_backup(edit_view.view_id);
+            if let Some(file_name) = edit_view.file_name.borrow().as_ref() {
+                self.remove_transcoded(file_name);
+            }
             self.view_id_to_w.borrow_mut().remove(&edit_view.view_id);
             self.views.borrow_mut().remove(&edit_view.view_id);
+            self.plugin_cmds.borrow_mut().remove(&edit_view.view_id);
+            self.language_servers.detach(edit_view);
             self.core.close_view(edit_view.view_id);
 
             // If we only have 0 or 1 EditViews left (and as such 0/1 tabs, which
@@ -1615,12 +2893,19 @@ impl MainWinExt for Rc<MainWin> {
                         &json!({ "word_wrap": val })
                     );
                 }
+                "line-ending" => {
+                    let val: String = gschema.get("line-ending");
+                    core.modify_user_config_domain(
+                        "general",
+                        &json!({ "line_ending": LineEnding::from_setting(&val).as_str() })
+                    );
+                }
                 "syntax-config" => {
                     let val = gschema.get_strv("syntax-config");
 
                     for x in &val {
-                        if let Ok(val) = serde_json::from_str(x.as_str()) {
-                            core.as_ref().modify_user_config(val);
+                        if let Ok(params) = serde_json::from_str::<SyntaxParams>(x.as_str()) {
+                            core.as_ref().modify_user_config(params.to_xi_config());
                         } else {
                             error!("Failed to deserialize syntax config. Resetting...");
                             gschema.reset("syntax-config");
@@ -1639,6 +2924,12 @@ impl MainWinExt for Rc<MainWin> {
                         .collect();
 
                     main_win.syntax_config.replace(syntax_config);
+
+                    for ev in main_win.views.borrow().values() {
+                        if let Some(lang) = ev.view_item.statusbar.syntax_label.get_text() {
+                            ev.set_auto_pairs(main_win.auto_pairs_for(&lang));
+                        }
+                    }
                 }
                 "theme-name" => {
                     if let Some(ev) = main_win.get_current_edit_view() {
@@ -1673,7 +2964,7 @@ impl MainWinExt for Rc<MainWin> {
                     }
                 }
                 // Valid keys, but no immediate action to be taken
-                "window-height" | "window-width" | "window-maximized" | "save-when-out-of-focus" | "session" | "terminal-path" | "restore-session" => {}
+                "window-height" | "window-width" | "window-maximized" | "sidebar-visible" | "sidebar-width" | "save-when-out-of-focus" | "session" | "session-splits" | "session-terminals" | "current-session" | "terminal-path" | "terminal-profile" | "terminal-follows-file" | "restore-session" | "hot-exit" | "local-history" | "local-history-max-snapshots" | "local-history-max-days" => {}
                 key => {
                     error!("Unknown GSettings key change event '{}'. Please make sure your GSchema is up-to-date.", key);
                 }
@@ -1743,17 +3034,116 @@ impl MainWinExt for Rc<MainWin> {
         if let Some(edit_view) = self.get_current_edit_view() {
             let name = { edit_view.file_name.borrow().clone() };
             if let Some(ref file_name) = name {
-                self.core.save(edit_view.view_id, file_name);
+                self.save_view(edit_view.view_id, file_name);
             } else {
                 self.save_as(&edit_view);
             }
         }
     }
 
+    /// Open the file at `path`, or switch to its tab if it's open already
+    fn open_file(&self, path: &std::path::Path) {
+        let file_name = path.to_string_lossy().into_owned();
+        let open_view = self
+            .views
+            .borrow()
+            .values()
+            .find(|ev| ev.file_name.borrow().as_ref() == Some(&file_name))
+            .map(|ev| ev.view_id);
+
+        if let Some(view_id) = open_view {
+            let w = self.view_id_to_w.borrow().get(&view_id).cloned();
+            if let Some(page_num) = w.and_then(|w| self.notebook.page_num(&w)) {
+                self.notebook.set_current_page(Some(page_num));
+            }
+        } else {
+            self.req_new_view(Some(file_name));
+        }
+    }
+
+    /// Open the file at `path` like `open_file` and move the cursor to `line` and `col` (a byte
+    /// offset into the line), both starting at 0
+    fn open_file_at(&self, path: &std::path::Path, line: u64, col: u64) {
+        let file_name = path.to_string_lossy().into_owned();
+        let open_view = self
+            .views
+            .borrow()
+            .values()
+            .find(|ev| ev.file_name.borrow().as_ref() == Some(&file_name))
+            .map(|ev| ev.view_id);
+
+        if let Some(view_id) = open_view {
+            self.open_file(path);
+            self.core.gesture_point_select(view_id, line, col);
+        } else {
+            // The cursor is moved once the view has been created in `new_view_response`
+            self.pending_positions
+                .borrow_mut()
+                .insert(file_name, (line, col));
+            self.open_file(path);
+        }
+    }
+
+    /// Discard the document of `edit_view` and load its file from disk again, e.g. after another
+    /// program has changed it. The new view takes the tab of the old one.
+    fn reload_view(&self, edit_view: &Rc<EditView>) {
+        // Xi shares the buffer between all views of a file, so the whole page has to be reopened
+        let edit_view = match self.split_of(edit_view.view_id) {
+            Some(split) => split.primary.clone(),
+            None => edit_view.clone(),
+        };
+        // The view might have been reloaded already via the other pane of its split
+        if !self.views.borrow().contains_key(&edit_view.view_id) {
+            return;
+        }
+        let file_name = match edit_view.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => return,
+        };
+        debug!("Reloading file '{}'", file_name);
+
+        let page = self
+            .view_id_to_w
+            .borrow()
+            .get(&edit_view.view_id)
+            .and_then(|w| self.notebook.page_num(w));
+        if let Some(page_num) = page {
+            let current = self.notebook.get_current_page() == Some(page_num);
+            self.pending_pages
+                .borrow_mut()
+                .insert(file_name.clone(), (page_num, current));
+        }
+        if let Some(position) = edit_view.cursor_positions().first() {
+            self.pending_positions
+                .borrow_mut()
+                .insert(file_name.clone(), *position);
+        }
+
+        // The user wants to discard the changes, so don't ask about saving them
+        edit_view.pristine.replace(true);
+        self.close_view(&edit_view);
+        self.req_new_view(Some(file_name));
+    }
+
     /// Request a new view from `xi-core` and send
     fn req_new_view(&self, file_name: Option<String>) {
         trace!("Requesting new view");
 
+        // Xi only works on UTF-8, so files in other encodings are opened as a decoded copy
+        let xi_path = match &file_name {
+            Some(file_name) => {
+                let encoding = self.forced_encodings.borrow_mut().remove(file_name);
+                match self.transcode(file_name, encoding) {
+                    Ok(xi_path) => Some(xi_path),
+                    Err(e) => {
+                        ErrorDialog::new(ErrorMsg::new(e, false));
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+
         let (tx, rx) = MainContext::channel(glib::source::PRIORITY_HIGH);
 
         rx.attach(
@@ -1768,7 +3158,7 @@ impl MainWinExt for Rc<MainWin> {
         );
 
         self.core
-            .new_view(file_name.as_ref(), move |res| tx.send(res).unwrap());
+            .new_view(xi_path.as_ref(), move |res| tx.send(res).unwrap());
     }
 
     /// When `xi-core` tells us to create a new view, we have to connect the ways to close the `EditView`,
@@ -1786,10 +3176,12 @@ impl MainWinExt for Rc<MainWin> {
             &self.window,
         );
 
-        if self.notebook.get_n_pages() == 0
-            && self.state.borrow().settings.gschema.get("show-terminal")
-        {
-            self.add_terminal(true);
+        if self.notebook.get_n_pages() == 0 {
+            if !self.terminals_restored.replace(true) {
+                self.restore_terminals();
+            } else if self.state.borrow().settings.gschema.get("show-terminal") {
+                self.add_terminal(true);
+            }
         }
         {
             let page_num = self.notebook.insert_page(
@@ -1806,55 +3198,159 @@ impl MainWinExt for Rc<MainWin> {
                 self.view_id_to_w.borrow_mut().insert(view_id, w);
             }
 
-            edit_view.top_bar.close_button.connect_clicked(
-                clone!(@weak self as main_win, @weak edit_view => @default-panic, move |_| {
+            self.connect_top_bar(&edit_view);
+            self.connect_file_changed(&edit_view);
+            self.connect_diagnostics_changed(&edit_view);
+        }
+        if let Some(path) = edit_view.file_name.borrow().as_ref() {
+            if let Some(transcoded) = self.transcoded.borrow().get(path) {
+                edit_view.set_encoding(transcoded.encoding);
+            }
+            MainWin::check_line_endings(&edit_view, path);
+        }
+        let split_orientation = if let Some(path) = edit_view.file_name.borrow().as_ref() {
+            let gschema = self.state.borrow().settings.gschema.clone();
+            gschema.session_add(path.clone());
+            gschema.get_session_split(path)
+        } else {
+            None
+        };
+        let saved_view = edit_view.file_name.borrow().as_ref().and_then(|path| {
+            self.pending_session
+                .borrow_mut()
+                .as_mut()
+                .and_then(|pending| pending.views.remove(path))
+        });
+        self.views.borrow_mut().insert(view_id, edit_view.clone());
+
+        if self.project_root.borrow().is_none() {
+            let parent = edit_view
+                .file_name
+                .borrow()
+                .as_ref()
+                .and_then(|f| std::path::Path::new(f).parent().map(PathBuf::from));
+            if let Some(parent) = parent {
+                self.set_project_root(parent);
+            }
+        }
+        self.reveal_in_sidebar(Some(&edit_view));
+
+        let position = edit_view
+            .file_name
+            .borrow()
+            .as_ref()
+            .and_then(|f| self.pending_positions.borrow_mut().remove(f));
+        if let Some((line, col)) = position {
+            self.core.gesture_point_select(view_id, line, col);
+        }
+
+        // Put reloaded files back into their old tab
+        let page = edit_view
+            .file_name
+            .borrow()
+            .as_ref()
+            .and_then(|f| self.pending_pages.borrow_mut().remove(f));
+        if let Some((page_num, current)) = page {
+            self.notebook
+                .reorder_child(&edit_view.root_widget, Some(page_num));
+            if current {
+                self.notebook.set_current_page(Some(page_num));
+            }
+        }
+
+        // Restore the unsaved changes of the last run
+        let backup = edit_view
+            .file_name
+            .borrow()
+            .as_ref()
+            .and_then(|f| self.pending_backups.borrow_mut().remove(f));
+        if let Some((id, backup)) = backup {
+            self.apply_backup(&edit_view, id, backup);
+        }
+
+        // Restore the view state of the session which is being restored
+        let split_orientation = match &saved_view {
+            Some(saved_view) => {
+                if let Some(language) = &saved_view.language {
+                    edit_view.set_language(language);
+                }
+                edit_view.set_selections(&saved_view.selections);
+                edit_view.restore_scroll(saved_view.first_line);
+                self.arrange_session_tabs();
+                saved_view.split.as_deref().and_then(orientation_from_str)
+            }
+            None => split_orientation,
+        };
+
+        // Restore the split layout of the last session
+        if let Some(orientation) = split_orientation {
+            self.split_view(&edit_view, orientation);
+        }
+    }
+
+    fn connect_top_bar(&self, edit_view: &Rc<EditView>) {
+        edit_view.top_bar.close_button.connect_clicked(
+            clone!(@weak self as main_win, @weak edit_view => @default-panic, move |_| {
+                if main_win.close_view(&edit_view) != SaveAction::Cancel {
+                    if let Some(path) = edit_view.file_name.borrow().as_ref() {
+                        main_win
+                            .state
+                            .borrow_mut()
+                            .settings
+                            .gschema
+                            .session_remove(path);
+                    }
+                }
+            }),
+        );
+
+        #[allow(clippy::collapsible_if)]
+        edit_view.top_bar.event_box.connect_button_press_event(
+            clone!(@weak self as main_win, @weak edit_view => @default-panic, move |_, eb| {
+                // 2 == middle click
+                if eb.get_button() == 2 {
                     if main_win.close_view(&edit_view) != SaveAction::Cancel {
                         if let Some(path) = edit_view.file_name.borrow().as_ref() {
                             main_win
                                 .state
                                 .borrow_mut()
-                                .settings
-                                .gschema
+                                .settings.gschema
                                 .session_remove(path);
                         }
                     }
-                }),
-            );
+                }
+                Inhibit(false)
+            }),
+        );
+    }
 
-            #[allow(clippy::collapsible_if)]
-            edit_view.top_bar.event_box.connect_button_press_event(
-                clone!(@weak self as main_win, @weak edit_view => @default-panic, move |_, eb| {
-                    // 2 == middle click
-                    if eb.get_button() == 2 {
-                        if main_win.close_view(&edit_view) != SaveAction::Cancel {
-                            if let Some(path) = edit_view.file_name.borrow().as_ref() {
-                                main_win
-                                    .state
-                                    .borrow_mut()
-                                    .settings.gschema
-                                    .session_remove(path);
-                            }
-                        }
-                    }
-                    Inhibit(false)
-                }),
-            );
-        }
-        if let Some(path) = edit_view.file_name.borrow().as_ref() {
-            self.state
-                .borrow_mut()
-                .settings
-                .gschema
-                .session_add(path.clone());
-        }
-        self.views.borrow_mut().insert(view_id, edit_view);
+    /// Reload or compare the file of `edit_view` when the user asks for it after it has been
+    /// changed on disk
+    fn connect_file_changed(&self, edit_view: &Rc<EditView>) {
+        edit_view.connect_file_changed(
+            clone!(@weak self as main_win, @weak edit_view => @default-return (), move |action| {
+                match action {
+                    FileChangedAction::Reload => main_win.reload_view(&edit_view),
+                    FileChangedAction::Compare => main_win.compare_with_saved(&edit_view),
+                }
+            }),
+        );
+    }
+
+    /// Keep the `Problems` up to date with the diagnostics of `edit_view`
+    fn connect_diagnostics_changed(&self, edit_view: &Rc<EditView>) {
+        edit_view.connect_diagnostics_changed(
+            clone!(@weak self as main_win => @default-panic, move || {
+                main_win.refresh_problems();
+            }),
+        );
     }
 
     fn save_all(&self) {
         for edit_view in self.views.borrow().values() {
             let name = { edit_view.file_name.borrow().clone() };
             if let Some(ref file_name) = name {
-                self.core.save(edit_view.view_id, file_name);
+                self.save_view(edit_view.view_id, file_name);
             } else {
                 self.save_as(&edit_view);
             }
@@ -1895,7 +3391,7 @@ impl MainWinExt for Rc<MainWin> {
                             Ok(_) => {
                                 debug!("Saving file '{:?}'", &file);
                                 let file = file.to_string_lossy();
-                                main_win.core.save(edit_view.view_id, &file);
+                                main_win.save_view(edit_view.view_id, &file);
                                 if let Some(old_file_name) = edit_view.file_name.borrow().as_ref() {
                                     main_win
                                     .state
@@ -1907,6 +3403,11 @@ impl MainWinExt for Rc<MainWin> {
 
                                 edit_view.set_file(&file);
                                 main_win.state.borrow_mut().settings.gschema.session_add(file.to_string());
+                                main_win.language_servers.attach(
+                                    &edit_view,
+                                    &edit_view.language(),
+                                    main_win.project_root.borrow().as_deref(),
+                                );
                             }
                         Err(e) => {
                             let err_msg = format!("{} '{}': {}", &gettext("Couldn’t save file"), &file_str, &e.to_string());
@@ -1953,13 +3454,17 @@ impl MainWinExt for Rc<MainWin> {
             RpcOperations::DefStyle(style) => self.def_style(style),
             RpcOperations::FindStatus(status) => self.find_status(&status),
             RpcOperations::ReplaceStatus(status) => self.replace_status(&status),
-            RpcOperations::Update(update) => self.update(update),
+            RpcOperations::Update(update) => {
+                self.update(update);
+                self.schedule_backup();
+            }
             RpcOperations::ScrollTo(scroll) => self.scroll_to(&scroll),
             RpcOperations::ThemeChanged(theme) => self.theme_changed(theme),
             RpcOperations::AvailableLanguages(langs) => self.available_languages(langs),
             RpcOperations::LanguageChanged(lang) => self.language_changed(&lang),
             RpcOperations::PluginStarted(plugin) => self.plugin_started(&plugin),
             RpcOperations::PluginStopped(plugin) => self.plugin_stopped(&plugin),
+            RpcOperations::UpdateCmds(cmds) => self.update_cmds(cmds),
             RpcOperations::MeasureWidth((id, measure_width)) => {
                 self.measure_width(measure_width, id)
             }
@@ -3,6 +3,9 @@
 // SPDX-License-Identifier: MIT
 
 use crate::main_win::MainWin;
+use crate::syntax_config::SyntaxParams;
+use editview::auto_pairs::AutoPairs;
+use editview::line_ending::LineEnding;
 use gio::prelude::{SettingsExt, SettingsExtManual};
 use glib::Receiver;
 use gtk::Application;
@@ -24,7 +27,7 @@ impl MainWinBuilder {
     pub fn new(application: Application) -> MainWinBuilder {
         let (client, recv) = Client::new();
         client.client_started(
-            std::env::var("XI_CONFIG_DIR").ok().as_ref(),
+            crate::functions::xi_config_dir().as_ref(),
             crate::globals::PLUGIN_DIR.map(|s| s.to_string()).as_ref(),
         );
 
@@ -37,11 +40,6 @@ impl MainWinBuilder {
     }
 
     fn init_config(&self) {
-        #[cfg(windows)]
-        const LINE_ENDING: &str = "\r\n";
-        #[cfg(not(windows))]
-        const LINE_ENDING: &str = "\n";
-
         debug!("Initialising user config");
 
         let gschema = gio::Settings::new("org.gnome.Tau");
@@ -51,6 +49,7 @@ impl MainWinBuilder {
         let translate_tabs_to_spaces = gschema.get::<bool>("translate-tabs-to-spaces");
         let use_tab_stops = gschema.get::<bool>("use-tab-stops");
         let word_wrap = gschema.get::<bool>("word-wrap");
+        let line_ending = gschema.get::<String>("line-ending");
 
         let font = gschema.get::<String>("font");
         let font_vec = font.split_whitespace().collect::<Vec<_>>();
@@ -78,15 +77,16 @@ impl MainWinBuilder {
                 } else { font_size },
                 "use_tab_stops": use_tab_stops,
                 "word_wrap": word_wrap,
-                "line_ending": LINE_ENDING,
+                "line_ending": LineEnding::from_setting(&line_ending).as_str(),
+                "surrounding_pairs": AutoPairs::default().to_strings(),
             }),
         );
 
         let val = gschema.get_strv("syntax-config");
 
         for x in val {
-            if let Ok(val) = serde_json::from_str(x.as_str()) {
-                self.core.as_ref().modify_user_config(val);
+            if let Ok(params) = serde_json::from_str::<SyntaxParams>(x.as_str()) {
+                self.core.as_ref().modify_user_config(params.to_xi_config());
             } else {
                 error!("Failed to deserialize syntax config. Resetting...");
                 gschema.reset("syntax-config");
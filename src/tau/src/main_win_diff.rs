@@ -0,0 +1,206 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Compares documents with their saved version, another tab or their version in HEAD in a
+//! `DiffView`
+
+use crate::diff_view::DiffView;
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::main_win::MainWin;
+use editview::{i18n::freplace, EditView, EditViewExt};
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{ButtonsType, ComboBoxText, DialogFlags, MessageDialog, MessageType, ResponseType};
+use std::rc::Rc;
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for comparing documents
+pub trait MainWinDiffExt {
+    fn compare_with_saved(&self, edit_view: &Rc<EditView>);
+
+    fn compare_with_tab(&self);
+
+    fn compare_with_head(&self, path: &std::path::Path, head: &str);
+
+    fn show_diff(
+        &self,
+        title: &str,
+        left_title: &str,
+        left_text: &str,
+        right_title: &str,
+        right_text: &str,
+    );
+}
+
+impl MainWinDiffExt for Rc<MainWin> {
+    /// Show the unsaved changes of `edit_view` next to the version of its file on disk
+    fn compare_with_saved(&self, edit_view: &Rc<EditView>) {
+        let file_name = match edit_view.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Please save the document before comparing it with the saved version."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let saved = match std::fs::read_to_string(&file_name) {
+            Ok(saved) => saved,
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(
+                    format!("{} “{}”: {}", gettext("Couldn’t read"), file_name, e),
+                    false,
+                ));
+                return;
+            }
+        };
+
+        let name = MainWin::view_name(edit_view);
+        edit_view.with_text(
+            clone!(@weak self as main_win => @default-return (), move |text| {
+                let text = match text {
+                    Ok(text) => text,
+                    Err(e) => {
+                        ErrorDialog::new(ErrorMsg::new(e, false));
+                        return;
+                    }
+                };
+                main_win.show_diff(
+                    &freplace(gettext("Changes in {}"), &[&name]),
+                    &freplace(gettext("{} (saved)"), &[&name]),
+                    &saved,
+                    &freplace(gettext("{} (unsaved)"), &[&name]),
+                    &text,
+                );
+            }),
+        );
+    }
+
+    /// Ask the user for another open document and show it next to the current one
+    fn compare_with_tab(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let current_page = self.notebook.get_current_page();
+        let others: Vec<Rc<EditView>> = (0..self.notebook.get_n_pages())
+            .filter(|n| Some(*n) != current_page)
+            .filter_map(|n| self.notebook.get_nth_page(Some(n)))
+            .filter_map(|w| self.w_to_ev.borrow().get(&w).cloned())
+            .collect();
+        if others.is_empty() {
+            ErrorDialog::new(ErrorMsg::new(
+                gettext("Please open another document to compare with."),
+                false,
+            ));
+            return;
+        }
+
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &freplace(
+                gettext("Compare “{}” with"),
+                &[&MainWin::view_name(&edit_view)],
+            ),
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Compare"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let combo_box = ComboBoxText::new();
+        for other in &others {
+            combo_box.append_text(&MainWin::view_name(other));
+        }
+        combo_box.set_active(Some(0));
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&combo_box, false, true, 0);
+        }
+        combo_box.show();
+
+        self.saving.replace(true);
+        let response = dialog.run();
+        let active = combo_box.get_active();
+        dialog.destroy();
+        self.saving.replace(false);
+
+        let other = match active.and_then(|n| others.get(n as usize)) {
+            Some(other) if response == ResponseType::Ok => other.clone(),
+            _ => return,
+        };
+
+        let left_name = MainWin::view_name(&edit_view);
+        let right_name = MainWin::view_name(&other);
+        edit_view.with_text(
+            clone!(@weak self as main_win, @weak other => @default-return (), move |left_text| {
+                other.with_text(clone!(@weak main_win => @default-return (), move |right_text| {
+                    match left_text.and_then(|left| right_text.map(|right| (left, right))) {
+                        Ok((left_text, right_text)) => main_win.show_diff(
+                            &format!("{} ↔ {}", left_name, right_name),
+                            &left_name,
+                            &left_text,
+                            &right_name,
+                            &right_text,
+                        ),
+                        Err(e) => {
+                            ErrorDialog::new(ErrorMsg::new(e, false));
+                        }
+                    }
+                }));
+            }),
+        );
+    }
+
+    /// Show the file at `path` on disk next to `head`, its version in HEAD
+    fn compare_with_head(&self, path: &std::path::Path, head: &str) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // The file doesn't exist anymore if it has been deleted
+        let current = std::fs::read_to_string(path).unwrap_or_default();
+        self.show_diff(
+            &freplace(gettext("Changes in {}"), &[&name]),
+            &freplace(gettext("{} (HEAD)"), &[&name]),
+            head,
+            &name,
+            &current,
+        );
+    }
+
+    /// Open a `DiffView` of `left_text` and `right_text` in a new tab
+    fn show_diff(
+        &self,
+        title: &str,
+        left_title: &str,
+        left_text: &str,
+        right_title: &str,
+        right_text: &str,
+    ) {
+        let diff_view = DiffView::new(title, left_title, left_text, right_title, right_text);
+        let widget = diff_view.widget.clone();
+
+        diff_view.top_bar.close_button.connect_clicked(
+            clone!(@weak self as main_win, @weak widget => @default-panic, move |_| {
+                if let Some(page_num) = main_win.notebook.page_num(&widget) {
+                    main_win.notebook.remove_page(Some(page_num));
+                }
+                if main_win.notebook.get_n_pages() < 2 {
+                    main_win.notebook.set_show_tabs(false);
+                }
+            }),
+        );
+
+        let page_num = self
+            .notebook
+            .append_page(&widget, Some(&diff_view.top_bar.event_box));
+        self.notebook.set_tab_reorderable(&widget, true);
+        self.notebook.set_current_page(Some(page_num));
+        if self.notebook.get_n_pages() > 1 {
+            self.notebook.set_show_tabs(true);
+        }
+    }
+}
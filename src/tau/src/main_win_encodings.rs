@@ -0,0 +1,288 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Opening and saving files in other encodings than UTF-8. Xi works on a decoded copy of them, see
+//! `Transcoded`
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::functions;
+use crate::main_win::{MainWin, MainWinExt};
+use editview::{
+    encoding::{self, Encoding},
+    i18n::freplace,
+    line_ending::LineEndingCount,
+    EditView, EditViewExt,
+};
+use gettextrs::gettext;
+use glib::{clone, MainContext};
+use gtk::prelude::*;
+use gtk::{ButtonsType, ComboBoxText, DialogFlags, MessageDialog, MessageType, ResponseType};
+use log::{debug, error, warn};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A file in another encoding than UTF-8. Xi works on a copy decoded to UTF-8, which is encoded
+/// again when the document is saved.
+pub(crate) struct Transcoded {
+    /// The decoded copy of the file, which xi has opened
+    pub(crate) copy: PathBuf,
+    /// The encoding the file had when it was opened
+    pub(crate) encoding: Encoding,
+}
+
+impl MainWin {
+    /// Save the document of `edit_view` to `file_name` in its encoding. Xi saves the document to
+    /// its UTF-8 copy, so it knows the document has been saved.
+    pub(crate) fn save_encoded(&self, edit_view: &Rc<EditView>, file_name: &str) {
+        let old = edit_view
+            .file_name
+            .borrow()
+            .as_ref()
+            .and_then(|f| self.transcoded.borrow_mut().remove(f));
+        let copy = old.map_or_else(|| functions::transcoded_copy(file_name), |old| old.copy);
+        if let Some(dir) = copy.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                error!("Failed to create dir for decoded copy {:?}: {}", dir, e);
+            }
+        }
+        let encoding = edit_view.encoding();
+        self.transcoded.borrow_mut().insert(
+            file_name.to_string(),
+            Transcoded {
+                copy: copy.clone(),
+                encoding,
+            },
+        );
+
+        let core = self.core.clone();
+        let view_id = edit_view.view_id;
+        let file_name = file_name.to_string();
+        edit_view.with_text(clone!(@weak edit_view => @default-return (), move |text| {
+            let result = text.and_then(|text| encoding.encode(&text)).and_then(|bytes| {
+                std::fs::write(&file_name, bytes).map_err(|e| e.to_string())
+            });
+            match result {
+                Ok(()) => core.save(view_id, &copy.to_string_lossy()),
+                Err(e) => {
+                    edit_view.cancel_save();
+                    ErrorDialog::new(ErrorMsg::new(
+                        format!("{} “{}”: {}", gettext("Couldn’t save"), file_name, e),
+                        false,
+                    ));
+                }
+            }
+        }));
+    }
+
+    /// Decode `file_name` to a UTF-8 copy for xi if it's in another encoding, which is detected
+    /// unless `encoding` is given. Returns the path xi should open.
+    pub fn transcode(&self, file_name: &str, encoding: Option<Encoding>) -> Result<String, String> {
+        let bytes = match std::fs::read(file_name) {
+            Ok(bytes) => bytes,
+            // Xi tells the user why the file can't be opened, or creates it if it doesn't exist
+            Err(_) => return Ok(file_name.to_string()),
+        };
+        let encoding = encoding.unwrap_or_else(|| encoding::detect(&bytes));
+        let text = encoding
+            .decode(&bytes)
+            .map_err(|e| format!("{} “{}”: {}", gettext("Couldn’t open"), file_name, e))?;
+        self.remove_transcoded(file_name);
+        if encoding.is_utf8() {
+            return Ok(file_name.to_string());
+        }
+
+        debug!("Decoding '{}' from {}", file_name, encoding);
+        let copy = functions::transcoded_copy(file_name);
+        copy.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&copy, text))
+            .map_err(|e| format!("{} “{}”: {}", gettext("Couldn’t open"), file_name, e))?;
+        let xi_path = copy.to_string_lossy().into_owned();
+        self.transcoded
+            .borrow_mut()
+            .insert(file_name.to_string(), Transcoded { copy, encoding });
+        Ok(xi_path)
+    }
+
+    /// The path of the file xi works on for `file_name`, its UTF-8 copy if it's in another encoding
+    pub(crate) fn xi_path(&self, file_name: &str) -> String {
+        match self.transcoded.borrow().get(file_name) {
+            Some(transcoded) => transcoded.copy.to_string_lossy().into_owned(),
+            None => file_name.to_string(),
+        }
+    }
+
+    /// Delete the UTF-8 copy of `file_name`, once it's not open anymore
+    pub(crate) fn remove_transcoded(&self, file_name: &str) {
+        if let Some(transcoded) = self.transcoded.borrow_mut().remove(file_name) {
+            if let Err(e) = std::fs::remove_file(&transcoded.copy) {
+                warn!("Failed to remove decoded copy {:?}: {}", transcoded.copy, e);
+            }
+        }
+    }
+
+    /// Warn in the statusbar of `edit_view` if its file mixes line endings. Xi only detects the
+    /// line ending used most often. The file is read on a background thread.
+    pub(crate) fn check_line_endings(edit_view: &Rc<EditView>, file_name: &str) {
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+        receiver.attach(
+            None,
+            clone!(@weak edit_view => @default-return Continue(false), move |mixed| {
+                edit_view.set_mixed_line_endings(mixed);
+                Continue(false)
+            }),
+        );
+
+        let encoding = edit_view.encoding();
+        let file_name = file_name.to_string();
+        std::thread::spawn(move || {
+            let text = match std::fs::read(&file_name)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| encoding.decode(&bytes))
+            {
+                Ok(text) => text,
+                Err(_) => return,
+            };
+            let count = LineEndingCount::new(&text);
+            if count.is_mixed() {
+                if let Some(dominant) = count.dominant() {
+                    warn!("'{}' mixes line endings, mostly {}", file_name, dominant);
+                }
+            }
+            sender.send(count.is_mixed()).ok();
+        });
+    }
+
+    /// Ask the user for an encoding, starting with `current`. `warning` is shown below the
+    /// `title`, e.g. to tell the user about losing unsaved changes.
+    pub(crate) fn choose_encoding(
+        &self,
+        title: &str,
+        warning: Option<&str>,
+        current: Encoding,
+    ) -> Option<Encoding> {
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            title,
+        );
+        dialog.set_property_secondary_text(warning);
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Select"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let combo_box = ComboBoxText::new();
+        for encoding in &Encoding::ALL {
+            combo_box.append(Some(encoding.name()), encoding.name());
+        }
+        combo_box.set_active_id(Some(current.name()));
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&combo_box, false, true, 0);
+        }
+        combo_box.show();
+
+        self.saving.replace(true);
+        let response = dialog.run();
+        let active = combo_box.get_active_id();
+        dialog.destroy();
+        self.saving.replace(false);
+
+        match active {
+            Some(name) if response == ResponseType::Ok => Encoding::from_name(&name),
+            _ => None,
+        }
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for documents in other encodings
+pub trait MainWinEncodingsExt {
+    fn reopen_with_encoding(&self);
+
+    fn save_with_encoding(&self);
+}
+
+impl MainWinEncodingsExt for Rc<MainWin> {
+    /// Ask the user for an encoding and load the current document's file again, decoded with it
+    fn reopen_with_encoding(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let file_name = match edit_view.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Only saved documents can be reopened with another encoding."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let warning = if *edit_view.pristine.borrow() {
+            None
+        } else {
+            Some(gettext("The unsaved changes will be lost."))
+        };
+        let encoding = match self.choose_encoding(
+            &freplace(
+                gettext("Reopen “{}” with Encoding"),
+                &[&MainWin::view_name(&edit_view)],
+            ),
+            warning.as_deref(),
+            edit_view.encoding(),
+        ) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        // Don't close the document if its file can't be decoded
+        let decoded = std::fs::read(&file_name)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| encoding.decode(&bytes));
+        if let Err(e) = decoded {
+            ErrorDialog::new(ErrorMsg::new(
+                format!("{} “{}”: {}", gettext("Couldn’t open"), file_name, e),
+                false,
+            ));
+            return;
+        }
+        self.forced_encodings
+            .borrow_mut()
+            .insert(file_name, encoding);
+        self.reload_view(&edit_view);
+    }
+
+    /// Ask the user for an encoding and save the current document in it
+    fn save_with_encoding(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let encoding = match self.choose_encoding(
+            &freplace(
+                gettext("Save “{}” with Encoding"),
+                &[&MainWin::view_name(&edit_view)],
+            ),
+            None,
+            edit_view.encoding(),
+        ) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        match self.split_of(edit_view.view_id) {
+            Some(split) => {
+                split.primary.set_encoding(encoding);
+                split.secondary.set_encoding(encoding);
+            }
+            None => edit_view.set_encoding(encoding),
+        }
+        let file_name = edit_view.file_name.borrow().clone();
+        match file_name {
+            Some(file_name) => self.save_view(edit_view.view_id, &file_name),
+            None => self.save_as(&edit_view),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Connects the `FindInFiles` panel of the sidebar to the documents of the `MainWin`
+
+use crate::find_in_files::{replace_in_file, replacement_edits, SearchOptions};
+use crate::main_win::MainWin;
+use editview::EditViewExt;
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use log::{debug, warn};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+impl MainWin {
+    /// Show the `FindInFiles` panel in the sidebar and focus its search entry
+    pub(crate) fn show_find_in_files(&self) {
+        let sidebar_box: gtk::Box = self.builder.get_object("sidebar").unwrap();
+        if !sidebar_box.get_visible() {
+            self.toggle_sidebar();
+        }
+        self.sidebar_stack.set_visible_child_name("search");
+        self.find_in_files.start();
+    }
+
+    /// Replace the matches of `options` in `files`. Files which are open are changed in their
+    /// `EditView`, so unsaved changes are kept and the replacement can be undone; all other files
+    /// are changed on disk. Returns the errors which occurred.
+    pub(crate) fn replace_in_files(
+        &self,
+        files: &[PathBuf],
+        options: &SearchOptions,
+        replacement: &str,
+    ) -> Vec<String> {
+        let regex = match options.to_regex() {
+            Ok(regex) => regex,
+            Err(e) => return vec![e.to_string()],
+        };
+
+        let mut errors = Vec::new();
+        for file in files {
+            let file_name = file.to_string_lossy().into_owned();
+            let open_view = self
+                .views
+                .borrow()
+                .values()
+                .find(|ev| ev.file_name.borrow().as_ref() == Some(&file_name))
+                .cloned();
+
+            if let Some(edit_view) = open_view {
+                // Expand captures the same way as on disk, without touching the view's own search
+                debug!("Replacing in open view {}", edit_view.view_id);
+                let regex = regex.clone();
+                let replacement = replacement.to_string();
+                let is_regex = options.is_regex;
+                let find_in_files = Rc::downgrade(&self.find_in_files);
+                edit_view.with_text(clone!(@weak edit_view => @default-return (), move |text| {
+                    match (text, find_in_files.upgrade()) {
+                        (Ok(text), _) => {
+                            let edits = replacement_edits(&regex, &text, &replacement, is_regex);
+                            edit_view.replace_ranges(&text, &edits);
+                        }
+                        (Err(e), Some(find_in_files)) => find_in_files.replace_failed(format!(
+                            "{} “{}”: {}",
+                            gettext("Couldn’t replace in"),
+                            file_name,
+                            e
+                        )),
+                        (Err(e), None) => warn!("Failed to replace in {}: {}", file_name, e),
+                    }
+                }));
+            } else if let Err(e) = replace_in_file(file, &regex, replacement, options.is_regex) {
+                errors.push(format!(
+                    "{} “{}”: {}",
+                    gettext("Couldn’t replace in"),
+                    file.display(),
+                    e
+                ));
+            }
+        }
+        errors
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Adds the saved files to the local history and lets the user restore or compare its snapshots,
+//! see `history`
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::functions;
+use crate::history;
+use crate::main_win::MainWin;
+use crate::main_win_diff::MainWinDiffExt;
+use chrono::{Local, TimeZone};
+use editview::{i18n::freplace, EditView, EditViewExt};
+use gettextrs::gettext;
+use gio::prelude::*;
+use gio::Settings;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{ButtonsType, DialogFlags, MessageDialog, MessageType, ResponseType};
+use log::error;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+impl MainWin {
+    /// How many snapshots the local history keeps for how long, as configured in the `GSchema`
+    pub(crate) fn history_retention(gschema: &Settings) -> history::Retention {
+        let max_snapshots: u32 = gschema.get("local-history-max-snapshots");
+        let max_days: u32 = gschema.get("local-history-max-days");
+        history::Retention {
+            max_snapshots: max_snapshots as usize,
+            max_days: u64::from(max_days),
+        }
+    }
+
+    /// Add `file_name` to the local history once `edit_view` has been saved to it, so the snapshot
+    /// is the text which has actually been written. The file is read on a background thread.
+    pub(crate) fn add_to_history(&self, edit_view: &Rc<EditView>, file_name: &str) {
+        let gschema = self.state.borrow().settings.gschema.clone();
+        let enabled: bool = gschema.get("local-history");
+        let dir = match functions::history_dir() {
+            Some(dir) if enabled => dir,
+            _ => return,
+        };
+        let retention = MainWin::history_retention(&gschema);
+        let file = PathBuf::from(file_name);
+        let encoding = edit_view.encoding();
+        edit_view.connect_saved_once(move || {
+            std::thread::spawn(move || {
+                let result = std::fs::read(&file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|bytes| encoding.decode(&bytes))
+                    .and_then(|text| history::add_snapshot(&dir, &file, &text, retention));
+                if let Err(e) = result {
+                    error!("Failed to add a snapshot to the local history: {}", e);
+                }
+            });
+        });
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for the local history
+pub trait MainWinHistoryExt {
+    fn local_history(&self);
+}
+
+impl MainWinHistoryExt for Rc<MainWin> {
+    /// Show the snapshots the local history has of the current document, so the user can compare
+    /// one with the document or restore it
+    fn local_history(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let name = MainWin::view_name(&edit_view);
+        let snapshots = match (
+            edit_view.file_name.borrow().as_ref(),
+            functions::history_dir(),
+        ) {
+            (Some(file_name), Some(dir)) => history::snapshots(&dir, Path::new(file_name)),
+            _ => Vec::new(),
+        };
+        if snapshots.is_empty() {
+            ErrorDialog::new(ErrorMsg::new(
+                freplace(
+                    gettext("There’s no local history of “{}” yet. It’s added to when the file is saved."),
+                    &[&name],
+                ),
+                false,
+            ));
+            return;
+        }
+
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &freplace(gettext("Local History of “{}”"), &[&name]),
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Restore"), ResponseType::Apply);
+        dialog.add_button(&gettext("Compare"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let times: Vec<String> = snapshots
+            .iter()
+            .map(|snapshot| {
+                Local
+                    .timestamp_millis(snapshot.saved_at as i64)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .collect();
+        let list_box = gtk::ListBox::new();
+        for time in &times {
+            let label = gtk::Label::new(Some(time));
+            label.set_xalign(0.0);
+            label.set_margin_start(6);
+            label.set_margin_end(6);
+            label.set_margin_top(3);
+            label.set_margin_bottom(3);
+            list_box.add(&label);
+        }
+        list_box.select_row(list_box.get_row_at_index(0).as_ref());
+        let scrolled_window =
+            gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        scrolled_window.set_min_content_height(250);
+        scrolled_window.add(&list_box);
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&scrolled_window, true, true, 0);
+        }
+        scrolled_window.show_all();
+
+        self.saving.replace(true);
+        let response = dialog.run();
+        let selected = list_box.get_selected_row().map(|row| row.get_index());
+        dialog.destroy();
+        self.saving.replace(false);
+
+        let (snapshot, saved_at) = match selected.map(|i| i as usize) {
+            Some(i) if i < snapshots.len() => (&snapshots[i], times[i].clone()),
+            _ => return,
+        };
+        let text = match snapshot.read() {
+            Ok(text) => text,
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(
+                    format!("{}: {}", gettext("Couldn’t read the snapshot"), e),
+                    false,
+                ));
+                return;
+            }
+        };
+
+        match response {
+            ResponseType::Ok => edit_view.with_text(
+                clone!(@weak self as main_win => @default-return (), move |current| {
+                    let current = match current {
+                        Ok(current) => current,
+                        Err(e) => {
+                            ErrorDialog::new(ErrorMsg::new(e, false));
+                            return;
+                        }
+                    };
+                    main_win.show_diff(
+                        &freplace(gettext("Changes in {} since {}"), &[&name, &saved_at]),
+                        &freplace(gettext("{} ({})"), &[&name, &saved_at]),
+                        &text,
+                        &freplace(gettext("{} (current)"), &[&name]),
+                        &current,
+                    );
+                }),
+            ),
+            // The restored text can be undone and only replaces the file once it's saved
+            ResponseType::Apply => edit_view.replace_text(&text),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Backs up the unsaved documents, so their changes survive a crash or quitting with hot exit,
+//! see `recovery`
+
+use crate::functions;
+use crate::main_win::{MainWin, MainWinExt};
+use crate::recovery::{self, Backup};
+use editview::{EditView, EditViewExt};
+use gio::prelude::*;
+use glib::{clone, MainContext};
+use gtk::prelude::*;
+use log::{debug, error, info};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use tau_rpc::ViewId;
+
+/// How long to wait after the last change before the unsaved documents are backed up
+const BACKUP_DELAY_MS: u32 = 2000;
+/// How long to wait for xi to send the text of the documents when quitting with hot exit
+const HOT_EXIT_TIMEOUT_MS: u32 = 5000;
+
+impl MainWin {
+    /// Whether unsaved changes are kept when quitting instead of asking to save them
+    pub(crate) fn hot_exit_enabled(&self) -> bool {
+        self.state.borrow().settings.gschema.get("hot-exit")
+    }
+
+    /// The `EditView`s of all open documents. Of split pages only the primary pane is included,
+    /// since both panes show the same document.
+    pub(crate) fn documents(&self) -> Vec<Rc<EditView>> {
+        self.views
+            .borrow()
+            .values()
+            .filter(|ev| {
+                self.split_of(ev.view_id)
+                    .map_or(true, |split| split.primary.view_id == ev.view_id)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Delete the backup of the document of the view with `view_id`, e.g. after it has been saved
+    pub(crate) fn remove_backup(&self, view_id: ViewId) {
+        if let Some(id) = self.backups.borrow_mut().remove(&view_id) {
+            if let Some(dir) = functions::recovery_dir() {
+                recovery::remove(&dir, &id);
+            }
+        }
+    }
+
+    /// Put the unsaved changes of `backup` back into `edit_view`. The backup is kept until the
+    /// document is saved or closed.
+    pub(crate) fn apply_backup(&self, edit_view: &EditView, id: String, backup: Backup) {
+        debug!(
+            "Restoring backup '{}' into EditView {}",
+            id, edit_view.view_id
+        );
+        if let Some(language) = &backup.language {
+            edit_view.set_language(language);
+        }
+        edit_view.replace_text(&backup.text);
+        self.backups.borrow_mut().insert(edit_view.view_id, id);
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for backing up unsaved documents
+pub trait MainWinHotExitExt {
+    fn schedule_backup(&self);
+
+    fn write_backups<F: FnOnce(Result<(), String>) + 'static>(&self, done: F);
+
+    fn hot_exit(&self);
+
+    fn restore_backups(&self, opened: &[String]) -> bool;
+
+    fn open_backup(&self, id: String, backup: Backup);
+}
+
+impl MainWinHotExitExt for Rc<MainWin> {
+    /// Back up the unsaved documents once they haven't changed for a bit, so their changes survive
+    /// a crash
+    fn schedule_backup(&self) {
+        if !self.hot_exit_enabled() || self.hot_exiting.get() {
+            return;
+        }
+        if let Some(id) = self.backup_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        let id = glib::timeout_add_local(
+            BACKUP_DELAY_MS,
+            clone!(@weak self as main_win => @default-return Continue(false), move || {
+                main_win.backup_timeout.replace(None);
+                main_win.write_backups(|result| {
+                    if let Err(e) = result {
+                        error!("{}", e);
+                    }
+                });
+                Continue(false)
+            }),
+        );
+        self.backup_timeout.replace(Some(id));
+    }
+
+    /// Write a backup of each document with unsaved changes to `functions::recovery_dir` and
+    /// delete those of documents which have been saved since. `done` is called once all backups
+    /// have been written, or with the reason why some of them couldn't be.
+    fn write_backups<F: FnOnce(Result<(), String>) + 'static>(&self, done: F) {
+        if let Some(id) = self.backup_timeout.borrow_mut().take() {
+            glib::source_remove(id);
+        }
+        let dir = match functions::recovery_dir() {
+            Some(dir) => dir,
+            None => {
+                done(Err(
+                    "Couldn't get the data dir to back up unsaved documents to".to_string(),
+                ));
+                return;
+            }
+        };
+
+        let documents = self.documents();
+        let done = Rc::new(RefCell::new(Some(done)));
+        // Xi might not send us the text of a document, so don't wait for it forever
+        let timeout = glib::timeout_add_local(
+            HOT_EXIT_TIMEOUT_MS,
+            clone!(@strong done => move || {
+                if let Some(done) = done.borrow_mut().take() {
+                    done(Err(
+                        "Timed out waiting for the text of the unsaved documents".to_string()
+                    ));
+                }
+                Continue(false)
+            }),
+        );
+        let timeout = RefCell::new(Some(timeout));
+        let remaining = Cell::new(documents.len() + 1);
+        let failed = RefCell::new(None);
+        let finish = Rc::new(move |result: Result<(), String>| {
+            if let Err(e) = result {
+                failed.replace(Some(e));
+            }
+            remaining.set(remaining.get() - 1);
+            if remaining.get() > 0 {
+                return;
+            }
+            if let Some(done) = done.borrow_mut().take() {
+                if let Some(id) = timeout.borrow_mut().take() {
+                    glib::source_remove(id);
+                }
+                done(failed.borrow_mut().take().map_or(Ok(()), Err));
+            }
+        });
+
+        for edit_view in documents {
+            if *edit_view.pristine.borrow() {
+                self.remove_backup(edit_view.view_id);
+                finish(Ok(()));
+                continue;
+            }
+            let id = self
+                .backups
+                .borrow_mut()
+                .entry(edit_view.view_id)
+                .or_insert_with(|| recovery::backup_id(&edit_view.view_id.to_string()))
+                .clone();
+            let path = edit_view.file_name.borrow().clone();
+            let language = edit_view.language_override();
+            let dir = dir.clone();
+            let finish = finish.clone();
+            edit_view.with_text(move |text| {
+                finish(text.and_then(|text| {
+                    recovery::write(&dir, &id, &Backup::new(path, language, text))
+                        .map_err(|e| format!("Failed to back up unsaved document: {}", e))
+                }));
+            });
+        }
+        finish(Ok(()));
+    }
+
+    /// Close the window without asking about unsaved changes. They're backed up instead and
+    /// restored on the next start-up. If they can't be, the user is asked about them after all.
+    fn hot_exit(&self) {
+        self.hot_exiting.set(true);
+        self.properties.borrow().save();
+        self.save_terminal_session();
+        self.write_backups(
+            clone!(@weak self as main_win => @default-return (), move |result| {
+                if let Err(e) = result {
+                    // Never lose the changes the backups were supposed to keep
+                    error!("{}, asking about the unsaved changes instead", e);
+                    main_win.hot_exiting.set(false);
+                    main_win.close_asking();
+                    return;
+                }
+                functions::remove_transcoded_dir();
+                main_win.window.destroy();
+            }),
+        );
+    }
+
+    /// Restore the unsaved documents of the last run, e.g. after a hot exit or a crash. The files
+    /// in `opened` are being opened already, e.g. with the session, so their changes are
+    /// restored once they have been. Returns whether there were any documents to restore.
+    fn restore_backups(&self, opened: &[String]) -> bool {
+        if self.backups_restored.replace(true) {
+            return false;
+        }
+        let backups = match functions::recovery_dir() {
+            Some(dir) => recovery::orphaned_backups(&dir),
+            None => return false,
+        };
+        if backups.is_empty() {
+            return false;
+        }
+        info!("Restoring {} unsaved documents", backups.len());
+
+        for (id, backup) in backups {
+            let path = match backup.path.clone() {
+                Some(path) => path,
+                None => {
+                    self.open_backup(id, backup);
+                    continue;
+                }
+            };
+            let open_view = self
+                .views
+                .borrow()
+                .values()
+                .find(|ev| ev.file_name.borrow().as_ref() == Some(&path))
+                .cloned();
+            if let Some(edit_view) = open_view {
+                self.apply_backup(&edit_view, id, backup);
+                continue;
+            }
+            self.pending_backups
+                .borrow_mut()
+                .insert(path.clone(), (id, backup));
+            if !opened.contains(&path) {
+                self.open_file(Path::new(&path));
+            }
+        }
+        true
+    }
+
+    /// Open a new untitled document with the text of `backup`
+    fn open_backup(&self, id: String, backup: Backup) {
+        let (tx, rx) = MainContext::channel(glib::source::PRIORITY_HIGH);
+
+        rx.attach(
+            None,
+            clone!(@weak self as main_win => @default-return Continue(false), move |res: Result<Value, Value>| {
+                match res {
+                    Ok(val) => {
+                        let view_id: ViewId = serde_json::from_value(val).unwrap();
+                        main_win.new_view(Ok((view_id, None)));
+                        let edit_view = main_win.views.borrow().get(&view_id).cloned();
+                        if let Some(edit_view) = edit_view {
+                            main_win.apply_backup(&edit_view, id.clone(), backup.clone());
+                        }
+                    }
+                    Err(e) => main_win.new_view(Err(serde_json::from_value(e).unwrap())),
+                }
+                Continue(false)
+            }),
+        );
+
+        self.core.new_view(None, move |res| tx.send(res).unwrap());
+    }
+}
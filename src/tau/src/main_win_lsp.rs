@@ -0,0 +1,234 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The actions of the `MainWin` which language servers carry out, like going to the definition of
+//! a symbol or renaming it, see `language_server`
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::lsp;
+use crate::main_win::{MainWin, MainWinExt};
+use editview::{completion, i18n::freplace, EditView, EditViewExt};
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{ButtonsType, DialogFlags, MessageDialog, MessageType, ResponseType};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+impl MainWin {
+    /// The word the last cursor of `edit_view` is in, e.g. the symbol the user wants to rename
+    pub(crate) fn word_at_cursor(edit_view: &EditView) -> String {
+        let (row, col) = match edit_view.cursor_positions().last() {
+            Some(position) => *position,
+            None => return String::new(),
+        };
+        let text = edit_view.line_text(row).unwrap_or_default();
+        let col = (col as usize).min(text.len());
+        let before = completion::word_prefix(&text[..col]);
+        let after: String = text[col..]
+            .chars()
+            .take_while(|c| completion::is_word_char(*c))
+            .collect();
+        format!("{}{}", before, after)
+    }
+
+    /// Apply the edits of a language server, e.g. to rename a symbol. Open documents are edited
+    /// in their views, so the user can undo the edits, other files are changed on disk.
+    pub(crate) fn apply_workspace_edit(&self, files: Vec<(PathBuf, Vec<lsp::TextEdit>)>) {
+        let mut errors = Vec::new();
+        for (path, edits) in files {
+            let file_name = path.to_string_lossy().into_owned();
+            let open_view = self
+                .views
+                .borrow()
+                .values()
+                .find(|ev| ev.file_name.borrow().as_ref() == Some(&file_name))
+                .cloned();
+            if let Some(edit_view) = open_view {
+                edit_view.with_text(clone!(@weak edit_view => @default-return (), move |text| {
+                    match text {
+                        Ok(text) => {
+                            edit_view.replace_ranges(&text, &lsp::convert_edits(&text, &edits));
+                        }
+                        Err(e) => {
+                            ErrorDialog::new(ErrorMsg::new(format!("{}: {}", file_name, e), false));
+                        }
+                    }
+                }));
+                continue;
+            }
+
+            let result = std::fs::read_to_string(&path)
+                .and_then(|text| std::fs::write(&path, lsp::apply_edits(&text, &edits)));
+            if let Err(e) = result {
+                errors.push(format!("{}: {}", file_name, e));
+            }
+        }
+
+        if !errors.is_empty() {
+            let msg = format!(
+                "{}\n{}",
+                gettext("Couldn’t change all files"),
+                errors.join("\n")
+            );
+            ErrorDialog::new(ErrorMsg::new(msg, false));
+        }
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for the actions of language servers
+pub trait MainWinLspExt {
+    fn go_to_definition(&self);
+
+    fn find_references(&self);
+
+    fn rename_symbol(&self);
+}
+
+impl MainWinLspExt for Rc<MainWin> {
+    /// Move the cursor to the definition of the symbol at the cursor, as its language server
+    /// knows it
+    fn go_to_definition(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let symbol = MainWin::word_at_cursor(&edit_view);
+        let requested = self.language_servers.request_at_cursor(
+            &edit_view,
+            "definitionProvider",
+            "textDocument/definition",
+            json!({}),
+            clone!(@weak self as main_win => @default-return (), move |result: Result<Value, String>| {
+                let location = result
+                    .ok()
+                    .and_then(|r| lsp::parse_locations(&r).into_iter().next());
+                match location {
+                    Some(location) => {
+                        let ((line, col), _) = main_win.language_servers.resolve(&location);
+                        main_win.open_file_at(&location.path, line, col);
+                    }
+                    None => {
+                        let msg =
+                            freplace(gettext("Couldn’t find the definition of “{}”"), &[&symbol]);
+                        ErrorDialog::new(ErrorMsg::new(msg, false));
+                    }
+                }
+            }),
+        );
+        if !requested {
+            ErrorDialog::new(ErrorMsg::new(
+                gettext("No language server can find definitions in this document."),
+                false,
+            ));
+        }
+    }
+
+    /// List the references to the symbol at the cursor in the search panel of the sidebar
+    fn find_references(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let symbol = MainWin::word_at_cursor(&edit_view);
+        let requested = self.language_servers.request_at_cursor(
+            &edit_view,
+            "referencesProvider",
+            "textDocument/references",
+            json!({ "context": { "includeDeclaration": true } }),
+            clone!(@weak self as main_win => @default-return (), move |result: Result<Value, String>| {
+                let mut files: BTreeMap<PathBuf, Vec<lsp::LspRange>> = BTreeMap::new();
+                for location in result.map(|r| lsp::parse_locations(&r)).unwrap_or_default() {
+                    files.entry(location.path).or_default().push(location.range);
+                }
+                let results = files
+                    .into_iter()
+                    .map(|(path, ranges)| {
+                        let lines = main_win.language_servers.matching_lines(&path, &ranges);
+                        (path, lines)
+                    })
+                    .collect();
+
+                let sidebar_box: gtk::Box = main_win.builder.get_object("sidebar").unwrap();
+                if !sidebar_box.get_visible() {
+                    main_win.toggle_sidebar();
+                }
+                main_win.sidebar_stack.set_visible_child_name("search");
+                main_win.find_in_files.show_references(&symbol, results);
+            }),
+        );
+        if !requested {
+            ErrorDialog::new(ErrorMsg::new(
+                gettext("No language server can find references in this document."),
+                false,
+            ));
+        }
+    }
+
+    /// Ask the user for a new name of the symbol at the cursor and let its language server
+    /// rename it in all files
+    fn rename_symbol(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let symbol = MainWin::word_at_cursor(&edit_view);
+        if symbol.is_empty() {
+            return;
+        }
+
+        let dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &freplace(gettext("Rename “{}” to"), &[&symbol]),
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Rename"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let entry = gtk::Entry::new();
+        entry.set_text(&symbol);
+        entry.set_activates_default(true);
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&entry, false, true, 0);
+        }
+        entry.show();
+
+        self.saving.replace(true);
+        let response = dialog.run();
+        let new_name = entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        dialog.destroy();
+        self.saving.replace(false);
+        if response != ResponseType::Ok || new_name.is_empty() || new_name == symbol {
+            return;
+        }
+
+        let requested = self.language_servers.request_at_cursor(
+            &edit_view,
+            "renameProvider",
+            "textDocument/rename",
+            json!({ "newName": new_name }),
+            clone!(@weak self as main_win => @default-return (), move |result: Result<Value, String>| {
+                match result {
+                    Ok(result) => {
+                        main_win.apply_workspace_edit(lsp::parse_workspace_edit(&result));
+                    }
+                    Err(e) => {
+                        let msg = freplace(gettext("Couldn’t rename “{}”"), &[&symbol]);
+                        ErrorDialog::new(ErrorMsg::new(format!("{}: {}", msg, e), false));
+                    }
+                }
+            }),
+        );
+        if !requested {
+            ErrorDialog::new(ErrorMsg::new(
+                gettext("No language server can rename symbols in this document."),
+                false,
+            ));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Splitting a page of the `MainWin`'s notebook into two `EditView`s of the same document,
+//! see `Split`
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::main_win::{MainWin, MainWinExt};
+use crate::session::SessionHandler;
+use editview::EditView;
+use gettextrs::gettext;
+use glib::{clone, MainContext};
+use gtk::prelude::*;
+use gtk::{Orientation, Paned, Widget};
+use log::trace;
+use std::cell::Cell;
+use std::rc::Rc;
+use tau_rpc::ViewId;
+
+/// Two `EditView`s of the same document, shown next to each other in one page of the `GtkNotebook`.
+/// Both are separate views in xi, so each of them has its own cursors and scroll position.
+pub(crate) struct Split {
+    /// The `GtkPaned` holding both `EditView`s. This is the page of the `GtkNotebook`.
+    pub(crate) paned: Paned,
+    /// The `EditView` which was split, its `TopBar` is the label of the tab
+    pub(crate) primary: Rc<EditView>,
+    /// The `EditView` which was added by splitting
+    pub(crate) secondary: Rc<EditView>,
+    /// Whether the `secondary` `EditView` had the focus last
+    pub(crate) secondary_focused: Cell<bool>,
+}
+
+impl Split {
+    /// Get the `EditView` which had the focus last
+    pub(crate) fn focused(&self) -> &Rc<EditView> {
+        if self.secondary_focused.get() {
+            &self.secondary
+        } else {
+            &self.primary
+        }
+    }
+}
+
+impl MainWin {
+    /// Get the `Split` the `EditView` with the `ViewId` is part of, if any
+    pub(crate) fn split_of(&self, view_id: ViewId) -> Option<Rc<Split>> {
+        let w = self.view_id_to_w.borrow().get(&view_id).cloned()?;
+        self.splits.borrow().get(&w).cloned()
+    }
+
+    /// Close the pane of `split` which shows `closed`. The other `EditView` takes over the whole
+    /// page of the `GtkNotebook` again.
+    pub(crate) fn remove_split_pane(&self, split: &Split, closed: &Rc<EditView>) {
+        let keep = if closed.view_id == split.primary.view_id {
+            &split.secondary
+        } else {
+            &split.primary
+        };
+        let page: Widget = split.paned.clone().upcast();
+        let keep_w: Widget = keep.root_widget.clone().upcast();
+
+        self.splits.borrow_mut().remove(&page);
+        self.w_to_ev.borrow_mut().remove(&page);
+        self.w_to_ev
+            .borrow_mut()
+            .insert(keep_w.clone(), keep.clone());
+        self.view_id_to_w
+            .borrow_mut()
+            .insert(keep.view_id, keep_w.clone());
+        self.view_id_to_w.borrow_mut().remove(&closed.view_id);
+        self.views.borrow_mut().remove(&closed.view_id);
+        self.plugin_cmds.borrow_mut().remove(&closed.view_id);
+        self.language_servers.detach(closed);
+
+        let page_num = self.notebook.page_num(&page);
+        split.paned.remove(&split.primary.root_widget);
+        split.paned.remove(&split.secondary.root_widget);
+        self.notebook.remove_page(page_num);
+        let page_num = self
+            .notebook
+            .insert_page(&keep_w, Some(&keep.top_bar.event_box), page_num);
+        self.notebook.set_tab_reorderable(&keep_w, true);
+        self.notebook.set_current_page(Some(page_num));
+        keep.view_item.ev_scrolled_window.grab_focus();
+
+        self.core.close_view(closed.view_id);
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for splitting pages
+pub trait MainWinSplitsExt {
+    fn split(&self, orientation: Orientation);
+
+    fn split_view(&self, edit_view: &Rc<EditView>, orientation: Orientation);
+
+    fn split_view_response(
+        &self,
+        page: &Widget,
+        orientation: Orientation,
+        view_id: ViewId,
+        file_name: String,
+    );
+
+    fn unsplit(&self, edit_view: &Rc<EditView>);
+}
+
+impl MainWinSplitsExt for Rc<MainWin> {
+    fn split(&self, orientation: Orientation) {
+        if let Some(edit_view) = self.get_current_edit_view() {
+            self.split_view(&edit_view, orientation);
+        }
+    }
+
+    fn split_view(&self, edit_view: &Rc<EditView>, orientation: Orientation) {
+        trace!("Splitting EditView {}", edit_view.view_id);
+
+        if let Some(split) = self.split_of(edit_view.view_id) {
+            // Only change the orientation if the page is split already
+            split.paned.set_orientation(orientation);
+            if let Some(path) = split.primary.file_name.borrow().as_ref() {
+                self.state
+                    .borrow()
+                    .settings
+                    .gschema
+                    .session_add_split(path, orientation);
+            }
+            return;
+        }
+
+        // Xi only shares the buffer between views of the same file, so the document has to be
+        // saved before it can be split
+        let file_name = match edit_view.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Please save the document before splitting it."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let page = match self.view_id_to_w.borrow().get(&edit_view.view_id) {
+            Some(w) => w.clone(),
+            None => return,
+        };
+
+        let (tx, rx) = MainContext::channel(glib::source::PRIORITY_HIGH);
+
+        rx.attach(
+            None,
+            clone!(@weak self as main_win, @strong file_name => @default-panic, move |res| {
+                match res {
+                    Ok(val) => main_win.split_view_response(&page, orientation, serde_json::from_value(val).unwrap(), file_name.clone()),
+                    Err(e) => main_win.new_view(Err(serde_json::from_value(e).unwrap())),
+                }
+                glib::source::Continue(false)
+            }),
+        );
+
+        let xi_path = self.xi_path(&file_name);
+        self.core
+            .new_view(Some(&xi_path), move |res| tx.send(res).unwrap());
+    }
+
+    fn split_view_response(
+        &self,
+        page: &Widget,
+        orientation: Orientation,
+        view_id: ViewId,
+        file_name: String,
+    ) {
+        trace!("Creating split EditView");
+
+        // The tab might have been closed or split in the meantime
+        let primary = match self.w_to_ev.borrow().get(page) {
+            Some(primary) if !self.splits.borrow().contains_key(page) => primary.clone(),
+            _ => {
+                self.core.close_view(view_id);
+                return;
+            }
+        };
+        let page_num = match self.notebook.page_num(page) {
+            Some(page_num) => page_num,
+            None => {
+                self.core.close_view(view_id);
+                return;
+            }
+        };
+
+        let hamburger_button = self.builder.get_object("hamburger_button").unwrap();
+        let secondary = EditView::new(
+            &self.state,
+            self.core.clone(),
+            &hamburger_button,
+            Some(file_name.clone()),
+            view_id,
+            &self.window,
+        );
+        secondary.set_encoding(primary.encoding());
+        secondary.set_mixed_line_endings(primary.mixed_line_endings());
+        // The secondary EditView's TopBar becomes the tab label once the primary pane is closed
+        self.connect_top_bar(&secondary);
+        self.connect_file_changed(&secondary);
+        self.connect_diagnostics_changed(&secondary);
+
+        let paned = Paned::new(orientation);
+        let paned_w: Widget = paned.clone().upcast();
+        let split = Rc::new(Split {
+            paned: paned.clone(),
+            primary: primary.clone(),
+            secondary: secondary.clone(),
+            secondary_focused: Cell::new(true),
+        });
+
+        for (edit_view, is_secondary) in &[(&primary, false), (&secondary, true)] {
+            let is_secondary = *is_secondary;
+            edit_view
+                .view_item
+                .ev_scrolled_window
+                .connect_focus_in_event(
+                    clone!(@weak split => @default-return Inhibit(false), move |_, _| {
+                        split.secondary_focused.set(is_secondary);
+                        Inhibit(false)
+                    }),
+                );
+        }
+
+        self.w_to_ev.borrow_mut().remove(page);
+        self.w_to_ev
+            .borrow_mut()
+            .insert(paned_w.clone(), primary.clone());
+        self.view_id_to_w
+            .borrow_mut()
+            .insert(primary.view_id, paned_w.clone());
+        self.view_id_to_w
+            .borrow_mut()
+            .insert(view_id, paned_w.clone());
+        self.splits.borrow_mut().insert(paned_w.clone(), split);
+        self.views.borrow_mut().insert(view_id, secondary.clone());
+
+        self.notebook.remove_page(Some(page_num));
+        paned.pack1(&primary.root_widget, true, false);
+        paned.pack2(&secondary.root_widget, true, false);
+        paned.show_all();
+        let page_num =
+            self.notebook
+                .insert_page(&paned, Some(&primary.top_bar.event_box), Some(page_num));
+        self.notebook.set_tab_reorderable(&paned, true);
+        self.notebook.set_current_page(Some(page_num));
+
+        // Give both panes the same amount of space
+        let allocation = self.notebook.get_allocation();
+        paned.set_position(match orientation {
+            Orientation::Vertical => allocation.height / 2,
+            _ => allocation.width / 2,
+        });
+        secondary.view_item.ev_scrolled_window.grab_focus();
+
+        self.state
+            .borrow()
+            .settings
+            .gschema
+            .session_add_split(&file_name, orientation);
+    }
+
+    fn unsplit(&self, edit_view: &Rc<EditView>) {
+        if let Some(split) = self.split_of(edit_view.view_id) {
+            trace!("Closing split EditView {}", edit_view.view_id);
+            self.remove_split_pane(&split, edit_view);
+            if let Some(path) = edit_view.file_name.borrow().as_ref() {
+                self.state
+                    .borrow()
+                    .settings
+                    .gschema
+                    .session_remove_split(path);
+            }
+        }
+    }
+}
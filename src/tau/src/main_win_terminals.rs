@@ -0,0 +1,609 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The terminals below the documents and sending the text of documents to them, see `terminals`
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use crate::functions;
+use crate::main_win::{MainWin, SaveAction};
+use crate::run_command;
+use crate::session::SessionHandler;
+use crate::terminals::{self, Foreground, SessionTerminal};
+use editview::{
+    i18n::{freplace, ni18n_f},
+    EditView,
+};
+use gdk::{enums::key, ModifierType};
+use gettextrs::gettext;
+use gio::prelude::*;
+use glib::{clone, MainContext, SpawnFlags};
+use gtk::prelude::*;
+use gtk::{
+    Builder, Button, ButtonsType, DialogFlags, IconSize, MessageDialog, MessageType, Orientation,
+    ResponseType,
+};
+use log::{debug, warn};
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use vte::{Terminal, TerminalExt};
+
+/// A terminal in the `term_notebook`
+pub(crate) struct TerminalTab {
+    /// The name of the `terminals::Profile` the terminal was started with
+    pub(crate) profile: String,
+    /// The process ID of the terminal's shell
+    pub(crate) pid: i32,
+    /// The label of the terminal's tab, which shows the title the shell sets
+    pub(crate) label: gtk::Label,
+}
+
+impl MainWin {
+    /// The terminal which is shown in the `term_notebook`, if the terminals are shown at all
+    pub(crate) fn current_terminal(&self) -> Option<Terminal> {
+        self.bottom_paned.get_child1()?;
+        self.term_notebook
+            .get_nth_page(self.term_notebook.get_current_page())
+            .and_then(|w| w.downcast::<Terminal>().ok())
+    }
+
+    /// Change the directory of the current terminal to the one of `edit_view`'s file, if the user
+    /// wants the terminal to follow the current file
+    pub(crate) fn follow_file_in_terminal(&self, edit_view: Option<&Rc<EditView>>) {
+        let follow: bool = self
+            .state
+            .borrow()
+            .settings
+            .gschema
+            .get("terminal-follows-file");
+        if !follow {
+            return;
+        }
+        let dir = edit_view.and_then(|ev| {
+            ev.file_name
+                .borrow()
+                .as_ref()
+                .and_then(|f| Path::new(f).parent().map(Path::to_path_buf))
+        });
+        let (term, dir) = match (self.current_terminal(), dir) {
+            (Some(term), Some(dir)) => (term, dir),
+            _ => return,
+        };
+        // Shells which report their directory let us skip needless `cd`s
+        let current = term
+            .get_current_directory_uri()
+            .and_then(|uri| gio::File::new_for_uri(&uri).get_path());
+        if current.as_ref() == Some(&dir) {
+            return;
+        }
+        // Typing into a running program (an editor, a REPL, ...) would corrupt its input, so only
+        // the idle shell is asked to change its directory
+        let pid = match self.terminals.borrow().get(&term) {
+            Some(info) => info.pid,
+            None => return,
+        };
+        match terminals::foreground_process(pid) {
+            Foreground::Shell => (),
+            Foreground::Process(name) => {
+                debug!(
+                    "Not changing the directory of the terminal, '{}' runs in it",
+                    name
+                );
+                return;
+            }
+            Foreground::Unknown => {
+                debug!("Not changing the directory of the terminal, its shell might be busy");
+                return;
+            }
+        }
+        // The leading space keeps the command out of the shell's history
+        term.feed_child(&format!(
+            " cd {}\n",
+            run_command::shell_quote(&dir.to_string_lossy())
+        ));
+    }
+
+    pub(crate) fn close_terminal(&self, term: &Terminal) {
+        self.terminals.borrow_mut().remove(term);
+        self.term_notebook
+            .remove_page(self.term_notebook.page_num(term));
+        if self.term_notebook.get_n_pages() < 2 {
+            self.term_notebook.set_show_tabs(false);
+        }
+    }
+
+    pub(crate) fn remove_terminal_area(&self) {
+        if let Some(termbox) = self.bottom_paned.get_child1() {
+            self.bottom_paned.remove(&termbox);
+        }
+        for term in self.open_terminals() {
+            self.close_terminal(&term);
+        }
+        self.hide_bottom_area_if_empty();
+    }
+
+    /// The terminals of the `term_notebook`, in the order of their tabs
+    pub(crate) fn open_terminals(&self) -> Vec<Terminal> {
+        self.term_notebook
+            .get_children()
+            .into_iter()
+            .filter_map(|w| w.downcast::<Terminal>().ok())
+            .collect()
+    }
+
+    /// The state of the terminals which are open, to restore them in the next session
+    pub(crate) fn session_terminals(&self) -> Vec<SessionTerminal> {
+        self.open_terminals()
+            .iter()
+            .filter_map(|term| {
+                let tabs = self.terminals.borrow();
+                let tab = tabs.get(term)?;
+                // Shells only report their directory if they're set up to, so fall back to asking
+                // the system
+                let cwd = term
+                    .get_current_directory_uri()
+                    .and_then(|uri| gio::File::new_for_uri(&uri).get_path())
+                    .or_else(|| terminals::process_cwd(tab.pid))
+                    .map(|cwd| cwd.to_string_lossy().to_string());
+                Some(SessionTerminal {
+                    profile: tab.profile.clone(),
+                    cwd,
+                    title: tab
+                        .label
+                        .get_text()
+                        .map(|t| t.to_string())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+
+    /// Remember the terminals which are open, so they're opened again in the next session
+    pub(crate) fn save_terminal_session(&self) {
+        let terminals = self.session_terminals();
+        debug!("Saving {} terminals to the session", terminals.len());
+        self.state
+            .borrow()
+            .settings
+            .gschema
+            .set_session_terminals(&terminals);
+    }
+
+    /// Ask the user whether to close the application although processes are still running in the
+    /// terminals. The first of these terminals is shown, so that the user can see what's running.
+    /// If we can't find out what runs in the terminals, the user is asked anyway.
+    ///
+    /// # Returns
+    ///
+    /// `SaveAction::Cancel` if the user wants to keep the processes running, otherwise
+    /// `SaveAction::CloseWithoutSave`
+    pub(crate) fn confirm_close_terminals(&self) -> SaveAction {
+        let running: Vec<(Terminal, Foreground)> = self
+            .open_terminals()
+            .into_iter()
+            .filter_map(|term| {
+                let pid = self.terminals.borrow().get(&term)?.pid;
+                Some((term, terminals::foreground_process(pid)))
+            })
+            .filter(|(_, foreground)| *foreground != Foreground::Shell)
+            .collect();
+        let (first, _) = match running.first() {
+            Some(first) => first,
+            None => return SaveAction::CloseWithoutSave,
+        };
+        self.term_notebook
+            .set_current_page(self.term_notebook.page_num(first));
+
+        let names: Vec<&str> = running
+            .iter()
+            .filter_map(|(_, foreground)| match foreground {
+                Foreground::Process(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let message = if names.is_empty() {
+            ni18n_f(
+                "Processes might still be running in the terminal and will be stopped when Tau is closed.",
+                "Processes might still be running in the terminals and will be stopped when Tau is closed.",
+                running.len() as u32,
+                &[],
+            )
+        } else {
+            ni18n_f(
+                "“{}” is still running in a terminal and will be stopped when Tau is closed.",
+                "“{}” are still running in terminals and will be stopped when Tau is closed.",
+                names.len() as u32,
+                &[&names.join("”, “")],
+            )
+        };
+        let ask_close_dialog = MessageDialog::new(
+            Some(&self.window),
+            DialogFlags::all(),
+            MessageType::Question,
+            ButtonsType::None,
+            gettext("Stop running processes?").as_str(),
+        );
+        ask_close_dialog.set_property_secondary_text(Some(message.as_str()));
+        ask_close_dialog.add_button(
+            &gettext("Cancel"),
+            ResponseType::Other(SaveAction::Cancel as u16),
+        );
+        ask_close_dialog.add_button(
+            &gettext("Close Anyway"),
+            ResponseType::Other(SaveAction::CloseWithoutSave as u16),
+        );
+        ask_close_dialog.set_default_response(ResponseType::Other(SaveAction::Cancel as u16));
+        self.saving.replace(true);
+        let ret: i32 = ask_close_dialog.run().into();
+        ask_close_dialog.destroy();
+        self.saving.replace(false);
+        match SaveAction::try_from(ret) {
+            Ok(SaveAction::CloseWithoutSave) => SaveAction::CloseWithoutSave,
+            _ => SaveAction::Cancel,
+        }
+    }
+}
+
+/// An extension trait for `Rc<MainWin>` like `MainWinExt`, for the terminals
+pub trait MainWinTerminalsExt {
+    fn add_terminal(&self, always_create_new: bool);
+
+    fn add_terminal_with_profile(
+        &self,
+        always_create_new: bool,
+        profile_name: &str,
+        restore: Option<&SessionTerminal>,
+    );
+
+    fn restore_terminals(&self);
+
+    fn feed_terminal(&self, text: &str);
+
+    fn send_to_terminal(&self);
+
+    fn run_current_file(&self);
+}
+
+impl MainWinTerminalsExt for Rc<MainWin> {
+    /// Add a Terminal to the bottom of the `MainWin`. If `always_create_new` is true, this will only
+    /// create a new terminal area if it doesn't exist yet. If it's false it adds a new terminal to the
+    /// `GtkNotebook` holding the terminals. The terminal uses the profile of the `terminal-profile`
+    /// key.
+    fn add_terminal(&self, always_create_new: bool) {
+        let profile: String = self.state.borrow().settings.gschema.get("terminal-profile");
+        self.add_terminal_with_profile(always_create_new, &profile, None);
+    }
+
+    /// Like `add_terminal`, but start the terminal with the `terminals::Profile` called
+    /// `profile_name`. The working directory and title of `restore` take precedence over the ones
+    /// of the profile, if it's a terminal of the last session.
+    fn add_terminal_with_profile(
+        &self,
+        always_create_new: bool,
+        profile_name: &str,
+        restore: Option<&SessionTerminal>,
+    ) {
+        let term_box = self.bottom_paned.get_child1();
+        if term_box.is_some() && always_create_new {
+            return;
+        }
+
+        let profile = terminals::load_profiles(functions::terminal_profiles_file().as_deref())
+            .into_iter()
+            .find(|p| p.name == profile_name)
+            .unwrap_or_else(|| {
+                warn!("Unknown terminal profile '{}'", profile_name);
+                terminals::default_profile()
+            });
+        let shell = profile
+            .shell
+            .clone()
+            .unwrap_or_else(|| self.state.borrow().settings.gschema.get("terminal-path"));
+
+        let file_dir = self.get_current_edit_view().and_then(|ev| {
+            ev.file_name
+                .borrow()
+                .as_ref()
+                .and_then(|f| Path::new(f).parent().map(Path::to_path_buf))
+        });
+        let cwd = restore
+            .and_then(|t| t.cwd.as_ref().map(PathBuf::from))
+            .filter(|cwd| cwd.is_dir())
+            .or_else(|| profile.cwd())
+            .or(file_dir)
+            .map(|cwd| cwd.to_string_lossy().to_string());
+
+        let mut argv = vec![Path::new(&shell)];
+        argv.extend(profile.args.iter().map(Path::new));
+        let envv = profile.envv();
+        let envv: Vec<&Path> = envv.iter().map(Path::new).collect();
+
+        let term = Terminal::new();
+        let pid = match term.spawn_sync(
+            vte::PtyFlags::DEFAULT,
+            cwd.as_deref(),
+            &argv,
+            &envv,
+            SpawnFlags::DEFAULT,
+            Some(&mut || functions::vte_callback()),
+            None::<&gio::Cancellable>,
+        ) {
+            Ok(pid) => pid,
+            Err(e) => {
+                ErrorDialog::new(ErrorMsg::new(
+                    freplace(
+                        gettext("Couldn’t start the shell “{}” of the terminal profile “{}”: {}"),
+                        &[&shell, &profile.name, &e.to_string()],
+                    ),
+                    false,
+                ));
+                return;
+            }
+        };
+        if let Some(font) = &profile.font {
+            term.set_font(Some(&pango::FontDescription::from_string(font)));
+        }
+
+        term.connect_key_press_event(move |term, ek| {
+            let ctrl = ek.get_state().contains(ModifierType::CONTROL_MASK);
+            let shift = ek.get_state().contains(ModifierType::SHIFT_MASK);
+            match ek.get_keyval() {
+                key::C if ctrl && shift => {
+                    term.copy_clipboard();
+                    return Inhibit(true);
+                }
+                key::V if ctrl && shift => {
+                    term.paste_clipboard();
+                    return Inhibit(true);
+                }
+                _ => {}
+            }
+            Inhibit(false)
+        });
+
+        let context_menu_builder =
+            Builder::new_from_resource("/org/gnome/Tau/terminal_context_menu.glade");
+        let gmenu: gio::Menu = context_menu_builder
+            .get_object("terminal_context_menu")
+            .unwrap();
+        let context_menu = gtk::Menu::new_from_model(&gmenu);
+        context_menu.set_property_attach_widget(Some(&term));
+
+        term.connect_button_press_event(move |_, eb| {
+            if eb.get_button() == 3 {
+                context_menu.popup_at_pointer(Some(&eb));
+            }
+
+            Inhibit(false)
+        });
+
+        if term_box.is_none() {
+            let termbox = gtk::Box::new(Orientation::Horizontal, 0);
+            termbox.pack_start(&self.term_notebook, true, true, 0);
+            self.bottom_paned.pack1(&termbox, true, false);
+
+            let buttonbox = gtk::Box::new(Orientation::Vertical, 0);
+            let close_button =
+                Button::new_from_icon_name(Some("window-close-symbolic"), IconSize::SmallToolbar);
+            let add_button =
+                Button::new_from_icon_name(Some("window-new-symbolic"), IconSize::SmallToolbar);
+            buttonbox.pack_start(&add_button, false, false, 0);
+            buttonbox.pack_start(&close_button, false, false, 0);
+            termbox.pack_start(&buttonbox, false, false, 0);
+
+            close_button.connect_clicked(
+                clone!(@weak self as main_win => @default-panic, move |_| {
+                    main_win.remove_terminal_area();
+                }),
+            );
+
+            // Let the user choose the profile of the new terminal if there's more than one
+            add_button.connect_clicked(
+                clone!(@weak self as main_win => @default-panic, move |button| {
+                    let profiles =
+                        terminals::load_profiles(functions::terminal_profiles_file().as_deref());
+                    if profiles.len() < 2 {
+                        main_win.add_terminal(false);
+                        return;
+                    }
+                    let menu = gtk::Menu::new();
+                    for profile in profiles {
+                        let item = gtk::MenuItem::new_with_label(&profile.name);
+                        item.connect_activate(
+                            clone!(@weak main_win => @default-panic, move |_| {
+                                main_win.add_terminal_with_profile(false, &profile.name, None);
+                            }),
+                        );
+                        menu.append(&item);
+                    }
+                    menu.set_property_attach_widget(Some(button));
+                    menu.show_all();
+                    menu.popup_at_widget(button, gdk::Gravity::West, gdk::Gravity::East, None);
+                }),
+            );
+
+            self.show_bottom_area();
+        }
+
+        let top_bar = editview::TopBar::new();
+        match restore.map(|t| t.title.as_str()).filter(|t| !t.is_empty()) {
+            Some(title) => top_bar.label.set_text(title),
+            None => top_bar.label.set_text(&format!(
+                "{} {}",
+                gettext("Terminal"),
+                self.term_notebook.get_n_pages() + 1
+            )),
+        }
+        self.term_notebook
+            .insert_page(&term, Some(&top_bar.event_box), None);
+        self.term_notebook.set_tab_reorderable(&term, true);
+        self.terminals.borrow_mut().insert(
+            term.clone(),
+            TerminalTab {
+                profile: profile.name.clone(),
+                pid: pid.0,
+                label: top_bar.label.clone(),
+            },
+        );
+
+        // Show the title the shell sets, e.g. the command which is running
+        let label = top_bar.label.clone();
+        term.connect_window_title_changed(clone!(@weak label => move |term| {
+            if let Some(title) = term.get_window_title().filter(|t| !t.is_empty()) {
+                label.set_text(&title);
+            }
+        }));
+
+        top_bar.close_button.connect_clicked(
+            clone!(@weak self as main_win, @weak term => @default-panic, move |_| {
+                main_win.close_terminal(&term);
+            }),
+        );
+
+        top_bar.event_box.connect_button_press_event(
+            clone!(@weak self as main_win, @weak term => @default-panic, move |_, eb| {
+                // 2 == middle click
+                if eb.get_button() == 2 {
+                    main_win.close_terminal(&term);
+                }
+                Inhibit(false)
+            }),
+        );
+        term.show();
+
+        if self.term_notebook.get_n_pages() < 2 {
+            self.term_notebook.set_show_tabs(false);
+        } else {
+            self.term_notebook.set_show_tabs(true);
+        }
+    }
+
+    /// Open the terminals of the last session if the user wants the session to be restored,
+    /// otherwise open a terminal if the user wants one on startup
+    fn restore_terminals(&self) {
+        let gschema = self.state.borrow().settings.gschema.clone();
+        let restore: bool = gschema.get("restore-session");
+        let session = if restore {
+            gschema.get_session_terminals()
+        } else {
+            Vec::new()
+        };
+        if session.is_empty() {
+            if gschema.get("show-terminal") {
+                self.add_terminal(true);
+            }
+            return;
+        }
+        debug!("Restoring {} terminals of the last session", session.len());
+        for terminal in &session {
+            self.add_terminal_with_profile(false, &terminal.profile, Some(terminal));
+        }
+    }
+
+    /// Type `text` into the current terminal, opening one if there's none
+    fn feed_terminal(&self, text: &str) {
+        self.add_terminal(true);
+        match self.current_terminal() {
+            Some(term) => term.feed_child(text),
+            None => warn!("Couldn't find a terminal to send text to"),
+        }
+    }
+
+    /// Send the selection of the current document to the terminal, or its current line if nothing
+    /// is selected, e.g. to run it in a REPL
+    fn send_to_terminal(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+
+        let (selection_tx, selection_rx) =
+            MainContext::sync_channel::<Value>(glib::source::PRIORITY_HIGH, 1);
+        selection_rx.attach(
+            None,
+            clone!(@weak self as main_win, @strong edit_view => @default-panic, move |selection| {
+                let text = match selection.as_str().filter(|s| !s.is_empty()) {
+                    Some(selection) => selection.to_string(),
+                    None => {
+                        let row = edit_view.cursor_positions().first().map(|(row, _)| *row);
+                        match row.and_then(|row| edit_view.line_text(row)) {
+                            Some(line) => line,
+                            None => return glib::Continue(false),
+                        }
+                    }
+                };
+                // Run what has been sent, like pressing return after pasting it would
+                if text.ends_with('\n') {
+                    main_win.feed_terminal(&text);
+                } else {
+                    main_win.feed_terminal(&format!("{}\n", text));
+                }
+                glib::Continue(false)
+            }),
+        );
+
+        self.core.copy(edit_view.view_id, move |res| match res {
+            Ok(selection) => selection_tx.send(selection).unwrap(),
+            Err(e) => {
+                warn!("Failed to get the selection due to error: {:?}", e);
+                selection_tx.send(Value::Null).unwrap();
+            }
+        });
+    }
+
+    /// Run the file of the current document in the terminal with the run command configured for
+    /// its syntax, see `run_command`
+    fn run_current_file(&self) {
+        let edit_view = match self.get_current_edit_view() {
+            Some(edit_view) => edit_view,
+            None => return,
+        };
+        let file_name = match edit_view.file_name.borrow().clone() {
+            Some(file_name) => file_name,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    gettext("Please save the document before running it."),
+                    false,
+                ));
+                return;
+            }
+        };
+        let syntax = edit_view.language();
+        let template = self
+            .syntax_config
+            .borrow()
+            .get(&syntax)
+            .and_then(|sc| sc.changes.run_command.clone())
+            .or_else(|| run_command::default_template(&syntax).map(String::from));
+        let template = match template {
+            Some(template) => template,
+            None => {
+                ErrorDialog::new(ErrorMsg::new(
+                    freplace(
+                        gettext("There’s no command to run “{}” files. You can set one in the syntax settings of the preferences."),
+                        &[&syntax],
+                    ),
+                    false,
+                ));
+                return;
+            }
+        };
+
+        let command = format!(
+            "{}\n",
+            run_command::expand(&template, Path::new(&file_name))
+        );
+        // Run what the user sees, not what's on disk, so wait until xi has written the file
+        if *edit_view.pristine.borrow() {
+            self.feed_terminal(&command);
+        } else {
+            edit_view.connect_saved_once(
+                clone!(@weak self as main_win => @default-return (), move || {
+                    main_win.feed_terminal(&command);
+                }),
+            );
+            self.save_view(edit_view.view_id, &file_name);
+        }
+    }
+}
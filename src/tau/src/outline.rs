@@ -0,0 +1,727 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use editview::completion::fuzzy_score;
+use editview::diff::split_lines;
+use editview::i18n::freplace;
+use editview::syntaxes::SYNTAX_SET;
+use editview::{EditView, EditViewExt};
+use gettextrs::gettext;
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{Builder, Label, ListStore, SearchEntry, TreePath, TreeView};
+use log::trace;
+use regex::Regex;
+use std::cell::{Cell, RefCell};
+use std::cmp::Reverse;
+use std::rc::{Rc, Weak};
+use std::thread;
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxSet};
+
+/// How long to wait after the last change of the document before looking for its symbols again
+const OUTLINE_REFRESH_DELAY_MS: u32 = 500;
+
+/// The columns of the `outline_liststore`
+const COLUMN_MARKUP: u32 = 0;
+const COLUMN_TOOLTIP: u32 = 1;
+
+/// What a symbol of the outline is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    /// A struct, enum, class, trait, interface or type alias
+    Type,
+    /// A module or namespace
+    Module,
+    /// A heading of a document, e.g. in Markdown
+    Heading,
+}
+
+impl SymbolKind {
+    /// The letter shown in front of the symbol in the outline
+    pub fn letter(self) -> &'static str {
+        match self {
+            SymbolKind::Function => "f",
+            SymbolKind::Type => "T",
+            SymbolKind::Module => "M",
+            SymbolKind::Heading => "#",
+        }
+    }
+
+    pub fn description(self) -> String {
+        match self {
+            SymbolKind::Function => gettext("Function"),
+            SymbolKind::Type => gettext("Type"),
+            SymbolKind::Module => gettext("Module"),
+            SymbolKind::Heading => gettext("Heading"),
+        }
+    }
+}
+
+/// A function, type or heading of a document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The line the symbol is defined on, starting at 0
+    pub line: u64,
+    /// How many symbols this one is nested in, e.g. 1 for the methods of a class
+    pub depth: usize,
+}
+
+/// The scopes syntect assigns to the names of the symbols we show, as prefixes
+const SYMBOL_SCOPES: &[(&str, SymbolKind)] = &[
+    ("entity.name.function", SymbolKind::Function),
+    ("entity.name.method", SymbolKind::Function),
+    ("entity.name.type", SymbolKind::Type),
+    ("entity.name.struct", SymbolKind::Type),
+    ("entity.name.enum", SymbolKind::Type),
+    ("entity.name.union", SymbolKind::Type),
+    ("entity.name.class", SymbolKind::Type),
+    ("entity.name.trait", SymbolKind::Type),
+    ("entity.name.interface", SymbolKind::Type),
+    ("entity.name.impl", SymbolKind::Type),
+    ("entity.name.module", SymbolKind::Module),
+    ("entity.name.namespace", SymbolKind::Module),
+    ("entity.name.section", SymbolKind::Heading),
+    ("markup.heading", SymbolKind::Heading),
+];
+
+/// Regexes finding the symbols of languages whose syntect syntax doesn't scope them (or which
+/// syntect doesn't know). The name of the symbol is the last capture group.
+fn patterns(syntax: &str) -> &'static [(SymbolKind, &'static str)] {
+    match syntax {
+        "Rust" => &[
+            (
+                SymbolKind::Function,
+                r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe|extern\s+"[^"]*")\s+)*fn\s+(\w+)"#,
+            ),
+            (
+                SymbolKind::Type,
+                r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:struct|enum|union|trait|type)\s+(\w+)",
+            ),
+            (
+                SymbolKind::Module,
+                r"^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(\w+)",
+            ),
+        ],
+        "Python" => &[
+            (SymbolKind::Function, r"^\s*(?:async\s+)?def\s+(\w+)"),
+            (SymbolKind::Type, r"^\s*class\s+(\w+)"),
+        ],
+        "Go" => &[
+            (SymbolKind::Function, r"^func\s+(?:\([^)]*\)\s*)?(\w+)"),
+            (SymbolKind::Type, r"^type\s+(\w+)"),
+        ],
+        "C" | "C++" | "Objective-C" | "Objective-C++" => &[
+            (
+                SymbolKind::Type,
+                r"^\s*(?:typedef\s+)?(?:struct|class|union|enum)\s+(\w+)\s*(?:[:{]|$)",
+            ),
+            (SymbolKind::Module, r"^\s*namespace\s+(\w+)"),
+            (
+                SymbolKind::Function,
+                r"^[A-Za-z_][\w\s\*&:<>,]*?[\s\*&](~?[A-Za-z_][\w:]*)\s*\([^;]*$",
+            ),
+        ],
+        "JavaScript" | "TypeScript" | "TypeScriptReact" => &[
+            (
+                SymbolKind::Function,
+                r"^\s*(?:export\s+)?(?:default\s+)?(?:async\s+)?function\s*\*?\s*(\w+)",
+            ),
+            (
+                SymbolKind::Type,
+                r"^\s*(?:export\s+)?(?:default\s+)?(?:abstract\s+)?(?:class|interface|enum)\s+(\w+)",
+            ),
+        ],
+        "Java" | "C#" => &[
+            (
+                SymbolKind::Type,
+                r"^\s*(?:(?:public|private|protected|internal|static|abstract|final|sealed|partial)\s+)*(?:class|interface|enum|struct|record)\s+(\w+)",
+            ),
+            (
+                SymbolKind::Function,
+                r"^\s*(?:(?:public|private|protected|internal|static|final|abstract|virtual|override|async|synchronized)\s+)+[\w<>\[\],\s]+\s+(\w+)\s*\(",
+            ),
+        ],
+        "Shell-Unix-Generic" | "Bourne Again Shell (bash)" => {
+            &[(SymbolKind::Function, r"^\s*(?:function\s+)?([\w-]+)\s*\(\)")]
+        }
+        "Markdown" | "MultiMarkdown" => &[(SymbolKind::Heading, r"^(#{1,6})\s+(.*?)[\s#]*$")],
+        _ => &[],
+    }
+}
+
+/// How far a symbol on `line` is nested: the level of a heading, otherwise the width of the
+/// indentation
+fn nesting_level(line: &str, kind: SymbolKind) -> usize {
+    if kind == SymbolKind::Heading {
+        let hashes = line.trim_start().chars().take_while(|c| *c == '#').count();
+        hashes.max(1)
+    } else {
+        line.chars()
+            .take_while(|c| c.is_whitespace())
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum()
+    }
+}
+
+/// Set the `depth` of the symbols from their nesting levels: a symbol is nested in the symbols
+/// before it which have a lower level
+fn nest(symbols: Vec<(Symbol, usize)>) -> Vec<Symbol> {
+    let mut parents: Vec<usize> = Vec::new();
+    symbols
+        .into_iter()
+        .map(|(mut symbol, level)| {
+            while parents.last().map_or(false, |parent| *parent >= level) {
+                parents.pop();
+            }
+            symbol.depth = parents.len();
+            parents.push(level);
+            symbol
+        })
+        .collect()
+}
+
+/// The name of a symbol, without the markup of headings and surrounding whitespace
+fn clean_name(name: &str, kind: SymbolKind) -> String {
+    let name = name.trim();
+    if kind == SymbolKind::Heading {
+        name.trim_matches('#').trim().to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Find the symbols of `text` by the scopes syntect assigns to it with the syntax `syntax`.
+/// Returns `None` if syntect doesn't know the syntax.
+fn parse_symbols(syntax_set: &SyntaxSet, syntax: &str, text: &str) -> Option<Vec<Symbol>> {
+    let syntax = syntax_set.find_syntax_by_name(syntax)?;
+    let selectors: Vec<(Scope, SymbolKind)> = SYMBOL_SCOPES
+        .iter()
+        .filter_map(|(scope, kind)| Scope::new(scope).ok().map(|scope| (scope, *kind)))
+        .collect();
+    let kind_of = |stack: &ScopeStack| {
+        stack.as_slice().iter().rev().find_map(|scope| {
+            selectors
+                .iter()
+                .find(|(selector, _)| selector.is_prefix_of(*scope))
+                .map(|(_, kind)| *kind)
+        })
+    };
+
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut symbols = Vec::new();
+    for (n, line) in split_lines(text).into_iter().enumerate() {
+        // The pieces of the line between the points where its scopes change
+        let mut pieces = Vec::new();
+        let mut pos = 0;
+        for (i, op) in state.parse_line(line, syntax_set) {
+            if pos < i {
+                pieces.push((pos..i, kind_of(&stack)));
+            }
+            stack.apply(&op);
+            pos = i;
+        }
+        if pos < line.len() {
+            pieces.push((pos..line.len(), kind_of(&stack)));
+        }
+
+        // The name is the first run of pieces which have the scope of a symbol
+        let mut pieces = pieces
+            .into_iter()
+            .skip_while(|(_, kind)| kind.is_none())
+            .peekable();
+        let kind = match pieces.peek() {
+            Some((_, Some(kind))) => *kind,
+            _ => continue,
+        };
+        let name: String = pieces
+            .take_while(|(_, kind)| kind.is_some())
+            .map(|(range, _)| &line[range])
+            .collect();
+
+        let name = clean_name(&name, kind);
+        if !name.is_empty() {
+            let symbol = Symbol {
+                name,
+                kind,
+                line: n as u64,
+                depth: 0,
+            };
+            symbols.push((symbol, nesting_level(line, kind)));
+        }
+    }
+    Some(nest(symbols))
+}
+
+/// Find the symbols of `text` with the regex heuristics for the syntax `syntax`
+fn match_symbols(syntax: &str, text: &str) -> Vec<Symbol> {
+    let regexes: Vec<(SymbolKind, Regex)> = patterns(syntax)
+        .iter()
+        .filter_map(|(kind, pattern)| Regex::new(pattern).ok().map(|regex| (*kind, regex)))
+        .collect();
+    if regexes.is_empty() {
+        return Vec::new();
+    }
+
+    let mut symbols = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        for (kind, regex) in &regexes {
+            let name = regex
+                .captures(line)
+                .and_then(|caps| caps.iter().flatten().last())
+                .map(|m| clean_name(m.as_str(), *kind));
+            if let Some(name) = name.filter(|name| !name.is_empty()) {
+                let symbol = Symbol {
+                    name,
+                    kind: *kind,
+                    line: n as u64,
+                    depth: 0,
+                };
+                symbols.push((symbol, nesting_level(line, *kind)));
+                break;
+            }
+        }
+    }
+    nest(symbols)
+}
+
+/// Find the functions, types and headings of `text`, which is written in the syntax called
+/// `syntax`, e.g. "Rust". They're taken from the scopes syntect assigns if it finds any, otherwise
+/// regex heuristics are used.
+pub fn find_symbols(syntax_set: &SyntaxSet, syntax: &str, text: &str) -> Vec<Symbol> {
+    match parse_symbols(syntax_set, syntax, text) {
+        Some(symbols) if !symbols.is_empty() => symbols,
+        _ => match_symbols(syntax, text),
+    }
+}
+
+/// The index of the symbol `line` belongs to, which is the last one defined before or on it
+pub fn symbol_at(symbols: &[Symbol], line: u64) -> Option<usize> {
+    symbols.iter().rposition(|symbol| symbol.line <= line)
+}
+
+/// The indices of the symbols whose names fuzzy-match `pattern`, best match first. All symbols are
+/// returned in order if `pattern` is empty.
+pub fn filter_symbols(symbols: &[Symbol], pattern: &str) -> Vec<usize> {
+    if pattern.is_empty() {
+        return (0..symbols.len()).collect();
+    }
+    let mut scored: Vec<(i64, usize)> = symbols
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| fuzzy_score(pattern, &s.name).map(|score| (score, i)))
+        .collect();
+    scored.sort_by_key(|(score, i)| (Reverse(*score), *i));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Sent to the `Outline` by its timeout and the thread finding the symbols
+enum OutlineMsg {
+    /// Find the symbols of the document again
+    Refresh,
+    /// The symbols found by the run with this generation
+    Symbols(u64, Vec<Symbol>),
+    /// Why the run with this generation couldn't look for symbols
+    Failed(u64, String),
+}
+
+/// The outline panel in the sidebar, which lists the functions, types and headings of the
+/// current document
+pub struct Outline {
+    /// The panel, to be added to the sidebar
+    pub widget: gtk::Box,
+    search_entry: SearchEntry,
+    status_label: Label,
+    treeview: TreeView,
+    list_store: ListStore,
+    sender: Sender<OutlineMsg>,
+    /// Sends the generation, syntax and text of a document to the thread finding its symbols
+    requests: crossbeam_channel::Sender<(u64, String, String)>,
+    /// The `EditView` whose symbols are shown
+    view: RefCell<Option<Weak<EditView>>>,
+    symbols: RefCell<Vec<Symbol>>,
+    /// The indices into `symbols` of the rows which are currently shown
+    shown: RefCell<Vec<usize>>,
+    /// Incremented for every run, so results of outdated ones are dropped
+    generation: Cell<u64>,
+    /// The pending refresh after the document has changed
+    timeout: RefCell<Option<glib::SourceId>>,
+    /// Called with the line of the symbol the user activates
+    on_symbol: RefCell<Option<Box<dyn Fn(u64)>>>,
+}
+
+impl Outline {
+    pub fn new() -> Rc<Self> {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/outline.glade");
+
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+        let (requests, request_rx) = crossbeam_channel::unbounded::<(u64, String, String)>();
+
+        // Parsing big documents takes a moment, so it's done in a single thread
+        let symbol_sender = sender.clone();
+        thread::spawn(move || {
+            while let Ok(request) = request_rx.recv() {
+                // Skip requests which have been superseded already
+                let (generation, syntax, text) = request_rx.try_iter().last().unwrap_or(request);
+                let symbols = find_symbols(&SYNTAX_SET, &syntax, &text);
+                if symbol_sender
+                    .send(OutlineMsg::Symbols(generation, symbols))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let outline = Rc::new(Self {
+            widget: builder.get_object("outline_box").unwrap(),
+            search_entry: builder.get_object("outline_search_entry").unwrap(),
+            status_label: builder.get_object("outline_status_label").unwrap(),
+            treeview: builder.get_object("outline_treeview").unwrap(),
+            list_store: builder.get_object("outline_liststore").unwrap(),
+            sender,
+            requests,
+            view: Default::default(),
+            symbols: Default::default(),
+            shown: Default::default(),
+            generation: Cell::new(0),
+            timeout: Default::default(),
+            on_symbol: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak outline => @default-return glib::Continue(false), move |msg| {
+                outline.receive(msg);
+                glib::Continue(true)
+            }),
+        );
+
+        // Only look for symbols while the panel is shown
+        outline
+            .widget
+            .connect_map(clone!(@weak outline => @default-panic, move |_| {
+                outline.refresh();
+            }));
+
+        outline.search_entry.connect_search_changed(
+            clone!(@weak outline => @default-panic, move |_| {
+                outline.filter();
+                outline.follow_cursor();
+            }),
+        );
+
+        outline
+            .search_entry
+            .connect_activate(clone!(@weak outline => @default-panic, move |_| {
+                if let Some(row) = outline.selected_row() {
+                    outline.open(row);
+                }
+            }));
+
+        outline.treeview.connect_row_activated(
+            clone!(@weak outline => @default-panic, move |_, path, _| {
+                if let Some(row) = path.get_indices().first() {
+                    outline.open(*row as usize);
+                }
+            }),
+        );
+
+        outline
+    }
+
+    /// Call `f` with the line of the symbols the user activates
+    pub fn connect_symbol_activated<F: Fn(u64) + 'static>(&self, f: F) {
+        self.on_symbol.replace(Some(Box::new(f)));
+    }
+
+    /// Show the symbols of `view` from now on
+    pub fn set_view(&self, view: Option<&Rc<EditView>>) {
+        let same = match (self.view(), view) {
+            (Some(old), Some(new)) => Rc::ptr_eq(&old, new),
+            (None, None) => true,
+            _ => false,
+        };
+        if same {
+            return;
+        }
+        self.view.replace(view.map(Rc::downgrade));
+        self.symbols.borrow_mut().clear();
+        self.search_entry.set_text("");
+        self.filter();
+        self.refresh();
+    }
+
+    /// Focus the search entry
+    pub fn start(&self) {
+        self.search_entry.grab_focus();
+    }
+
+    fn view(&self) -> Option<Rc<EditView>> {
+        self.view.borrow().as_ref().and_then(Weak::upgrade)
+    }
+
+    /// Look for the symbols again once the document hasn't changed for a bit
+    pub fn invalidate(&self) {
+        if !self.widget.get_mapped() {
+            return;
+        }
+        if let Some(timeout) = self.timeout.borrow_mut().take() {
+            glib::source_remove(timeout);
+        }
+        let sender = self.sender.clone();
+        let timeout = glib::timeout_add_local(OUTLINE_REFRESH_DELAY_MS, move || {
+            sender.send(OutlineMsg::Refresh).unwrap();
+            glib::Continue(false)
+        });
+        self.timeout.replace(Some(timeout));
+    }
+
+    /// Look for the symbols of the document in a separate thread
+    fn refresh(&self) {
+        if !self.widget.get_mapped() {
+            return;
+        }
+        if let Some(timeout) = self.timeout.borrow_mut().take() {
+            glib::source_remove(timeout);
+        }
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        let view = match self.view() {
+            Some(view) => view,
+            None => {
+                self.status_label
+                    .set_text(&gettext("Open a file to see its outline"));
+                return;
+            }
+        };
+        trace!("Looking for the symbols of EditView '{}'", view.view_id);
+        let syntax = view
+            .view_item
+            .statusbar
+            .syntax_label
+            .get_text()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        let requests = self.requests.clone();
+        let sender = self.sender.clone();
+        view.with_text(move |text| match text {
+            Ok(text) => {
+                requests.send((generation, syntax, text)).ok();
+            }
+            Err(e) => {
+                sender.send(OutlineMsg::Failed(generation, e)).ok();
+            }
+        });
+    }
+
+    fn receive(&self, msg: OutlineMsg) {
+        match msg {
+            OutlineMsg::Refresh => {
+                self.timeout.replace(None);
+                self.refresh();
+            }
+            OutlineMsg::Symbols(generation, symbols) => {
+                if generation != self.generation.get() {
+                    return;
+                }
+                if *self.symbols.borrow() == symbols {
+                    return;
+                }
+                self.symbols.replace(symbols);
+                self.filter();
+                self.follow_cursor();
+            }
+            OutlineMsg::Failed(generation, e) => {
+                if generation == self.generation.get() {
+                    self.status_label.set_text(&e);
+                }
+            }
+        }
+    }
+
+    /// Show the symbols matching the search entry
+    fn filter(&self) {
+        let pattern = self
+            .search_entry
+            .get_text()
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        let symbols = self.symbols.borrow();
+        let shown = filter_symbols(&symbols, &pattern);
+
+        self.list_store.clear();
+        for i in &shown {
+            let symbol = &symbols[*i];
+            // Nesting isn't shown while filtering, the best match comes first instead
+            let indent = if pattern.is_empty() {
+                "    ".repeat(symbol.depth)
+            } else {
+                String::new()
+            };
+            let markup = format!(
+                "{}<tt><span alpha=\"60%\">{}</span></tt> {}",
+                indent,
+                symbol.kind.letter(),
+                glib::markup_escape_text(&symbol.name)
+            );
+            let tooltip = freplace(
+                gettext("{}, line {}"),
+                &[&symbol.kind.description(), &(symbol.line + 1).to_string()],
+            );
+            self.list_store.insert_with_values(
+                None,
+                &[COLUMN_MARKUP, COLUMN_TOOLTIP],
+                &[&markup, &tooltip],
+            );
+        }
+
+        if symbols.is_empty() {
+            self.status_label.set_text(&gettext("No symbols found"));
+        } else if shown.is_empty() {
+            self.status_label.set_text(&gettext("No matching symbols"));
+        } else {
+            self.status_label.set_text("");
+        }
+        self.shown.replace(shown);
+    }
+
+    /// Select the symbol the first cursor of the document is in
+    pub fn follow_cursor(&self) {
+        let line = match self
+            .view()
+            .and_then(|view| view.cursor_positions().first().copied())
+        {
+            Some((line, _)) => line,
+            None => return,
+        };
+        let symbol = match symbol_at(&self.symbols.borrow(), line) {
+            Some(symbol) => symbol,
+            None => {
+                self.treeview.get_selection().unselect_all();
+                return;
+            }
+        };
+        let row = self.shown.borrow().iter().position(|i| *i == symbol);
+        match row {
+            Some(row) => {
+                let path = TreePath::new_from_indicesv(&[row as i32]);
+                self.treeview.get_selection().select_path(&path);
+                self.treeview.scroll_to_cell(
+                    Some(&path),
+                    None::<&gtk::TreeViewColumn>,
+                    false,
+                    0.0,
+                    0.0,
+                );
+            }
+            None => self.treeview.get_selection().unselect_all(),
+        }
+    }
+
+    fn selected_row(&self) -> Option<usize> {
+        let (paths, _) = self.treeview.get_selection().get_selected_rows();
+        paths
+            .first()
+            .and_then(|path| path.get_indices().first().copied())
+            .map(|row| row as usize)
+            .or_else(|| Some(0).filter(|_| !self.shown.borrow().is_empty()))
+    }
+
+    /// Jump to the symbol in `row`
+    fn open(&self, row: usize) {
+        let line = match self.shown.borrow().get(row) {
+            Some(i) => self.symbols.borrow()[*i].line,
+            None => return,
+        };
+        if let Some(f) = &*self.on_symbol.borrow() {
+            f(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(symbols: &[Symbol]) -> Vec<(&str, SymbolKind, usize)> {
+        symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.depth))
+            .collect()
+    }
+
+    #[test]
+    fn rust_heuristics() {
+        let text = "mod foo {\n    pub struct Bar;\n\n    pub(crate) async fn baz() {}\n}\nfn main() {\n    let f = foo::baz();\n}\n";
+        let symbols = match_symbols("Rust", text);
+        assert_eq!(
+            names(&symbols),
+            vec![
+                ("foo", SymbolKind::Module, 0),
+                ("Bar", SymbolKind::Type, 1),
+                ("baz", SymbolKind::Function, 1),
+                ("main", SymbolKind::Function, 0),
+            ]
+        );
+        assert_eq!(symbols[2].line, 3);
+    }
+
+    #[test]
+    fn markdown_headings() {
+        let text = "# Title\n\nText\n## Usage ##\n### Details\n## License\n";
+        let symbols = match_symbols("Markdown", text);
+        assert_eq!(
+            names(&symbols),
+            vec![
+                ("Title", SymbolKind::Heading, 0),
+                ("Usage", SymbolKind::Heading, 1),
+                ("Details", SymbolKind::Heading, 2),
+                ("License", SymbolKind::Heading, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntect_scopes() {
+        let syntax_set = &SYNTAX_SET;
+        let text = "struct Foo;\n\nimpl Foo {\n    fn new() -> Self {\n        Foo\n    }\n}\n";
+        let symbols = find_symbols(syntax_set, "Rust", text);
+        assert!(symbols
+            .iter()
+            .any(|s| s.name == "Foo" && s.kind == SymbolKind::Type && s.line == 0));
+        let new = symbols.iter().find(|s| s.name == "new").unwrap();
+        assert_eq!((new.kind, new.line), (SymbolKind::Function, 3));
+
+        // Unknown syntaxes have no symbols
+        assert!(find_symbols(syntax_set, "Plain Text", "fn foo() {}\n").is_empty());
+    }
+
+    #[test]
+    fn cursor_symbol() {
+        let symbols = match_symbols(
+            "Python",
+            "import os\n\nclass A:\n    def f(self):\n        pass\n\ndef g():\n    pass\n",
+        );
+        assert_eq!(symbol_at(&symbols, 0), None);
+        assert_eq!(symbol_at(&symbols, 2), Some(0));
+        assert_eq!(symbol_at(&symbols, 4), Some(1));
+        assert_eq!(symbol_at(&symbols, 7), Some(2));
+        assert_eq!(symbols[1].depth, 1);
+    }
+
+    #[test]
+    fn fuzzy_filter() {
+        let symbols = match_symbols(
+            "Go",
+            "func main() {}\nfunc (s *Server) handleRequest() {}\ntype Server struct {}\n",
+        );
+        assert_eq!(filter_symbols(&symbols, ""), vec![0, 1, 2]);
+        assert_eq!(filter_symbols(&symbols, "hr"), vec![1]);
+        assert!(filter_symbols(&symbols, "xyz").is_empty());
+    }
+}
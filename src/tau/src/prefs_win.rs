@@ -2,8 +2,11 @@
 // Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
 // SPDX-License-Identifier: MIT
 
+use crate::functions;
 use crate::main_win::StartedPlugins;
+use crate::run_command;
 use crate::syntax_config::{Changes, Domain, SyntaxParams};
+use crate::terminals;
 use editview::MainState;
 use gettextrs::gettext;
 use gio::prelude::*;
@@ -58,9 +61,19 @@ impl PrefsWin {
         let insert_spaces_switch: Switch = builder.get_object("insert_spaces_switch").unwrap();
         let save_when_out_of_focus_switch: Switch =
             builder.get_object("save_when_out_of_focus_switch").unwrap();
+        let line_ending_combo_box: ComboBoxText =
+            builder.get_object("line_ending_combo_box").unwrap();
         let show_lintcount_switch: Switch = builder.get_object("show_linecount_switch").unwrap();
         let full_title_switch: Switch = builder.get_object("full_title_switch").unwrap();
         let restore_session_switch: Switch = builder.get_object("restore_session_switch").unwrap();
+        let hot_exit_switch: Switch = builder.get_object("hot_exit_switch").unwrap();
+        let local_history_switch: Switch = builder.get_object("local_history_switch").unwrap();
+        let local_history_max_snapshots_spinbutton: SpinButton = builder
+            .get_object("local_history_max_snapshots_spinbutton")
+            .unwrap();
+        let local_history_max_days_spinbutton: SpinButton = builder
+            .get_object("local_history_max_days_spinbutton")
+            .unwrap();
 
         let draw_trailing_tabs_radio: RadioButton =
             builder.get_object("tabs_trailing_radio_button").unwrap();
@@ -96,11 +109,21 @@ impl PrefsWin {
         let syntax_config_tab_size_spinbutton: SpinButton = builder
             .get_object("syntax_config_tab_size_spinbutton")
             .unwrap();
+        let syntax_config_auto_pairs_switch: Switch = builder
+            .get_object("syntax_config_auto_pairs_switch")
+            .unwrap();
+        let syntax_config_run_command_entry: Entry = builder
+            .get_object("syntax_config_run_command_entry")
+            .unwrap();
         let syntax_config_apply_button: Button =
             builder.get_object("syntax_config_apply_button").unwrap();
 
         let show_terminal_switch: Switch = builder.get_object("show_terminal_switch").unwrap();
         let terminal_path_entry: Entry = builder.get_object("terminal_path_entry").unwrap();
+        let terminal_follows_file_switch: Switch =
+            builder.get_object("terminal_follows_file_switch").unwrap();
+        let terminal_profile_combo_box: ComboBoxText =
+            builder.get_object("terminal_profile_combo_box").unwrap();
 
         let syntax_changes = gschema.get_strv("syntax-config");
         let syntax_config: HashMap<String, SyntaxParams> = syntax_changes
@@ -150,6 +173,8 @@ impl PrefsWin {
                 syntax_config_tab_size_spinbutton.set_sensitive(false);
                 syntax_config_insert_spaces_switch.set_sensitive(false);
                 syntax_config_tab_size_switch.set_sensitive(false);
+                syntax_config_auto_pairs_switch.set_sensitive(false);
+                syntax_config_run_command_entry.set_sensitive(false);
                 syntax_config_apply_button.set_sensitive(false);
             } else {
                 for (i, lang) in main_state.avail_languages.iter().enumerate() {
@@ -166,6 +191,8 @@ impl PrefsWin {
                             &syntax_config.borrow(),
                             &syntax_config_insert_spaces_switch,
                             &syntax_config_tab_size_spinbutton,
+                            &syntax_config_auto_pairs_switch,
+                            &syntax_config_run_command_entry,
                         );
                     }
                 }
@@ -318,6 +345,13 @@ impl PrefsWin {
             SettingsBindFlags::DEFAULT,
         );
 
+        gschema.bind(
+            "line-ending",
+            &line_ending_combo_box,
+            "active-id",
+            SettingsBindFlags::DEFAULT,
+        );
+
         gschema.bind(
             "show-linecount",
             &show_lintcount_switch,
@@ -332,6 +366,34 @@ impl PrefsWin {
             SettingsBindFlags::DEFAULT,
         );
 
+        gschema.bind(
+            "hot-exit",
+            &hot_exit_switch,
+            "active",
+            SettingsBindFlags::DEFAULT,
+        );
+
+        gschema.bind(
+            "local-history",
+            &local_history_switch,
+            "active",
+            SettingsBindFlags::DEFAULT,
+        );
+
+        gschema.bind(
+            "local-history-max-snapshots",
+            &local_history_max_snapshots_spinbutton,
+            "value",
+            SettingsBindFlags::DEFAULT,
+        );
+
+        gschema.bind(
+            "local-history-max-days",
+            &local_history_max_days_spinbutton,
+            "value",
+            SettingsBindFlags::DEFAULT,
+        );
+
         gschema.bind(
             "full-title",
             &full_title_switch,
@@ -353,9 +415,28 @@ impl PrefsWin {
             SettingsBindFlags::DEFAULT,
         );
 
+        for profile in terminals::load_profiles(functions::terminal_profiles_file().as_deref()) {
+            terminal_profile_combo_box.append(Some(&profile.name), &profile.name);
+        }
+        gschema.bind(
+            "terminal-profile",
+            &terminal_profile_combo_box,
+            "active-id",
+            SettingsBindFlags::DEFAULT,
+        );
+
+        gschema.bind(
+            "terminal-follows-file",
+            &terminal_follows_file_switch,
+            "active",
+            SettingsBindFlags::DEFAULT,
+        );
+
         syntax_config_combo_box.connect_changed(clone!(
             @strong syntax_config_insert_spaces_switch,
             @strong syntax_config_tab_size_spinbutton,
+            @strong syntax_config_auto_pairs_switch,
+            @strong syntax_config_run_command_entry,
             @strong syntax_config
             => move |cb| {
                 if let Some(lang) = cb.get_active_text() {
@@ -364,6 +445,8 @@ impl PrefsWin {
                         &syntax_config.borrow(),
                         &syntax_config_insert_spaces_switch,
                         &syntax_config_tab_size_spinbutton,
+                        &syntax_config_auto_pairs_switch,
+                        &syntax_config_run_command_entry,
                     );
                 }
             }
@@ -375,6 +458,8 @@ impl PrefsWin {
                 @strong syntax_config_insert_spaces_switch,
                 @strong syntax_config_tab_size_switch,
                 @strong syntax_config_tab_size_spinbutton,
+                @strong syntax_config_auto_pairs_switch,
+                @strong syntax_config_run_command_entry,
                 @strong syntax_config,
                 @weak gschema
                 => @default-panic, move |_| {
@@ -390,12 +475,25 @@ impl PrefsWin {
                             None
                         };
 
+                        // Auto pairs are enabled by default, so only store it if they're disabled
+                        let auto_pairs = if syntax_config_auto_pairs_switch.get_active() {
+                            None
+                        } else {
+                            Some(false)
+                        };
+
+                        // An empty entry means the default command is used
+                        let run_command = syntax_config_run_command_entry
+                            .get_text()
+                            .map(|text| text.trim().to_string())
+                            .filter(|text| !text.is_empty());
+
                         let mut syntax_config = syntax_config.borrow_mut();
-                        if tab_size.is_none() && insert_spaces.is_none() {
-                            syntax_config.remove(lang.as_str());
-                        } else if let Some(config) = syntax_config.get_mut(lang.as_str()) {
+                        if let Some(config) = syntax_config.get_mut(lang.as_str()) {
                             config.changes.translate_tabs_to_spaces = insert_spaces;
                             config.changes.tab_size = tab_size;
+                            config.changes.auto_pairs = auto_pairs;
+                            config.changes.run_command = run_command;
                         } else {
                             let params = SyntaxParams {
                                 domain: Domain {
@@ -403,11 +501,15 @@ impl PrefsWin {
                                 },
                                 changes: Changes {
                                     tab_size,
-                                    translate_tabs_to_spaces: insert_spaces
+                                    translate_tabs_to_spaces: insert_spaces,
+                                    auto_pairs,
+                                    surrounding_pairs: None,
+                                    run_command,
                                 },
                             };
                             syntax_config.insert(lang.to_string(), params);
                         }
+                        syntax_config.retain(|_, sc| !sc.changes.is_empty());
 
                         let json_setting: Vec<String> = syntax_config.iter().map(|(_, sc)| serde_json::to_string(sc).unwrap()).collect();
                         let json_setting: Vec<_> = json_setting.iter().map(AsRef::as_ref).collect();
@@ -456,7 +558,24 @@ fn syntax_config_set_buttons(
     syntax_config: &HashMap<String, SyntaxParams>,
     insert_spaces_switch: &Switch,
     tab_size_spinbutton: &SpinButton,
+    auto_pairs_switch: &Switch,
+    run_command_entry: &Entry,
 ) {
+    auto_pairs_switch.set_active(
+        syntax_config
+            .get(lang)
+            .and_then(|config| config.changes.auto_pairs)
+            .unwrap_or(true),
+    );
+
+    run_command_entry.set_text(
+        syntax_config
+            .get(lang)
+            .and_then(|config| config.changes.run_command.as_deref())
+            .unwrap_or_default(),
+    );
+    run_command_entry.set_placeholder_text(run_command::default_template(lang));
+
     if let Some(config) = syntax_config.get(lang) {
         // This is an Option, so set a default here
         let insert_spaces = if let Some(setting) = config.changes.translate_tabs_to_spaces {
@@ -0,0 +1,181 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use editview::diagnostics::{self, Diagnostic, Severity};
+use editview::i18n::ni18n_f;
+use editview::EditView;
+use gettextrs::gettext;
+use glib::clone;
+use gtk::prelude::*;
+use gtk::{Builder, Button, Label, ListStore, TreePath, TreeView};
+use log::trace;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+
+/// The columns of the `problems_liststore`
+const COLUMN_ICON: u32 = 0;
+const COLUMN_MARKUP: u32 = 1;
+const COLUMN_LOCATION: u32 = 2;
+const COLUMN_TOOLTIP: u32 = 3;
+
+/// The themed icon shown in front of diagnostics of `severity`
+fn icon_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "dialog-error-symbolic",
+        Severity::Warning => "dialog-warning-symbolic",
+        Severity::Information | Severity::Hint => "dialog-information-symbolic",
+    }
+}
+
+/// The summary shown next to the panel's title, e.g. "2 errors, 1 warning"
+fn summary(diagnostics: &[Diagnostic]) -> String {
+    if diagnostics.is_empty() {
+        return gettext("No problems");
+    }
+    let (errors, warnings, others) = diagnostics::count(diagnostics);
+    let mut parts = vec![
+        ni18n_f(
+            "{} error",
+            "{} errors",
+            errors as u32,
+            &[&errors.to_string()],
+        ),
+        ni18n_f(
+            "{} warning",
+            "{} warnings",
+            warnings as u32,
+            &[&warnings.to_string()],
+        ),
+    ];
+    if others > 0 {
+        parts.push(ni18n_f(
+            "{} message",
+            "{} messages",
+            others as u32,
+            &[&others.to_string()],
+        ));
+    }
+    parts.join(", ")
+}
+
+/// The panel below the documents, next to the terminals, which lists the diagnostics of all open
+/// documents
+pub struct Problems {
+    /// The panel, to be added to the bottom of the `MainWin`
+    pub widget: gtk::Box,
+    status_label: Label,
+    close_button: Button,
+    treeview: TreeView,
+    list_store: ListStore,
+    /// The document and line-cache row each row of the list points at
+    shown: RefCell<Vec<(Weak<EditView>, u64)>>,
+    /// Called with the document and row of the diagnostic the user activates
+    on_activate: RefCell<Option<Box<dyn Fn(&Rc<EditView>, u64)>>>,
+}
+
+impl Problems {
+    pub fn new() -> Rc<Self> {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/problems.glade");
+
+        let problems = Rc::new(Self {
+            widget: builder.get_object("problems_box").unwrap(),
+            status_label: builder.get_object("problems_status_label").unwrap(),
+            close_button: builder.get_object("problems_close_button").unwrap(),
+            treeview: builder.get_object("problems_treeview").unwrap(),
+            list_store: builder.get_object("problems_liststore").unwrap(),
+            shown: Default::default(),
+            on_activate: Default::default(),
+        });
+
+        problems.treeview.connect_row_activated(
+            clone!(@weak problems => @default-panic, move |_, path, _| {
+                if let Some(row) = path.get_indices().first() {
+                    problems.open(*row as usize);
+                }
+            }),
+        );
+
+        problems
+    }
+
+    /// Call `f` with the document and row of the diagnostic the user activates
+    pub fn connect_problem_activated<F: Fn(&Rc<EditView>, u64) + 'static>(&self, f: F) {
+        self.on_activate.replace(Some(Box::new(f)));
+    }
+
+    /// Call `f` when the user closes the panel
+    pub fn connect_close<F: Fn() + 'static>(&self, f: F) {
+        self.close_button.connect_clicked(move |_| f());
+    }
+
+    /// Focus the list, selecting its first row
+    pub fn start(&self) {
+        if self.treeview.get_selection().count_selected_rows() == 0
+            && !self.shown.borrow().is_empty()
+        {
+            let path = TreePath::new_from_indicesv(&[0]);
+            self.treeview.get_selection().select_path(&path);
+        }
+        self.treeview.grab_focus();
+    }
+
+    /// List the diagnostics of `views` in their order, e.g. the order of their tabs
+    pub fn update(&self, views: &[Rc<EditView>]) {
+        trace!("Updating the problems of {} documents", views.len());
+        let mut shown = Vec::new();
+        let mut all = Vec::new();
+
+        self.list_store.clear();
+        for view in views {
+            let file_name = view.file_name.borrow().clone();
+            let name = file_name
+                .as_deref()
+                .and_then(|f| Path::new(f).file_name())
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| gettext("Untitled"));
+            for diagnostic in view.diagnostics() {
+                let ((line, col), _) = diagnostic.range;
+                let markup = match &diagnostic.source {
+                    Some(source) => format!(
+                        "{} <span alpha=\"60%\">{}</span>",
+                        glib::markup_escape_text(&diagnostic.message),
+                        glib::markup_escape_text(source)
+                    ),
+                    None => glib::markup_escape_text(&diagnostic.message).to_string(),
+                };
+                let location = format!("{}:{}:{}", name, line + 1, col + 1);
+                let tooltip = match &file_name {
+                    Some(file_name) => format!("{}:{}:{}", file_name, line + 1, col + 1),
+                    None => location.clone(),
+                };
+                self.list_store.insert_with_values(
+                    None,
+                    &[COLUMN_ICON, COLUMN_MARKUP, COLUMN_LOCATION, COLUMN_TOOLTIP],
+                    &[
+                        &icon_name(diagnostic.severity),
+                        &markup,
+                        &location,
+                        &tooltip,
+                    ],
+                );
+                shown.push((Rc::downgrade(view), line));
+                all.push(diagnostic);
+            }
+        }
+
+        self.status_label.set_text(&summary(&all));
+        self.shown.replace(shown);
+    }
+
+    /// Jump to the diagnostic in `row`
+    fn open(&self, row: usize) {
+        let (view, line) = match self.shown.borrow().get(row) {
+            Some((view, line)) => (view.upgrade(), *line),
+            None => return,
+        };
+        if let (Some(view), Some(f)) = (view, &*self.on_activate.borrow()) {
+            f(&view, line);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use git2::Repository;
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Don't index more files than this, e.g. if the project root is the home directory
+const MAX_FILES: usize = 50_000;
+
+/// How many paths are collected before they're handed out
+const BATCH_SIZE: usize = 500;
+
+/// The git repository `root` is in, with the path of `root` relative to its working directory
+fn find_repo(root: &Path) -> Option<(Repository, PathBuf)> {
+    let repo = Repository::discover(root).ok()?;
+    let root = fs::canonicalize(root).ok()?;
+    let workdir = fs::canonicalize(repo.workdir()?).ok()?;
+    let prefix = root.strip_prefix(workdir).ok()?.to_path_buf();
+    Some((repo, prefix))
+}
+
+/// Check whether `path`, relative to the working directory of `repo`, is ignored by its
+/// `.gitignore`s
+fn is_ignored(repo: &Repository, path: &Path) -> bool {
+    repo.is_path_ignored(path).unwrap_or_else(|e| {
+        warn!(
+            "Failed to check if {:?} is ignored due to error: {}",
+            path, e
+        );
+        false
+    })
+}
+
+/// Collect the paths of all files under `root`, relative to it, leaving out hidden and
+/// `.gitignore`d entries. The paths are handed to `send` in batches, as soon as they're found;
+/// stops early if `send` returns false. Meant to be run in a separate thread.
+pub fn index_files<F: FnMut(Vec<String>) -> bool>(root: &Path, mut send: F) {
+    debug!("Indexing files under {:?}", root);
+
+    // Git knows best which files its `.gitignore`s leave out
+    let repo = find_repo(root);
+    let mut batch = Vec::new();
+    let mut n_files = 0;
+    // Directories which still have to be visited
+    let mut stack: Vec<(PathBuf, String)> = vec![(root.to_path_buf(), String::new())];
+
+    while let Some((dir, relative_dir)) = stack.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to read directory {:?} due to error: {}", dir, e);
+                continue;
+            }
+        };
+
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let relative = if relative_dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative_dir, name)
+            };
+            // Don't follow symlinks to directories, they might loop
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if let Some((repo, prefix)) = &repo {
+                if is_ignored(repo, &prefix.join(&relative)) {
+                    continue;
+                }
+            }
+
+            if is_dir {
+                stack.push((entry.path(), relative));
+            } else {
+                batch.push(relative);
+                n_files += 1;
+                if batch.len() >= BATCH_SIZE && !send(std::mem::take(&mut batch)) {
+                    return;
+                }
+                if n_files >= MAX_FILES {
+                    warn!(
+                        "Not indexing more than {} files under {:?}",
+                        MAX_FILES, root
+                    );
+                    send(batch);
+                    return;
+                }
+            }
+        }
+    }
+
+    send(batch);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn index_leaves_out_ignored_files() {
+        let dir = TempDir::new("project");
+        Repository::init(&*dir).unwrap();
+        let files = [
+            "src/main.rs",
+            "target/debug/tau",
+            "debug.log",
+            "docs/keep.log",
+            "docs/draft.tmp",
+        ];
+        for file in &files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "/target\n*.log\n!docs/keep.log\n").unwrap();
+        fs::write(dir.join("docs/.gitignore"), "*.tmp\n").unwrap();
+
+        let mut files = Vec::new();
+        index_files(&dir, |batch| {
+            files.extend(batch);
+            true
+        });
+        files.sort();
+        assert_eq!(files, vec!["docs/keep.log", "src/main.rs"]);
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::fuzzy_list::{rank, FuzzyList};
+use crate::project::index_files;
+use editview::completion::fuzzy_score;
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{Builder, Widget};
+use log::{debug, trace};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// How many files are shown at most
+const MAX_RESULTS: usize = 100;
+
+/// Sent from the indexing thread to the `QuickOpen`
+enum IndexMsg {
+    /// More files have been found by the indexing run with this generation
+    Batch(u64, Vec<String>),
+    /// The indexing run with this generation is complete
+    Done(u64),
+}
+
+/// A popover which fuzzy-searches the files under the project root, opened with Ctrl+P
+pub struct QuickOpen {
+    list: Rc<FuzzyList>,
+    sender: Sender<IndexMsg>,
+    /// The directory `files` belong to
+    root: RefCell<Option<PathBuf>>,
+    /// All files under `root`, relative to it
+    files: RefCell<Vec<String>>,
+    /// The files found by the running reindexing, they replace `files` once it's complete
+    pending: RefCell<Vec<String>>,
+    /// Whether `files` has been indexed completely at least once
+    complete: Cell<bool>,
+    /// Whether an indexing thread is running right now
+    indexing: Cell<bool>,
+    /// Incremented for every indexing run, so outdated runs stop and their results are dropped
+    generation: Arc<AtomicU64>,
+}
+
+impl QuickOpen {
+    /// Create a new `QuickOpen`, which calls `on_file` with the file the user has chosen
+    pub fn new<W, F>(relative_to: &W, on_file: F) -> Rc<Self>
+    where
+        W: IsA<Widget>,
+        F: Fn(PathBuf) + 'static,
+    {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/quick_open.glade");
+
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+
+        let quick_open = Rc::new(Self {
+            list: FuzzyList::new(&builder, "quick_open", relative_to),
+            sender,
+            root: Default::default(),
+            files: Default::default(),
+            pending: Default::default(),
+            complete: Cell::new(false),
+            indexing: Cell::new(false),
+            generation: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak quick_open => @default-return glib::Continue(false), move |msg| {
+                quick_open.receive(msg);
+                glib::Continue(true)
+            }),
+        );
+
+        quick_open.list.connect_search_changed(
+            clone!(@weak quick_open => @default-panic, move || {
+                quick_open.filter();
+            }),
+        );
+
+        quick_open.list.connect_activated(
+            clone!(@weak quick_open => @default-panic, move |index| {
+                let file = quick_open.files.borrow().get(index).cloned();
+                let root = quick_open.root.borrow().clone();
+                if let (Some(root), Some(file)) = (root, file) {
+                    on_file(root.join(file));
+                }
+            }),
+        );
+
+        quick_open
+    }
+
+    /// Show the popover at the top of the widget it's relative to, offering the files under `root`
+    pub fn show(&self, root: &Path) {
+        if self.root.borrow().as_deref() != Some(root) {
+            self.root.replace(Some(root.to_path_buf()));
+            self.files.borrow_mut().clear();
+            self.complete.set(false);
+            self.start_indexing(root);
+        } else if !self.indexing.get() {
+            // Pick up files which have been added or removed since the last time
+            self.start_indexing(root);
+        }
+
+        self.list.show();
+    }
+
+    /// Index the files under `root` in a separate thread, stopping any indexing which is still
+    /// running
+    fn start_indexing(&self, root: &Path) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.pending.borrow_mut().clear();
+        self.indexing.set(true);
+
+        let current_generation = self.generation.clone();
+        let sender = self.sender.clone();
+        let root = root.to_path_buf();
+        thread::spawn(move || {
+            index_files(&root, |batch| {
+                current_generation.load(Ordering::SeqCst) == generation
+                    && sender.send(IndexMsg::Batch(generation, batch)).is_ok()
+            });
+            sender.send(IndexMsg::Done(generation)).ok();
+        });
+    }
+
+    fn receive(&self, msg: IndexMsg) {
+        let current_generation = self.generation.load(Ordering::SeqCst);
+        match msg {
+            IndexMsg::Batch(generation, batch) if generation == current_generation => {
+                if self.complete.get() {
+                    self.pending.borrow_mut().extend(batch);
+                } else {
+                    // Offer the files as soon as they're found while indexing for the first time
+                    self.files.borrow_mut().extend(batch);
+                    self.refresh();
+                }
+            }
+            IndexMsg::Done(generation) if generation == current_generation => {
+                if self.complete.get() {
+                    let pending = std::mem::take(&mut *self.pending.borrow_mut());
+                    self.files.replace(pending);
+                }
+                self.files.borrow_mut().sort();
+                debug!("Indexed {} files", self.files.borrow().len());
+                self.complete.set(true);
+                self.indexing.set(false);
+                self.refresh();
+            }
+            _ => {}
+        }
+    }
+
+    /// Filter the files again if the popover is visible
+    fn refresh(&self) {
+        if self.list.is_visible() {
+            self.filter();
+        }
+    }
+
+    /// Show the files which match the text of the search entry, best match first. Matches in the
+    /// file name are preferred over matches in the directories.
+    fn filter(&self) {
+        let pattern = self.list.pattern();
+        let files = self.files.borrow();
+        trace!("Filtering {} files for '{}'", files.len(), pattern);
+
+        let shown = rank(&pattern, &files, MAX_RESULTS, |path| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let name_score = fuzzy_score(&pattern, name).map(|score| score + 10_000);
+            name_score.max(fuzzy_score(&pattern, path))
+        });
+        let rows: Vec<(&str, &str)> = shown
+            .iter()
+            .map(|i| {
+                let path = &files[*i];
+                match path.rfind('/') {
+                    Some(pos) => (&path[pos + 1..], &path[..pos]),
+                    None => (path.as_str(), ""),
+                }
+            })
+            .collect();
+        self.list.set_rows(shown, rows);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Backups of the documents with unsaved changes, which are restored after Tau has been quit with
+//! hot exit or has crashed. Each document is stored as a JSON file in `functions::recovery_dir`,
+//! the files the documents belong to are never touched.
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Backups older than this are deleted instead of restored
+pub const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The unsaved state of a document
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Backup {
+    /// The file of the document, `None` if it's untitled
+    #[serde(default)]
+    pub path: Option<String>,
+    /// The syntax the user has chosen, if it's not the detected one
+    #[serde(default)]
+    pub language: Option<String>,
+    pub text: String,
+    /// When the backup was written, in seconds since the Unix epoch
+    pub saved_at: u64,
+    /// The process which wrote the backup
+    pub pid: u32,
+}
+
+impl Backup {
+    pub fn new(path: Option<String>, language: Option<String>, text: String) -> Self {
+        Self {
+            path,
+            language,
+            text,
+            saved_at: now(),
+            pid: std::process::id(),
+        }
+    }
+
+    /// Whether restoring the backup wouldn't give the user anything back, e.g. because it's too
+    /// old or the document has been saved with the same text since
+    fn is_stale(&self, now: u64) -> bool {
+        if now.saturating_sub(self.saved_at) > MAX_AGE.as_secs() {
+            return true;
+        }
+        match &self.path {
+            Some(path) => std::fs::read_to_string(path).map_or(false, |text| text == self.text),
+            None => self.text.is_empty(),
+        }
+    }
+}
+
+/// The current time in seconds since the Unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Whether the process `pid` is still running, in which case its backups belong to it
+fn process_running(pid: u32) -> bool {
+    pid != std::process::id() && Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+/// The ID of the backup of a document of this process, which is unique across all instances
+pub fn backup_id(view_id: &str) -> String {
+    format!("{}-{}", std::process::id(), view_id)
+}
+
+/// Write `backup` to `dir`, replacing an older backup with the same ID atomically
+pub fn write(dir: &Path, id: &str, backup: &Backup) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{}.json", id));
+    let tmp_path = dir.join(format!("{}.json.tmp", id));
+    let json = serde_json::to_string(backup).map_err(|e| e.to_string())?;
+    std::fs::write(&tmp_path, json)
+        .and_then(|_| std::fs::rename(&tmp_path, &path))
+        .map_err(|e| format!("{:?}: {}", path, e))
+}
+
+/// Delete the backup with `id`, e.g. once its document has been saved or closed
+pub fn remove(dir: &Path, id: &str) {
+    let path = dir.join(format!("{}.json", id));
+    if let Err(e) = std::fs::remove_file(&path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!("Failed to remove backup {:?}: {}", path, e);
+        }
+    }
+}
+
+/// The backups left behind by instances of Tau which aren't running anymore, with their IDs and
+/// sorted by when they were written. Stale and unreadable backups are deleted, as are older
+/// backups of the same file.
+pub fn orphaned_backups(dir: &Path) -> Vec<(String, Backup)> {
+    let now = now();
+    let mut backups: Vec<(String, Backup)> = Vec::new();
+    for path in std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+    {
+        let id = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.ends_with(".json") => name.trim_end_matches(".json").to_string(),
+            // Left over when writing a backup has been interrupted
+            Some(name) if name.ends_with(".json.tmp") => {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            _ => continue,
+        };
+        let backup = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<Backup>(&json).map_err(|e| e.to_string()));
+        match backup {
+            Ok(backup) if process_running(backup.pid) => {}
+            Ok(backup) if !backup.is_stale(now) => backups.push((id, backup)),
+            Ok(_) => remove(dir, &id),
+            Err(e) => {
+                warn!("Deleting unreadable backup {:?}: {}", path, e);
+                remove(dir, &id);
+            }
+        }
+    }
+
+    backups.sort_by_key(|(_, backup)| backup.saved_at);
+    // Only the newest backup of each file is restored
+    let mut newest: Vec<(String, Backup)> = Vec::new();
+    for (id, backup) in backups.into_iter().rev() {
+        let duplicate =
+            backup.path.is_some() && newest.iter().any(|(_, newer)| newer.path == backup.path);
+        if duplicate {
+            remove(dir, &id);
+        } else {
+            newest.insert(0, (id, backup));
+        }
+    }
+    newest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn backup(path: Option<&str>, text: &str, saved_at: u64) -> Backup {
+        Backup {
+            path: path.map(String::from),
+            language: None,
+            text: text.to_string(),
+            saved_at,
+            // A process which can't be running
+            pid: u32::max_value(),
+        }
+    }
+
+    #[test]
+    fn restore_orphaned_backups() {
+        let dir = TempDir::new("recovery");
+        let saved = dir.join("saved.txt");
+        std::fs::write(&saved, "saved text").unwrap();
+        let saved = saved.to_str().unwrap();
+        let now = now();
+
+        write(&dir, "1-untitled", &backup(None, "draft", now - 10)).unwrap();
+        write(&dir, "1-old", &backup(Some("/a.rs"), "old", now - 20)).unwrap();
+        write(&dir, "2-new", &backup(Some("/a.rs"), "new", now - 5)).unwrap();
+        write(&dir, "1-saved", &backup(Some(saved), "saved text", now)).unwrap();
+        write(
+            &dir,
+            "1-expired",
+            &backup(None, "x", now - MAX_AGE.as_secs() - 1),
+        )
+        .unwrap();
+        write(&dir, "1-empty", &backup(None, "", now)).unwrap();
+        let mut running = backup(None, "mine", now);
+        running.pid = 1;
+        write(&dir, "1-running", &running).unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        std::fs::write(dir.join("1-partial.json.tmp"), "{").unwrap();
+
+        let ids: Vec<String> = orphaned_backups(&dir)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec!["1-untitled", "2-new"]);
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "1-running.json",
+                "1-untitled.json",
+                "2-new.json",
+                "saved.txt"
+            ]
+        );
+
+        remove(&dir, "2-new");
+        remove(&dir, "2-new");
+    }
+}
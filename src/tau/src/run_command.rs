@@ -0,0 +1,100 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! The commands which run the current file in the terminal. They're templates in which `{file}`,
+//! `{dir}` and `{name}` are replaced with the file's path, its directory and its name without
+//! extension.
+
+use std::path::Path;
+
+/// The template used for files of `syntax` (as xi calls it) if the user hasn't configured one
+pub fn default_template(syntax: &str) -> Option<&'static str> {
+    let template = match syntax {
+        "Python" => "python3 {file}",
+        "Ruby" => "ruby {file}",
+        "Perl" => "perl {file}",
+        "Lua" => "lua {file}",
+        "PHP" => "php {file}",
+        "JavaScript" => "node {file}",
+        "Go" => "go run {file}",
+        "Bourne Again Shell (bash)" => "bash {file}",
+        "Shell-Unix-Generic" => "sh {file}",
+        "Rust" => "rustc {file} -o \"${TMPDIR:-/tmp}\"/{name} && \"${TMPDIR:-/tmp}\"/{name}",
+        "C" => "cc {file} -o \"${TMPDIR:-/tmp}\"/{name} && \"${TMPDIR:-/tmp}\"/{name}",
+        "C++" => "c++ {file} -o \"${TMPDIR:-/tmp}\"/{name} && \"${TMPDIR:-/tmp}\"/{name}",
+        _ => return None,
+    };
+    Some(template)
+}
+
+/// Quote `s` so `sh` treats it as a single word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Fill the placeholders of `template` for the file at `path`, quoting the values for `sh`
+pub fn expand(template: &str, path: &Path) -> String {
+    let file = path.to_string_lossy();
+    let dir = path
+        .parent()
+        .map(|dir| dir.to_string_lossy())
+        .unwrap_or_default();
+    let name = path
+        .file_stem()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let placeholders = [("{file}", file), ("{dir}", dir), ("{name}", name)];
+
+    // Replace them in one pass, so placeholders in the path itself are kept
+    let mut command = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        command.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholders.iter().find(|(p, _)| rest.starts_with(p)) {
+            Some((placeholder, value)) => {
+                command.push_str(&shell_quote(value));
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                command.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    command.push_str(rest);
+    command
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("main.py"), "'main.py'");
+        assert_eq!(shell_quote("it's.py"), r"'it'\''s.py'");
+    }
+
+    #[test]
+    fn expansion() {
+        let path = Path::new("/home/user/my project/main.py");
+        assert_eq!(
+            expand("python3 {file}", path),
+            "python3 '/home/user/my project/main.py'"
+        );
+        assert_eq!(
+            expand("cd {dir} && ./{name}", path),
+            "cd '/home/user/my project' && ./'main'"
+        );
+        assert_eq!(
+            expand(default_template("Rust").unwrap(), Path::new("/src/a.rs")),
+            "rustc '/src/a.rs' -o \"${TMPDIR:-/tmp}\"/'a' && \"${TMPDIR:-/tmp}\"/'a'"
+        );
+        assert_eq!(
+            expand("echo {file} ${HOME}", Path::new("/tmp/{dir}.sh")),
+            "echo '/tmp/{dir}.sh' ${HOME}"
+        );
+        assert_eq!(default_template("Plain Text"), None);
+    }
+}
@@ -1,7 +1,27 @@
 // Copyright (C) 2019 Tom Steu <steudtner.tom@gmail.com>
 // SPDX-License-Identifier: MIT
 
+use crate::sessions::{SavedView, Session};
+use crate::terminals::SessionTerminal;
 use gio::SettingsExt;
+use gtk::Orientation;
+
+/// How an `Orientation` is stored in the `session-splits` key
+pub fn orientation_to_str(orientation: Orientation) -> &'static str {
+    match orientation {
+        Orientation::Vertical => "vertical",
+        _ => "horizontal",
+    }
+}
+
+/// The `Orientation` stored as `s` by `orientation_to_str`
+pub fn orientation_from_str(s: &str) -> Option<Orientation> {
+    match s {
+        "vertical" => Some(Orientation::Vertical),
+        "horizontal" => Some(Orientation::Horizontal),
+        _ => None,
+    }
+}
 
 pub trait SessionHandler {
     /// Add path to session
@@ -10,6 +30,20 @@ pub trait SessionHandler {
     fn session_remove(&self, path: &str);
     /// List all paths in current session
     fn get_session(&self) -> Vec<String>;
+    /// Forget about all paths and split views of the current session
+    fn session_clear(&self);
+    /// Remember that the file at `path` is shown in a split view
+    fn session_add_split(&self, path: &str, orientation: Orientation);
+    /// Forget about the split view of the file at `path`
+    fn session_remove_split(&self, path: &str);
+    /// Get the orientation of the split view of the file at `path`, if it's split
+    fn get_session_split(&self, path: &str) -> Option<Orientation>;
+    /// Remember the terminals which are open, replacing the ones of the previous session
+    fn set_session_terminals(&self, terminals: &[SessionTerminal]);
+    /// List the terminals of the most recent session
+    fn get_session_terminals(&self) -> Vec<SessionTerminal>;
+    /// The documents, split views and terminals stored in the keys as the `Session` called `name`
+    fn session_from_keys(&self, name: &str) -> Session;
 }
 
 impl SessionHandler for gio::Settings {
@@ -29,6 +63,7 @@ impl SessionHandler for gio::Settings {
             .filter_map(|x| if *x != path { Some(x.as_str()) } else { None })
             .collect();
         self.set_strv("session", new_session.as_slice()).unwrap();
+        self.session_remove_split(path);
     }
 
     fn get_session(&self) -> Vec<String> {
@@ -37,4 +72,77 @@ impl SessionHandler for gio::Settings {
             .map(|x| x.to_string())
             .collect()
     }
+
+    fn session_clear(&self) {
+        self.set_strv("session", &[]).unwrap();
+        self.set_strv("session-splits", &[]).unwrap();
+    }
+
+    fn session_add_split(&self, path: &str, orientation: Orientation) {
+        self.session_remove_split(path);
+        let entry = format!("{}:{}", orientation_to_str(orientation), path);
+        let old_splits = self.get_strv("session-splits");
+        let mut new_splits: Vec<_> = old_splits.iter().map(|x| x.as_str()).collect();
+        new_splits.push(&entry);
+        self.set_strv("session-splits", new_splits.as_slice())
+            .unwrap();
+    }
+
+    fn session_remove_split(&self, path: &str) {
+        let old_splits = self.get_strv("session-splits");
+        let new_splits: Vec<_> = old_splits
+            .iter()
+            .map(|x| x.as_str())
+            .filter(|x| x.splitn(2, ':').nth(1) != Some(path))
+            .collect();
+        if new_splits.len() != old_splits.len() {
+            self.set_strv("session-splits", new_splits.as_slice())
+                .unwrap();
+        }
+    }
+
+    fn get_session_split(&self, path: &str) -> Option<Orientation> {
+        self.get_strv("session-splits").iter().find_map(|x| {
+            let mut parts = x.splitn(2, ':');
+            let orientation = parts.next().and_then(orientation_from_str)?;
+            if parts.next() == Some(path) {
+                Some(orientation)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn set_session_terminals(&self, terminals: &[SessionTerminal]) {
+        let json: Vec<String> = terminals.iter().map(SessionTerminal::to_json).collect();
+        let json: Vec<_> = json.iter().map(String::as_str).collect();
+        self.set_strv("session-terminals", &json).unwrap();
+    }
+
+    fn get_session_terminals(&self) -> Vec<SessionTerminal> {
+        self.get_strv("session-terminals")
+            .iter()
+            .filter_map(|x| SessionTerminal::from_json(x))
+            .collect()
+    }
+
+    fn session_from_keys(&self, name: &str) -> Session {
+        let views = self
+            .get_session()
+            .into_iter()
+            .map(|path| SavedView {
+                split: self
+                    .get_session_split(&path)
+                    .map(|orientation| orientation_to_str(orientation).to_string()),
+                path,
+                ..SavedView::default()
+            })
+            .collect();
+        Session {
+            name: name.to_string(),
+            views,
+            terminals: self.get_session_terminals(),
+            ..Session::default()
+        }
+    }
 }
@@ -0,0 +1,178 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Named sessions, which the user can save and switch between. Each session is stored as a JSON
+//! file in `functions::sessions_dir` and records the open documents with their view state, the
+//! window geometry, the project and the terminals.
+
+use crate::terminals::SessionTerminal;
+use editview::text::Range;
+use gettextrs::gettext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The session which is used if the user hasn't chosen one
+pub const DEFAULT_SESSION: &str = "Default";
+
+/// An open document and the state of its view
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct SavedView {
+    pub path: String,
+    /// The selections and cursors, see `EditView::selections`
+    pub selections: Vec<Range>,
+    /// The line at the top of the visible area
+    pub first_line: f64,
+    /// The syntax the user has chosen, if it's not the detected one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The orientation of the split view of the document, as stored in `session-splits`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split: Option<String>,
+}
+
+/// The size of the window and its sidebar
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Geometry {
+    pub width: i32,
+    pub height: i32,
+    pub maximized: bool,
+    pub sidebar_visible: bool,
+    pub sidebar_width: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Session {
+    pub name: String,
+    /// The open documents, in the order of their tabs
+    pub views: Vec<SavedView>,
+    /// The index of the document in `views` whose tab is shown
+    pub active: Option<usize>,
+    pub project_root: Option<String>,
+    pub window: Option<Geometry>,
+    pub terminals: Vec<SessionTerminal>,
+}
+
+/// Make sure `name` can be used as the name of a session's file
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() || name.starts_with('.') || name.contains('/') {
+        Err(gettext(
+            "Session names can’t be empty, start with a dot or contain slashes.",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The file the session called `name` is stored in
+fn session_file(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    validate_name(name)?;
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+/// The names of all saved sessions, sorted alphabetically
+pub fn list_sessions(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .filter_map(|path| {
+            path.file_stem()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+impl Session {
+    /// Load the session called `name` from `dir`, `None` if it hasn't been saved yet
+    pub fn load(dir: &Path, name: &str) -> Result<Option<Self>, String> {
+        let path = session_file(dir, name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut session: Self = serde_json::from_str(&json)
+            .map_err(|e| format!("{} {:?}: {}", gettext("Couldn’t load session"), path, e))?;
+        session.name = name.to_string();
+        Ok(Some(session))
+    }
+
+    /// Save the session to its file in `dir`, replacing the file atomically
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let path = session_file(dir, &self.name)?;
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        let tmp_path = path.with_extension("json.tmp");
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&tmp_path, json)
+            .and_then(|_| std::fs::rename(&tmp_path, &path))
+            .map_err(|e| format!("{} {:?}: {}", gettext("Couldn’t save session"), path, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn names() {
+        assert!(validate_name("Work").is_ok());
+        assert!(validate_name("tau 0.12").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name(" ").is_err());
+        assert!(validate_name(".hidden").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = TempDir::new("sessions");
+        let session = Session {
+            name: "Work".to_string(),
+            views: vec![
+                SavedView {
+                    path: "/home/user/main.rs".to_string(),
+                    selections: vec![((3, 4), (5, 0)), ((7, 1), (7, 1))],
+                    first_line: 2.5,
+                    language: Some("Rust".to_string()),
+                    split: Some("vertical".to_string()),
+                },
+                SavedView {
+                    path: "/home/user/README.md".to_string(),
+                    ..SavedView::default()
+                },
+            ],
+            active: Some(1),
+            project_root: Some("/home/user".to_string()),
+            window: Some(Geometry {
+                width: 800,
+                height: 600,
+                maximized: false,
+                sidebar_visible: true,
+                sidebar_width: 250,
+            }),
+            terminals: vec![SessionTerminal {
+                profile: "Default".to_string(),
+                cwd: None,
+                title: "Terminal 1".to_string(),
+            }],
+        };
+
+        assert_eq!(Session::load(&dir, "Work"), Ok(None));
+        session.save(&dir).unwrap();
+        Session {
+            name: "Other".to_string(),
+            ..Session::default()
+        }
+        .save(&dir)
+        .unwrap();
+        assert_eq!(Session::load(&dir, "Work"), Ok(Some(session)));
+        assert_eq!(list_sessions(&dir), vec!["Other", "Work"]);
+        assert!(Session::load(&dir, "../Work").is_err());
+    }
+}
@@ -0,0 +1,586 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use crate::errors::{ErrorDialog, ErrorMsg};
+use gettextrs::gettext;
+use gio::prelude::*;
+use gio::{Cancellable, FileMonitor, FileMonitorEvent, FileMonitorFlags, SimpleAction};
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{
+    Builder, ButtonsType, DialogFlags, Entry, Inhibit, MessageDialog, MessageType, ResponseType,
+    TreeIter, TreeStore, TreeView,
+};
+use log::{debug, trace, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// The columns of the `sidebar_treestore`
+const COLUMN_NAME: u32 = 0;
+const COLUMN_PATH: u32 = 1;
+const COLUMN_IS_DIR: u32 = 2;
+const COLUMN_ICON_NAME: u32 = 3;
+
+/// The sidebar next to the `Notebook`, which shows the files of the project directory as a tree.
+/// Directories are only read once they're expanded and are kept up to date with a
+/// `gio::FileMonitor` from then on.
+pub struct Sidebar {
+    treeview: TreeView,
+    tree_store: TreeStore,
+    /// The directory shown in the sidebar
+    root: RefCell<Option<PathBuf>>,
+    /// The directories whose entries have been loaded, with the `FileMonitor` watching them
+    loaded: RefCell<HashMap<PathBuf, Option<FileMonitor>>>,
+    /// Sends the directories which have changed on disk, so they're reloaded
+    sender: Sender<PathBuf>,
+    rename_action: SimpleAction,
+    delete_action: SimpleAction,
+    /// Called with the files the user opens
+    on_file: RefCell<Option<Box<dyn Fn(PathBuf)>>>,
+}
+
+impl Sidebar {
+    /// Create a new `Sidebar` from the widgets in the `MainWin`'s `builder`
+    pub fn new(builder: &Builder) -> Rc<Self> {
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT_IDLE);
+
+        let sidebar = Rc::new(Self {
+            treeview: builder.get_object("sidebar_treeview").unwrap(),
+            tree_store: builder.get_object("sidebar_treestore").unwrap(),
+            root: Default::default(),
+            loaded: Default::default(),
+            sender,
+            rename_action: SimpleAction::new("rename", None),
+            delete_action: SimpleAction::new("delete", None),
+            on_file: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak sidebar => @default-return glib::Continue(false), move |dir| {
+                sidebar.reload(&dir);
+                glib::Continue(true)
+            }),
+        );
+
+        sidebar.treeview.connect_test_expand_row(
+            clone!(@weak sidebar => @default-return Inhibit(false), move |_, iter, _| {
+                if let Some(dir) = sidebar.get_path(iter) {
+                    if !sidebar.loaded.borrow().contains_key(&dir) {
+                        sidebar.load_children(Some(iter), &dir);
+                    }
+                }
+                Inhibit(false)
+            }),
+        );
+
+        sidebar.treeview.connect_row_activated(
+            clone!(@weak sidebar => @default-panic, move |treeview, path, _| {
+                let iter = match sidebar.tree_store.get_iter(path) {
+                    Some(iter) => iter,
+                    None => return,
+                };
+                if sidebar.is_dir(&iter) {
+                    if treeview.row_expanded(path) {
+                        treeview.collapse_row(path);
+                    } else {
+                        treeview.expand_row(path, false);
+                    }
+                } else if let Some(file) = sidebar.get_path(&iter) {
+                    sidebar.open(file);
+                }
+            }),
+        );
+
+        let action_group = gio::SimpleActionGroup::new();
+
+        {
+            let new_file_action = SimpleAction::new("new_file", None);
+            new_file_action.connect_activate(clone!(@weak sidebar => @default-panic, move |_,_| {
+                trace!("Handling action: 'sidebar.new_file'");
+                if let Some(file) = sidebar.create(false) {
+                    sidebar.open(file);
+                }
+            }));
+            action_group.add_action(&new_file_action);
+        }
+        {
+            let new_folder_action = SimpleAction::new("new_folder", None);
+            new_folder_action.connect_activate(
+                clone!(@weak sidebar => @default-panic, move |_,_| {
+                    trace!("Handling action: 'sidebar.new_folder'");
+                    sidebar.create(true);
+                }),
+            );
+            action_group.add_action(&new_folder_action);
+        }
+        {
+            sidebar.rename_action.connect_activate(
+                clone!(@weak sidebar => @default-panic, move |_,_| {
+                    trace!("Handling action: 'sidebar.rename'");
+                    sidebar.rename();
+                }),
+            );
+            action_group.add_action(&sidebar.rename_action);
+        }
+        {
+            sidebar.delete_action.connect_activate(
+                clone!(@weak sidebar => @default-panic, move |_,_| {
+                    trace!("Handling action: 'sidebar.delete'");
+                    sidebar.delete();
+                }),
+            );
+            action_group.add_action(&sidebar.delete_action);
+        }
+
+        sidebar
+            .treeview
+            .insert_action_group("sidebar", Some(&action_group));
+
+        let context_menu_builder =
+            Builder::new_from_resource("/org/gnome/Tau/sidebar_context_menu.glade");
+        let gmenu: gio::Menu = context_menu_builder
+            .get_object("sidebar_context_menu")
+            .unwrap();
+        let context_menu = gtk::Menu::new_from_model(&gmenu);
+        context_menu.set_property_attach_widget(Some(&sidebar.treeview));
+
+        sidebar.treeview.connect_button_press_event(
+            clone!(@weak sidebar => @default-return Inhibit(false), move |treeview, eb| {
+                if eb.get_button() != 3 {
+                    return Inhibit(false);
+                }
+
+                // Act on the row below the pointer, or on the project directory if there's none
+                let (x, y) = eb.get_position();
+                let selection = treeview.get_selection();
+                match treeview.get_path_at_pos(x as i32, y as i32) {
+                    Some((Some(path), _, _, _)) => selection.select_path(&path),
+                    _ => selection.unselect_all(),
+                }
+                let selected = selection.count_selected_rows() > 0;
+                sidebar.rename_action.set_enabled(selected);
+                sidebar.delete_action.set_enabled(selected);
+
+                context_menu.popup_at_pointer(Some(&eb));
+                Inhibit(true)
+            }),
+        );
+
+        sidebar
+    }
+
+    /// Call `f` with the files the user opens, either by activating them or by creating them
+    pub fn connect_file_activated<F: Fn(PathBuf) + 'static>(&self, f: F) {
+        self.on_file.replace(Some(Box::new(f)));
+    }
+
+    fn open(&self, file: PathBuf) {
+        if let Some(on_file) = self.on_file.borrow().as_ref() {
+            on_file(file);
+        }
+    }
+
+    /// Show the files in `root`, dropping everything which has been loaded so far
+    pub fn set_root(&self, root: PathBuf) {
+        if self.root.borrow().as_ref() == Some(&root) {
+            return;
+        }
+        debug!("Showing {:?} in the sidebar", root);
+
+        for monitor in self.loaded.borrow_mut().drain().filter_map(|(_, m)| m) {
+            monitor.cancel();
+        }
+        self.tree_store.clear();
+        self.load_children(None, &root);
+        self.root.replace(Some(root));
+    }
+
+    /// Expand the tree down to `file`, then select and scroll to it. Does nothing if `file` isn't
+    /// in the project directory.
+    pub fn reveal(&self, file: &Path) {
+        let iter = match self.find_iter(file) {
+            Some(iter) => iter,
+            None => return,
+        };
+        if let Some(path) = self.tree_store.get_path(&iter) {
+            trace!("Revealing {:?} in the sidebar", file);
+            self.treeview.expand_to_path(&path);
+            self.treeview.get_selection().select_path(&path);
+            self.treeview
+                .scroll_to_cell(Some(&path), None::<&gtk::TreeViewColumn>, true, 0.5, 0.0);
+        }
+    }
+
+    fn get_path(&self, iter: &TreeIter) -> Option<PathBuf> {
+        self.tree_store
+            .get_value(iter, COLUMN_PATH as i32)
+            .get::<String>()
+            .ok()
+            .flatten()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    }
+
+    fn is_dir(&self, iter: &TreeIter) -> bool {
+        self.tree_store
+            .get_value(iter, COLUMN_IS_DIR as i32)
+            .get_some::<bool>()
+            .unwrap_or(false)
+    }
+
+    /// Find the child of `parent` (or the top level row if it's `None`) showing `path`
+    fn find_child(&self, parent: Option<&TreeIter>, path: &Path) -> Option<TreeIter> {
+        let iter = self.tree_store.iter_children(parent)?;
+        loop {
+            if self.get_path(&iter).as_deref() == Some(path) {
+                return Some(iter);
+            }
+            if !self.tree_store.iter_next(&iter) {
+                return None;
+            }
+        }
+    }
+
+    /// Find the row showing `path`, loading the directories on the way to it
+    fn find_iter(&self, path: &Path) -> Option<TreeIter> {
+        let root = self.root.borrow().clone()?;
+        let relative = path.strip_prefix(&root).ok()?;
+
+        let mut iter = None;
+        let mut current = root;
+        for component in relative.components() {
+            if !self.loaded.borrow().contains_key(&current) {
+                self.load_children(iter.as_ref(), &current);
+            }
+            current.push(component);
+            iter = Some(self.find_child(iter.as_ref(), &current)?);
+        }
+        iter
+    }
+
+    /// Replace the children of `parent` with the entries of `dir`. Directories come first and
+    /// hidden entries are left out. Directories get a placeholder child, so they can be expanded
+    /// before their entries are loaded.
+    fn load_children(&self, parent: Option<&TreeIter>, dir: &Path) {
+        trace!("Loading the entries of {:?} into the sidebar", dir);
+
+        if let Some(child) = self.tree_store.iter_children(parent) {
+            while self.tree_store.remove(&child) {}
+        }
+
+        // Subdirectories are shown collapsed again, so they have to be loaded anew
+        self.loaded.borrow_mut().retain(|path, monitor| {
+            let keep = path == dir || !path.starts_with(dir);
+            if let (false, Some(monitor)) = (keep, monitor) {
+                monitor.cancel();
+            }
+            keep
+        });
+
+        let mut entries: Vec<(String, bool)> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .filter_map(|e| {
+                    let name = e.file_name().into_string().ok()?;
+                    let is_dir = e.path().is_dir();
+                    Some((name, is_dir))
+                })
+                .filter(|(name, _)| !name.starts_with('.'))
+                .collect(),
+            Err(e) => {
+                warn!("Failed to read directory {:?} due to error: {}", dir, e);
+                Vec::new()
+            }
+        };
+        entries.sort_by(|(a_name, a_dir), (b_name, b_dir)| {
+            b_dir
+                .cmp(a_dir)
+                .then_with(|| a_name.to_lowercase().cmp(&b_name.to_lowercase()))
+        });
+
+        for (name, is_dir) in entries {
+            let path = dir.join(&name).to_string_lossy().to_string();
+            let icon_name = if is_dir {
+                "folder-symbolic"
+            } else {
+                "text-x-generic-symbolic"
+            };
+            let iter = self.tree_store.insert_with_values(
+                parent,
+                None,
+                &[COLUMN_NAME, COLUMN_PATH, COLUMN_IS_DIR, COLUMN_ICON_NAME],
+                &[&name, &path, &is_dir, &icon_name],
+            );
+            if is_dir {
+                self.tree_store.insert_with_values(
+                    Some(&iter),
+                    None,
+                    &[COLUMN_NAME, COLUMN_PATH, COLUMN_IS_DIR],
+                    &[&"", &"", &false],
+                );
+            }
+        }
+
+        if !self.loaded.borrow().contains_key(dir) {
+            let monitor = self.monitor(dir);
+            self.loaded.borrow_mut().insert(dir.to_path_buf(), monitor);
+        }
+    }
+
+    /// Watch `dir` for entries which are created, deleted or renamed
+    fn monitor(&self, dir: &Path) -> Option<FileMonitor> {
+        let monitor = gio::File::new_for_path(dir)
+            .monitor_directory(FileMonitorFlags::WATCH_MOVES, None::<&Cancellable>)
+            .map_err(|e| warn!("Failed to monitor directory {:?} due to error: {}", dir, e))
+            .ok()?;
+
+        let sender = self.sender.clone();
+        let dir = dir.to_path_buf();
+        monitor.connect_changed(move |_, _, _, event| match event {
+            FileMonitorEvent::Created
+            | FileMonitorEvent::Deleted
+            | FileMonitorEvent::Moved
+            | FileMonitorEvent::MovedIn
+            | FileMonitorEvent::MovedOut
+            | FileMonitorEvent::Renamed => {
+                sender.send(dir.clone()).ok();
+            }
+            _ => {}
+        });
+
+        Some(monitor)
+    }
+
+    /// Load the entries of `dir` anew if they've been loaded before, keeping the subdirectories
+    /// which were expanded expanded
+    fn reload(&self, dir: &Path) {
+        if !self.loaded.borrow().contains_key(dir) {
+            return;
+        }
+
+        let parent = if self.root.borrow().as_deref() == Some(dir) {
+            None
+        } else {
+            match self.find_iter(dir) {
+                Some(iter) => Some(iter),
+                None => return,
+            }
+        };
+
+        let mut expanded = Vec::new();
+        self.collect_expanded(parent.as_ref(), &mut expanded);
+
+        self.load_children(parent.as_ref(), dir);
+
+        expanded.sort_by_key(|path| path.components().count());
+        for path in expanded {
+            if let Some(tree_path) = self
+                .find_iter(&path)
+                .and_then(|iter| self.tree_store.get_path(&iter))
+            {
+                self.treeview.expand_row(&tree_path, false);
+            }
+        }
+    }
+
+    /// Add the paths of all expanded directories below `parent` to `expanded`
+    fn collect_expanded(&self, parent: Option<&TreeIter>, expanded: &mut Vec<PathBuf>) {
+        let iter = match self.tree_store.iter_children(parent) {
+            Some(iter) => iter,
+            None => return,
+        };
+        loop {
+            let is_expanded = self
+                .tree_store
+                .get_path(&iter)
+                .map(|path| self.treeview.row_expanded(&path))
+                .unwrap_or(false);
+            if is_expanded {
+                if let Some(path) = self.get_path(&iter) {
+                    expanded.push(path);
+                }
+                self.collect_expanded(Some(&iter), expanded);
+            }
+            if !self.tree_store.iter_next(&iter) {
+                return;
+            }
+        }
+    }
+
+    /// The path and whether it's a directory of the selected row
+    fn selected(&self) -> Option<(PathBuf, bool)> {
+        let (_, iter) = self.treeview.get_selection().get_selected()?;
+        let path = self.get_path(&iter)?;
+        Some((path, self.is_dir(&iter)))
+    }
+
+    /// The directory new files are created in: the selected directory, the directory of the
+    /// selected file or the project directory
+    fn target_dir(&self) -> Option<PathBuf> {
+        match self.selected() {
+            Some((path, true)) => Some(path),
+            Some((path, false)) => path.parent().map(Path::to_path_buf),
+            None => self.root.borrow().clone(),
+        }
+    }
+
+    /// Ask the user for a name in a dialog showing `message`, with `initial` filled in
+    fn ask_name(&self, message: &str, initial: &str) -> Option<String> {
+        let window = self
+            .treeview
+            .get_toplevel()
+            .and_then(|w| w.downcast::<gtk::Window>().ok());
+        let dialog = MessageDialog::new(
+            window.as_ref(),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            message,
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("OK"), ResponseType::Ok);
+        dialog.set_default_response(ResponseType::Ok);
+
+        let entry = Entry::new();
+        entry.set_text(initial);
+        entry.set_activates_default(true);
+        if let Ok(message_area) = dialog.get_message_area().downcast::<gtk::Box>() {
+            message_area.pack_start(&entry, false, true, 0);
+        }
+        entry.show();
+
+        let response = dialog.run();
+        let name = entry.get_text().map(|s| s.to_string()).unwrap_or_default();
+        dialog.destroy();
+
+        if response != ResponseType::Ok {
+            return None;
+        }
+        let name = name.trim();
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            ErrorDialog::new(ErrorMsg::new(
+                format!("{}: “{}”", gettext("Invalid file name"), name),
+                false,
+            ));
+            return None;
+        }
+        Some(name.to_string())
+    }
+
+    /// Ask for a name and create a file or directory of that name. Returns the path of what has
+    /// been created.
+    fn create(&self, is_dir: bool) -> Option<PathBuf> {
+        let dir = self.target_dir()?;
+        let message = if is_dir {
+            gettext("Name of the new folder")
+        } else {
+            gettext("Name of the new file")
+        };
+        let path = dir.join(self.ask_name(&message, "")?);
+        debug!("Creating {:?}", path);
+
+        let res = if is_dir {
+            fs::create_dir(&path)
+        } else {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map(|_| ())
+        };
+        if let Err(e) = res {
+            ErrorDialog::new(ErrorMsg::new(
+                format!("{} “{}”: {}", gettext("Couldn’t create"), path.display(), e),
+                false,
+            ));
+            return None;
+        }
+
+        self.reload(&dir);
+        self.reveal(&path);
+        Some(path)
+    }
+
+    /// Ask for a new name for the selected file or directory and rename it
+    fn rename(&self) {
+        let (path, _) = match self.selected() {
+            Some(selected) => selected,
+            None => return,
+        };
+        let (dir, old_name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir.to_path_buf(), name.to_string_lossy().to_string()),
+            _ => return,
+        };
+        let new_name = match self.ask_name(&gettext("New name"), &old_name) {
+            Some(name) if name != old_name => name,
+            _ => return,
+        };
+        let new_path = dir.join(new_name);
+        debug!("Renaming {:?} to {:?}", path, new_path);
+
+        let res = if new_path.exists() {
+            Err(gettext("A file with this name already exists"))
+        } else {
+            fs::rename(&path, &new_path).map_err(|e| e.to_string())
+        };
+        if let Err(e) = res {
+            ErrorDialog::new(ErrorMsg::new(
+                format!("{} “{}”: {}", gettext("Couldn’t rename"), path.display(), e),
+                false,
+            ));
+            return;
+        }
+
+        self.reload(&dir);
+        self.reveal(&new_path);
+    }
+
+    /// Move the selected file or directory to the trash after the user confirmed it
+    fn delete(&self) {
+        let (path, _) = match self.selected() {
+            Some(selected) => selected,
+            None => return,
+        };
+
+        let window = self
+            .treeview
+            .get_toplevel()
+            .and_then(|w| w.downcast::<gtk::Window>().ok());
+        let dialog = MessageDialog::new(
+            window.as_ref(),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            MessageType::Question,
+            ButtonsType::None,
+            &format!(
+                "{} “{}”?",
+                gettext("Move to trash"),
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+        );
+        dialog.add_button(&gettext("Cancel"), ResponseType::Cancel);
+        dialog.add_button(&gettext("Move to Trash"), ResponseType::Accept);
+        dialog.set_default_response(ResponseType::Cancel);
+        let response = dialog.run();
+        dialog.destroy();
+        if response != ResponseType::Accept {
+            return;
+        }
+        debug!("Moving {:?} to the trash", path);
+
+        if let Err(e) = gio::File::new_for_path(&path).trash(None::<&Cancellable>) {
+            ErrorDialog::new(ErrorMsg::new(
+                format!("{} “{}”: {}", gettext("Couldn’t delete"), path.display(), e),
+                false,
+            ));
+            return;
+        }
+
+        if let Some(dir) = path.parent() {
+            self.reload(dir);
+        }
+    }
+}
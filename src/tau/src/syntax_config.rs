@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 
 pub use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SyntaxParams {
@@ -9,6 +10,20 @@ pub struct SyntaxParams {
     pub changes: Changes,
 }
 
+impl SyntaxParams {
+    /// The parameters of xi's `modify_user_config`, without the settings only Tau uses
+    pub fn to_xi_config(&self) -> Value {
+        json!({
+            "domain": self.domain,
+            "changes": XiChanges {
+                translate_tabs_to_spaces: self.changes.translate_tabs_to_spaces,
+                tab_size: self.changes.tab_size,
+                surrounding_pairs: self.changes.surrounding_pairs.as_ref(),
+            },
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Domain {
     pub syntax: String,
@@ -18,4 +33,55 @@ pub struct Domain {
 pub struct Changes {
     pub translate_tabs_to_spaces: Option<bool>,
     pub tab_size: Option<u32>,
+    /// Whether brackets and quotes are closed automatically. Only used by Tau, not by xi.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_pairs: Option<bool>,
+    /// The pairs xi surrounds selections with, which are also closed automatically
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub surrounding_pairs: Option<Vec<(String, String)>>,
+    /// The command template which runs the current file in the terminal, see `run_command`. Only
+    /// used by Tau, not by xi.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_command: Option<String>,
+}
+
+/// The settings of `Changes` xi knows about
+#[derive(Serialize)]
+struct XiChanges<'a> {
+    translate_tabs_to_spaces: Option<bool>,
+    tab_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    surrounding_pairs: Option<&'a Vec<(String, String)>>,
+}
+
+impl Changes {
+    /// Returns true if none of the settings differ from the general config
+    pub fn is_empty(&self) -> bool {
+        self.translate_tabs_to_spaces.is_none()
+            && self.tab_size.is_none()
+            && self.auto_pairs.is_none()
+            && self.surrounding_pairs.is_none()
+            && self.run_command.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn xi_config_leaves_out_tau_settings() {
+        let params: SyntaxParams = serde_json::from_str(
+            r#"{"domain": {"syntax": "Rust"}, "changes": {"tab_size": 4, "auto_pairs": false,
+                "run_command": "cargo run"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            params.to_xi_config(),
+            json!({
+                "domain": { "syntax": "Rust" },
+                "changes": { "translate_tabs_to_spaces": null, "tab_size": 4 },
+            })
+        );
+    }
 }
@@ -0,0 +1,692 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+use editview::i18n::freplace;
+use gettextrs::gettext;
+use glib::{clone, MainContext, Sender};
+use gtk::prelude::*;
+use gtk::{Builder, Button, Image, Label, Spinner, TextBuffer, TextTag, TextView};
+use log::{debug, trace, warn};
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, BufReader};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often the thread reading the output of a task checks whether it has exited
+const EXIT_POLL_INTERVAL_MS: u64 = 50;
+
+/// A command of the project the user can run, e.g. `cargo build`
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Task {
+    pub name: String,
+    /// Run with `sh -c`, so it may use pipes, `&&` and so on
+    pub command: String,
+    /// The directory the command is run in, relative to the project root
+    #[serde(default)]
+    pub cwd: Option<String>,
+}
+
+impl Task {
+    fn new(name: &str, command: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            cwd: None,
+        }
+    }
+}
+
+/// The file in the project directory `root` the user defines its tasks in
+pub fn tasks_file(root: &Path) -> PathBuf {
+    root.join(".tau").join("tasks.json")
+}
+
+/// Parse the tasks defined in a `tasks.json`, which is a list like
+/// `[{ "name": "Build", "command": "make", "cwd": "src" }]`
+pub fn parse_tasks(text: &str) -> Result<Vec<Task>, serde_json::Error> {
+    serde_json::from_str(text)
+}
+
+/// The tasks of the build systems found in the project directory `root`, used if the project
+/// doesn't define its own
+pub fn default_tasks(root: &Path) -> Vec<Task> {
+    let mut tasks = Vec::new();
+    if root.join("Cargo.toml").is_file() {
+        tasks.push(Task::new("cargo build", "cargo build"));
+        tasks.push(Task::new("cargo test", "cargo test"));
+        tasks.push(Task::new("cargo run", "cargo run"));
+    }
+    if ["Makefile", "makefile", "GNUmakefile"]
+        .iter()
+        .any(|name| root.join(name).is_file())
+    {
+        tasks.push(Task::new("make", "make"));
+        tasks.push(Task::new("make test", "make test"));
+    }
+    if root.join("package.json").is_file() {
+        tasks.push(Task::new("npm run build", "npm run build"));
+        tasks.push(Task::new("npm test", "npm test"));
+    }
+    tasks
+}
+
+/// Load the tasks the project in `root` defines, falling back to the default ones of its build
+/// system if there's no `tasks.json`
+pub fn load_tasks(root: &Path) -> Result<Vec<Task>, String> {
+    let path = tasks_file(root);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => parse_tasks(&text).map_err(|e| {
+            format!(
+                "{} “{}”: {}",
+                gettext("Couldn’t parse"),
+                path.to_string_lossy(),
+                e
+            )
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(default_tasks(root)),
+        Err(e) => Err(format!(
+            "{} “{}”: {}",
+            gettext("Couldn’t read"),
+            path.to_string_lossy(),
+            e
+        )),
+    }
+}
+
+/// A position in a file mentioned in the output of a task, e.g. `src/main.rs:10:5`
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputLink {
+    /// The byte range of the mention in the line of output
+    pub start: usize,
+    pub end: usize,
+    pub path: String,
+    /// The line and column as printed, starting at 1
+    pub line: u64,
+    pub col: Option<u64>,
+}
+
+/// Finds mentions of positions in files in the output of compilers, test runners and
+/// interpreters
+pub struct LinkFinder {
+    /// `path:line` and `path:line:col`, as printed by rustc, gcc, clang, go and many more
+    location: Regex,
+    /// `File "path", line 10`, as printed in Python tracebacks
+    python: Regex,
+    /// The escape sequences of coloured output
+    ansi: Regex,
+}
+
+impl LinkFinder {
+    pub fn new() -> Self {
+        Self {
+            location: Regex::new(
+                r#"(?P<path>(?:[A-Za-z]:)?[^\s:"'()\[\]<>,]*[./\\][^\s:"'()\[\]<>,]*):(?P<line>\d+)(?::(?P<col>\d+))?"#,
+            )
+            .unwrap(),
+            python: Regex::new(r#"File "(?P<path>[^"]+)", line (?P<line>\d+)"#).unwrap(),
+            ansi: Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap(),
+        }
+    }
+
+    /// Remove the colours and other escape sequences from a line of output
+    pub fn strip_ansi(&self, line: &str) -> String {
+        self.ansi.replace_all(line, "").into_owned()
+    }
+
+    /// The positions in files `line` mentions, in order
+    pub fn find(&self, line: &str) -> Vec<OutputLink> {
+        let mut links: Vec<OutputLink> = Vec::new();
+        for regex in &[&self.python, &self.location] {
+            for caps in regex.captures_iter(line) {
+                let whole = caps.get(0).unwrap();
+                if links
+                    .iter()
+                    .any(|l| l.start < whole.end() && whole.start() < l.end)
+                {
+                    continue;
+                }
+                let line_number = match caps["line"].parse() {
+                    Ok(line_number) if line_number > 0 => line_number,
+                    _ => continue,
+                };
+                links.push(OutputLink {
+                    start: whole.start(),
+                    end: whole.end(),
+                    path: caps["path"].to_string(),
+                    line: line_number,
+                    col: caps.name("col").and_then(|col| col.as_str().parse().ok()),
+                });
+            }
+        }
+        links.sort_by_key(|l| l.start);
+        links
+    }
+}
+
+/// How the last run of a task has ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskState {
+    Running,
+    Succeeded,
+    /// Failed with the exit code, if it has exited at all and wasn't killed by a signal
+    Failed(Option<i32>),
+    Stopped,
+}
+
+/// Sent to the `TaskRunner` by the thread reading the output of a task, with the generation of
+/// the run
+enum TaskMsg {
+    Output(u64, String),
+    Exited(u64, Option<i32>),
+}
+
+/// A link in the output buffer, by buffer line and char offsets, to an existing file
+struct BufferLink {
+    line: i32,
+    start: i32,
+    end: i32,
+    path: PathBuf,
+    /// Starting at 0, as `open_file_at` takes them
+    position: (u64, u64),
+}
+
+/// Runs the tasks of the project and shows their output in a pane below the documents, next to
+/// the terminals
+pub struct TaskRunner {
+    /// The output pane, to be added to the bottom of the `MainWin`
+    pub widget: gtk::Box,
+    /// Shows the state of the last task in the header bar
+    pub status_button: Button,
+    status_spinner: Spinner,
+    status_image: Image,
+    status_name: Label,
+    title_label: Label,
+    status_label: Label,
+    stop_button: Button,
+    rerun_button: Button,
+    close_button: Button,
+    text_view: TextView,
+    buffer: TextBuffer,
+    link_tag: TextTag,
+    finder: LinkFinder,
+    sender: Sender<TaskMsg>,
+    /// Incremented for every run, so output of outdated ones is dropped
+    generation: Cell<u64>,
+    child: RefCell<Option<Arc<Mutex<Child>>>>,
+    /// The last task which has been run, and the directory it's been run in
+    last: RefCell<Option<(Task, PathBuf)>>,
+    links: RefCell<Vec<BufferLink>>,
+    /// Called with the file, line and column of the links the user clicks, both starting at 0
+    on_link: RefCell<Option<Box<dyn Fn(&Path, u64, u64)>>>,
+}
+
+impl TaskRunner {
+    pub fn new() -> Rc<Self> {
+        let builder = Builder::new_from_resource("/org/gnome/Tau/task_runner.glade");
+        let (sender, receiver) = MainContext::channel(glib::source::PRIORITY_DEFAULT);
+
+        let text_view: TextView = builder.get_object("task_output_textview").unwrap();
+        let buffer = text_view.get_buffer().unwrap();
+        let link_tag = TextTag::new(Some("link"));
+        link_tag.set_property_underline(pango::Underline::Single);
+        link_tag.set_property_foreground(Some("#1c71d8"));
+        buffer.get_tag_table().unwrap().add(&link_tag);
+
+        let runner = Rc::new(Self {
+            widget: builder.get_object("task_output_box").unwrap(),
+            status_button: builder.get_object("task_status_button").unwrap(),
+            status_spinner: builder.get_object("task_status_spinner").unwrap(),
+            status_image: builder.get_object("task_status_image").unwrap(),
+            status_name: builder.get_object("task_status_name").unwrap(),
+            title_label: builder.get_object("task_output_title").unwrap(),
+            status_label: builder.get_object("task_output_status_label").unwrap(),
+            stop_button: builder.get_object("task_output_stop_button").unwrap(),
+            rerun_button: builder.get_object("task_output_rerun_button").unwrap(),
+            close_button: builder.get_object("task_output_close_button").unwrap(),
+            text_view,
+            buffer,
+            link_tag,
+            finder: LinkFinder::new(),
+            sender,
+            generation: Cell::new(0),
+            child: Default::default(),
+            last: Default::default(),
+            links: Default::default(),
+            on_link: Default::default(),
+        });
+
+        receiver.attach(
+            None,
+            clone!(@weak runner => @default-return glib::Continue(false), move |msg| {
+                runner.receive(msg);
+                glib::Continue(true)
+            }),
+        );
+
+        runner
+            .stop_button
+            .connect_clicked(clone!(@weak runner => @default-panic, move |_| {
+                runner.stop();
+            }));
+
+        runner
+            .rerun_button
+            .connect_clicked(clone!(@weak runner => @default-panic, move |_| {
+                runner.rerun();
+            }));
+
+        runner.text_view.connect_button_release_event(
+            clone!(@weak runner => @default-panic, move |text_view, eb| {
+                if eb.get_button() != 1 || runner.buffer.get_has_selection() {
+                    return Inhibit(false);
+                }
+                let (x, y) = eb.get_position();
+                let (x, y) = text_view.window_to_buffer_coords(
+                    gtk::TextWindowType::Widget,
+                    x as i32,
+                    y as i32,
+                );
+                if let Some(iter) = text_view.get_iter_at_location(x, y) {
+                    runner.open_link(iter.get_line(), iter.get_line_offset());
+                }
+                Inhibit(false)
+            }),
+        );
+
+        runner
+    }
+
+    /// Call `f` with the file, line and column (both starting at 0) of the links the user clicks
+    pub fn connect_link_activated<F: Fn(&Path, u64, u64) + 'static>(&self, f: F) {
+        self.on_link.replace(Some(Box::new(f)));
+    }
+
+    /// Call `f` when the user closes the output pane
+    pub fn connect_close<F: Fn() + 'static>(&self, f: F) {
+        self.close_button.connect_clicked(move |_| f());
+    }
+
+    pub fn last_task(&self) -> Option<Task> {
+        self.last.borrow().as_ref().map(|(task, _)| task.clone())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.child.borrow().is_some()
+    }
+
+    /// Run `task` of the project in `root`, stopping the one which is running
+    pub fn run(&self, task: &Task, root: &Path) {
+        let dir = match &task.cwd {
+            Some(cwd) => root.join(cwd),
+            None => root.to_path_buf(),
+        };
+        self.start(task, &dir);
+    }
+
+    /// Run the last task again. Returns false if no task has been run yet.
+    pub fn rerun(&self) -> bool {
+        let last = self.last.borrow().clone();
+        match last {
+            Some((task, dir)) => {
+                self.start(&task, &dir);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run `task` in `dir` and show its output
+    fn start(&self, task: &Task, dir: &Path) {
+        self.stop();
+        self.last.replace(Some((task.clone(), dir.to_path_buf())));
+        let generation = self.generation.get() + 1;
+        self.generation.set(generation);
+
+        self.buffer.set_text("");
+        self.links.borrow_mut().clear();
+        self.title_label.set_text(&task.name);
+        debug!("Running task '{}' in '{:?}'", task.command, dir);
+
+        let mut child = match spawn_task(&task.command, dir) {
+            Ok(child) => child,
+            Err(e) => {
+                self.append_line(&format!(
+                    "{}: {}",
+                    freplace(gettext("Couldn’t run “{}”"), &[&task.command]),
+                    e
+                ));
+                self.set_state(TaskState::Failed(None));
+                return;
+            }
+        };
+        self.append_line(&format!("$ {}", task.command));
+
+        let stdout = child.stdout.take().unwrap();
+        let child = Arc::new(Mutex::new(child));
+        self.child.replace(Some(child.clone()));
+        self.set_state(TaskState::Running);
+
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).split(b'\n') {
+                let line = match line {
+                    Ok(line) => String::from_utf8_lossy(&line).into_owned(),
+                    Err(e) => {
+                        warn!("Failed to read the output of a task due to error: {}", e);
+                        break;
+                    }
+                };
+                if sender.send(TaskMsg::Output(generation, line)).is_err() {
+                    return;
+                }
+            }
+            // Don't hold the lock while waiting, so the task can still be stopped
+            let code = loop {
+                match child.lock().try_wait() {
+                    Ok(Some(status)) => break status.code(),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Failed to wait for a task due to error: {}", e);
+                        break None;
+                    }
+                }
+                thread::sleep(Duration::from_millis(EXIT_POLL_INTERVAL_MS));
+            };
+            sender.send(TaskMsg::Exited(generation, code)).ok();
+        });
+    }
+
+    /// Kill the task which is running, if any
+    pub fn stop(&self) {
+        if let Some(child) = self.child.borrow_mut().take() {
+            trace!("Stopping the running task");
+            if let Err(e) = kill_task(&mut child.lock()) {
+                warn!("Failed to stop task due to error: {}", e);
+            }
+            // Output which is still on its way belongs to a stopped run
+            self.generation.set(self.generation.get() + 1);
+            self.append_line(&gettext("Stopped"));
+            self.set_state(TaskState::Stopped);
+        }
+    }
+
+    fn receive(&self, msg: TaskMsg) {
+        match msg {
+            TaskMsg::Output(generation, line) => {
+                if generation == self.generation.get() {
+                    self.append_line(&line);
+                }
+            }
+            TaskMsg::Exited(generation, code) => {
+                if generation != self.generation.get() {
+                    return;
+                }
+                self.child.replace(None);
+                let state = if code == Some(0) {
+                    TaskState::Succeeded
+                } else {
+                    TaskState::Failed(code)
+                };
+                self.set_state(state);
+            }
+        }
+    }
+
+    /// Add a line of output, turning mentions of existing files into links
+    fn append_line(&self, line: &str) {
+        let line = self.finder.strip_ansi(line.trim_end_matches('\r'));
+        let mut end = self.buffer.get_end_iter();
+        let buffer_line = end.get_line();
+        self.buffer.insert(&mut end, &line);
+
+        let dir = self.last.borrow().as_ref().map(|(_, dir)| dir.clone());
+        for link in self.finder.find(&line) {
+            let path = match &dir {
+                Some(dir) => dir.join(&link.path),
+                None => PathBuf::from(&link.path),
+            };
+            if !path.is_file() {
+                continue;
+            }
+            let start = line[..link.start].chars().count() as i32;
+            let end = start + line[link.start..link.end].chars().count() as i32;
+            let start_iter = self.buffer.get_iter_at_line_offset(buffer_line, start);
+            let end_iter = self.buffer.get_iter_at_line_offset(buffer_line, end);
+            self.buffer
+                .apply_tag(&self.link_tag, &start_iter, &end_iter);
+            self.links.borrow_mut().push(BufferLink {
+                line: buffer_line,
+                start,
+                end,
+                path,
+                position: (link.line - 1, link.col.unwrap_or(1).saturating_sub(1)),
+            });
+        }
+
+        let mut end = self.buffer.get_end_iter();
+        self.buffer.insert(&mut end, "\n");
+
+        // Follow the output, like a terminal does
+        let mark = self.buffer.get_insert().unwrap();
+        self.buffer.place_cursor(&self.buffer.get_end_iter());
+        self.text_view.scroll_mark_onscreen(&mark);
+    }
+
+    /// Open the link at `offset` (in chars) of `line` of the output, if there's one
+    fn open_link(&self, line: i32, offset: i32) {
+        let target = self
+            .links
+            .borrow()
+            .iter()
+            .find(|l| l.line == line && l.start <= offset && offset < l.end)
+            .map(|l| (l.path.clone(), l.position));
+        if let (Some((path, (line, col))), Some(f)) = (target, &*self.on_link.borrow()) {
+            f(&path, line, col);
+        }
+    }
+
+    /// Show `state` in the output pane and the header bar
+    fn set_state(&self, state: TaskState) {
+        let name = self.last_task().map(|task| task.name).unwrap_or_default();
+        let (icon, status) = match state {
+            TaskState::Running => (None, gettext("Running…")),
+            TaskState::Succeeded => (Some("emblem-ok-symbolic"), gettext("Succeeded")),
+            TaskState::Failed(Some(code)) => (
+                Some("dialog-error-symbolic"),
+                freplace(gettext("Failed with exit code {}"), &[&code.to_string()]),
+            ),
+            TaskState::Failed(None) => (Some("dialog-error-symbolic"), gettext("Failed")),
+            TaskState::Stopped => (Some("process-stop-symbolic"), gettext("Stopped")),
+        };
+        let running = state == TaskState::Running;
+
+        self.status_label.set_text(&status);
+        self.stop_button.set_sensitive(running);
+        self.rerun_button.set_sensitive(!running);
+
+        self.status_name.set_text(&name);
+        self.status_button
+            .set_tooltip_text(Some(&format!("{}: {}", name, status)));
+        if running {
+            self.status_spinner.start();
+        } else {
+            self.status_spinner.stop();
+        }
+        self.status_spinner.set_visible(running);
+        if let Some(icon) = icon {
+            self.status_image
+                .set_from_icon_name(Some(icon), gtk::IconSize::Button);
+        }
+        self.status_image.set_visible(!running);
+        self.status_button.show();
+    }
+}
+
+/// Run `command` with `sh -c` in `dir`, in a process group of its own, so `kill_task` can stop
+/// the processes it starts too, e.g. the compiler `cargo build` runs
+fn spawn_task(command: &str, dir: &Path) -> io::Result<Child> {
+    let mut cmd = Command::new("sh");
+    // Merge stderr into stdout so the lines stay in the order they've been printed in
+    cmd.arg("-c")
+        .arg(format!("exec 2>&1\n{}", command))
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped());
+    #[cfg(unix)]
+    set_process_group(&mut cmd);
+    cmd.spawn()
+}
+
+/// Start `cmd` in a process group of its own, which is led by it
+#[cfg(unix)]
+fn set_process_group(cmd: &mut Command) {
+    // Safe since setpgid is async-signal-safe and nothing is allocated
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+/// Kill the process group of a task started by `spawn_task`
+#[cfg(unix)]
+fn kill_task(child: &mut Child) -> io::Result<()> {
+    // The group has the ID of the shell, which leads it
+    let pgid = child.id() as libc::pid_t;
+    if unsafe { libc::kill(-pgid, libc::SIGKILL) } == 0 {
+        Ok(())
+    } else {
+        // The group is gone already, so at most the shell is left to be reaped
+        child.kill()
+    }
+}
+
+/// Kill a task started by `spawn_task`. There are no process groups here, so the processes the
+/// shell has started keep running.
+#[cfg(not(unix))]
+fn kill_task(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+impl Drop for TaskRunner {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.borrow_mut().take() {
+            kill_task(&mut child.lock()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn links(line: &str) -> Vec<(String, u64, Option<u64>)> {
+        LinkFinder::new()
+            .find(line)
+            .into_iter()
+            .map(|l| (l.path, l.line, l.col))
+            .collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stop_task_with_children() {
+        let started = std::time::Instant::now();
+        let mut child = spawn_task("sleep 30; echo done", &std::env::temp_dir()).unwrap();
+        let stdout = child.stdout.take().unwrap();
+        // Give the shell time to start sleep
+        thread::sleep(Duration::from_millis(200));
+        kill_task(&mut child).unwrap();
+
+        // The pipe is only closed once sleep, which holds it too, has been killed as well
+        let mut output = String::new();
+        io::Read::read_to_string(&mut BufReader::new(stdout), &mut output).unwrap();
+        assert!(!child.wait().unwrap().success());
+        assert_eq!(output, "");
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn compiler_output() {
+        assert_eq!(
+            links("  --> src/main.rs:10:5"),
+            vec![("src/main.rs".to_string(), 10, Some(5))]
+        );
+        assert_eq!(
+            links("main.c:3:12: error: expected ‘;’ before ‘}’ token"),
+            vec![("main.c".to_string(), 3, Some(12))]
+        );
+        assert_eq!(
+            links("./lib/util.go:42: undefined: foo"),
+            vec![("./lib/util.go".to_string(), 42, None)]
+        );
+        assert_eq!(
+            links(r"C:\src\main.c:7:1: warning"),
+            vec![(r"C:\src\main.c".to_string(), 7, Some(1))]
+        );
+    }
+
+    #[test]
+    fn python_tracebacks() {
+        let line = r#"  File "/home/user/app/main.py", line 12, in <module>"#;
+        assert_eq!(
+            links(line),
+            vec![("/home/user/app/main.py".to_string(), 12, None)]
+        );
+        let finder = LinkFinder::new();
+        let link = &finder.find(line)[0];
+        assert_eq!(
+            &line[link.start..link.end],
+            r#"File "/home/user/app/main.py", line 12"#
+        );
+    }
+
+    #[test]
+    fn no_links() {
+        assert!(links("Compiling tau v0.11.3").is_empty());
+        assert!(links("Finished in 12:30:45").is_empty());
+        assert!(links("src/main.rs:0:1").is_empty());
+    }
+
+    #[test]
+    fn ansi() {
+        let finder = LinkFinder::new();
+        assert_eq!(
+            finder.strip_ansi("\x1b[0m\x1b[1m\x1b[38;5;9merror\x1b[0m: oops"),
+            "error: oops"
+        );
+    }
+
+    #[test]
+    fn tasks() {
+        let text = r#"[
+            { "name": "Build", "command": "make" },
+            { "name": "Docs", "command": "make html", "cwd": "docs" }
+        ]"#;
+        assert_eq!(
+            parse_tasks(text).unwrap(),
+            vec![
+                Task::new("Build", "make"),
+                Task {
+                    name: "Docs".to_string(),
+                    command: "make html".to_string(),
+                    cwd: Some("docs".to_string()),
+                }
+            ]
+        );
+        assert!(parse_tasks(r#"[{ "name": "Build" }]"#).is_err());
+    }
+}
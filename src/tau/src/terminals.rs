@@ -0,0 +1,240 @@
+// Copyright (C) 2019-2020 Rasmus Thomsen <oss@cogitri.dev>
+// SPDX-License-Identifier: MIT
+
+//! Profiles for the embedded terminals and the state we keep about them between sessions. Profiles
+//! are configured in `terminal_profiles.json`, e.g.:
+//!
+//! ```json
+//! [
+//!     { "name": "Fish", "shell": "/usr/bin/fish", "args": ["--private"] },
+//!     { "name": "Build", "cwd": "~/src", "env": { "CARGO_TERM_COLOR": "always" },
+//!       "font": "Monospace 9" }
+//! ]
+//! ```
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The name of the profile which uses the shell of the `terminal-path` key
+pub const DEFAULT_PROFILE: &str = "Default";
+
+/// How a terminal is spawned
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    /// The shell to run. The one of the `terminal-path` key is used if this isn't set.
+    pub shell: Option<String>,
+    pub args: Vec<String>,
+    /// Variables added to the environment of the shell
+    pub env: BTreeMap<String, String>,
+    /// The directory the shell starts in. The current file's directory is used if this isn't set.
+    pub cwd: Option<String>,
+    /// A Pango font description, e.g. "Monospace 11"
+    pub font: Option<String>,
+}
+
+impl Profile {
+    /// The working directory, with a leading `~` replaced by the home directory
+    pub fn cwd(&self) -> Option<PathBuf> {
+        let cwd = self.cwd.as_deref()?;
+        let home = dirs::home_dir().filter(|_| cwd == "~" || cwd.starts_with("~/"));
+        match home {
+            Some(home) => Some(home.join(cwd[1..].trim_start_matches('/'))),
+            None => Some(PathBuf::from(cwd)),
+        }
+    }
+
+    /// The environment in the `NAME=value` form VTE expects
+    pub fn envv(&self) -> Vec<String> {
+        self.env
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect()
+    }
+}
+
+/// Parse the profiles in `json`. A "Default" profile is added if `json` doesn't define one.
+pub fn parse_profiles(json: &str) -> Result<Vec<Profile>, String> {
+    let mut profiles: Vec<Profile> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    if let Some(profile) = profiles.iter().find(|p| p.name.is_empty()) {
+        return Err(format!("The profile {:?} has no name", profile));
+    }
+    if !profiles.iter().any(|p| p.name == DEFAULT_PROFILE) {
+        profiles.insert(0, default_profile());
+    }
+    Ok(profiles)
+}
+
+/// The profile used when the user hasn't configured any
+pub fn default_profile() -> Profile {
+    Profile {
+        name: DEFAULT_PROFILE.to_string(),
+        ..Profile::default()
+    }
+}
+
+/// Load the profiles from the file at `path`, falling back to the default profile
+pub fn load_profiles(path: Option<&Path>) -> Vec<Profile> {
+    let path = match path {
+        Some(path) if path.exists() => path,
+        _ => return vec![default_profile()],
+    };
+    match std::fs::read_to_string(path).map_err(|e| e.to_string()) {
+        Ok(json) => parse_profiles(&json).unwrap_or_else(|e| {
+            error!("Failed to parse terminal profiles {:?}: {}", path, e);
+            vec![default_profile()]
+        }),
+        Err(e) => {
+            error!("Failed to read terminal profiles {:?}: {}", path, e);
+            vec![default_profile()]
+        }
+    }
+}
+
+/// A terminal of the last session, as stored in the `session-terminals` key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionTerminal {
+    pub profile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub title: String,
+}
+
+impl SessionTerminal {
+    pub fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| warn!("Failed to parse session terminal '{}': {}", json, e))
+            .ok()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// What runs in the foreground of a terminal
+#[derive(Clone, Debug, PartialEq)]
+pub enum Foreground {
+    /// The shell waits for commands
+    Shell,
+    /// Another program runs, the name of its executable
+    Process(String),
+    /// We can't find out, e.g. on other systems than Linux
+    Unknown,
+}
+
+/// Whether we can look at the processes of the terminals in `/proc`. In a Flatpak the shells
+/// might be spawned on the host, where our pids don't mean anything.
+#[cfg(target_os = "linux")]
+fn can_inspect_processes() -> bool {
+    !Path::new("/.flatpak-info").exists()
+}
+
+/// The foreground process group of the terminal of the process, from the contents of its
+/// `/proc/<pid>/stat`
+#[cfg(target_os = "linux")]
+fn parse_tpgid(stat: &str) -> Option<i32> {
+    // The name of the executable is in parentheses and may contain spaces and parentheses itself
+    let fields = &stat[stat.rfind(')')? + 1..];
+    // state, ppid, pgrp, session, tty_nr, tpgid
+    fields.split_whitespace().nth(5)?.parse().ok()
+}
+
+/// What runs in the foreground of the terminal whose shell is `shell_pid`
+#[cfg(target_os = "linux")]
+pub fn foreground_process(shell_pid: i32) -> Foreground {
+    if !can_inspect_processes() {
+        return Foreground::Unknown;
+    }
+    let tpgid = std::fs::read_to_string(format!("/proc/{}/stat", shell_pid))
+        .ok()
+        .and_then(|stat| parse_tpgid(&stat));
+    match tpgid {
+        Some(tpgid) if tpgid > 0 && tpgid != shell_pid => {
+            match std::fs::read_to_string(format!("/proc/{}/comm", tpgid)) {
+                Ok(comm) => Foreground::Process(comm.trim().to_string()),
+                // It has exited in the meantime
+                Err(_) => Foreground::Shell,
+            }
+        }
+        Some(_) => Foreground::Shell,
+        None => Foreground::Unknown,
+    }
+}
+
+/// What runs in the foreground of the terminal whose shell is `shell_pid`
+#[cfg(not(target_os = "linux"))]
+pub fn foreground_process(_shell_pid: i32) -> Foreground {
+    Foreground::Unknown
+}
+
+/// The working directory of the process, if we can find it out
+#[cfg(target_os = "linux")]
+pub fn process_cwd(pid: i32) -> Option<PathBuf> {
+    if !can_inspect_processes() {
+        return None;
+    }
+    std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+/// The working directory of the process, if we can find it out
+#[cfg(not(target_os = "linux"))]
+pub fn process_cwd(_pid: i32) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let profiles = parse_profiles(
+            r#"[{ "name": "Build", "shell": "/bin/zsh", "args": ["-l"],
+                  "env": { "A": "1", "B": "x=y" }, "font": "Monospace 9" }]"#,
+        )
+        .unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0], default_profile());
+        assert_eq!(profiles[1].shell.as_deref(), Some("/bin/zsh"));
+        assert_eq!(profiles[1].args, vec!["-l".to_string()]);
+        assert_eq!(profiles[1].envv(), vec!["A=1", "B=x=y"]);
+        assert_eq!(profiles[1].cwd(), None);
+
+        let profiles = parse_profiles(r#"[{ "name": "Default", "cwd": "/srv" }]"#).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].cwd(), Some(PathBuf::from("/srv")));
+
+        assert!(parse_profiles(r#"[{ "shell": "/bin/sh" }]"#).is_err());
+        assert!(parse_profiles("{").is_err());
+    }
+
+    #[test]
+    fn session_terminals() {
+        let terminal = SessionTerminal {
+            profile: "Default".to_string(),
+            cwd: Some("/home/user/my project".to_string()),
+            title: "Terminal 1".to_string(),
+        };
+        assert_eq!(
+            SessionTerminal::from_json(&terminal.to_json()),
+            Some(terminal)
+        );
+        assert_eq!(
+            SessionTerminal::from_json(r#"{"profile":"Fish"}"#).map(|t| t.cwd),
+            Some(None)
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn foreground_process_group() {
+        let stat = "4242 (my (odd) sh) S 4200 4242 4242 34817 4300 4194304 1200 0 0 0";
+        assert_eq!(parse_tpgid(stat), Some(4300));
+        assert_eq!(parse_tpgid("4242 (sh"), None);
+    }
+}